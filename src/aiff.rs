@@ -0,0 +1,158 @@
+//! AIFF ・ AIFF-C の復号（ `aiff` フィーチャー）
+//!
+//! 読めるのは整数の PCM （ビッグエンディアンと AIFF-C の `sowt` ）と 32 ビット浮動小数点数だけ
+
+use crate::samples::Decoded;
+use std::convert::TryFrom;
+
+/// 標本の並び方
+#[derive(Clone, Copy)]
+enum Encoding {
+    BigEndian,
+    LittleEndian,
+    Float,
+}
+
+struct Common {
+    channels: usize,
+    frames: usize,
+    bits: usize,
+    samplerate: f64,
+    encoding: Encoding,
+}
+
+/// AIFF ファイルの中身を復号する．2 チャンネル以上なら先頭の 2 チャンネル
+pub fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    let compressed = match bytes.get(8..12) {
+        Some(b"AIFF") => false,
+        Some(b"AIFC") => true,
+        _ => return Err("not an AIFF file".to_string()),
+    };
+    let mut common = None;
+    let mut sound = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let size = u32::from_be_bytes(<[u8; 4]>::try_from(&rest[4..8]).unwrap()) as usize;
+        let data = rest
+            .get(8..8 + size)
+            .ok_or_else(|| "truncated chunk".to_string())?;
+        match &rest[..4] {
+            b"COMM" => common = Some(parse_common(data, compressed)?),
+            b"SSND" => sound = Some(data),
+            _ => {}
+        }
+        // チャンクは偶数バイトに揃える
+        rest = rest.get(8 + size + size % 2..).unwrap_or_default();
+    }
+    let common = common.ok_or_else(|| "no COMM chunk".to_string())?;
+    let sound = sound.ok_or_else(|| "no SSND chunk".to_string())?;
+    if sound.len() < 8 {
+        return Err("truncated SSND chunk".to_string());
+    }
+    let offset = u32::from_be_bytes(<[u8; 4]>::try_from(&sound[..4]).unwrap()) as usize;
+    let data = sound.get(8 + offset..).unwrap_or_default();
+    if common.channels == 0 {
+        return Err("no channels".to_string());
+    }
+    let width = common.bits.div_ceil(8);
+    let frames = common.frames.min(data.len() / (width * common.channels));
+    let sample = |i: usize| {
+        let bytes = &data[i * width..(i + 1) * width];
+        match common.encoding {
+            Encoding::Float => f64::from(f32::from_be_bytes(<[u8; 4]>::try_from(bytes).unwrap())),
+            encoding => {
+                // 左に詰めた符号つき整数
+                let x = bytes.iter().enumerate().fold(0i32, |x, (j, byte)| {
+                    let shift = match encoding {
+                        Encoding::LittleEndian => 8 * j,
+                        _ => 8 * (width - 1 - j),
+                    };
+                    x | i32::from(*byte) << (shift + 8 * (4 - width))
+                });
+                f64::from(x) / 2f64.powi(31)
+            }
+        }
+    };
+    Ok(Decoded {
+        channels: (0..common.channels.min(2))
+            .map(|channel| {
+                (0..frames)
+                    .map(|frame| sample(frame * common.channels + channel))
+                    .collect()
+            })
+            .collect(),
+        samplerate: common.samplerate,
+        loop_points: None,
+    })
+}
+
+fn parse_common(data: &[u8], compressed: bool) -> Result<Common, String> {
+    if data.len() < 18 {
+        return Err("truncated COMM chunk".to_string());
+    }
+    let channels = u16::from_be_bytes([data[0], data[1]]) as usize;
+    let frames = u32::from_be_bytes(<[u8; 4]>::try_from(&data[2..6]).unwrap()) as usize;
+    let bits = u16::from_be_bytes([data[6], data[7]]) as usize;
+    let samplerate = extended(<[u8; 10]>::try_from(&data[8..18]).unwrap());
+    let encoding = match data.get(18..22) {
+        _ if !compressed => Encoding::BigEndian,
+        Some(b"NONE" | b"twos") => Encoding::BigEndian,
+        Some(b"sowt") => Encoding::LittleEndian,
+        Some(b"fl32" | b"FL32") => Encoding::Float,
+        Some(kind) => {
+            return Err(format!(
+                "unsupported AIFF-C compression `{}`",
+                String::from_utf8_lossy(kind)
+            ))
+        }
+        None => return Err("truncated COMM chunk".to_string()),
+    };
+    let valid = match encoding {
+        Encoding::Float => bits == 32,
+        _ => (1..=32).contains(&bits),
+    };
+    if !valid {
+        return Err(format!("unsupported sample size {}", bits));
+    }
+    Ok(Common {
+        channels,
+        frames,
+        bits,
+        samplerate,
+        encoding,
+    })
+}
+
+/// 80 ビットの拡張精度浮動小数点数（標本化周波数）
+fn extended(bytes: [u8; 10]) -> f64 {
+    let exponent = i32::from(u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7fff);
+    let mantissa = u64::from_be_bytes(<[u8; 8]>::try_from(&bytes[2..]).unwrap());
+    let sign = if bytes[0] & 0x80 == 0 { 1. } else { -1. };
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_pcm() {
+        let mut comm = vec![0, 2, 0, 0, 0, 2, 0, 16];
+        // 44100 = 1.3458251953125 × 2^15
+        comm.extend(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        let mut ssnd = vec![0; 8];
+        ssnd.extend(&[0x40, 0x00, 0xc0, 0x00, 0x7f, 0xff, 0x80, 0x00]);
+        let mut bytes = b"FORM\0\0\0\0AIFF".to_vec();
+        for (id, data) in &[(b"COMM", &comm), (b"SSND", &ssnd)] {
+            bytes.extend(*id);
+            bytes.extend(&(data.len() as u32).to_be_bytes());
+            bytes.extend(*data);
+        }
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.samplerate, 44100.);
+        assert_eq!(
+            decoded.channels,
+            vec![vec![0.5, 32767. / 32768.], vec![-0.5, -1.]]
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! 標本列の解析（ピーク，実効値，ラウドネス，音高，発音時刻）
+
+use crate::filter::Biquad;
+use num::complex::Complex64;
+
+/// 絶対値の最大値
+pub fn peak(samples: &[f64]) -> f64 {
+    samples.iter().fold(0., |max, x| x.abs().max(max))
+}
+
+/// 実効値（二乗平均平方根）
+pub fn rms(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.;
+    }
+    (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+/// ITU-R BS.1770 の K 特性フィルタ（高域シェルフと高域通過）を任意の標本化周波数で作る
+fn k_weighting(samplerate: f64) -> [Biquad; 2] {
+    use std::f64::consts::PI;
+    // 高域シェルフ
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / samplerate).tan();
+    let vh = 10f64.powf(gain / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+    );
+    // 高域通過
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / samplerate).tan();
+    let a0 = 1. + k / q + k * k;
+    let highpass = Biquad::new(
+        [1., -2., 1.],
+        [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+    );
+    [shelf, highpass]
+}
+
+/// 統合ラウドネス（ LUFS ，モノラル）．ITU-R BS.1770-4 に従い，
+/// 400 ms のブロック（ 75 % 重複）に絶対ゲート -70 LUFS と相対ゲート -10 LU をかける．
+///
+/// 無音やブロックに満たない長さでは負の無限大
+pub fn lufs_integrated(samples: &[f64], samplerate: f64) -> f64 {
+    lufs_integrated_channels(&[samples], samplerate)
+}
+
+/// 複数チャンネルの統合ラウドネス（ LUFS ）．ブロックの平均二乗をチャンネルについて足す
+pub fn lufs_integrated_channels(channels: &[&[f64]], samplerate: f64) -> f64 {
+    let weighted: Vec<Vec<f64>> = channels
+        .iter()
+        .map(|samples| {
+            let [mut shelf, mut highpass] = k_weighting(samplerate);
+            samples
+                .iter()
+                .map(|x| highpass.process(shelf.process(*x)))
+                .collect()
+        })
+        .collect();
+    let len = weighted.iter().map(Vec::len).min().unwrap_or(0);
+    let block = (0.4 * samplerate).round() as usize;
+    let step = (0.1 * samplerate).round() as usize;
+    if block == 0 || step == 0 || len < block {
+        return f64::NEG_INFINITY;
+    }
+    let powers: Vec<f64> = (0..=(len - block) / step)
+        .map(|i| {
+            weighted
+                .iter()
+                .map(|weighted| {
+                    let block = &weighted[i * step..i * step + block];
+                    block.iter().map(|x| x * x).sum::<f64>() / block.len() as f64
+                })
+                .sum()
+        })
+        .collect();
+    let loudness = |power: f64| -0.691 + 10. * power.log10();
+    let gated = |threshold: f64| -> Vec<f64> {
+        powers
+            .iter()
+            .copied()
+            .filter(|power| loudness(*power) > threshold)
+            .collect()
+    };
+    let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+    let absolute = gated(-70.);
+    if absolute.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+    let relative = gated(loudness(mean(&absolute)) - 10.);
+    loudness(mean(&relative))
+}
+
+/// 真のピークを求めるときの倍率と，補間に使う片側の標本数
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: isize = 12;
+
+/// 真のピーク（ ITU-R BS.1770 ）．標本の間を窓つき sinc 関数で 4 倍に補間した絶対値の最大値
+pub fn true_peak(samples: &[f64]) -> f64 {
+    use std::f64::consts::PI;
+    let len = samples.len() as isize;
+    let mut max = peak(samples);
+    for i in 0..len {
+        for phase in 1..TRUE_PEAK_OVERSAMPLING {
+            let t = phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            let x: f64 = (i - TRUE_PEAK_TAPS + 1..=i + TRUE_PEAK_TAPS)
+                .filter(|j| (0..len).contains(j))
+                .map(|j| {
+                    let d = (i - j) as f64 + t;
+                    let window = 0.5 + 0.5 * (PI * d / TRUE_PEAK_TAPS as f64).cos();
+                    samples[j as usize] * (PI * d).sin() / (PI * d) * window
+                })
+                .sum();
+            max = max.max(x.abs());
+        }
+    }
+    max
+}
+
+/// 検出する基本周波数の範囲（ Hz ）
+const PITCH_RANGE: (f64, f64) = (40., 2000.);
+/// YIN の累積平均正規化差分関数のしきい値
+const YIN_THRESHOLD: f64 = 0.1;
+
+/// YIN 法で基本周波数（ Hz ）を推定する．
+/// 標本列の中央の部分を解析する．音高が見つからなければ 0
+pub fn detect_pitch(samples: &[f64], samplerate: f64) -> f64 {
+    let min_lag = ((samplerate / PITCH_RANGE.1).floor() as usize).max(2);
+    let max_lag = ((samplerate / PITCH_RANGE.0).ceil() as usize).min(samples.len() / 2);
+    if max_lag <= min_lag {
+        return 0.;
+    }
+    // 窓の長さは最大の遅れと同じ
+    let window = max_lag;
+    let start = (samples.len() - 2 * window) / 2;
+    let frame = &samples[start..start + 2 * window];
+    // 差分関数
+    let difference: Vec<f64> = (0..=max_lag)
+        .map(|lag| {
+            (0..window)
+                .map(|j| (frame[j] - frame[j + lag]).powi(2))
+                .sum()
+        })
+        .collect();
+    // 累積平均正規化差分関数
+    let mut normalized = vec![1.; max_lag + 1];
+    let mut sum = 0.;
+    for lag in 1..=max_lag {
+        sum += difference[lag];
+        normalized[lag] = if sum > 0. {
+            difference[lag] * lag as f64 / sum
+        } else {
+            1.
+        };
+    }
+    // しきい値を下回った最初の谷．なければ全体の最小値
+    let lag = match (min_lag..=max_lag).find(|&lag| normalized[lag] < YIN_THRESHOLD) {
+        Some(mut lag) => {
+            while lag < max_lag && normalized[lag + 1] < normalized[lag] {
+                lag += 1;
+            }
+            lag
+        }
+        None => {
+            let lag = (min_lag..=max_lag)
+                .min_by(|&x, &y| {
+                    normalized[x]
+                        .partial_cmp(&normalized[y])
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap();
+            if normalized[lag] > 0.5 {
+                // 周期性がない（無音や雑音）
+                return 0.;
+            }
+            lag
+        }
+    };
+    // 放物線補間
+    let lag = if lag > min_lag && lag < max_lag {
+        let (a, b, c) = (normalized[lag - 1], normalized[lag], normalized[lag + 1]);
+        let denominator = a - 2. * b + c;
+        if denominator.abs() > 1e-12 {
+            lag as f64 + (a - c) / (2. * denominator)
+        } else {
+            lag as f64
+        }
+    } else {
+        lag as f64
+    };
+    samplerate / lag
+}
+
+/// 音高を追跡する間隔（秒）
+pub const PITCH_HOP: f64 = 0.01;
+
+/// `PITCH_HOP` 秒ごとの基本周波数（ Hz ）．`i` 番目は時刻 `i * PITCH_HOP` を中心とする
+/// フレームの推定で，音高が見つからなければ 0
+pub fn track_pitch(samples: &[f64], samplerate: f64) -> Vec<f64> {
+    let half = (samplerate / PITCH_RANGE.0).ceil() as usize;
+    let hop = PITCH_HOP * samplerate;
+    let frames = (samples.len() as f64 / hop).ceil() as usize;
+    (0..frames)
+        .map(|i| {
+            let center = (i as f64 * hop).round() as usize;
+            // 端は 0 で埋める
+            let frame: Vec<f64> = (center..center + 2 * half)
+                .map(|j| {
+                    j.checked_sub(half)
+                        .and_then(|j| samples.get(j))
+                        .map_or(0., |x| *x)
+                })
+                .collect();
+            detect_pitch(&frame, samplerate)
+        })
+        .collect()
+}
+
+/// 高速フーリエ変換（基数 2 ，その場で変換）．長さは 2 の冪でなければならない
+pub fn fft(buffer: &mut [Complex64]) {
+    let n = buffer.len();
+    debug_assert!(n.is_power_of_two());
+    // ビット反転の並べ替え
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= n {
+        let ratio = Complex64::from_polar(1., -std::f64::consts::TAU / length as f64);
+        for chunk in buffer.chunks_mut(length) {
+            let mut w = Complex64::new(1., 0.);
+            let (left, right) = chunk.split_at_mut(length / 2);
+            for (x, y) in left.iter_mut().zip(right) {
+                let t = *y * w;
+                *y = *x - t;
+                *x += t;
+                w *= ratio;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+/// 振幅スペクトル（ハン窓をかけて `frame.len()` 点の FFT ，長さは半分）
+pub fn spectrum(frame: &[f64]) -> Vec<f64> {
+    let n = frame.len();
+    let mut buffer: Vec<Complex64> = frame
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let window = 0.5 - 0.5 * (std::f64::consts::TAU * i as f64 / n as f64).cos();
+            Complex64::new(x * window, 0.)
+        })
+        .collect();
+    fft(&mut buffer);
+    buffer[..n / 2].iter().map(|x| x.norm()).collect()
+}
+
+/// 発音時刻検出のフレーム長と間隔（標本数）
+const ONSET_FRAME: usize = 1024;
+const ONSET_HOP: usize = 256;
+/// 正規化したスペクトルフラックスに対するしきい値
+const ONSET_THRESHOLD: f64 = 0.1;
+/// 発音と発音の最小の間隔（秒）
+const ONSET_MIN_INTERVAL: f64 = 0.03;
+
+/// スペクトルフラックス（振幅スペクトルの増加分の和）の山を拾って，発音時刻（秒）を返す
+pub fn detect_onsets(samples: &[f64], samplerate: f64) -> Vec<f64> {
+    if samples.len() < ONSET_FRAME {
+        return Vec::new();
+    }
+    // 先頭の発音も拾えるように，前に無音のフレームを 1 つ置く
+    let mut padded = vec![0.; ONSET_FRAME];
+    padded.extend_from_slice(samples);
+    let spectra: Vec<Vec<f64>> = (0..=(padded.len() - ONSET_FRAME) / ONSET_HOP)
+        .map(|i| spectrum(&padded[i * ONSET_HOP..i * ONSET_HOP + ONSET_FRAME]))
+        .collect();
+    let mut flux: Vec<f64> = spectra
+        .windows(2)
+        .map(|pair| {
+            pair[1]
+                .iter()
+                .zip(&pair[0])
+                .map(|(x, y)| (x - y).max(0.))
+                .sum()
+        })
+        .collect();
+    let max = flux.iter().cloned().fold(0., f64::max);
+    if max <= 0. {
+        return Vec::new();
+    }
+    flux.iter_mut().for_each(|x| *x /= max);
+    let min_interval = (ONSET_MIN_INTERVAL * samplerate / ONSET_HOP as f64).ceil() as usize;
+    let mut onsets = Vec::new();
+    let mut last: Option<usize> = None;
+    for t in 0..flux.len() {
+        let neighborhood = &flux[t.saturating_sub(3)..(t + 4).min(flux.len())];
+        let local = &flux[t.saturating_sub(10)..(t + 4).min(flux.len())];
+        let mean = local.iter().sum::<f64>() / local.len() as f64;
+        let is_peak = neighborhood.iter().all(|x| *x <= flux[t]);
+        if is_peak
+            && flux[t] >= mean + ONSET_THRESHOLD
+            && !matches!(last, Some(last) if t - last < min_interval)
+        {
+            last = Some(t);
+            // フレーム t + 1 は元の標本列の (t + 1) * HOP - FRAME から始まる．
+            // 立ち上がりはおよそそのフレームの中央にある
+            let sample = ((t + 1) * ONSET_HOP) as f64 - ONSET_FRAME as f64 / 2.;
+            onsets.push((sample / samplerate).max(0.));
+        }
+    }
+    onsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, seconds: f64, samplerate: f64) -> Vec<f64> {
+        (0..(seconds * samplerate) as usize)
+            .map(|i| amplitude * (std::f64::consts::TAU * frequency * i as f64 / samplerate).sin())
+            .collect()
+    }
+
+    #[test]
+    fn peak_and_rms() {
+        let samples = sine(100., 0.5, 1., 48000.);
+        assert!((peak(&samples) - 0.5).abs() < 1e-3);
+        assert!((rms(&samples) - 0.5 / 2f64.sqrt()).abs() < 1e-3);
+        assert_eq!(rms(&[]), 0.);
+    }
+
+    #[test]
+    fn lufs_full_scale_sine() {
+        // 1 kHz ，振幅 1 の正弦波はおよそ -3.01 LUFS
+        for &samplerate in &[44100., 48000.] {
+            let lufs = lufs_integrated(&sine(1000., 1., 2., samplerate), samplerate);
+            assert!((lufs + 3.01).abs() < 0.1, "{}", lufs);
+        }
+        let lufs = lufs_integrated(&sine(1000., 0.1, 2., 48000.), 48000.);
+        assert!((lufs + 23.01).abs() < 0.1, "{}", lufs);
+        // 同じ音の 2 チャンネルは 3 dB 大きい
+        let samples = sine(1000., 0.1, 2., 48000.);
+        let lufs = lufs_integrated_channels(&[&samples, &samples], 48000.);
+        assert!((lufs + 20.).abs() < 0.1, "{}", lufs);
+    }
+
+    #[test]
+    fn true_peak_between_samples() {
+        // 標本化周波数の 4 分の 1 で位相が 45° ずれた正弦波は，標本の絶対値が 1 / √2 になる
+        let samples: Vec<f64> = (0..1000)
+            .map(|i| (std::f64::consts::FRAC_PI_2 * i as f64 + std::f64::consts::FRAC_PI_4).sin())
+            .collect();
+        assert!((peak(&samples) - 0.5f64.sqrt()).abs() < 1e-9);
+        assert!(
+            (true_peak(&samples) - 1.).abs() < 0.02,
+            "{}",
+            true_peak(&samples)
+        );
+    }
+
+    #[test]
+    fn pitch_sine() {
+        for &frequency in &[55., 220., 440., 1000.] {
+            let pitch = detect_pitch(&sine(frequency, 0.5, 0.5, 44100.), 44100.);
+            assert!((pitch - frequency).abs() < frequency * 0.005, "{}", pitch);
+        }
+    }
+
+    #[test]
+    fn pitch_harmonics() {
+        // 倍音を含んでも基本周波数を返す
+        let fundamental = sine(110., 0.3, 0.5, 44100.);
+        let harmonic = sine(220., 0.5, 0.5, 44100.);
+        let samples: Vec<f64> = fundamental
+            .iter()
+            .zip(&harmonic)
+            .map(|(x, y)| x + y)
+            .collect();
+        let pitch = detect_pitch(&samples, 44100.);
+        assert!((pitch - 110.).abs() < 1., "{}", pitch);
+    }
+
+    #[test]
+    fn pitch_silence() {
+        assert_eq!(detect_pitch(&[0.; 44100], 44100.), 0.);
+        assert_eq!(detect_pitch(&[0.5; 10], 44100.), 0.);
+    }
+
+    #[test]
+    fn fft_sine() {
+        let mut buffer: Vec<Complex64> = (0..64)
+            .map(|i| Complex64::new((std::f64::consts::TAU * 4. * i as f64 / 64.).cos(), 0.))
+            .collect();
+        fft(&mut buffer);
+        for (i, x) in buffer.iter().enumerate() {
+            let expected = if i == 4 || i == 60 { 32. } else { 0. };
+            assert!((x.norm() - expected).abs() < 1e-9, "{} {}", i, x);
+        }
+    }
+
+    #[test]
+    fn onsets_clicks() {
+        // 0.1 秒，0.6 秒，1.1 秒に減衰するノイズ状の打撃音
+        let samplerate = 44100.;
+        let mut samples = vec![0.; (1.5 * samplerate) as usize];
+        for &onset in &[0.1, 0.6, 1.1] {
+            let start = (onset * samplerate) as usize;
+            for i in 0..4410 {
+                let decay = (-(i as f64) / 500.).exp();
+                samples[start + i] += decay * ((i * 7919 % 200) as f64 / 100. - 1.);
+            }
+        }
+        let onsets = detect_onsets(&samples, samplerate);
+        assert_eq!(onsets.len(), 3, "{:?}", onsets);
+        for (onset, expected) in onsets.iter().zip(&[0.1, 0.6, 1.1]) {
+            assert!((onset - expected).abs() < 0.015, "{:?}", onsets);
+        }
+        assert!(detect_onsets(&[0.; 44100], samplerate).is_empty());
+    }
+
+    #[test]
+    fn lufs_silence() {
+        assert_eq!(lufs_integrated(&[0.; 48000], 48000.), f64::NEG_INFINITY);
+    }
+}
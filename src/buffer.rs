@@ -0,0 +1,149 @@
+//! 長さの決まった音（標本列）の編集（ `reverse` ， `trim` ， `append` ， `fade_edges` ）
+//!
+//! 標本列でない音は長さぶん描いてから編集する．結果はいつも `Sound::Samples`
+
+use crate::function::DEFAULT_SAMPLERATE;
+use crate::samples::Decoded;
+use crate::sound::Sound;
+
+/// 編集できる音か確かめる
+pub fn check(name: &str, sound: &Sound) -> Result<(), String> {
+    match sound.duration() {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "`{}` must have a finite length (record it with `render`)",
+            name
+        )),
+    }
+}
+
+/// 逆再生
+pub fn reverse(sound: &Sound) -> Sound {
+    let samplerate = samplerate(sound);
+    let mut channels = channels(sound, samplerate);
+    for samples in &mut channels {
+        samples.reverse();
+    }
+    build(channels, samplerate)
+}
+
+/// 時刻 `[start, end)` （秒）を切り出す．`end` は音の長さで止まる
+pub fn trim(sound: &Sound, start: f64, end: f64) -> Sound {
+    let samplerate = samplerate(sound);
+    let channels = channels(sound, samplerate)
+        .into_iter()
+        .map(|samples| {
+            let index = |t: f64| ((t * samplerate).round() as usize).min(samples.len());
+            samples[index(start)..index(end).max(index(start))].to_vec()
+        })
+        .collect();
+    build(channels, samplerate)
+}
+
+/// `a` の後に `b` をつなぐ．標本化周波数は `a` に合わせ，どちらかがステレオならステレオ
+pub fn append(a: &Sound, b: &Sound) -> Sound {
+    let samplerate = samplerate(a);
+    let (a, b) = (channels(a, samplerate), channels(b, samplerate));
+    let count = a.len().max(b.len());
+    let channel = |channels: &[Vec<f64>], i: usize| channels[i.min(channels.len() - 1)].clone();
+    let channels = (0..count)
+        .map(|i| [channel(&a, i), channel(&b, i)].concat())
+        .collect();
+    build(channels, samplerate)
+}
+
+/// 両端を `ms` ミリ秒ずつ直線で上げ下げする．音が短ければ半分ずつ
+pub fn fade_edges(sound: &Sound, ms: f64) -> Sound {
+    let samplerate = samplerate(sound);
+    let mut channels = channels(sound, samplerate);
+    for samples in &mut channels {
+        let len = samples.len();
+        let fade = ((ms / 1000. * samplerate).round() as usize).min(len / 2);
+        for i in 0..fade {
+            let gain = i as f64 / fade as f64;
+            samples[i] *= gain;
+            samples[len - 1 - i] *= gain;
+        }
+    }
+    build(channels, samplerate)
+}
+
+/// 標本化周波数 `samplerate` で描いた標本列（ `freeze` ）
+pub fn render(sound: &Sound, samplerate: f64) -> Sound {
+    build(channels(sound, samplerate), samplerate)
+}
+
+/// 標本列ならその標本化周波数
+fn samplerate(sound: &Sound) -> f64 {
+    match sound {
+        Sound::Samples { samplerate, .. } => *samplerate,
+        Sound::Stereo(left, _) => samplerate(left),
+        _ => DEFAULT_SAMPLERATE,
+    }
+}
+
+/// チャンネルごとの標本列．同じ標本化周波数の標本列は描かずにそのまま使う
+fn channels(sound: &Sound, samplerate: f64) -> Vec<Vec<f64>> {
+    let render = |sound: Sound| match sound {
+        Sound::Samples {
+            samples,
+            samplerate: rate,
+            offset,
+            looping: None,
+        } if rate == samplerate && offset == 0. => samples.to_vec(),
+        sound => {
+            let duration = sound.duration().expect("infinite sound");
+            sound.render(duration, samplerate)
+        }
+    };
+    if sound.channels() == 2 {
+        vec![
+            render(sound.clone().channel(0)),
+            render(sound.clone().channel(1)),
+        ]
+    } else {
+        vec![render(sound.clone())]
+    }
+}
+
+fn build(channels: Vec<Vec<f64>>, samplerate: f64) -> Sound {
+    Decoded {
+        channels,
+        samplerate,
+        loop_points: None,
+    }
+    .into_sound()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn samples(samples: &[f64]) -> Sound {
+        Sound::Samples {
+            samples: Rc::new(samples.to_vec()),
+            samplerate: 10.,
+            offset: 0.,
+            looping: None,
+        }
+    }
+
+    fn rendered(sound: Sound) -> Vec<f64> {
+        let duration = sound.duration().unwrap();
+        sound.render(duration, 10.)
+    }
+
+    #[test]
+    fn edits() {
+        let a = samples(&[1., 2., 3., 4.]);
+        assert_eq!(rendered(reverse(&a)), [4., 3., 2., 1.]);
+        assert_eq!(rendered(trim(&a, 0.1, 0.3)), [2., 3.]);
+        assert_eq!(rendered(trim(&a, 0.2, 10.)), [3., 4.]);
+        assert_eq!(rendered(append(&a, &samples(&[5.]))), [1., 2., 3., 4., 5.]);
+        assert_eq!(
+            rendered(fade_edges(&samples(&[1.; 6]), 200.)),
+            [0., 0.5, 1., 1., 0.5, 0.]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! 台本と，それが読むファイルをまとめた 1 つのファイル（ `.crb` ）
+//!
+//! 形式は，見出し行 `cryss-bundle 1` のあとに，
+//! ファイルごとに `<バイト数> <名前>` の行と中身を並べたもの．
+//! 最初のファイルが台本
+
+use crate::lexer::Lexer;
+use crate::token::Token;
+use std::path::{Component, Path, PathBuf};
+
+const HEADER: &str = "cryss-bundle 1\n";
+
+/// 第 1 引数の文字列リテラルをファイル名として読む組み込み関数
+const READERS: &[&str] = &["load_preset", "read", "embed"];
+
+/// 台本と一緒にまとめる設定ファイル
+const CONFIG: &str = "cryss.toml";
+
+#[derive(Debug, PartialEq)]
+pub struct Bundle {
+    /// 名前（台本からの相対パス）と中身．最初が台本
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    /// 台本 `script` と，それが読むファイル，隣にある `cryss.toml` を集める
+    pub fn collect(script: &Path) -> Result<Bundle, String> {
+        let read = |path: &Path| {
+            std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))
+        };
+        let directory = script.parent().unwrap_or_else(|| Path::new(""));
+        let source = read(script)?;
+        let name = script
+            .file_name()
+            .ok_or_else(|| format!("{} is not a file", script.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let mut files = vec![(name, source.clone())];
+        for name in referenced_files(&source)? {
+            let path = Path::new(&name);
+            if !is_relative(path) {
+                return Err(format!(
+                    "cannot bundle `{}` (only paths relative to the script are allowed)",
+                    name
+                ));
+            }
+            if files.iter().all(|(other, _)| *other != name) {
+                let content = read(&directory.join(path))?;
+                files.push((name, content));
+            }
+        }
+        let config = directory.join(CONFIG);
+        if config.exists() && files.iter().all(|(other, _)| other != CONFIG) {
+            files.push((CONFIG.to_string(), read(&config)?));
+        }
+        Ok(Bundle { files })
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = HEADER.as_bytes().to_vec();
+        for (name, content) in &self.files {
+            bytes.extend(format!("{} {}\n", content.len(), name).bytes());
+            bytes.extend(content);
+        }
+        bytes
+    }
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Bundle, String> {
+        bytes = bytes
+            .strip_prefix(HEADER.as_bytes())
+            .ok_or_else(|| "not a cryss bundle".to_string())?;
+        let mut files = Vec::new();
+        while !bytes.is_empty() {
+            let newline = bytes
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or_else(|| "truncated bundle".to_string())?;
+            let line = String::from_utf8_lossy(&bytes[..newline]).into_owned();
+            let (length, name) = line
+                .split_once(' ')
+                .and_then(|(length, name)| Some((length.parse::<usize>().ok()?, name)))
+                .ok_or_else(|| format!("invalid entry `{}`", line))?;
+            if !is_relative(Path::new(name)) {
+                return Err(format!("invalid file name `{}`", name));
+            }
+            bytes = &bytes[newline + 1..];
+            if bytes.len() < length {
+                return Err(format!("truncated file `{}`", name));
+            }
+            files.push((name.to_string(), bytes[..length].to_vec()));
+            bytes = &bytes[length..];
+        }
+        if files.is_empty() {
+            return Err("empty bundle".to_string());
+        }
+        Ok(Bundle { files })
+    }
+    /// `directory` に展開し，台本のパスを返す
+    pub fn extract(&self, directory: &Path) -> Result<PathBuf, String> {
+        for (name, content) in &self.files {
+            let path = directory.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|err| format!("cannot create {}: {}", parent.display(), err))?;
+            }
+            std::fs::write(&path, content)
+                .map_err(|err| format!("cannot write {}: {}", path.display(), err))?;
+        }
+        Ok(directory.join(&self.files[0].0))
+    }
+}
+
+/// 台本の中で `READERS` の関数に渡されている文字列リテラル
+fn referenced_files(source: &[u8]) -> Result<Vec<String>, String> {
+    let mut lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_vec())), false);
+    let mut log = Vec::new();
+    let mut tokens = Vec::new();
+    loop {
+        match lexer.next(&mut log) {
+            Ok(Some((_, token))) => tokens.push(token),
+            Ok(None) => break,
+            Err(_) => return Err("cannot tokenize the script".to_string()),
+        }
+    }
+    let mut files = Vec::new();
+    // `samples { "名前": "パス" ... }` の中にいるか
+    let mut in_samples = false;
+    for window in tokens.windows(3) {
+        match window {
+            [Token::Identifier(function), Token::OpeningParenthesis, Token::String(name)]
+                if READERS.contains(&function.as_str()) =>
+            {
+                files.push(name.clone())
+            }
+            [Token::KeywordSamples, ..] => in_samples = true,
+            [Token::ClosingBrace, ..] => in_samples = false,
+            [Token::String(_), Token::Colon, Token::String(path)] if in_samples => {
+                files.push(path.clone())
+            }
+            _ => {}
+        }
+    }
+    Ok(files)
+}
+
+/// 台本の置き場所の外を指さないパス
+pub fn is_relative(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let directory = std::env::temp_dir().join(format!("cryss-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("presets")).unwrap();
+        std::fs::write(
+            directory.join("piece.crs"),
+            "let p = load_preset(\"presets/pad.json\");\nwrite(Sin(p.freq), 1, \"out.wav\");\n",
+        )
+        .unwrap();
+        std::fs::write(directory.join("presets/pad.json"), "{\"freq\": 440}").unwrap();
+        let bundle = Bundle::collect(&directory.join("piece.crs")).unwrap();
+        let names: Vec<_> = bundle.files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["piece.crs", "presets/pad.json"]);
+
+        let bytes = bundle.to_bytes();
+        assert_eq!(Bundle::from_bytes(&bytes).unwrap(), bundle);
+        assert!(Bundle::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Bundle::from_bytes(b"cryss-bundle 1\n3 ../x\nabc").is_err());
+
+        let extracted = directory.join("extracted");
+        let script = bundle.extract(&extracted).unwrap();
+        assert_eq!(script, extracted.join("piece.crs"));
+        assert_eq!(
+            std::fs::read(extracted.join("presets/pad.json")).unwrap(),
+            b"{\"freq\": 440}"
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! 読み込んだ音声ファイルのキャッシュ（既定では `~/.cache/cryss` ）
+//!
+//! 元のファイルの SHA-256 を名前にして，復号した標本をそのまま置く．
+//! 合計が上限を超えたら，最後に使ったのが古いものから消す
+
+use crate::samples::Decoded;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+/// キャッシュファイルの先頭
+const MAGIC: &[u8; 8] = b"CRYSSC2\0";
+
+/// 合計の大きさの既定の上限（バイト）
+pub const DEFAULT_MAX_SIZE: u64 = 1 << 30;
+
+pub struct Cache {
+    directory: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    pub fn new(directory: PathBuf, max_size: u64) -> Cache {
+        Cache {
+            directory,
+            max_size,
+        }
+    }
+    /// 既定の置き場所（ `$XDG_CACHE_HOME/cryss` か `~/.cache/cryss` ）
+    pub fn default_directory() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(base) if !base.is_empty() => PathBuf::from(base),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(base.join("cryss"))
+    }
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(key)
+    }
+    /// `key` の標本．なければ（読めなければ） `None`
+    pub fn get(&self, key: &str) -> Option<Decoded> {
+        let path = self.path(key);
+        let decoded = decode(&std::fs::read(&path).ok()?)?;
+        // 使った時刻として更新時刻を進める
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(std::time::SystemTime::now());
+        }
+        Some(decoded)
+    }
+    /// `key` として `decoded` を置き，上限を超えた分を消す
+    pub fn put(&self, key: &str, decoded: &Decoded) -> Result<(), String> {
+        std::fs::create_dir_all(&self.directory)
+            .map_err(|err| format!("cannot create {}: {}", self.directory.display(), err))?;
+        let path = self.path(key);
+        std::fs::write(&path, encode(decoded))
+            .map_err(|err| format!("cannot write {}: {}", path.display(), err))?;
+        self.evict(&path)
+    }
+    /// 合計が上限以下になるまで，`keep` 以外を古いものから消す
+    fn evict(&self, keep: &Path) -> Result<(), String> {
+        let mut files = entries(&self.directory)?;
+        let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+        files.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in files {
+            if total <= self.max_size {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                // 他のスレッドが先に消した
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                result => {
+                    result.map_err(|err| format!("cannot remove {}: {}", path.display(), err))?
+                }
+            }
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+/// `directory` のキャッシュをすべて消し，消した合計の大きさを返す
+pub fn clean(directory: &Path) -> Result<u64, String> {
+    let mut total = 0;
+    for (path, size, _) in entries(directory)? {
+        std::fs::remove_file(&path)
+            .map_err(|err| format!("cannot remove {}: {}", path.display(), err))?;
+        total += size;
+    }
+    Ok(total)
+}
+
+/// キャッシュファイルとその大きさ，更新時刻
+fn entries(directory: &Path) -> Result<Vec<(PathBuf, u64, std::time::SystemTime)>, String> {
+    let read_dir = match std::fs::read_dir(directory) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format!("cannot read {}: {}", directory.display(), err)),
+    };
+    let mut ret = Vec::new();
+    for entry in read_dir {
+        let entry = entry.map_err(|err| format!("cannot read {}: {}", directory.display(), err))?;
+        let metadata = entry
+            .metadata()
+            .map_err(|err| format!("cannot read {}: {}", entry.path().display(), err))?;
+        if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            ret.push((entry.path(), metadata.len(), modified));
+        }
+    }
+    Ok(ret)
+}
+
+/// 先頭 8 バイト，チャンネル数（ 1 バイト），標本化周波数，1 チャンネルの標本数，
+/// ループの始まりと終わり（なければどちらも `u64::MAX` ），標本（チャンネル順）．
+/// 数はすべてリトルエンディアン
+fn encode(decoded: &Decoded) -> Vec<u8> {
+    let len = decoded.channels.iter().map(Vec::len).min().unwrap_or(0);
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(decoded.channels.len() as u8);
+    bytes.extend(&decoded.samplerate.to_le_bytes());
+    bytes.extend(&(len as u64).to_le_bytes());
+    let (start, end) = decoded
+        .loop_points
+        .map_or((u64::MAX, u64::MAX), |(start, end)| {
+            (start as u64, end as u64)
+        });
+    bytes.extend(&start.to_le_bytes());
+    bytes.extend(&end.to_le_bytes());
+    for samples in &decoded.channels {
+        for x in &samples[..len] {
+            bytes.extend(&x.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Option<Decoded> {
+    let rest = bytes.strip_prefix(MAGIC)?;
+    let (&channels, rest) = rest.split_first()?;
+    let number = |bytes: &[u8]| <[u8; 8]>::try_from(bytes.get(..8)?).ok();
+    let samplerate = f64::from_le_bytes(number(rest)?);
+    let len = u64::from_le_bytes(number(rest.get(8..)?)?) as usize;
+    let start = u64::from_le_bytes(number(rest.get(16..)?)?);
+    let end = u64::from_le_bytes(number(rest.get(24..)?)?);
+    let rest = &rest[32..];
+    if !matches!(channels, 1 | 2) || rest.len() != channels as usize * len * 8 {
+        return None;
+    }
+    let channels = (0..channels as usize)
+        .map(|i| {
+            rest[i * len * 8..(i + 1) * len * 8]
+                .chunks(8)
+                .map(|x| f64::from_le_bytes(<[u8; 8]>::try_from(x).unwrap()))
+                .collect()
+        })
+        .collect();
+    Some(Decoded {
+        channels,
+        samplerate,
+        loop_points: if start == u64::MAX {
+            None
+        } else {
+            Some((start as usize, end as usize))
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_eviction() {
+        let directory = std::env::temp_dir().join(format!("cryss-cache-{}", std::process::id()));
+        let mono = Decoded {
+            channels: vec![vec![0.5, -0.25, 1.]],
+            samplerate: 8000.,
+            loop_points: Some((1, 3)),
+        };
+        let stereo = Decoded {
+            channels: vec![vec![0.5, -0.25, 1.], vec![0., 0., 0.]],
+            samplerate: 8000.,
+            loop_points: None,
+        };
+        // 2 つは入らない上限
+        let cache = Cache::new(directory.clone(), 100);
+        cache.put("a", &mono).unwrap();
+        assert_eq!(cache.get("a"), Some(mono));
+        cache.put("b", &stereo).unwrap();
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some(stereo));
+        assert!(clean(&directory).unwrap() > 0);
+        assert_eq!(cache.get("b"), None);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
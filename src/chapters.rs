@@ -0,0 +1,102 @@
+//! マーカーと区間をトラックの区切りとして書き出す（ `write_cue` ， `write_chapters` ）
+
+use crate::preset;
+use crate::timeline::Chapter;
+use std::fmt::Write;
+
+/// CUE シートのトラック数の上限
+const MAX_TRACKS: usize = 99;
+
+/// 音声ファイル `file` の CUE シート．時刻は 1/75 秒単位に丸める
+pub fn cue(chapters: &[Chapter], file: &str) -> Result<String, String> {
+    if chapters.len() > MAX_TRACKS {
+        return Err(format!(
+            "too many tracks for a cue sheet ({}, at most {})",
+            chapters.len(),
+            MAX_TRACKS
+        ));
+    }
+    // CUE シートの文字列には `"` を書けない
+    let quote = |string: &str| format!("\"{}\"", string.replace('"', "'"));
+    let mut cue = format!("FILE {} WAVE\n", quote(file));
+    for (i, chapter) in chapters.iter().enumerate() {
+        let frames = (chapter.start * 75.).round() as u64;
+        writeln!(cue, "  TRACK {:02} AUDIO", i + 1).unwrap();
+        writeln!(cue, "    TITLE {}", quote(&chapter.title)).unwrap();
+        writeln!(
+            cue,
+            "    INDEX 01 {:02}:{:02}:{:02}",
+            frames / 75 / 60,
+            frames / 75 % 60,
+            frames % 75
+        )
+        .unwrap();
+    }
+    Ok(cue)
+}
+
+/// チャプターの JSON．マーカーは次のチャプターの始まりで終わり，最後のマーカーの終わりは `null`
+pub fn json(chapters: &[Chapter]) -> String {
+    let mut json = String::from("{\n  \"chapters\": [");
+    for (i, chapter) in chapters.iter().enumerate() {
+        let end = chapter
+            .end
+            .or_else(|| chapters.get(i + 1).map(|next| next.start))
+            .map_or("null".to_string(), |end| end.to_string());
+        let separator = if i == 0 { "\n" } else { ",\n" };
+        write!(
+            json,
+            "{}    {{\"title\": {}, \"start\": {}, \"end\": {}}}",
+            separator,
+            preset::quote(&chapter.title),
+            chapter.start,
+            end
+        )
+        .unwrap();
+    }
+    if !chapters.is_empty() {
+        json.push_str("\n  ");
+    }
+    json.push_str("]\n}\n");
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::Timeline;
+
+    fn chapters(from: Option<&str>) -> Vec<Chapter> {
+        let mut timeline = Timeline::new();
+        timeline.add_marker("intro".to_string(), 0.);
+        timeline.add_region("verse \"1\"".to_string(), 61.5, 90.);
+        timeline.add_marker("outro".to_string(), 120.);
+        timeline.set_window(from.map(str::to_string), None);
+        timeline.chapters().unwrap()
+    }
+
+    #[test]
+    fn cue_sheet() {
+        let cue = cue(&chapters(None), "album.wav").unwrap();
+        assert_eq!(
+            cue.lines()
+                .filter(|line| line.contains("INDEX"))
+                .collect::<Vec<_>>(),
+            [
+                "    INDEX 01 00:00:00",
+                "    INDEX 01 01:01:38",
+                "    INDEX 01 02:00:00"
+            ]
+        );
+        assert!(cue.contains("TITLE \"verse '1'\""));
+    }
+
+    #[test]
+    fn chapters_json() {
+        assert_eq!(
+            json(&chapters(Some("verse \"1\""))),
+            "{\n  \"chapters\": [\n    {\"title\": \"verse \\\"1\\\"\", \"start\": 0, \"end\": 28.5}\n  ]\n}\n"
+        );
+        assert_eq!(json(&[]), "{\n  \"chapters\": []\n}\n");
+    }
+}
@@ -0,0 +1,147 @@
+//! 和音のボイシング．音の高さは MIDI ノート番号（半音単位の実数）で表す
+
+/// 一番近い同じピッチクラスの音
+fn nearest(from: f64, to: f64) -> f64 {
+    to + 12. * ((from - to) / 12.).round()
+}
+
+/// `next` の各音のオクターブを選び直して，
+/// `prev` からの各声部の動きの合計が最小になるようにする．
+///
+/// `next` の音が `prev` より多いときは，同じ声部から複数の音に分かれてよい．
+/// 結果は低い順に並べる
+pub fn voice_lead(prev: &[f64], next: &[f64]) -> Vec<f64> {
+    if prev.is_empty() {
+        return sorted(next.to_vec());
+    }
+    fn search(
+        prev: &[f64],
+        next: &[f64],
+        used: &mut Vec<bool>,
+        current: &mut Vec<f64>,
+        cost: f64,
+        best: &mut Option<(f64, Vec<f64>)>,
+    ) {
+        if matches!(best, Some((best, _)) if *best <= cost) {
+            return;
+        }
+        let j = current.len();
+        if j == next.len() {
+            *best = Some((cost, current.clone()));
+            return;
+        }
+        // 声部が足りないときは同じ声部を使い回してよい
+        let reuse = next.len() - j > used.iter().filter(|used| !**used).count();
+        for (i, &from) in prev.iter().enumerate() {
+            if used[i] && !reuse {
+                continue;
+            }
+            let to = nearest(from, next[j]);
+            let was = used[i];
+            used[i] = true;
+            current.push(to);
+            search(prev, next, used, current, cost + (to - from).abs(), best);
+            current.pop();
+            used[i] = was;
+        }
+    }
+    let mut best = None;
+    search(
+        prev,
+        next,
+        &mut vec![false; prev.len()],
+        &mut Vec::new(),
+        0.,
+        &mut best,
+    );
+    sorted(best.map(|(_, notes)| notes).unwrap_or_default())
+}
+
+/// 第 `n` 転回形．負なら上の音から順に 1 オクターブ下げる
+pub fn invert(chord: &[f64], n: i64) -> Vec<f64> {
+    let chord = sorted(chord.to_vec());
+    if chord.is_empty() {
+        return chord;
+    }
+    // 音の数だけ転回すると 1 オクターブ上がる
+    let len = chord.len() as i64;
+    let (octaves, rest) = (n.div_euclid(len) as f64, n.rem_euclid(len) as usize);
+    chord[rest..]
+        .iter()
+        .map(|note| note + 12. * octaves)
+        .chain(chord[..rest].iter().map(|note| note + 12. * (octaves + 1.)))
+        .collect()
+}
+
+/// ドロップ・ボイシング：上から `n` 番目の音を 1 オクターブ下げる
+pub fn drop(chord: &[f64], n: i64) -> Vec<f64> {
+    let mut chord = sorted(chord.to_vec());
+    if n >= 1 && n as usize <= chord.len() {
+        let index = chord.len() - n as usize;
+        chord[index] -= 12.;
+    }
+    sorted(chord)
+}
+
+/// 開離配置：下から 2 番目，4 番目，… の音を 1 オクターブ上げる
+pub fn spread(chord: &[f64]) -> Vec<f64> {
+    let chord = sorted(chord.to_vec());
+    sorted(
+        chord
+            .iter()
+            .enumerate()
+            .map(|(i, note)| if i % 2 == 1 { note + 12. } else { *note })
+            .collect(),
+    )
+}
+
+fn sorted(mut chord: Vec<f64>) -> Vec<f64> {
+    chord.sort_by(|x, y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+    chord
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voice_lead_minimal_motion() {
+        // C (C4 E4 G4) -> F (F A C) は C4 F4 A4
+        assert_eq!(
+            voice_lead(&[60., 64., 67.], &[65., 69., 72.]),
+            [60., 65., 69.]
+        );
+        // C -> G (G B D) は B3 D4 G4
+        assert_eq!(
+            voice_lead(&[60., 64., 67.], &[55., 59., 62.]),
+            [59., 62., 67.]
+        );
+    }
+
+    #[test]
+    fn voice_lead_different_sizes() {
+        assert_eq!(voice_lead(&[60., 64., 67.], &[62., 65.]), [62., 65.]);
+        assert_eq!(voice_lead(&[60., 67.], &[60., 64., 67.]), [60., 64., 67.]);
+    }
+
+    #[test]
+    fn inversions() {
+        assert_eq!(invert(&[60., 64., 67.], 1), [64., 67., 72.]);
+        assert_eq!(invert(&[60., 64., 67.], -1), [55., 60., 64.]);
+        assert_eq!(invert(&[60., 64., 67.], -4), [43., 48., 52.]);
+        assert_eq!(
+            invert(&[60., 64., 67.], 7),
+            [64., 67., 72.].map(|note| note + 24.)
+        );
+        let high = invert(&[60., 64., 67.], 1_000_000_000_000);
+        assert_eq!(
+            high,
+            [64., 67., 72.].map(|note| note + 12. * 333_333_333_333.)
+        );
+        assert!(invert(&[60., 64., 67.], i64::MIN)
+            .iter()
+            .all(|note| note.is_finite()));
+        assert_eq!(drop(&[60., 64., 67., 71.], 2), [55., 60., 64., 71.]);
+        assert_eq!(spread(&[60., 64., 67.]), [60., 67., 76.]);
+    }
+}
@@ -0,0 +1,64 @@
+//! 添字や個数など，整数が要るところで実数を整数に直す規則
+//!
+//! 既定では小数部を切り捨てて警告を出す． `--strict-types` では小数部があればエラーにする．
+//! エラーのメッセージは，呼び出し側が位置をつけて `RuntimeFailure` にする
+
+use crate::events;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+thread_local! {
+    static STRICT: Cell<bool> = const { Cell::new(false) };
+    /// 一度出した警告（同じ警告を繰り返さない）
+    static WARNED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+pub fn set_strict(strict: bool) {
+    STRICT.with(|cell| cell.set(strict));
+}
+
+/// `what` （メッセージ用の名前）として使う実数 `x` を整数に直す
+pub fn integer(x: f64, what: &str) -> Result<i64, String> {
+    let truncated = x.trunc();
+    if truncated != x {
+        let message = format!("{} must be an integer (got {})", what, x);
+        if STRICT.with(Cell::get) {
+            return Err(message);
+        }
+        if WARNED.with(|warned| warned.borrow_mut().insert(message.clone())) {
+            events::warning(&format!("{}; truncated to {}", message, truncated));
+        }
+    }
+    Ok(truncated as i64)
+}
+
+/// 負でない整数に直す．負ならエラー
+pub fn count(x: f64, what: &str) -> Result<usize, String> {
+    let n = integer(x, what)?;
+    if n < 0 {
+        return Err(format!("{} must not be negative (got {})", what, x));
+    }
+    Ok(n as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissive_and_strict() {
+        assert_eq!(integer(-2.7, "index"), Ok(-2));
+        assert_eq!(count(3., "bands"), Ok(3));
+        assert_eq!(
+            count(-1., "width"),
+            Err("width must not be negative (got -1)".to_string())
+        );
+        set_strict(true);
+        assert_eq!(integer(4., "index"), Ok(4));
+        assert_eq!(
+            integer(1.5, "index"),
+            Err("index must be an integer (got 1.5)".to_string())
+        );
+        set_strict(false);
+    }
+}
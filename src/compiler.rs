@@ -1,22 +1,104 @@
 //! 抽象構文木（ `mod syntax` ）を型チェックして実行可能なプログラム（ `mod program` ）にする．
 
+use crate::types::Type;
 use crate::{error, function, pos, program, sound, syntax, value};
 use std::collections::HashMap;
 
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-/// variable は，そのスコープに存在する変数
+/// variable は，そのスコープに存在する変数．整数の式は実数にする
 fn compile_expression(
     expression: syntax::Expression,
     variables: &HashMap<String, value::Value>,
     functions: &HashMap<String, function::Function>,
+) -> Result<(program::Expression, pos::Range), error::Error> {
+    let (expr, range) = compile_exact(expression, variables, functions)?;
+    Ok((promote(expr), range))
+}
+
+/// 整数の式を実数の式にする
+fn promote(expr: program::Expression) -> program::Expression {
+    match expr {
+        program::Expression::Int(expr) => program::RealExpression::Int(expr.into()).into(),
+        other => other,
+    }
+}
+
+/// 文字列と連結できる値を文字列にする
+fn stringify(expr: program::Expression) -> Option<program::StringExpression> {
+    match expr {
+        program::Expression::String(expr) => Some(expr),
+        program::Expression::Real(expr) => Some(program::StringExpression::Real(expr.into())),
+        program::Expression::Int(expr) => Some(program::StringExpression::Int(expr.into())),
+        program::Expression::Boolean(expr) => Some(program::StringExpression::Boolean(expr.into())),
+        _ => None,
+    }
+}
+
+/// `f"..."` ．`{name}` は変数 `name` の値を埋め込み， `{{` `}}` は波括弧そのもの．
+/// 閉じていない `{` や対のない `}` はそのまま残す
+fn interpolate(
+    range: pos::Range,
+    string: &str,
+    variables: &HashMap<String, value::Value>,
+) -> Result<program::StringExpression, error::Error> {
+    use program::StringExpression;
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = string;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            literal.push(c);
+            rest = &rest[2..];
+            continue;
+        }
+        let name = rest
+            .strip_prefix('{')
+            .and_then(|rest| rest.split_once('}'))
+            .map(|(name, _)| name);
+        if let Some(name) = name {
+            if !variables.contains_key(name) {
+                return Err(error::Error::UndefinedVariable(name.to_string(), range));
+            }
+            let identifier = syntax::Node::Identifier(name.to_string());
+            let value = compile_exact(
+                syntax::Expression::new(range.clone(), identifier),
+                variables,
+                &HashMap::new(),
+            )?
+            .0;
+            let ty = value.ty();
+            parts.push(StringExpression::Const(std::mem::take(&mut literal)));
+            parts.push(stringify(value).ok_or(error::Error::TypeMismatchUnary(range.clone(), ty))?);
+            rest = &rest[name.len() + 2..];
+            continue;
+        }
+        literal.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    parts.push(StringExpression::Const(literal));
+    let mut parts = parts.into_iter();
+    let first = parts.next().unwrap();
+    Ok(parts.fold(first, |left, right| {
+        StringExpression::Add(left.into(), right.into())
+    }))
+}
+
+/// 整数の式を整数のままにする `compile_expression`
+fn compile_exact(
+    expression: syntax::Expression,
+    variables: &HashMap<String, value::Value>,
+    functions: &HashMap<String, function::Function>,
 ) -> Result<(program::Expression, pos::Range), error::Error> {
     use error::Error;
-    use program::Expression::{Boolean, Real, Sound, String};
+    use program::Expression::{
+        Array, Boolean, Function, Int, Real, Record, Sound, SoundArray, SoundRecord, String,
+    };
     use program::{
-        Argument, BooleanExpression, RealExpression, SoundExpression, StringExpression,
-        VoidExpression,
+        Argument, ArrayExpression, BooleanExpression, FunctionExpression, IntExpression,
+        RealExpression, RecordExpression, SoundArrayExpression, SoundExpression,
+        SoundRecordExpression, StringExpression, VoidExpression,
     };
     use syntax::Node;
     use value::Value;
@@ -24,15 +106,31 @@ fn compile_expression(
     let ret = match expression.node {
         Node::Identifier(name) => match variables.get(&name) {
             Some(Value::Real(rc)) => RealExpression::Reference(rc.clone()).into(),
+            Some(Value::Int(rc)) => IntExpression::Reference(rc.clone()).into(),
             Some(Value::Boolean(rc)) => BooleanExpression::Reference(rc.clone()).into(),
             Some(Value::Sound(rc)) => SoundExpression::Reference(rc.clone()).into(),
             Some(Value::String(rc)) => StringExpression::Reference(rc.clone()).into(),
-            None => return Err(Error::UndefinedVariable(name, expression.range)),
+            Some(Value::Array(rc)) => ArrayExpression::Reference(rc.clone()).into(),
+            Some(Value::Record(rc)) => RecordExpression::Reference(rc.clone()).into(),
+            Some(Value::SoundRecord(rc)) => SoundRecordExpression::Reference(rc.clone()).into(),
+            Some(Value::SoundArray(rc)) => SoundArrayExpression::Reference(rc.clone()).into(),
+            Some(Value::Function(rc)) => FunctionExpression::Reference(rc.clone()).into(),
+            // 関数の名前は関数の値
+            None => match functions.get(&name) {
+                Some(function) => FunctionExpression::Const(Rc::new(function.clone())).into(),
+                None => return Err(Error::UndefinedVariable(name, expression.range)),
+            },
         },
         Node::Invocation(name, arguments, mut named_arguments) => {
-            let function = match functions.get(&name) {
-                Some(function) => function,
-                None => return Err(Error::UndefinedFunction(name, expression.range)),
+            // 関数の値の入った変数は同じ名前の関数より優先する
+            let dynamic;
+            let function = match (variables.get(&name), functions.get(&name)) {
+                (Some(Value::Function(rc)), _) => {
+                    dynamic = function::Function::dynamic(rc.clone());
+                    &dynamic
+                }
+                (_, Some(function)) => function,
+                (_, None) => return Err(Error::UndefinedFunction(name, expression.range)),
             };
             let mut vec = Vec::new();
             let mut sounds = Vec::new();
@@ -43,9 +141,33 @@ fn compile_expression(
                     arguments.len(),
                 ));
             }
-            for (expr, expected) in arguments.into_iter().zip(&function.arguments) {
-                let argument = compile_expression(expr, variables, functions)?;
-                match (expected, argument.0) {
+            let arguments = arguments
+                .into_iter()
+                .map(|expr| compile_exact(expr, variables, functions))
+                .collect::<Result<Vec<_>, _>>()?;
+            // 最初の引数の型が合わなければ，その型で呼ぶ組み込み関数を探す（ `len("abc")` ）
+            let overloaded;
+            let function = match (arguments.first(), function.arguments.first()) {
+                (Some((given, _)), Some(expected))
+                    if promote(given.clone()).ty() != expected.ty() =>
+                {
+                    match function::Function::overload(&name, &given.ty()) {
+                        Some(function) => {
+                            overloaded = function;
+                            &overloaded
+                        }
+                        None => function,
+                    }
+                }
+                _ => function,
+            };
+            for (argument, expected) in arguments.into_iter().zip(&function.arguments) {
+                let given = match expected {
+                    Value::Int(_) => argument.0,
+                    _ => promote(argument.0),
+                };
+                match (expected, given) {
+                    (Value::Int(rc), Int(expr)) => vec.push(Argument::Int(rc.clone(), expr)),
                     (Value::Real(rc), Real(expr)) => vec.push(Argument::Real(rc.clone(), expr)),
                     (Value::Boolean(rc), Boolean(expr)) => {
                         vec.push(Argument::Boolean(rc.clone(), expr))
@@ -54,17 +176,44 @@ fn compile_expression(
                     (Value::String(rc), String(expr)) => {
                         vec.push(Argument::String(rc.clone(), expr))
                     }
-                    (Value::Real(rc), Sound(expr)) => {
+                    (Value::Array(rc), Array(expr)) => vec.push(Argument::Array(rc.clone(), expr)),
+                    (Value::Record(rc), Record(expr)) => {
+                        vec.push(Argument::Record(rc.clone(), expr))
+                    }
+                    (Value::SoundRecord(rc), SoundRecord(expr)) => {
+                        vec.push(Argument::SoundRecord(rc.clone(), expr))
+                    }
+                    (Value::SoundArray(rc), SoundArray(expr)) => {
+                        vec.push(Argument::SoundArray(rc.clone(), expr))
+                    }
+                    (Value::Function(rc), Function(expr))
+                        if rc.borrow().signature() == expr.function().signature() =>
+                    {
+                        vec.push(Argument::Function(rc.clone(), expr))
+                    }
+                    // 実数の引数に音を渡せるのは，変調できる引数だけ
+                    (Value::Real(rc), Sound(expr)) if function.body.modulatable(rc) => {
                         sounds.push((rc.clone(), expr));
                     }
+                    // Sound の引数に実数を渡すと定数の音になる
+                    (Value::Sound(rc), Real(expr)) => {
+                        vec.push(Argument::Sound(rc.clone(), SoundExpression::Real(expr)))
+                    }
                     (_, other) => return Err(Error::TypeMismatchArgument(argument.1, other.ty())),
                 }
             }
             for (name, argument) in &function.named_arguments {
                 match named_arguments.remove(name) {
                     Some(given) => {
-                        let given = compile_expression(given, variables, functions)?;
-                        match (argument, given.0) {
+                        let given = compile_exact(given, variables, functions)?;
+                        let expr = match argument {
+                            Argument::Int(..) => given.0,
+                            _ => promote(given.0),
+                        };
+                        match (argument, expr) {
+                            (Argument::Int(rc, _), Int(expr)) => {
+                                vec.push(Argument::Int(rc.clone(), expr))
+                            }
                             (Argument::Real(rc, _), Real(expr)) => {
                                 vec.push(Argument::Real(rc.clone(), expr))
                             }
@@ -74,10 +223,34 @@ fn compile_expression(
                             (Argument::Sound(rc, _), Sound(expr)) => {
                                 vec.push(Argument::Sound(rc.clone(), expr))
                             }
+                            (Argument::Sound(rc, _), Real(expr)) => {
+                                vec.push(Argument::Sound(rc.clone(), SoundExpression::Real(expr)))
+                            }
                             (Argument::String(rc, _), String(expr)) => {
                                 vec.push(Argument::String(rc.clone(), expr))
                             }
-                            (Argument::Real(rc, _), Sound(expr)) => sounds.push((rc.clone(), expr)),
+                            (Argument::Array(rc, _), Array(expr)) => {
+                                vec.push(Argument::Array(rc.clone(), expr))
+                            }
+                            (Argument::Record(rc, _), Record(expr)) => {
+                                vec.push(Argument::Record(rc.clone(), expr))
+                            }
+                            (Argument::SoundRecord(rc, _), SoundRecord(expr)) => {
+                                vec.push(Argument::SoundRecord(rc.clone(), expr))
+                            }
+                            (Argument::SoundArray(rc, _), SoundArray(expr)) => {
+                                vec.push(Argument::SoundArray(rc.clone(), expr))
+                            }
+                            (Argument::Function(rc, _), Function(expr))
+                                if rc.borrow().signature() == expr.function().signature() =>
+                            {
+                                vec.push(Argument::Function(rc.clone(), expr))
+                            }
+                            (Argument::Real(rc, _), Sound(expr))
+                                if function.body.modulatable(rc) =>
+                            {
+                                sounds.push((rc.clone(), expr))
+                            }
                             (_, other) => {
                                 return Err(Error::TypeMismatchArgument(given.1, other.ty()))
                             }
@@ -99,42 +272,132 @@ fn compile_expression(
             if sounds.is_empty() {
                 match &function.body {
                     function::Body::Real(body) => {
-                        RealExpression::Invocation(body.clone(), vec).into()
+                        RealExpression::Invocation(body.clone(), vec, expression.range.clone())
+                            .into()
                     }
                     function::Body::Sound(body) => {
-                        SoundExpression::Invocation(body.clone(), vec).into()
+                        SoundExpression::Invocation(body.clone(), vec, expression.range.clone())
+                            .into()
                     }
                     function::Body::Void(body) => {
-                        VoidExpression::Invocation(body.clone(), vec).into()
+                        VoidExpression::Invocation(body.clone(), vec, expression.range.clone())
+                            .into()
                     }
                     function::Body::String(body) => {
-                        StringExpression::Invocation(body.clone(), vec).into()
+                        StringExpression::Invocation(body.clone(), vec, expression.range.clone())
+                            .into()
+                    }
+                    function::Body::Int(body) => {
+                        IntExpression::Invocation(body.clone(), vec).into()
                     }
                     function::Body::Boolean(body) => {
                         BooleanExpression::Invocation(body.clone(), vec).into()
                     }
+                    function::Body::Array(body) => {
+                        ArrayExpression::Invocation(body.clone(), vec, expression.range.clone())
+                            .into()
+                    }
+                    function::Body::Record(body) => {
+                        RecordExpression::Invocation(body.clone(), vec, expression.range.clone())
+                            .into()
+                    }
+                    function::Body::SoundArray(body) => SoundArrayExpression::Invocation(
+                        body.clone(),
+                        vec,
+                        expression.range.clone(),
+                    )
+                    .into(),
                 }
             } else {
                 match &function.body {
                     function::Body::Real(body) => {
                         SoundExpression::Apply(body.clone(), vec, sounds).into()
                     }
-                    _ => todo!(),
+                    function::Body::Sound(body) => SoundExpression::Modulate(
+                        body.clone(),
+                        vec,
+                        sounds,
+                        expression.range.clone(),
+                    )
+                    .into(),
+                    // `Body::modulatable` が偽なので音は渡されない
+                    _ => unreachable!(),
                 }
             }
         }
+        Node::Lambda(parameters, body) => {
+            use function::{Body, UserDefined};
+            let Parameters {
+                scope,
+                arguments,
+                named_arguments,
+                incoming,
+                visible,
+            } = compile_parameters(parameters, variables, functions)?;
+            let (body, body_range) = compile_expression(*body, &scope, functions)?;
+            let range = expression.range.clone();
+            macro_rules! lambda {
+                ($body:ident, $function:ident, $expr:expr) => {{
+                    let function = Rc::new(UserDefined::new(
+                        "lambda".to_string(),
+                        range,
+                        incoming,
+                        visible,
+                    ));
+                    function.set_body(program::Statement::Return($expr), variables);
+                    Body::$body(Rc::new(function::$function::UserDefined(function)))
+                }};
+            }
+            let body = match body {
+                Real(expr) => lambda!(Real, RealFunction, expr),
+                Boolean(expr) => lambda!(Boolean, BooleanFunction, expr),
+                Sound(expr) => lambda!(Sound, SoundFunction, expr),
+                String(expr) => lambda!(String, StringFunction, expr),
+                Array(expr) => lambda!(Array, ArrayFunction, expr),
+                Record(expr) => lambda!(Record, RecordFunction, expr),
+                SoundArray(expr) => lambda!(SoundArray, SoundArrayFunction, expr),
+                program::Expression::Void(expr) => lambda!(Void, VoidFunction, expr),
+                other => return Err(Error::TypeMismatchReturn(body_range, other.ty())),
+            };
+            FunctionExpression::Const(Rc::new(function::Function {
+                body,
+                arguments,
+                named_arguments,
+            }))
+            .into()
+        }
         Node::Parameter(_) => todo!(),
         Node::Number(value) => RealExpression::Const(value).into(),
+        Node::Int(value) => IntExpression::Const(value).into(),
         Node::String(string) => StringExpression::Const(string).into(),
-        Node::Print(expr) => match compile_expression(*expr, variables, functions)? {
+        Node::FormatString(string) => {
+            interpolate(expression.range.clone(), &string, variables)?.into()
+        }
+        Node::Print(expr) => match compile_exact(*expr, variables, functions)? {
             (Real(expr), _) => RealExpression::Print(expr.into()).into(),
+            (Int(expr), _) => IntExpression::Print(expr.into()).into(),
             (Boolean(expr), _) => BooleanExpression::Print(expr.into()).into(),
             (Sound(expr), _) => SoundExpression::Play(expr.into()).into(),
             (String(expr), _) => StringExpression::Print(expr.into()).into(),
+            (Array(expr), _) => ArrayExpression::Print(expr.into()).into(),
+            (Record(expr), _) => RecordExpression::Print(expr.into()).into(),
             (other, range) => return Err(Error::TypeMismatchUnary(range, other.ty())),
         },
-        Node::Minus(expr) => match compile_expression(*expr, variables, functions)? {
+        Node::Index(array, index) => match (
+            compile_expression(*array, variables, functions)?,
+            compile_expression(*index, variables, functions)?,
+        ) {
+            ((Array(array), _), (Real(index), range)) => {
+                RealExpression::Index(array.into(), index.into(), range).into()
+            }
+            ((SoundArray(array), _), (Real(index), range)) => {
+                SoundExpression::Index(array.into(), index.into(), range).into()
+            }
+            ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
+        },
+        Node::Minus(expr) => match compile_exact(*expr, variables, functions)? {
             (Real(expr), _) => RealExpression::Minus(expr.into()).into(),
+            (Int(expr), _) => IntExpression::Minus(expr.into(), expression.range.clone()).into(),
             (Sound(expr), _) => SoundExpression::Minus(expr.into()).into(),
             (other, range) => return Err(Error::TypeMismatchUnary(range, other.ty())),
         },
@@ -148,57 +411,90 @@ fn compile_expression(
             (other, range) => return Err(Error::TypeMismatchUnary(range, other.ty())),
         },
         Node::Add(left, right) => {
-            let l = compile_expression(*left, variables, functions)?;
-            let r = compile_expression(*right, variables, functions)?;
+            let l = compile_exact(*left, variables, functions)?;
+            let r = compile_exact(*right, variables, functions)?;
             match (l.0, r.0) {
-                (Real(left), Real(right)) => RealExpression::Add(left.into(), right.into()).into(),
-                (Sound(left), Sound(right)) => {
-                    SoundExpression::Add(left.into(), right.into()).into()
-                }
-                (Sound(left), Real(right)) => {
-                    SoundExpression::Add(left.into(), SoundExpression::Real(right).into()).into()
-                }
-                (Real(left), Sound(right)) => {
-                    SoundExpression::Add(SoundExpression::Real(left).into(), right.into()).into()
+                (Int(left), Int(right)) => {
+                    IntExpression::Add(left.into(), right.into(), expression.range.clone()).into()
                 }
-                (String(left), String(right)) => {
-                    StringExpression::Add(left.into(), right.into()).into()
+                (left @ String(_), right) | (left, right @ String(_)) => {
+                    let (x, y) = (left.ty(), right.ty());
+                    match (stringify(left), stringify(right)) {
+                        (Some(left), Some(right)) => {
+                            StringExpression::Add(left.into(), right.into()).into()
+                        }
+                        _ => return Err(Error::TypeMismatchBinary(l.1, x, r.1, y)),
+                    }
                 }
-                (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                (left, right) => match (promote(left), promote(right)) {
+                    (Real(left), Real(right)) => {
+                        RealExpression::Add(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Sound(right)) => {
+                        SoundExpression::Add(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Real(right)) => {
+                        SoundExpression::Add(left.into(), SoundExpression::Real(right).into())
+                            .into()
+                    }
+                    (Real(left), Sound(right)) => {
+                        SoundExpression::Add(SoundExpression::Real(left).into(), right.into())
+                            .into()
+                    }
+                    (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                },
             }
         }
         Node::Sub(left, right) => {
-            let l = compile_expression(*left, variables, functions)?;
-            let r = compile_expression(*right, variables, functions)?;
+            let l = compile_exact(*left, variables, functions)?;
+            let r = compile_exact(*right, variables, functions)?;
             match (l.0, r.0) {
-                (Real(left), Real(right)) => RealExpression::Sub(left.into(), right.into()).into(),
-                (Sound(left), Sound(right)) => {
-                    SoundExpression::Sub(left.into(), right.into()).into()
-                }
-                (Sound(left), Real(right)) => {
-                    SoundExpression::Sub(left.into(), SoundExpression::Real(right).into()).into()
-                }
-                (Real(left), Sound(right)) => {
-                    SoundExpression::Sub(SoundExpression::Real(left).into(), right.into()).into()
+                (Int(left), Int(right)) => {
+                    IntExpression::Sub(left.into(), right.into(), expression.range.clone()).into()
                 }
-                (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                (left, right) => match (promote(left), promote(right)) {
+                    (Real(left), Real(right)) => {
+                        RealExpression::Sub(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Sound(right)) => {
+                        SoundExpression::Sub(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Real(right)) => {
+                        SoundExpression::Sub(left.into(), SoundExpression::Real(right).into())
+                            .into()
+                    }
+                    (Real(left), Sound(right)) => {
+                        SoundExpression::Sub(SoundExpression::Real(left).into(), right.into())
+                            .into()
+                    }
+                    (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                },
             }
         }
         Node::Mul(left, right) => {
-            let l = compile_expression(*left, variables, functions)?;
-            let r = compile_expression(*right, variables, functions)?;
+            let l = compile_exact(*left, variables, functions)?;
+            let r = compile_exact(*right, variables, functions)?;
             match (l.0, r.0) {
-                (Real(left), Real(right)) => RealExpression::Mul(left.into(), right.into()).into(),
-                (Sound(left), Sound(right)) => {
-                    SoundExpression::Mul(left.into(), right.into()).into()
-                }
-                (Sound(left), Real(right)) => {
-                    SoundExpression::Mul(left.into(), SoundExpression::Real(right).into()).into()
+                (Int(left), Int(right)) => {
+                    IntExpression::Mul(left.into(), right.into(), expression.range.clone()).into()
                 }
-                (Real(left), Sound(right)) => {
-                    SoundExpression::Mul(SoundExpression::Real(left).into(), right.into()).into()
-                }
-                (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                (left, right) => match (promote(left), promote(right)) {
+                    (Real(left), Real(right)) => {
+                        RealExpression::Mul(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Sound(right)) => {
+                        SoundExpression::Mul(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Real(right)) => {
+                        SoundExpression::Mul(left.into(), SoundExpression::Real(right).into())
+                            .into()
+                    }
+                    (Real(left), Sound(right)) => {
+                        SoundExpression::Mul(SoundExpression::Real(left).into(), right.into())
+                            .into()
+                    }
+                    (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                },
             }
         }
         Node::Div(left, right) => {
@@ -219,20 +515,29 @@ fn compile_expression(
             }
         }
         Node::Rem(left, right) => {
-            let l = compile_expression(*left, variables, functions)?;
-            let r = compile_expression(*right, variables, functions)?;
+            let l = compile_exact(*left, variables, functions)?;
+            let r = compile_exact(*right, variables, functions)?;
             match (l.0, r.0) {
-                (Real(left), Real(right)) => RealExpression::Rem(left.into(), right.into()).into(),
-                (Sound(left), Sound(right)) => {
-                    SoundExpression::Rem(left.into(), right.into()).into()
+                (Int(left), Int(right)) => {
+                    IntExpression::Rem(left.into(), right.into(), expression.range.clone()).into()
                 }
-                (Sound(left), Real(right)) => {
-                    SoundExpression::Rem(left.into(), SoundExpression::Real(right).into()).into()
-                }
-                (Real(left), Sound(right)) => {
-                    SoundExpression::Rem(SoundExpression::Real(left).into(), right.into()).into()
-                }
-                (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                (left, right) => match (promote(left), promote(right)) {
+                    (Real(left), Real(right)) => {
+                        RealExpression::Rem(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Sound(right)) => {
+                        SoundExpression::Rem(left.into(), right.into()).into()
+                    }
+                    (Sound(left), Real(right)) => {
+                        SoundExpression::Rem(left.into(), SoundExpression::Real(right).into())
+                            .into()
+                    }
+                    (Real(left), Sound(right)) => {
+                        SoundExpression::Rem(SoundExpression::Real(left).into(), right.into())
+                            .into()
+                    }
+                    (x, y) => return Err(Error::TypeMismatchBinary(l.1, x.ty(), r.1, y.ty())),
+                },
             }
         }
         Node::Pow(left, right) => {
@@ -253,35 +558,75 @@ fn compile_expression(
             }
         }
         Node::LeftShift(left, right) => match (
+            compile_exact(*left, variables, functions)?,
+            compile_exact(*right, variables, functions)?,
+        ) {
+            ((Int(left), _), (Int(right), _)) => {
+                IntExpression::LeftShift(left.into(), right.into(), expression.range.clone()).into()
+            }
+            ((l, x), (r, y)) => match ((promote(l), x), (promote(r), y)) {
+                ((Sound(left), _), (Real(right), _)) => {
+                    SoundExpression::LeftShift(left.into(), right.into()).into()
+                }
+                ((Real(left), _), (Real(right), _)) => {
+                    SoundExpression::LeftShift(SoundExpression::Real(left).into(), right.into())
+                        .into()
+                }
+                ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
+            },
+        },
+        Node::RightShift(left, right) => match (
+            compile_exact(*left, variables, functions)?,
+            compile_exact(*right, variables, functions)?,
+        ) {
+            ((Int(left), _), (Int(right), _)) => {
+                IntExpression::RightShift(left.into(), right.into(), expression.range.clone())
+                    .into()
+            }
+            ((l, x), (r, y)) => match ((promote(l), x), (promote(r), y)) {
+                ((Sound(left), _), (Real(right), _)) => {
+                    SoundExpression::RightShift(left.into(), right.into()).into()
+                }
+                ((Real(left), _), (Real(right), _)) => {
+                    SoundExpression::RightShift(SoundExpression::Real(left).into(), right.into())
+                        .into()
+                }
+                ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
+            },
+        },
+        Node::Less(left, right) => match (
             compile_expression(*left, variables, functions)?,
             compile_expression(*right, variables, functions)?,
         ) {
-            ((Sound(left), _), (Real(right), _)) => {
-                SoundExpression::LeftShift(left.into(), right.into()).into()
-            }
             ((Real(left), _), (Real(right), _)) => {
-                SoundExpression::LeftShift(SoundExpression::Real(left).into(), right.into()).into()
+                BooleanExpression::RealLess(left.into(), right.into()).into()
+            }
+            ((String(left), _), (String(right), _)) => {
+                BooleanExpression::StringLess(left.into(), right.into()).into()
             }
             ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
         },
-        Node::RightShift(left, right) => match (
+        Node::LessEqual(left, right) => match (
             compile_expression(*left, variables, functions)?,
             compile_expression(*right, variables, functions)?,
         ) {
-            ((Sound(left), _), (Real(right), _)) => {
-                SoundExpression::RightShift(left.into(), right.into()).into()
-            }
             ((Real(left), _), (Real(right), _)) => {
-                SoundExpression::RightShift(SoundExpression::Real(left).into(), right.into()).into()
+                BooleanExpression::RealLessEqual(left.into(), right.into()).into()
+            }
+            ((String(left), _), (String(right), _)) => {
+                BooleanExpression::StringLessEqual(left.into(), right.into()).into()
             }
             ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
         },
-        Node::Less(left, right) => match (
+        Node::GreaterEqual(left, right) => match (
             compile_expression(*left, variables, functions)?,
             compile_expression(*right, variables, functions)?,
         ) {
             ((Real(left), _), (Real(right), _)) => {
-                BooleanExpression::RealLess(left.into(), right.into()).into()
+                BooleanExpression::RealGreaterEqual(left.into(), right.into()).into()
+            }
+            ((String(left), _), (String(right), _)) => {
+                BooleanExpression::StringGreaterEqual(left.into(), right.into()).into()
             }
             ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
         },
@@ -292,6 +637,9 @@ fn compile_expression(
             ((Real(left), _), (Real(right), _)) => {
                 BooleanExpression::RealGreater(left.into(), right.into()).into()
             }
+            ((String(left), _), (String(right), _)) => {
+                BooleanExpression::StringGreater(left.into(), right.into()).into()
+            }
             ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
         },
         Node::Equal(left, right) => match (
@@ -336,12 +684,276 @@ fn compile_expression(
             }
             ((l, x), (r, y)) => return Err(Error::TypeMismatchBinary(x, l.ty(), y, r.ty())),
         },
-        Node::Group(expr) => compile_expression(*expr, variables, functions)?.0,
+        Node::Group(expr) => compile_exact(*expr, variables, functions)?.0,
+        Node::Score(mut rows) if rows.len() == 1 => {
+            // 一行だけのものは配列．Sound の要素があれば Sound の配列にする
+            let mut vec = Vec::new();
+            let mut has_sound = false;
+            for expr in rows.pop().unwrap() {
+                match compile_expression(expr, variables, functions)? {
+                    (Real(expr), _) => vec.push(SoundExpression::Real(expr)),
+                    (Sound(expr), _) => {
+                        has_sound = true;
+                        vec.push(expr);
+                    }
+                    (other, range) => return Err(Error::TypeMismatchElement(range, other.ty())),
+                }
+            }
+            if has_sound {
+                SoundArrayExpression::Literal(vec).into()
+            } else {
+                ArrayExpression::Literal(
+                    vec.into_iter()
+                        .map(|expr| match expr {
+                            SoundExpression::Real(expr) => expr,
+                            _ => unreachable!(),
+                        })
+                        .collect(),
+                )
+                .into()
+            }
+        }
         Node::Score(_) => todo!(),
+        Node::Record(fields) => {
+            let mut vec: Vec<(std::string::String, SoundExpression)> = Vec::new();
+            let mut has_sound = false;
+            for (name, expr) in fields {
+                if vec.iter().any(|(field, _)| *field == name) {
+                    return Err(Error::DuplicateField(name, expr.range));
+                }
+                // Sound のフィールドがあれば Sound のレコードにする
+                match compile_expression(expr, variables, functions)? {
+                    (Real(expr), _) => vec.push((name, SoundExpression::Real(expr))),
+                    (Sound(expr), _) => {
+                        has_sound = true;
+                        vec.push((name, expr));
+                    }
+                    (other, range) => return Err(Error::TypeMismatchElement(range, other.ty())),
+                }
+            }
+            if has_sound {
+                SoundRecordExpression::Literal(vec).into()
+            } else {
+                RecordExpression::Literal(
+                    vec.into_iter()
+                        .map(|(name, expr)| match expr {
+                            SoundExpression::Real(expr) => (name, expr),
+                            _ => unreachable!(),
+                        })
+                        .collect(),
+                )
+                .into()
+            }
+        }
+        Node::Field(record, name) => match compile_expression(*record, variables, functions)? {
+            (Record(record), _) => {
+                RealExpression::Field(record.into(), name, expression.range.clone()).into()
+            }
+            (SoundRecord(record), _) => {
+                SoundExpression::Field(record.into(), name, expression.range.clone()).into()
+            }
+            (other, range) => return Err(Error::TypeMismatchUnary(range, other.ty())),
+        },
     };
     Ok((ret, expression.range))
 }
 
+/// 型 `ty` の変数を新しく作る
+fn new_variable(ty: &Type) -> value::Value {
+    use value::Value;
+    match ty {
+        Type::Real => Value::from(0.),
+        Type::Int => Value::from(0i64),
+        Type::Boolean => Value::from(false),
+        Type::Sound => Value::from(sound::Sound::Const(0.)),
+        Type::String => Value::from(String::new()),
+        Type::Array => Value::from(Vec::<f64>::new()),
+        Type::Record => Value::from(Vec::<(String, f64)>::new()),
+        Type::SoundRecord => Value::from(Vec::<(String, sound::Sound)>::new()),
+        Type::SoundArray => Value::from(Vec::<sound::Sound>::new()),
+        Type::Void | Type::Function(_) => unreachable!(),
+    }
+}
+
+/// 既定値 `expr` を変数 `value` に書き込む名前つき引数．型が合わなければ `Err` で式を返す
+fn default_argument(
+    value: &value::Value,
+    expr: program::Expression,
+) -> Result<program::Argument, program::Expression> {
+    use program::{Argument, Expression, SoundExpression};
+    use value::Value;
+    let expr = match value {
+        Value::Int(_) => expr,
+        _ => promote(expr),
+    };
+    Ok(match (value, expr) {
+        (Value::Real(rc), Expression::Real(expr)) => Argument::Real(rc.clone(), expr),
+        (Value::Int(rc), Expression::Int(expr)) => Argument::Int(rc.clone(), expr),
+        (Value::Boolean(rc), Expression::Boolean(expr)) => Argument::Boolean(rc.clone(), expr),
+        (Value::Sound(rc), Expression::Sound(expr)) => Argument::Sound(rc.clone(), expr),
+        (Value::Sound(rc), Expression::Real(expr)) => {
+            Argument::Sound(rc.clone(), SoundExpression::Real(expr))
+        }
+        (Value::String(rc), Expression::String(expr)) => Argument::String(rc.clone(), expr),
+        (Value::Array(rc), Expression::Array(expr)) => Argument::Array(rc.clone(), expr),
+        (Value::Record(rc), Expression::Record(expr)) => Argument::Record(rc.clone(), expr),
+        (Value::SoundRecord(rc), Expression::SoundRecord(expr)) => {
+            Argument::SoundRecord(rc.clone(), expr)
+        }
+        (Value::SoundArray(rc), Expression::SoundArray(expr)) => {
+            Argument::SoundArray(rc.clone(), expr)
+        }
+        (Value::Function(rc), Expression::Function(expr))
+            if rc.borrow().signature() == expr.function().signature() =>
+        {
+            Argument::Function(rc.clone(), expr)
+        }
+        (_, expr) => return Err(expr),
+    })
+}
+
+/// 関数の引数の変数（ `def` とラムダ式 ）
+struct Parameters {
+    /// 定義したスコープに，本体から見える引数を加えたもの
+    scope: HashMap<String, value::Value>,
+    /// `Function::arguments` と `Function::named_arguments`
+    arguments: Vec<value::Value>,
+    named_arguments: Vec<(String, program::Argument)>,
+    /// 呼び出し側が書き込む変数と本体から見える変数（位置引数，名前つき引数の順）
+    incoming: Vec<value::Value>,
+    visible: Vec<value::Value>,
+}
+
+/// 引数の変数を作る．既定値は定義したスコープでコンパイルする
+fn compile_parameters(
+    parameters: Vec<syntax::Parameter>,
+    variables: &HashMap<String, value::Value>,
+    functions: &HashMap<String, function::Function>,
+) -> Result<Parameters, error::Error> {
+    use error::Error;
+
+    for (i, parameter) in parameters.iter().enumerate() {
+        if parameters[..i]
+            .iter()
+            .any(|other| other.name == parameter.name)
+        {
+            return Err(Error::DuplicateParameter(
+                parameter.name.clone(),
+                parameter.range.clone(),
+            ));
+        }
+    }
+    let mut scope = variables.clone();
+    let (mut positional, mut named) = (Vec::new(), Vec::new());
+    let mut named_arguments = Vec::new();
+    for parameter in parameters {
+        // 型を書かなければ，整数の既定値でも実数の引数にする
+        let ty = &parameter.ty;
+        let default = parameter
+            .default
+            .map(|expr| match ty {
+                Some(Type::Int) => compile_exact(expr, variables, functions),
+                _ => compile_expression(expr, variables, functions),
+            })
+            .transpose()?;
+        let incoming = match (parameter.ty, &default) {
+            (Some(ty), _) => new_variable(&ty),
+            (None, Some((program::Expression::Void(_), range))) => {
+                return Err(Error::VoidRHS(range.clone()))
+            }
+            // 関数の値の引数は既定値の関数で型が決まる
+            (None, Some((program::Expression::Function(expr), _))) => {
+                value::Value::Function(Rc::new(RefCell::new(expr.function())))
+            }
+            (None, Some((expr, _))) => new_variable(&expr.ty()),
+            (None, None) => return Err(Error::UntypedParameter(parameter.name, parameter.range)),
+        };
+        let visible = incoming.deep_copy();
+        scope.insert(parameter.name.clone(), visible.clone());
+        match default {
+            Some((expr, range)) => match default_argument(&incoming, expr) {
+                Ok(argument) => {
+                    named_arguments.push((parameter.name, argument));
+                    named.push((incoming, visible));
+                }
+                Err(expr) => return Err(Error::TypeMismatchArgument(range, expr.ty())),
+            },
+            None => positional.push((incoming, visible)),
+        }
+    }
+    let arguments = positional
+        .iter()
+        .map(|(incoming, _)| incoming.clone())
+        .collect();
+    let (incoming, visible) = positional.into_iter().chain(named).unzip();
+    Ok(Parameters {
+        scope,
+        arguments,
+        named_arguments,
+        incoming,
+        visible,
+    })
+}
+
+/// 関数定義 `def` を `functions` に加える．
+/// 本体は定義したスコープに引数を加えたスコープでコンパイルする
+fn define(
+    range: pos::Range,
+    name: String,
+    parameters: Vec<syntax::Parameter>,
+    ty: Type,
+    body: syntax::Statement,
+    variables: &HashMap<String, value::Value>,
+    functions: &mut HashMap<String, function::Function>,
+) -> Result<(), error::Error> {
+    use error::Error;
+    use function::{Body, Function, UserDefined};
+
+    let Parameters {
+        mut scope,
+        arguments,
+        named_arguments,
+        incoming,
+        visible,
+    } = compile_parameters(parameters, variables, functions)?;
+
+    // 本体より先に登録して，本体から自分を呼べるようにする
+    let previous = functions.remove(&name);
+    macro_rules! define {
+        ($body:ident, $function:ident, $expr:ident) => {{
+            let function = Rc::new(UserDefined::new(name.clone(), range, incoming, visible));
+            functions.insert(
+                name.clone(),
+                Function {
+                    body: Body::$body(Rc::new(function::$function::UserDefined(function.clone()))),
+                    arguments,
+                    named_arguments,
+                },
+            );
+            compile_statement::<program::$expr>(body, &mut scope, functions)
+                .map(|body| function.set_body(body, variables))
+        }};
+    }
+    let result = match ty {
+        Type::Real => define!(Real, RealFunction, RealExpression),
+        Type::Boolean => define!(Boolean, BooleanFunction, BooleanExpression),
+        Type::Sound => define!(Sound, SoundFunction, SoundExpression),
+        Type::String => define!(String, StringFunction, StringExpression),
+        Type::Array => define!(Array, ArrayFunction, ArrayExpression),
+        Type::Record => define!(Record, RecordFunction, RecordExpression),
+        Type::SoundArray => define!(SoundArray, SoundArrayFunction, SoundArrayExpression),
+        Type::Int => define!(Int, IntFunction, IntExpression),
+        Type::Void => define!(Void, VoidFunction, VoidExpression),
+        Type::SoundRecord | Type::Function(_) => Err(Error::UnknownType(range)),
+    };
+    if let Err(err) = result {
+        functions.remove(&name);
+        functions.extend(previous.map(|previous| (name, previous)));
+        return Err(err);
+    }
+    Ok(())
+}
+
 pub fn compile_statement<Expr: program::Evaluatable>(
     statement: syntax::Statement,
     variables: &mut HashMap<String, value::Value>,
@@ -359,11 +971,18 @@ pub fn compile_statement<Expr: program::Evaluatable>(
                 Some(rc) => rc,
                 None => return Err(Error::UndefinedVariable(name, range)),
             };
-            let rhs = compile_expression(expr, variables, functions)?;
-            match (lhs, rhs.0) {
+            let rhs = compile_exact(expr, variables, functions)?;
+            let expr = match lhs {
+                value::Value::Int(_) => rhs.0,
+                _ => promote(rhs.0),
+            };
+            match (lhs, expr) {
                 (value::Value::Real(rc), program::Expression::Real(expr)) => {
                     program::Statement::RealSubstitution(rc.clone(), expr)
                 }
+                (value::Value::Int(rc), program::Expression::Int(expr)) => {
+                    program::Statement::IntSubstitution(rc.clone(), expr)
+                }
                 (value::Value::Boolean(rc), program::Expression::Boolean(expr)) => {
                     program::Statement::BooleanSubstitution(rc.clone(), expr)
                 }
@@ -373,17 +992,48 @@ pub fn compile_statement<Expr: program::Evaluatable>(
                 (value::Value::String(rc), program::Expression::String(expr)) => {
                     program::Statement::StringSubstitution(rc.clone(), expr)
                 }
+                (value::Value::Array(rc), program::Expression::Array(expr)) => {
+                    program::Statement::ArraySubstitution(rc.clone(), expr)
+                }
+                (value::Value::Record(rc), program::Expression::Record(expr)) => {
+                    program::Statement::RecordSubstitution(rc.clone(), expr)
+                }
+                (value::Value::SoundRecord(rc), program::Expression::SoundRecord(expr)) => {
+                    program::Statement::SoundRecordSubstitution(rc.clone(), expr)
+                }
+                (value::Value::SoundArray(rc), program::Expression::SoundArray(expr)) => {
+                    program::Statement::SoundArraySubstitution(rc.clone(), expr)
+                }
+                (value::Value::Function(rc), program::Expression::Function(expr))
+                    if rc.borrow().signature() == expr.function().signature() =>
+                {
+                    program::Statement::FunctionSubstitution(rc.clone(), expr)
+                }
                 (_, r) => return Err(Error::TypeMismatchBinary(range, lhs.ty(), rhs.1, r.ty())),
             }
         }
-        syntax::Statement::Declaration(range, name, expr) => {
-            let rhs = compile_expression(expr, variables, functions)?;
+        syntax::Statement::Declaration(range, name, ty, expr) => {
+            let rhs = match ty {
+                Some(Type::Int) => compile_exact(expr, variables, functions)?,
+                _ => compile_expression(expr, variables, functions)?,
+            };
+            match ty {
+                Some(ty) if ty != rhs.0.ty() => {
+                    return Err(Error::TypeMismatchBinary(range, ty, rhs.1, rhs.0.ty()))
+                }
+                _ => {}
+            }
             match rhs.0 {
                 program::Expression::Real(expr) => {
                     let rc = Rc::new(Cell::new(0.));
                     variables.insert(name, value::Value::Real(rc.clone()));
                     program::Statement::RealSubstitution(rc, expr)
                 }
+                program::Expression::Int(expr) => {
+                    let rc = Rc::new(Cell::new(0));
+                    variables.insert(name, value::Value::Int(rc.clone()));
+                    program::Statement::IntSubstitution(rc, expr)
+                }
                 program::Expression::Boolean(expr) => {
                     let rc = Rc::new(Cell::new(false));
                     variables.insert(name, value::Value::Boolean(rc.clone()));
@@ -399,11 +1049,140 @@ pub fn compile_statement<Expr: program::Evaluatable>(
                     variables.insert(name, value::Value::Sound(rc.clone()));
                     program::Statement::SoundSubstitution(rc, expr)
                 }
+                program::Expression::Array(expr) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    variables.insert(name, value::Value::Array(rc.clone()));
+                    program::Statement::ArraySubstitution(rc, expr)
+                }
+                program::Expression::Record(expr) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    variables.insert(name, value::Value::Record(rc.clone()));
+                    program::Statement::RecordSubstitution(rc, expr)
+                }
+                program::Expression::SoundRecord(expr) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    variables.insert(name, value::Value::SoundRecord(rc.clone()));
+                    program::Statement::SoundRecordSubstitution(rc, expr)
+                }
+                program::Expression::SoundArray(expr) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    variables.insert(name, value::Value::SoundArray(rc.clone()));
+                    program::Statement::SoundArraySubstitution(rc, expr)
+                }
+                program::Expression::Function(expr) => {
+                    let rc = Rc::new(RefCell::new(expr.function()));
+                    variables.insert(name, value::Value::Function(rc.clone()));
+                    program::Statement::FunctionSubstitution(rc, expr)
+                }
                 program::Expression::Void(_) => {
                     return Err(Error::VoidRHS(range));
                 }
             }
         }
+        syntax::Statement::Destructuring(range, pattern, expr) => {
+            use program::{
+                ArrayExpression, Expression, RealExpression, RecordExpression,
+                SoundArrayExpression, SoundExpression, SoundRecordExpression, Statement,
+            };
+            use syntax::Pattern;
+            let (rhs, rhs_range) = compile_expression(expr, variables, functions)?;
+            let names = match &pattern {
+                Pattern::Array(names) | Pattern::Record(names) => names,
+            };
+            for (i, (range, name)) in names.iter().enumerate() {
+                if names[..i].iter().any(|(_, other)| other == name) {
+                    return Err(Error::DuplicateField(name.clone(), range.clone()));
+                }
+            }
+            // 右辺は一度だけ評価して一時変数に入れ，そこから要素を取り出す
+            let mut statements = Vec::new();
+            let elements: Vec<Expression> = match (rhs, &pattern) {
+                (Expression::Array(expr), Pattern::Array(names)) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    statements.push(Statement::ArraySubstitution(rc.clone(), expr));
+                    names
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (range, _))| {
+                            RealExpression::Index(
+                                ArrayExpression::Reference(rc.clone()).into(),
+                                RealExpression::Const(i as f64).into(),
+                                range.clone(),
+                            )
+                            .into()
+                        })
+                        .collect()
+                }
+                (Expression::SoundArray(expr), Pattern::Array(names)) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    statements.push(Statement::SoundArraySubstitution(rc.clone(), expr));
+                    names
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (range, _))| {
+                            SoundExpression::Index(
+                                SoundArrayExpression::Reference(rc.clone()).into(),
+                                RealExpression::Const(i as f64).into(),
+                                range.clone(),
+                            )
+                            .into()
+                        })
+                        .collect()
+                }
+                (Expression::Record(expr), Pattern::Record(names)) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    statements.push(Statement::RecordSubstitution(rc.clone(), expr));
+                    names
+                        .iter()
+                        .map(|(range, name)| {
+                            RealExpression::Field(
+                                RecordExpression::Reference(rc.clone()).into(),
+                                name.clone(),
+                                range.clone(),
+                            )
+                            .into()
+                        })
+                        .collect()
+                }
+                (Expression::SoundRecord(expr), Pattern::Record(names)) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    statements.push(Statement::SoundRecordSubstitution(rc.clone(), expr));
+                    names
+                        .iter()
+                        .map(|(range, name)| {
+                            SoundExpression::Field(
+                                SoundRecordExpression::Reference(rc.clone()).into(),
+                                name.clone(),
+                                range.clone(),
+                            )
+                            .into()
+                        })
+                        .collect()
+                }
+                (rhs, _) => {
+                    return Err(Error::TypeMismatchDestructuring(
+                        range + rhs_range,
+                        rhs.ty(),
+                    ))
+                }
+            };
+            for ((_, name), element) in names.iter().zip(elements) {
+                statements.push(match element {
+                    Expression::Real(expr) => {
+                        let rc = Rc::new(Cell::new(0.));
+                        variables.insert(name.clone(), value::Value::Real(rc.clone()));
+                        Statement::RealSubstitution(rc, expr)
+                    }
+                    Expression::Sound(expr) => {
+                        let rc = Rc::new(RefCell::new(sound::Sound::Const(0.)));
+                        variables.insert(name.clone(), value::Value::Sound(rc.clone()));
+                        Statement::SoundSubstitution(rc, expr)
+                    }
+                    _ => unreachable!(),
+                });
+            }
+            Statement::Block(statements)
+        }
         syntax::Statement::Block(vec) => {
             let copied = &mut variables.clone();
             program::Statement::Block(
@@ -422,7 +1201,92 @@ pub fn compile_statement<Expr: program::Evaluatable>(
             let stmt = compile_statement(*stmt, copied, functions)?;
             program::Statement::While(cond, stmt.into())
         }
-        syntax::Statement::If(expr, stmt1, stmt2) => {
+        syntax::Statement::For(range, name, expr, stmt) => {
+            use function::RealFunction;
+            use program::{
+                ArrayExpression, BooleanExpression, Expression, RealExpression,
+                SoundArrayExpression, SoundExpression, Statement,
+            };
+            // 配列を一度だけ評価して一時変数に入れ，添字を数えながら while 文で回す
+            let (array, array_range) = compile_expression(expr, variables, functions)?;
+            let index = Rc::new(Cell::new(0.));
+            let copied = &mut variables.clone();
+            let (mut statements, len, element) = match array {
+                Expression::Array(expr) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    let element = Rc::new(Cell::new(0.));
+                    copied.insert(name, value::Value::Real(element.clone()));
+                    (
+                        vec![Statement::ArraySubstitution(rc.clone(), expr)],
+                        RealExpression::Invocation(
+                            Rc::new(RealFunction::Len(rc.clone())),
+                            Vec::new(),
+                            array_range.clone(),
+                        ),
+                        Statement::RealSubstitution(
+                            element,
+                            RealExpression::Index(
+                                ArrayExpression::Reference(rc).into(),
+                                RealExpression::Reference(index.clone()).into(),
+                                array_range.clone(),
+                            ),
+                        ),
+                    )
+                }
+                Expression::SoundArray(expr) => {
+                    let rc = Rc::new(RefCell::new(Vec::new()));
+                    let element = Rc::new(RefCell::new(sound::Sound::Const(0.)));
+                    copied.insert(name, value::Value::Sound(element.clone()));
+                    (
+                        vec![Statement::SoundArraySubstitution(rc.clone(), expr)],
+                        RealExpression::Invocation(
+                            Rc::new(RealFunction::SoundLen(rc.clone())),
+                            Vec::new(),
+                            array_range.clone(),
+                        ),
+                        Statement::SoundSubstitution(
+                            element,
+                            SoundExpression::Index(
+                                SoundArrayExpression::Reference(rc).into(),
+                                RealExpression::Reference(index.clone()).into(),
+                                array_range.clone(),
+                            ),
+                        ),
+                    )
+                }
+                other => {
+                    return Err(Error::TypeMismatchIteration(
+                        range + array_range,
+                        other.ty(),
+                    ))
+                }
+            };
+            let stmt = compile_statement(*stmt, copied, functions)?;
+            statements.push(Statement::RealSubstitution(
+                index.clone(),
+                RealExpression::Const(0.),
+            ));
+            statements.push(Statement::While(
+                BooleanExpression::RealLess(
+                    RealExpression::Reference(index.clone()).into(),
+                    len.into(),
+                ),
+                Statement::Block(vec![
+                    element,
+                    Statement::RealSubstitution(
+                        index.clone(),
+                        RealExpression::Add(
+                            RealExpression::Reference(index).into(),
+                            RealExpression::Const(1.).into(),
+                        ),
+                    ),
+                    stmt,
+                ])
+                .into(),
+            ));
+            Statement::Block(statements)
+        }
+        syntax::Statement::If(_, expr, stmt1, stmt2) => {
             let cond = compile_expression(expr, variables, functions)?;
             let cond = match cond.0 {
                 program::Expression::Boolean(expr) => expr,
@@ -440,9 +1304,16 @@ pub fn compile_statement<Expr: program::Evaluatable>(
         syntax::Statement::Continue(_) => todo!(),
         syntax::Statement::Return(r#return, expr) => {
             let expr = expr
-                .map(|expr| compile_expression(expr, variables, functions))
+                .map(|expr| compile_exact(expr, variables, functions))
                 .transpose()?;
-            match Expr::from(expr) {
+            // `-> int` の関数でなければ整数は実数に直して返す
+            let expr = match Expr::from(expr) {
+                Err(Some((expr @ program::Expression::Int(_), range))) => {
+                    Expr::from(Some((promote(expr), range)))
+                }
+                other => other,
+            };
+            match expr {
                 Ok(expr) => program::Statement::Return(expr),
                 Err(Some((expr, range))) => {
                     return Err(Error::TypeMismatchReturn(range, expr.ty()))
@@ -450,8 +1321,12 @@ pub fn compile_statement<Expr: program::Evaluatable>(
                 Err(None) => return Err(Error::EmptyExpressionReturn(r#return)),
             }
         }
-        syntax::Statement::Definition(_, _, _) => {
-            todo!();
+        syntax::Statement::Definition(range, name, parameters, ty, body) => {
+            define(range, name, parameters, ty, *body, variables, functions)?;
+            program::Statement::Expression(None)
         }
+        // 台本の最初に読み込むものなので，ブロックの中には書けない
+        syntax::Statement::Samples(range, _) => return Err(Error::NestedSamples(range)),
+        syntax::Statement::Command(range, _) => return Err(Error::UnknownCommand(range)),
     })
 }
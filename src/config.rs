@@ -0,0 +1,269 @@
+//! 設定ファイル（ `~/.config/cryss/config.toml` と台本の隣の `cryss.toml` ）
+//!
+//! `[render]` が既定の設定で， `[profile.<名前>]` が `--profile <名前>` で選ぶ設定．
+//! `[cache]` は音声ファイルのキャッシュの設定．
+//! 後に重ねたものほど強く，コマンドライン引数がいちばん強い．
+//! 読めるのは TOML のうち `キー = 値` （文字列と数）と表の見出しだけ
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// 台本の隣に置く設定ファイルの名前
+pub const SCRIPT_CONFIG: &str = "cryss.toml";
+
+/// 書き出しの設定．`None` の項目は下の設定（なければ既定値）のまま
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Render {
+    /// 書き出す範囲（マーカー名・区間名）
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// `write` などで `samplerate` を省略したときの標本化周波数
+    pub samplerate: Option<f64>,
+    /// WAV の量子化ビット数（ 16 ， 24 ， 32 ）
+    pub bits: Option<u16>,
+    /// `dither` を省略したときのディザーの種類（ `none` ， `tpdf` ， `shaped` ）
+    pub dither: Option<String>,
+    /// 音声ファイルの読み込みに使うスレッド数
+    pub threads: Option<usize>,
+    /// 相対パスで書き出すファイルの置き場所
+    pub output_dir: Option<String>,
+    /// `play` で標準入力から WAV を読んで鳴らすコマンド
+    pub player: Option<String>,
+    /// `record` で標準出力に WAV を書くコマンド
+    pub recorder: Option<String>,
+}
+
+impl Render {
+    /// `other` で指定のある項目を上書きする
+    pub fn merge(&mut self, other: &Render) {
+        fn set<T: Clone>(x: &mut Option<T>, y: &Option<T>) {
+            if y.is_some() {
+                *x = y.clone();
+            }
+        }
+        set(&mut self.from, &other.from);
+        set(&mut self.to, &other.to);
+        set(&mut self.samplerate, &other.samplerate);
+        set(&mut self.bits, &other.bits);
+        set(&mut self.dither, &other.dither);
+        set(&mut self.threads, &other.threads);
+        set(&mut self.output_dir, &other.output_dir);
+        set(&mut self.player, &other.player);
+        set(&mut self.recorder, &other.recorder);
+    }
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match (key, value) {
+            ("from", Value::String(s)) => self.from = Some(s),
+            ("to", Value::String(s)) => self.to = Some(s),
+            ("samplerate", Value::Number(x)) if x > 0. => self.samplerate = Some(x),
+            ("bits", Value::Number(x)) if x == 16. || x == 24. || x == 32. => {
+                self.bits = Some(x as u16)
+            }
+            ("dither", Value::String(s)) => self.dither = Some(s),
+            ("threads", Value::Number(x)) if x >= 1. && x.fract() == 0. => {
+                self.threads = Some(x as usize)
+            }
+            ("output_dir", Value::String(s)) => self.output_dir = Some(s),
+            ("player", Value::String(s)) => self.player = Some(s),
+            ("recorder", Value::String(s)) => self.recorder = Some(s),
+            (
+                "from" | "to" | "samplerate" | "bits" | "dither" | "threads" | "output_dir"
+                | "player" | "recorder",
+                _,
+            ) => return Err(format!("invalid value for `{}`", key)),
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// キャッシュの設定
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cache {
+    /// キャッシュを置くディレクトリ
+    pub directory: Option<String>,
+    /// 合計の大きさの上限（メガバイト）
+    pub max_size: Option<f64>,
+}
+
+impl Cache {
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match (key, value) {
+            ("directory", Value::String(s)) => self.directory = Some(s),
+            ("max_size", Value::Number(x)) if x >= 0. => self.max_size = Some(x),
+            ("directory" | "max_size", _) => return Err(format!("invalid value for `{}`", key)),
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// 1 つの設定ファイルの中身
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    render: Render,
+    profiles: HashMap<String, Render>,
+    cache: Cache,
+}
+
+/// 設定ファイルの表
+enum Table {
+    Render,
+    Profile(String),
+    Cache,
+}
+
+enum Value {
+    String(String),
+    Number(f64),
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut table = None;
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .split('#')
+                    .next()
+                    .unwrap()
+                    .trim_end()
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("unclosed table header".to_string()))?
+                    .trim();
+                table = Some(match header {
+                    "render" => Table::Render,
+                    "cache" => Table::Cache,
+                    _ => match header.strip_prefix("profile.") {
+                        Some(name) if !name.is_empty() => Table::Profile(name.to_string()),
+                        _ => return Err(error(format!("unknown table `[{}]`", header))),
+                    },
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `key = value`".to_string()))?;
+            let value = parse_value(value.trim()).map_err(error)?;
+            match &table {
+                Some(Table::Render) => config.render.set(key.trim(), value),
+                Some(Table::Profile(name)) => config
+                    .profiles
+                    .entry(name.clone())
+                    .or_default()
+                    .set(key.trim(), value),
+                Some(Table::Cache) => config.cache.set(key.trim(), value),
+                None => return Err(error("key outside of a table".to_string())),
+            }
+            .map_err(error)?;
+        }
+        Ok(config)
+    }
+    /// `path` を読む．なければ `None`
+    pub fn load(path: &Path) -> Result<Option<Config>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text)
+                .map(Some)
+                .map_err(|message| format!("{}: {}", path.display(), message)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("cannot read {}: {}", path.display(), err)),
+        }
+    }
+}
+
+/// 値（行末のコメントは除く）
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(rest) = text.strip_prefix('"') {
+        let end = rest
+            .find('"')
+            .ok_or_else(|| "unterminated string".to_string())?;
+        let trailing = rest[end + 1..].trim();
+        if !(trailing.is_empty() || trailing.starts_with('#')) {
+            return Err(format!("unexpected `{}` after string", trailing));
+        }
+        return Ok(Value::String(rest[..end].to_string()));
+    }
+    let text = text.split('#').next().unwrap().trim();
+    text.replace('_', "")
+        .parse()
+        .map(Value::Number)
+        .map_err(|_| format!("invalid value `{}`", text))
+}
+
+/// 利用者ごとの設定ファイル（ `$XDG_CONFIG_HOME/cryss/config.toml` か `~/.config/cryss/config.toml` ）
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(base) if !base.is_empty() => PathBuf::from(base),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("cryss").join("config.toml"))
+}
+
+/// 設定ファイルを順に重ねたキャッシュの設定
+pub fn resolve_cache(configs: &[Config]) -> Cache {
+    let mut cache = Cache::default();
+    for config in configs {
+        if config.cache.directory.is_some() {
+            cache.directory = config.cache.directory.clone();
+        }
+        if config.cache.max_size.is_some() {
+            cache.max_size = config.cache.max_size;
+        }
+    }
+    cache
+}
+
+/// 設定ファイルを順に重ね，プロファイル `profile` を選んだ設定．
+/// 各ファイルの中ではプロファイルが `[render]` より強い
+pub fn resolve(configs: &[Config], profile: Option<&str>) -> Result<Render, String> {
+    if let Some(profile) = profile {
+        if configs
+            .iter()
+            .all(|config| !config.profiles.contains_key(profile))
+        {
+            return Err(format!("undefined profile `{}`", profile));
+        }
+    }
+    let mut render = Render::default();
+    for config in configs {
+        render.merge(&config.render);
+        if let Some(profile) = profile.and_then(|profile| config.profiles.get(profile)) {
+            render.merge(profile);
+        }
+    }
+    Ok(render)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        let user = Config::parse(
+            "# 利用者の設定\n[render]\nsamplerate = 48000\n\n[profile.draft]\nsamplerate = 22050 # 速く\nbits = 16\n[profile.hq]\nsamplerate = 96_000\nbits = 24\n",
+        )
+        .unwrap();
+        let script =
+            Config::parse("[render]\nfrom = \"intro\"\n[profile.draft]\noutput_dir = \"draft\"\n")
+                .unwrap();
+        let configs = [user, script];
+        let draft = resolve(&configs, Some("draft")).unwrap();
+        assert_eq!(draft.samplerate, Some(22050.));
+        assert_eq!(draft.bits, Some(16));
+        assert_eq!(draft.from.as_deref(), Some("intro"));
+        assert_eq!(draft.output_dir.as_deref(), Some("draft"));
+        assert_eq!(resolve(&configs, None).unwrap().samplerate, Some(48000.));
+        assert!(resolve(&configs, Some("lofi")).is_err());
+        assert!(Config::parse("[render]\nbits = 12\n").is_err());
+        assert!(Config::parse("[render]\ncolor = \"red\"\n").is_err());
+        let cache = Config::parse("[cache]\nmax_size = 256\n").unwrap();
+        assert_eq!(resolve_cache(&[cache]).max_size, Some(256.));
+    }
+}
@@ -0,0 +1,125 @@
+//! 整数に量子化するときのディザー（ `dither = "none" | "tpdf" | "shaped"` ）
+//!
+//! `tpdf` は 2 つの一様乱数の和（三角分布，幅 ±1 LSB ）を加えてから丸める．
+//! `shaped` はさらに量子化誤差をフィードバックして，雑音を耳につきにくい高域へ寄せる．
+//! 誤差のフィルタは Lipshitz ， Vanderkooy ， Wannamaker (1991) の 5 次の E 特性で，
+//! 雑音の伝達関数は `1 - (2.033 z⁻¹ - 2.165 z⁻² + 1.959 z⁻³ - 1.590 z⁻⁴ + 0.6149 z⁻⁵)` ．
+//! 44.1 kHz で 4 kHz 付近を最も低くし，その分ナイキスト周波数付近を持ち上げる
+
+use rand::prelude::*;
+
+/// 雑音整形の誤差フィルタの係数
+const SHAPING: [f64; 5] = [2.033, -2.165, 1.959, -1.590, 0.6149];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    /// 切り捨てるだけ
+    None,
+    Tpdf,
+    Shaped,
+}
+
+impl Dither {
+    pub fn from_name(name: &str) -> Result<Dither, String> {
+        match name {
+            "none" => Ok(Dither::None),
+            "tpdf" => Ok(Dither::Tpdf),
+            "shaped" => Ok(Dither::Shaped),
+            _ => Err(format!(
+                "unknown dither `{}` (expected `none`, `tpdf` or `shaped`)",
+                name
+            )),
+        }
+    }
+}
+
+/// チャンネルごとに誤差を覚えて量子化する
+pub struct Quantizer {
+    dither: Dither,
+    /// 1 に当たる整数
+    amplitude: f64,
+    rng: StdRng,
+    /// チャンネルごとの直近の量子化誤差（新しい順）
+    errors: Vec<[f64; 5]>,
+}
+
+impl Quantizer {
+    pub fn new(dither: Dither, amplitude: f64, channels: usize) -> Quantizer {
+        Quantizer {
+            dither,
+            amplitude,
+            rng: StdRng::seed_from_u64(0),
+            errors: vec![[0.; 5]; channels],
+        }
+    }
+    /// `channel` 番目のチャンネルの標本 `x` を整数にする
+    pub fn quantize(&mut self, channel: usize, x: f64) -> i32 {
+        let x = self.amplitude * x.clamp(-1., 1.);
+        let (min, max) = (-self.amplitude - 1., self.amplitude);
+        match self.dither {
+            Dither::None => x as i32,
+            Dither::Tpdf => {
+                let noise = self.rng.gen::<f64>() - self.rng.gen::<f64>();
+                (x + noise).round().clamp(min, max) as i32
+            }
+            Dither::Shaped => {
+                let errors = &mut self.errors[channel];
+                let target = x - SHAPING
+                    .iter()
+                    .zip(errors.iter())
+                    .map(|(h, e)| h * e)
+                    .sum::<f64>();
+                let noise = self.rng.gen::<f64>() - self.rng.gen::<f64>();
+                let y = (target + noise).round().clamp(min, max);
+                errors.rotate_right(1);
+                // 大きな音で上限に張りついたときに誤差がたまり続けないように
+                errors[0] = (y - target).clamp(-1., 1.);
+                y as i32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis;
+
+    /// 小さな正弦波を 16 ビットに量子化したときの誤差の，低域（ 0〜4 kHz ）と高域（ 16 kHz〜）の電力
+    fn noise_bands(dither: Dither) -> (f64, f64) {
+        const N: usize = 1 << 16;
+        const SAMPLERATE: f64 = 44100.;
+        let amplitude = 32767.;
+        let mut quantizer = Quantizer::new(dither, amplitude, 1);
+        let error: Vec<f64> = (0..N)
+            .map(|i| {
+                let x = 0.01 * (std::f64::consts::TAU * 441. * i as f64 / SAMPLERATE).sin();
+                quantizer.quantize(0, x) as f64 - x * amplitude
+            })
+            .collect();
+        let spectrum = analysis::spectrum(&error);
+        let bin = |frequency: f64| (frequency / SAMPLERATE * N as f64) as usize;
+        let power = |bins: &[f64]| bins.iter().map(|x| x * x).sum::<f64>() / bins.len() as f64;
+        (
+            power(&spectrum[1..bin(4000.)]),
+            power(&spectrum[bin(16000.)..]),
+        )
+    }
+
+    #[test]
+    fn spectrum() {
+        // 三角分布のディザーは白色雑音
+        let (low, high) = noise_bands(Dither::Tpdf);
+        assert!((low / high).log10().abs() < 0.1, "{} {}", low, high);
+        // 雑音整形は低域を下げて高域を上げる
+        let (shaped_low, shaped_high) = noise_bands(Dither::Shaped);
+        assert!(shaped_low < low / 10., "{} {}", shaped_low, low);
+        assert!(shaped_high > high * 10., "{} {}", shaped_high, high);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Dither::from_name("shaped"), Ok(Dither::Shaped));
+        assert!(Dither::from_name("triangular").is_err());
+    }
+}
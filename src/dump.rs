@@ -0,0 +1,69 @@
+//! パッチのデバッグ用に標本列とスペクトルを CSV に書き出す
+
+use crate::analysis;
+
+/// 1 行に 1 標本（ステレオなら 1 フレーム）．1 列目は時刻（秒）
+pub fn samples_csv(channels: &[Vec<f64>], samplerate: f64) -> String {
+    let mut csv = String::from("time");
+    if channels.len() == 1 {
+        csv.push_str(",sample\n");
+    } else {
+        for i in 0..channels.len() {
+            csv.push_str(&format!(",channel{}", i));
+        }
+        csv.push('\n');
+    }
+    let length = channels.iter().map(Vec::len).min().unwrap_or(0);
+    for i in 0..length {
+        csv.push_str(&(i as f64 / samplerate).to_string());
+        for channel in channels {
+            csv.push_str(&format!(",{}", channel[i]));
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// `block` 標本ごとの振幅スペクトル（ハン窓）．
+/// 1 行目は各列の周波数（ Hz ），以降の行は先頭がブロックの開始時刻（秒）
+pub fn spectra_csv(samples: &[f64], samplerate: f64, block: usize) -> Result<String, String> {
+    if !block.is_power_of_two() || block < 2 {
+        return Err(format!("block size must be a power of two (got {})", block));
+    }
+    let mut csv = String::from("time");
+    for bin in 0..block / 2 {
+        csv.push_str(&format!(",{}", bin as f64 * samplerate / block as f64));
+    }
+    csv.push('\n');
+    for (i, frame) in samples.chunks_exact(block).enumerate() {
+        csv.push_str(&((i * block) as f64 / samplerate).to_string());
+        for magnitude in analysis::spectrum(frame) {
+            csv.push_str(&format!(",{}", magnitude));
+        }
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            samples_csv(&[vec![0.5, -1.]], 2.),
+            "time,sample\n0,0.5\n0.5,-1\n"
+        );
+        assert_eq!(
+            samples_csv(&[vec![1.], vec![2.]], 1.),
+            "time,channel0,channel1\n0,1,2\n"
+        );
+        let spectra = spectra_csv(&[1.; 10], 8., 4).unwrap();
+        let lines: Vec<_> = spectra.lines().collect();
+        assert_eq!(lines[0], "time,0,2");
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("0.5,"));
+        assert!(spectra_csv(&[], 8., 6).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! ダイナミクス系の処理（エンベロープの検出，コンプレッサー，ゲート）
+
+/// 時定数 `time` （秒）の 1 次の平滑化の係数．0 秒なら即座に追従する
+fn coefficient(time: f64, samplerate: f64) -> f64 {
+    if time > 0. {
+        (-1. / (time * samplerate)).exp()
+    } else {
+        0.
+    }
+}
+
+/// 振幅のエンベロープ（ピーク検出）．上がるときは `attack` ，下がるときは `release` で追従する
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    attack: f64,
+    release: f64,
+    level: f64,
+}
+
+impl Envelope {
+    pub fn new(attack: f64, release: f64, samplerate: f64) -> Envelope {
+        Envelope {
+            attack: coefficient(attack, samplerate),
+            release: coefficient(release, samplerate),
+            level: 0.,
+        }
+    }
+    pub fn next(&mut self, x: f64) -> f64 {
+        let x = x.abs();
+        let coefficient = if x > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.level = x + (self.level - x) * coefficient;
+        self.level
+    }
+}
+
+/// コンプレッサーの設定．`threshold` と `makeup` は dB ，`attack` と `release` は秒
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compressor {
+    pub threshold: f64,
+    pub ratio: f64,
+    pub attack: f64,
+    pub release: f64,
+    pub makeup: f64,
+}
+
+impl Compressor {
+    /// 検出した振幅 `level` に対する利得（倍率）
+    pub fn gain(&self, level: f64) -> f64 {
+        let over = 20. * level.log10() - self.threshold;
+        let reduction = if over > 0. {
+            over * (1. - self.ratio.recip())
+        } else {
+            0.
+        };
+        10f64.powf((self.makeup - reduction) / 20.)
+    }
+}
+
+/// ゲートの設定．`threshold` は dB ，ほかは秒
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gate {
+    pub threshold: f64,
+    pub attack: f64,
+    pub hold: f64,
+    pub release: f64,
+}
+
+/// ゲートの検出器の戻りの時間
+const GATE_DETECTOR_RELEASE: f64 = 0.005;
+
+/// 検出用の信号で `sound` の音量を変える処理
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dynamics {
+    /// 検出用の信号のエンベロープそのものを出す（ `attack` ， `release` は秒）
+    Follow {
+        attack: f64,
+        release: f64,
+    },
+    Compress(Compressor),
+    Gate(Gate),
+}
+
+impl Dynamics {
+    pub fn state(&self, samplerate: f64) -> State {
+        match *self {
+            Dynamics::Follow { attack, release } => {
+                State::Follow(Envelope::new(attack, release, samplerate))
+            }
+            Dynamics::Compress(compressor) => State::Compress(
+                compressor,
+                Envelope::new(compressor.attack, compressor.release, samplerate),
+            ),
+            Dynamics::Gate(gate) => State::Gate {
+                threshold: gate.threshold,
+                detector: Envelope::new(0., GATE_DETECTOR_RELEASE, samplerate),
+                hold: (gate.hold * samplerate).round() as i64,
+                held: 0,
+                attack: coefficient(gate.attack, samplerate),
+                release: coefficient(gate.release, samplerate),
+                gain: 0.,
+            },
+        }
+    }
+}
+
+/// `Dynamics` を標本ごとに処理する状態
+#[derive(Clone, Debug)]
+pub enum State {
+    Follow(Envelope),
+    Compress(Compressor, Envelope),
+    Gate {
+        threshold: f64,
+        detector: Envelope,
+        /// 閾値を下回ってから閉じ始めるまでの標本数
+        hold: i64,
+        /// 閉じ始めるまでの残りの標本数
+        held: i64,
+        attack: f64,
+        release: f64,
+        gain: f64,
+    },
+}
+
+impl State {
+    /// 音 `x` を検出用の信号 `sidechain` に従って処理する
+    pub fn process(&mut self, x: f64, sidechain: f64) -> f64 {
+        match self {
+            State::Follow(envelope) => envelope.next(sidechain),
+            State::Compress(compressor, envelope) => x * compressor.gain(envelope.next(sidechain)),
+            State::Gate {
+                threshold,
+                detector,
+                hold,
+                held,
+                attack,
+                release,
+                gain,
+            } => {
+                let level = 20. * detector.next(sidechain).log10();
+                if level >= *threshold {
+                    *held = *hold;
+                } else if *held > 0 {
+                    *held -= 1;
+                }
+                let open = level >= *threshold || *held > 0;
+                let (target, coefficient) = if open { (1., *attack) } else { (0., *release) };
+                *gain = target + (*gain - target) * coefficient;
+                x * *gain
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope() {
+        let mut envelope = Envelope::new(0., 0.01, 1000.);
+        assert_eq!(envelope.next(-0.5), 0.5);
+        // 10 ms （ 10 標本）で 1 / e まで下がる
+        let level = (0..10).map(|_| envelope.next(0.)).last().unwrap();
+        assert!((level - 0.5 / std::f64::consts::E).abs() < 1e-12);
+    }
+
+    #[test]
+    fn compressor_gain() {
+        let compressor = Compressor {
+            threshold: -20.,
+            ratio: 4.,
+            attack: 0.,
+            release: 0.,
+            makeup: 0.,
+        };
+        assert_eq!(compressor.gain(0.01), 1.);
+        // 閾値を 20 dB 超えたら 15 dB 下げる
+        assert!((20. * compressor.gain(1.).log10() + 15.).abs() < 1e-9);
+        assert_eq!(compressor.gain(0.), 1.);
+    }
+
+    #[test]
+    fn gate_hold_and_release() {
+        let gate = Gate {
+            threshold: -20.,
+            attack: 0.,
+            hold: 0.01,
+            release: 0.,
+        };
+        let mut state = Dynamics::Gate(gate).state(1000.);
+        assert_eq!(state.process(0.5, 0.001), 0.);
+        assert_eq!(state.process(0.5, 1.), 0.5);
+        // 検出器が下がりきっても， 10 標本の間は開いたまま
+        let outputs: Vec<_> = (0..40).map(|_| state.process(0.5, 0.)).collect();
+        assert!(outputs[..10].iter().all(|&x| x == 0.5));
+        assert_eq!(*outputs.last().unwrap(), 0.);
+    }
+}
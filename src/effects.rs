@@ -0,0 +1,280 @@
+//! 時間領域の効果（ `delay` ， `reverb` ）と制御信号の平滑化（ `smooth` ， `slew` ， `seq` の `glide` ）
+//!
+//! 遅延と残響は過去の標本をリングバッファに持つ．残響は Freeverb （ Schroeder 型）と同じく，
+//! 減衰させた 8 本の櫛形フィルタを並べ，4 段の全域通過フィルタに通す
+
+/// 音にかける効果．時間は秒，ほかは 0 から 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// `time` 秒遅らせた音を `feedback` 倍して戻す．`mix` は遅らせた音の割合
+    Delay { time: f64, feedback: f64, mix: f64 },
+    /// `size` が大きいほど長く， `damp` が大きいほど高音が早く消える
+    Reverb { size: f64, damp: f64, mix: f64 },
+    /// 1 次の平滑化で，周波数や音量などの制御信号の段差をなめらかにつなぐ．
+    /// 時定数は値が上がるときに `rise` 秒，下がるときに `fall` 秒
+    Lag { rise: f64, fall: f64 },
+}
+
+/// Freeverb の櫛形フィルタと全域通過フィルタの長さ（ 44100 Hz での標本数）
+const COMBS: [f64; 8] = [1116., 1188., 1277., 1356., 1422., 1491., 1557., 1617.];
+const ALLPASSES: [f64; 4] = [556., 441., 341., 225.];
+const FREEVERB_SAMPLERATE: f64 = 44100.;
+/// 櫛形フィルタに入れる前の利得（ 8 本を足すので小さくする）
+const REVERB_INPUT: f64 = 0.015;
+/// `mix` が 1 のときの残響の利得（ Freeverb の `scalewet` ）
+const REVERB_WET: f64 = 3.;
+
+impl Effect {
+    pub fn name(&self) -> String {
+        match self {
+            Effect::Delay {
+                time,
+                feedback,
+                mix,
+            } => format!("delay {} s feedback {} mix {}", time, feedback, mix),
+            Effect::Reverb { size, damp, mix } => {
+                format!("reverb size {} damp {} mix {}", size, damp, mix)
+            }
+            Effect::Lag { rise, fall } if rise == fall => format!("smooth {} s", rise),
+            Effect::Lag { rise, fall } => format!("slew rise {} s fall {} s", rise, fall),
+        }
+    }
+    /// 櫛形フィルタの帰還の係数
+    fn reverb_feedback(size: f64) -> f64 {
+        0.7 + 0.28 * size
+    }
+    /// 入力が止まってから -60 dB まで減衰する時間（秒）のおおよその値
+    pub fn ring_time(&self) -> f64 {
+        let decay = |period: f64, gain: f64| {
+            if gain == 0. {
+                period
+            } else {
+                period * (1. + 1000f64.ln() / -gain.abs().ln())
+            }
+        };
+        match *self {
+            Effect::Delay { time, feedback, .. } => decay(time, feedback),
+            Effect::Reverb { size, .. } => decay(
+                COMBS[COMBS.len() - 1] / FREEVERB_SAMPLERATE,
+                Effect::reverb_feedback(size),
+            ),
+            // 制御信号をならすだけなので，入力が止まったら止める
+            Effect::Lag { .. } => 0.,
+        }
+    }
+    pub fn state(&self, samplerate: f64) -> State {
+        let buffer = |length: f64| vec![0.; (length.round() as usize).max(1)];
+        match *self {
+            Effect::Delay {
+                time,
+                feedback,
+                mix,
+            } => State::Delay {
+                line: Line::new(buffer(time * samplerate)),
+                feedback,
+                mix,
+            },
+            Effect::Reverb { size, damp, mix } => {
+                let scale = samplerate / FREEVERB_SAMPLERATE;
+                State::Reverb {
+                    combs: COMBS
+                        .iter()
+                        .map(|length| (Line::new(buffer(length * scale)), 0.))
+                        .collect(),
+                    allpasses: ALLPASSES
+                        .iter()
+                        .map(|length| Line::new(buffer(length * scale)))
+                        .collect(),
+                    feedback: Effect::reverb_feedback(size),
+                    damp: 0.4 * damp,
+                    mix,
+                }
+            }
+            Effect::Lag { rise, fall } => {
+                let coefficient = |time: f64| (-(time * samplerate).recip()).exp();
+                State::Lag {
+                    rise: coefficient(rise),
+                    fall: coefficient(fall),
+                    value: None,
+                }
+            }
+        }
+    }
+}
+
+/// リングバッファによる遅延線
+#[derive(Clone, Debug)]
+pub struct Line {
+    buffer: Vec<f64>,
+    index: usize,
+}
+
+impl Line {
+    fn new(buffer: Vec<f64>) -> Line {
+        Line { buffer, index: 0 }
+    }
+    /// バッファの長さだけ前に書いた値
+    fn read(&self) -> f64 {
+        self.buffer[self.index]
+    }
+    fn write(&mut self, x: f64) {
+        self.buffer[self.index] = x;
+        self.index = (self.index + 1) % self.buffer.len();
+    }
+}
+
+/// `Effect` を標本ごとに処理する状態
+#[derive(Clone, Debug)]
+pub enum State {
+    Delay {
+        line: Line,
+        feedback: f64,
+        mix: f64,
+    },
+    Reverb {
+        /// 櫛形フィルタと，その帰還路の低域通過フィルタの値
+        combs: Vec<(Line, f64)>,
+        allpasses: Vec<Line>,
+        feedback: f64,
+        damp: f64,
+        mix: f64,
+    },
+    /// 最初の標本ではまだ値がない（そこから滑らせない）
+    Lag {
+        rise: f64,
+        fall: f64,
+        value: Option<f64>,
+    },
+}
+
+impl State {
+    pub fn process(&mut self, x: f64) -> f64 {
+        match self {
+            State::Delay {
+                line,
+                feedback,
+                mix,
+            } => {
+                let delayed = line.read();
+                line.write(x + *feedback * delayed);
+                (1. - *mix) * x + *mix * delayed
+            }
+            State::Reverb {
+                combs,
+                allpasses,
+                feedback,
+                damp,
+                mix,
+            } => {
+                let input = x * REVERB_INPUT;
+                let mut y: f64 = combs
+                    .iter_mut()
+                    .map(|(line, filtered)| {
+                        let output = line.read();
+                        *filtered = output * (1. - *damp) + *filtered * *damp;
+                        line.write(input + *filtered * *feedback);
+                        output
+                    })
+                    .sum();
+                for line in allpasses {
+                    let delayed = line.read();
+                    line.write(y + delayed * 0.5);
+                    y = delayed - y;
+                }
+                (1. - *mix) * x + *mix * REVERB_WET * y
+            }
+            State::Lag { rise, fall, value } => {
+                let y = value.map_or(x, |y| {
+                    let coefficient = if x > y { *rise } else { *fall };
+                    x + (y - x) * coefficient
+                });
+                *value = Some(y);
+                y
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::Sound;
+
+    #[test]
+    fn echoes() {
+        let delay = Sound::Effect {
+            sound: Sound::Begin(0.).into(),
+            effect: Effect::Delay {
+                time: 0.5,
+                feedback: 0.5,
+                mix: 0.5,
+            },
+        }
+        .cut(0.25);
+        assert_eq!(
+            delay.render(2.5, 4.),
+            vec![0.5, 0., 0.5, 0., 0.25, 0., 0.125, 0., 0.0625, 0.]
+        );
+    }
+
+    #[test]
+    fn reverb_decays() {
+        let effect = Effect::Reverb {
+            size: 0.5,
+            damp: 0.5,
+            mix: 1.,
+        };
+        let reverb = Sound::Effect {
+            sound: Sound::Begin(0.).into(),
+            effect,
+        }
+        .cut(0.001);
+        let samples = reverb.render(effect.ring_time() + 0.5, 8000.);
+        // 最初の反射は最短の櫛形フィルタの長さの後
+        let first = samples.iter().position(|&x| x != 0.).unwrap();
+        assert_eq!(first, (1116. * 8000. / 44100f64).round() as usize);
+        let peak = samples.iter().fold(0f64, |peak, x| peak.max(x.abs()));
+        let last = samples[samples.len() - 400..]
+            .iter()
+            .fold(0f64, |peak, x| peak.max(x.abs()));
+        assert!(last < peak / 1000., "{} {}", last, peak);
+    }
+
+    #[test]
+    fn glide_slews_between_notes() {
+        let note = |value: f64| Sound::Mul(Sound::Const(value).into(), Sound::End(-1.).into());
+        let glide = Sound::Effect {
+            sound: crate::sound::seq([note(1.), note(2.)]).into(),
+            effect: Effect::Lag {
+                rise: 0.5,
+                fall: 0.5,
+            },
+        };
+        assert_eq!(glide.duration(), Some(2.));
+        let samples = glide.render(2., 4.);
+        // 最初の音からは滑らせず，次の音へは時定数 0.5 秒で近づく
+        assert_eq!(samples[..4], [1.; 4]);
+        let a = (-0.5f64).exp();
+        assert!((samples[4] - (2. - a)).abs() < 1e-12);
+        assert!((samples[7] - (2. - a.powi(4))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn slew_rises_and_falls_separately() {
+        let step = |value: f64, length: f64| {
+            Sound::Mul(Sound::Const(value).into(), Sound::End(-length).into())
+        };
+        let slew = Sound::Effect {
+            sound: crate::sound::seq([step(0., 0.25), step(1., 1.), step(0., 1.)]).into(),
+            effect: Effect::Lag {
+                rise: 0.25,
+                fall: 0.,
+            },
+        };
+        let samples = slew.render(2.25, 4.);
+        assert_eq!(samples[0], 0.);
+        assert!((samples[1] - (1. - (-1f64).exp())).abs() < 1e-12);
+        // 時定数 0 ならすぐに追いつく
+        assert_eq!(samples[5..], [0.; 4]);
+    }
+}
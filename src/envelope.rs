@@ -0,0 +1,97 @@
+//! ADSR エンベロープ（ `adsr(attack, decay, sustain, release)` ）
+//!
+//! 時刻 0 に鳴り始め， `attack` 秒で 0 から 1 へ， `decay` 秒で `sustain` へ直線で動き，そのまま保つ．
+//! `length` 秒で離鍵し，その時点の値から `release` 秒で 0 に戻る．
+//! 離鍵がアタックや減衰の途中でも，その時点の値から戻る
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adsr {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+    /// 離鍵までの時間（秒）．無限大なら離さない
+    pub length: f64,
+}
+
+impl Adsr {
+    /// 離さないときの時刻 `t` の値
+    fn held(&self, t: f64) -> f64 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1. - (1. - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+    /// 時刻 `t` の値
+    pub fn value(&self, t: f64) -> f64 {
+        if t < 0. {
+            0.
+        } else if t < self.length {
+            self.held(t)
+        } else if t < self.length + self.release {
+            self.held(self.length) * (1. - (t - self.length) / self.release)
+        } else {
+            0.
+        }
+    }
+    /// 鳴り終わるまでの長さ（秒）．離さないなら `None`
+    pub fn duration(&self) -> Option<f64> {
+        if self.length.is_finite() {
+            Some(self.length + self.release)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::Sound;
+
+    #[test]
+    fn stages() {
+        let adsr = Adsr {
+            attack: 0.125,
+            decay: 0.25,
+            sustain: 0.5,
+            release: 0.5,
+            length: 1.,
+        };
+        let values: Vec<_> = [-0.125, 0.0625, 0.125, 0.25, 0.5, 1., 1.25, 1.5, 2.]
+            .iter()
+            .map(|&t| adsr.value(t))
+            .collect();
+        assert_eq!(values, vec![0., 0.5, 1., 0.75, 0.5, 0.5, 0.25, 0., 0.]);
+        assert_eq!(adsr.duration(), Some(1.5));
+        // アタックの途中で離す
+        let short = Adsr {
+            length: 0.0625,
+            ..adsr
+        };
+        assert_eq!(short.value(0.3125), 0.25);
+    }
+
+    #[test]
+    fn shifted() {
+        let sound = Sound::Adsr {
+            envelope: Adsr {
+                attack: 0.5,
+                decay: 0.,
+                sustain: 1.,
+                release: 0.5,
+                length: 1.,
+            },
+            offset: 0.,
+        }
+        .shift(-1.);
+        assert_eq!(sound.duration(), Some(2.5));
+        assert_eq!(
+            sound.render(3., 4.),
+            vec![0., 0., 0., 0., 0., 0.5, 1., 1., 1., 0.5, 0., 0.]
+        );
+    }
+}
@@ -1,13 +1,30 @@
 //! プログラム（ `mod program` ）を実行する環境
 
+use crate::analysis;
+use crate::cache::Cache;
 use crate::compiler;
-use crate::error::Error;
-use crate::function::Function;
+use crate::error::{Error, RuntimeFailure};
+use crate::events::{self, Field};
+use crate::filter::Filter;
+use crate::function::{self, Function};
+#[cfg(feature = "record")]
+use crate::input::{AudioInput, Recorder};
+use crate::master::Master;
+use crate::oscillator::Waveform;
+use crate::output::{AudioBackend, Output, Player};
+use crate::paths;
+use crate::pos;
+use crate::probe::Probes;
 use crate::program::VoidExpression;
+use crate::progress;
+use crate::samples;
 use crate::sound::Sound;
-use crate::syntax::Statement;
+use crate::spatial;
+use crate::syntax::{SampleEntry, Statement};
+use crate::timeline::Timeline;
+use crate::types::Type;
 use crate::value::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
@@ -15,37 +32,57 @@ use std::rc::Rc;
 pub struct Environment {
     variables: HashMap<String, Value>,
     functions: HashMap<String, Function>,
+    timeline: Rc<RefCell<Timeline>>,
+    samples: Rc<RefCell<samples::Registry>>,
+    /// `write` などで `samplerate` を省略したときの標本化周波数
+    samplerate: Rc<Cell<f64>>,
+    /// `write` などで `overwrite` を省略したときに上書きするか
+    overwrite: Rc<Cell<bool>>,
+    /// `write` のファイル名の代わりに使う名前（ `--out` ）
+    out: Rc<RefCell<Option<String>>>,
+    /// 外から値を与えた変数（ `--param` ）．台本の最上位での宣言より優先する
+    parameters: HashSet<String>,
+    /// `play` の再生先
+    backend: Rc<RefCell<Box<dyn AudioBackend>>>,
+    /// `record` の録音元
+    #[cfg(feature = "record")]
+    input: Rc<RefCell<Box<dyn AudioInput>>>,
+    probes: Rc<RefCell<Probes>>,
+    active: bool,
+    /// `:snapshot` で保存した状態
+    snapshots: HashMap<String, Snapshot>,
+    /// `samples { }` で読み込んだ音のキャッシュ
+    cache: Option<Cache>,
+    /// `samples { }` を読み込むスレッドの数
+    threads: usize,
+}
+
+/// ある時点の変数と関数の束縛と，変数の中身．
+/// 束縛（ `Rc` ）を戻すことで後から宣言した変数や定義した関数を消し，中身を書き戻すことで代入を取り消す
+struct Snapshot {
+    variables: Vec<(String, Value, Value)>,
+    functions: HashMap<String, Function>,
     active: bool,
 }
 
 impl Environment {
+    /// 既定の書き出し先の環境（テスト用）
+    #[cfg(any(test, feature = "test-util"))]
     pub fn new() -> Environment {
+        Environment::with_output(Rc::new(RefCell::new(crate::output::WavFiles::default())))
+    }
+    /// `write` などの書き出し先を `output` にした環境
+    pub fn with_output(output: Rc<RefCell<dyn Output>>) -> Environment {
         let mut variables = HashMap::new();
-        variables.insert("true".to_string(), Value::Boolean(Rc::new(Cell::new(true))));
-        variables.insert(
-            "false".to_string(),
-            Value::Boolean(Rc::new(Cell::new(false))),
-        );
-        variables.insert(
-            "PI".to_string(),
-            Value::Real(Rc::new(Cell::new(std::f64::consts::PI))),
-        );
-        variables.insert(
-            "E".to_string(),
-            Value::Real(Rc::new(Cell::new(std::f64::consts::PI))),
-        );
-        variables.insert(
-            "Begin".to_string(),
-            Value::Sound(Rc::new(RefCell::new(Sound::Begin(0.)))),
-        );
-        variables.insert(
-            "End".to_string(),
-            Value::Sound(Rc::new(RefCell::new(Sound::End(0.)))),
-        );
-        variables.insert(
-            "Rand".to_string(),
-            Value::Sound(Rc::new(RefCell::new(Sound::Rand))),
-        );
+        variables.insert("true".to_string(), Value::from(true));
+        variables.insert("false".to_string(), Value::from(false));
+        variables.insert("PI".to_string(), Value::from(std::f64::consts::PI));
+        variables.insert("E".to_string(), Value::from(std::f64::consts::PI));
+        variables.insert("Begin".to_string(), Value::from(Sound::Begin(0.)));
+        variables.insert("End".to_string(), Value::from(Sound::End(0.)));
+        variables.insert("Rand".to_string(), Value::from(Sound::Rand));
+        // `write` などの長さに渡すと音の長さを使う
+        variables.insert("auto".to_string(), Value::from(f64::NAN));
         let mut functions = HashMap::new();
         functions.insert("sqrt".to_string(), Function::primitive_real_1(f64::sqrt));
         functions.insert("sin".to_string(), Function::primitive_real_1(f64::sin));
@@ -58,27 +95,1156 @@ impl Environment {
         functions.insert("abs".to_string(), Function::primitive_real_1(f64::abs));
         functions.insert("max".to_string(), Function::primitive_real_2(f64::max));
         functions.insert("min".to_string(), Function::primitive_real_2(f64::min));
+        functions.insert("channels".to_string(), Function::primitive_real_0(|| 1.));
+        functions.insert("version".to_string(), Function::version());
+        functions.insert("has_feature".to_string(), Function::has_feature());
+        functions.insert(
+            "now".to_string(),
+            Function::primitive_real_0(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0., |duration| duration.as_secs_f64())
+            }),
+        );
+        functions.insert(
+            "render_time".to_string(),
+            Function::render_time(std::time::Instant::now()),
+        );
+        functions.insert("mtof".to_string(), Function::mtof());
+        functions.insert("voice_lead".to_string(), Function::voice_lead());
+        functions.insert("invert".to_string(), Function::invert());
+        functions.insert("drop".to_string(), Function::drop());
+        functions.insert("spread".to_string(), Function::spread());
+        functions.insert("save_preset".to_string(), Function::save_preset());
+        functions.insert("load_preset".to_string(), Function::load_preset());
+        functions.insert("render".to_string(), Function::render());
+        functions.insert(
+            "peak".to_string(),
+            Function::analysis(|samples, _| analysis::peak(samples)),
+        );
+        functions.insert(
+            "rms".to_string(),
+            Function::analysis(|samples, _| analysis::rms(samples)),
+        );
+        functions.insert(
+            "lufs_integrated".to_string(),
+            Function::analysis(analysis::lufs_integrated),
+        );
+        functions.insert(
+            "detect_pitch".to_string(),
+            Function::analysis(analysis::detect_pitch),
+        );
+        functions.insert(
+            "detect_onsets".to_string(),
+            Function::array_analysis(analysis::detect_onsets),
+        );
+        functions.insert("duration".to_string(), Function::duration());
+        functions.insert("show".to_string(), Function::show());
+        functions.insert("debug_dump".to_string(), Function::debug_dump());
         functions.insert("Sin".to_string(), Function::sin());
         functions.insert("Linear".to_string(), Function::linear());
         functions.insert("Exp".to_string(), Function::exp());
-        functions.insert("write".to_string(), Function::write());
+        functions.insert(
+            "square".to_string(),
+            Function::oscillator(Waveform::Square { duty: 0.5 }),
+        );
+        functions.insert("saw".to_string(), Function::oscillator(Waveform::Saw));
+        functions.insert(
+            "triangle".to_string(),
+            Function::oscillator(Waveform::Triangle),
+        );
+        functions.insert("noise".to_string(), Function::noise());
+        functions.insert("sync".to_string(), Function::sync());
+        functions.insert(
+            "lowpass".to_string(),
+            Function::filter(|frequency, q| Filter::Lowpass { frequency, q }),
+        );
+        functions.insert(
+            "highpass".to_string(),
+            Function::filter(|frequency, q| Filter::Highpass { frequency, q }),
+        );
+        functions.insert(
+            "bandpass".to_string(),
+            Function::filter(|frequency, q| Filter::Bandpass { frequency, q }),
+        );
+        functions.insert("unison".to_string(), Function::unison());
+        functions.insert("seq".to_string(), Function::seq());
+        functions.insert("repeat".to_string(), Function::repeat());
+        functions.insert("map".to_string(), Function::map());
+        functions.insert("map_sounds".to_string(), Function::map_sounds());
+        functions.insert("len".to_string(), Function::len());
+        functions.insert("len_sounds".to_string(), Function::len_sounds());
+        functions.insert("format".to_string(), Function::format());
+        functions.insert("push".to_string(), Function::push());
+        functions.insert("push_sounds".to_string(), Function::push_sounds());
+        functions.insert("shift".to_string(), Function::shift());
+        functions.insert("delay".to_string(), Function::delay());
+        functions.insert("reverb".to_string(), Function::reverb());
+        functions.insert("smooth".to_string(), Function::smooth());
+        functions.insert("slew".to_string(), Function::slew());
+        functions.insert("adsr".to_string(), Function::adsr());
+        functions.insert("steps".to_string(), Function::steps());
+        functions.insert("pan".to_string(), Function::pan());
+        functions.insert("left".to_string(), Function::route(spatial::left));
+        functions.insert("right".to_string(), Function::route(spatial::right));
+        functions.insert(
+            "swap_channels".to_string(),
+            Function::route(spatial::swap_channels),
+        );
+        functions.insert("mono".to_string(), Function::route(spatial::mono));
+        functions.insert("mid_side".to_string(), Function::route(spatial::mid_side));
+        functions.insert("compress".to_string(), Function::compress());
+        functions.insert("follow".to_string(), Function::follow());
+        functions.insert("gate".to_string(), Function::gate());
+        functions.insert("vocode".to_string(), Function::vocode());
+        functions.insert("split_bands".to_string(), Function::split_bands());
+        functions.insert("merge_bands".to_string(), Function::merge_bands());
+        functions.insert(
+            "track_and_resynth".to_string(),
+            Function::track_and_resynth(),
+        );
+        functions.insert("binaural".to_string(), Function::binaural());
+        functions.insert("reverse".to_string(), Function::reverse());
+        functions.insert("trim".to_string(), Function::trim());
+        functions.insert("append".to_string(), Function::append());
+        functions.insert("fade_edges".to_string(), Function::fade_edges());
+        functions.insert("freeze".to_string(), Function::freeze());
+        let samples = Rc::new(RefCell::new(samples::Registry::new()));
+        functions.insert("sample".to_string(), Function::sample(samples.clone()));
+        functions.insert("multisample".to_string(), Function::multisample());
+        functions.insert("read".to_string(), Function::read());
+        let probes = Rc::new(RefCell::new(Probes::default()));
+        functions.insert("probe".to_string(), Function::probe(probes.clone()));
+        functions.insert("probes".to_string(), Function::probes(probes.clone()));
+        let timeline = Rc::new(RefCell::new(Timeline::new()));
+        let samplerate = Rc::new(Cell::new(function::DEFAULT_SAMPLERATE));
+        functions.insert(
+            "samplerate".to_string(),
+            Function::samplerate(samplerate.clone()),
+        );
+        let overwrite = Rc::new(Cell::new(false));
+        let out = Rc::new(RefCell::new(None));
+        let master = Rc::new(RefCell::new(Master::default()));
+        let backend: Rc<RefCell<Box<dyn AudioBackend>>> =
+            Rc::new(RefCell::new(Box::new(Player::default())));
+        functions.insert(
+            "play".to_string(),
+            Function::play(master.clone(), backend.clone(), samplerate.clone()),
+        );
+        #[cfg(feature = "record")]
+        let input: Rc<RefCell<Box<dyn AudioInput>>> =
+            Rc::new(RefCell::new(Box::new(Recorder::default())));
+        #[cfg(feature = "record")]
+        functions.insert(
+            "record".to_string(),
+            Function::record(input.clone(), samplerate.clone()),
+        );
+        functions.insert(
+            "write".to_string(),
+            Function::write(
+                timeline.clone(),
+                master.clone(),
+                output.clone(),
+                samplerate.clone(),
+                overwrite.clone(),
+                out.clone(),
+            ),
+        );
+        functions.insert(
+            "set_master".to_string(),
+            Function::set_master(master.clone()),
+        );
+        functions.insert(
+            "write_stems".to_string(),
+            Function::write_stems(
+                timeline.clone(),
+                output.clone(),
+                samplerate.clone(),
+                overwrite.clone(),
+            ),
+        );
+        functions.insert("marker".to_string(), Function::marker(timeline.clone()));
+        functions.insert("region".to_string(), Function::region(timeline.clone()));
+        functions.insert(
+            "write_cue".to_string(),
+            Function::write_cue(timeline.clone()),
+        );
+        functions.insert(
+            "write_chapters".to_string(),
+            Function::write_chapters(timeline.clone()),
+        );
+        functions.insert(
+            "tempo_change".to_string(),
+            Function::tempo_change(timeline.clone()),
+        );
+        functions.insert(
+            "tempo_ramp".to_string(),
+            Function::tempo_ramp(timeline.clone()),
+        );
+        functions.insert(
+            "time_at_bar".to_string(),
+            Function::time_at_bar(timeline.clone()),
+        );
+        functions.insert(
+            "time_at_beat".to_string(),
+            Function::time_at_beat(timeline.clone()),
+        );
+        functions.insert(
+            "write_region".to_string(),
+            Function::write_region(
+                timeline.clone(),
+                master,
+                output,
+                samplerate.clone(),
+                overwrite.clone(),
+                out.clone(),
+            ),
+        );
         Environment {
             variables,
             functions,
+            timeline,
+            samples,
+            samplerate,
+            overwrite,
+            out,
+            parameters: HashSet::new(),
+            backend,
+            #[cfg(feature = "record")]
+            input,
+            probes,
             active: true,
+            snapshots: HashMap::new(),
+            cache: None,
+            threads: std::thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+    /// 宣言を飛ばした変数 `name` （ `--param` ）を宣言の型 `ty` に合わせる．
+    /// `int` と書いてあれば整数にし，小数部があればエラー
+    fn declare_parameter(
+        &mut self,
+        range: pos::Range,
+        name: &str,
+        ty: Option<Type>,
+    ) -> Result<(), Error> {
+        let x = match (ty, self.variables.get(name)) {
+            (Some(Type::Int), Some(Value::Real(rc))) => rc.get(),
+            _ => return Ok(()),
+        };
+        // 2^63 は i64 に収まらない
+        if x.fract() != 0. || !(-(2f64.powi(63))..2f64.powi(63)).contains(&x) {
+            let message = format!("parameter `{}` must be an integer (got {})", name, x);
+            return Err(Error::RuntimeFailure(range, message));
+        }
+        self.variables
+            .insert(name.to_string(), Value::from(x as i64));
+        Ok(())
+    }
+    /// 対話環境のメタコマンド
+    fn command(&mut self, range: pos::Range, command: &str) -> Result<(), Error> {
+        let words: Vec<_> = command.split_whitespace().collect();
+        match words[..] {
+            ["snapshot", name] => {
+                let variables = self
+                    .variables
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone(), value.deep_copy()))
+                    .collect();
+                let snapshot = Snapshot {
+                    variables,
+                    functions: self.functions.clone(),
+                    active: self.active,
+                };
+                self.snapshots.insert(name.to_string(), snapshot);
+                eprintln!("saved snapshot {}", name);
+            }
+            ["restore", name] => {
+                let snapshot = self
+                    .snapshots
+                    .get(name)
+                    .ok_or_else(|| Error::UndefinedSnapshot(name.to_string(), range))?;
+                self.variables.clear();
+                for (name, value, saved) in &snapshot.variables {
+                    value.assign(saved);
+                    self.variables.insert(name.clone(), value.clone());
+                }
+                self.functions = snapshot.functions.clone();
+                self.active = snapshot.active;
+                eprintln!("restored snapshot {}", name);
+            }
+            _ => return Err(Error::UnknownCommand(range)),
         }
+        Ok(())
+    }
+    /// `samples { }` の音をすべて読み込む．1 つでも失敗したら何も登録しない
+    fn load_samples(&mut self, entries: Vec<SampleEntry>) -> Result<(), Error> {
+        let mut loaded = samples::Registry::new();
+        for entry in &entries {
+            if loaded.contains_key(&entry.name) || self.samples.borrow().contains_key(&entry.name) {
+                return Err(Error::DuplicateSample(
+                    entry.name.clone(),
+                    entry.range.clone(),
+                ));
+            }
+            // 読み込むまで場所を取っておく
+            loaded.insert(entry.name.clone(), Sound::Const(0.));
+        }
+        let files: Vec<_> = entries
+            .iter()
+            .map(|entry| (paths::resolve_lossy(&entry.path), entry.sha256.clone()))
+            .collect();
+        let results = samples::load_all(&files, self.cache.as_ref(), self.threads);
+        for (entry, result) in entries.into_iter().zip(results) {
+            let range = entry.range;
+            let sound = result.map_err(|message| Error::SampleLoadFailure(range, message))?;
+            loaded.insert(entry.name, sound);
+        }
+        self.samples.borrow_mut().extend(loaded);
+        Ok(())
+    }
+    /// `samples { }` で読み込む音を `cache` にためる
+    pub fn set_cache(&mut self, cache: Cache) {
+        self.cache = Some(cache);
+    }
+    /// `samples { }` を `threads` 個のスレッドで読み込む
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+    /// `write` で書き出す範囲をマーカー名・区間名で制限する
+    pub fn set_window(&mut self, from: Option<String>, to: Option<String>) {
+        self.timeline.borrow_mut().set_window(from, to);
+    }
+    /// `write` などで `samplerate` を省略したときの標本化周波数を変える
+    pub fn set_samplerate(&mut self, samplerate: f64) {
+        self.samplerate.set(samplerate);
+    }
+    /// `probe` の記録（ `--probe-report` ）
+    pub fn probe_report(&self) -> String {
+        self.probes.borrow().report()
+    }
+    /// `play` の再生先
+    pub fn set_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        *self.backend.borrow_mut() = backend;
+    }
+    /// 書き出しの進み具合を `callback` に知らせる
+    pub fn set_progress(&mut self, callback: progress::Callback) {
+        progress::set(Some(callback));
+    }
+    /// `record` の録音元
+    #[cfg(feature = "record")]
+    pub fn set_input(&mut self, input: Box<dyn AudioInput>) {
+        *self.input.borrow_mut() = input;
+    }
+    /// `write` などで `overwrite` を省略したときに既存のファイルを上書きするか（ `--force` ）
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite.set(overwrite);
+    }
+    /// `write` と `write_region` で台本のファイル名の代わりに `filename` に書き出す（ `--out` ）
+    pub fn set_out(&mut self, filename: Option<String>) {
+        *self.out.borrow_mut() = filename;
+    }
+    /// 変数 `name` を `value` にする（ `--param` ）．台本の最上位の `let name = ...` は飛ばす
+    pub fn set_parameter(&mut self, name: &str, value: f64) {
+        self.variables.insert(name.to_string(), Value::from(value));
+        self.parameters.insert(name.to_string());
+    }
+    /// `write` などで書き出す長さを `seconds` 秒までにし，標本化周波数を `samplerate` 以下にする
+    pub fn set_preview(&mut self, seconds: f64, samplerate: Option<f64>) {
+        self.timeline.borrow_mut().set_preview(seconds, samplerate);
+    }
+    /// 変数 `name` の値
+    pub fn variable(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+    /// 変数の値を Rust の値として取り出す（テスト用）
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn get<T>(&self, name: &str) -> Result<T, String>
+    where
+        T: for<'a> std::convert::TryFrom<&'a Value, Error = String>,
+    {
+        let value = self
+            .variables
+            .get(name)
+            .ok_or_else(|| format!("undefined variable `{}`", name))?;
+        T::try_from(value)
     }
     pub fn run(&mut self, statement: Statement) -> Result<(), Error> {
+        let statement = match statement {
+            Statement::Samples(_, entries) => return self.load_samples(entries),
+            Statement::Command(range, command) => return self.command(range, &command),
+            Statement::Declaration(range, name, ty, _) if self.parameters.contains(&name) => {
+                return self.declare_parameter(range, &name, ty)
+            }
+            statement => statement,
+        };
+        let start = std::time::Instant::now();
         let statement = compiler::compile_statement::<VoidExpression>(
             statement,
             &mut self.variables,
             &mut self.functions,
         )?;
+        events::record(
+            "compile",
+            &[("seconds", Field::Number(start.elapsed().as_secs_f64()))],
+        );
         if self.active {
-            if let Some(()) = statement.run() {
-                self.active = false;
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| statement.run())) {
+                Ok(Some(())) => self.active = false,
+                Ok(None) => {}
+                Err(payload) => match payload.downcast::<RuntimeFailure>() {
+                    Ok(failure) => {
+                        let RuntimeFailure(range, message) = *failure;
+                        return Err(Error::RuntimeFailure(range, message));
+                    }
+                    Err(payload) => std::panic::resume_unwind(payload),
+                },
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser;
+
+    /// 台本を実行した環境．エラーがあれば panic する
+    fn run(source: &str) -> Environment {
+        let mut environment = Environment::new();
+        execute(&mut environment, source, false).unwrap_or_else(|message| panic!("{}", message));
+        environment
+    }
+
+    /// `environment` で台本を実行する．`interactive` なら対話環境として読む．
+    /// エラーがあればそのメッセージを返す
+    fn execute(
+        environment: &mut Environment,
+        source: &str,
+        interactive: bool,
+    ) -> Result<(), String> {
+        let mut lexer = Lexer::new(
+            Box::new(std::io::Cursor::new(source.to_string())),
+            interactive,
+        );
+        let mut log = Vec::new();
+        loop {
+            let result = match parser::parse_statement(&mut lexer, &mut log) {
+                Ok(Some(statement)) => environment.run(statement),
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                let mut message = Vec::new();
+                err.write_diagnostic(&mut message, &log)
+                    .expect("cannot print error message");
+                return Err(String::from_utf8_lossy(&message).into_owned());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn write_creates_directories() {
+        let directory = std::env::temp_dir().join(format!("cryss-mkdirs-{}", std::process::id()));
+        let path = directory.join("takes").join("a.wav");
+        run(&format!("write(Sin(440), 0.01, \"{}\");\n", path.display()));
+        assert!(path.exists());
+        let path = directory.join("missing").join("b.wav");
+        let message = execute(
+            &mut Environment::new(),
+            &format!(
+                "let s = Sin(440);\nwrite(s, 0.01, \"{}\", mkdirs = false);\n",
+                path.display()
+            ),
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("cannot write"), "{}", message);
+        assert!(message.contains(" at 2:1-"), "{}", message);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn overwrite_and_numbering() {
+        let directory =
+            std::env::temp_dir().join(format!("cryss-overwrite-{}", std::process::id()));
+        let path = |name: &str| directory.join(name).display().to_string();
+        let write = |name: &str, rest: &str| {
+            format!("write(Sin(440), 0.01, \"{}\"{});\n", path(name), rest)
+        };
+        run(&(write("a.wav", "") + &write("take-{n}.wav", "") + &write("take-{n}.wav", "")));
+        assert!(directory.join("take-1.wav").exists());
+        assert!(directory.join("take-2.wav").exists());
+        let message = execute(&mut Environment::new(), &write("a.wav", ""), false).unwrap_err();
+        assert!(message.contains("already exists"), "{}", message);
+        run(&write("a.wav", ", overwrite = true"));
+        let mut environment = Environment::new();
+        environment.set_overwrite(true);
+        execute(&mut environment, &write("a.wav", ""), false).unwrap();
+        assert!(execute(
+            &mut environment,
+            &write("a.wav", ", overwrite = false"),
+            false
+        )
+        .is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn undefined_marker() {
+        let path = std::env::temp_dir().join(format!("cryss-window-{}.wav", std::process::id()));
+        let mut environment = Environment::new();
+        environment.set_window(Some("intro".to_string()), None);
+        let message = execute(
+            &mut environment,
+            &format!(
+                "marker(\"verse\", 1);\nwrite(Sin(440), 2, \"{}\");\n",
+                path.display()
+            ),
+            false,
+        )
+        .unwrap_err();
+        assert!(
+            message.contains("undefined marker or region `intro`"),
+            "{}",
+            message
+        );
+        assert!(message.contains(" at 2:1-"), "{}", message);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn read_back() {
+        let path = std::env::temp_dir().join(format!("cryss-read-{}.wav", std::process::id()));
+        let environment = run(&format!(
+            "write(Sin(1000), 0.5, \"{0}\", samplerate = 8000, overwrite = true);\n\
+             let s = read(\"{0}\");\nlet d = duration(s);\n",
+            path.display()
+        ));
+        assert_eq!(environment.get::<f64>("d"), Ok(0.5));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn function_errors() {
+        let path = std::env::temp_dir().join(format!("cryss-bits-{}.wav", std::process::id()));
+        for (source, expected) in [
+            (
+                "let p = peak(Sin(440));\n",
+                "cannot analyze an infinite sound",
+            ),
+            ("let s = sample(\"nope\");\n", "undefined sample `nope`"),
+            ("set_master(\"nope\");\n", "nope"),
+            (
+                &format!("write(Sin(440), 1, \"{}\", bits = 12);\n", path.display()),
+                "unsupported bit depth 12",
+            ),
+        ] {
+            let message = execute(&mut Environment::new(), source, false).unwrap_err();
+            assert!(message.contains(expected), "{}", message);
+            assert!(message.contains(" at 1:"), "{}", message);
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn preset_errors() {
+        let path = std::env::temp_dir().join(format!("cryss-preset-{}.json", std::process::id()));
+        let load = |rest: &str| {
+            format!(
+                "let x = 1;\nlet p = load_preset(\"{}\"{});\n",
+                path.display(),
+                rest
+            )
+        };
+        let message = execute(&mut Environment::new(), &load(""), false).unwrap_err();
+        assert!(message.contains("cannot read"), "{}", message);
+        assert!(message.contains(&path.display().to_string()), "{}", message);
+        assert!(message.contains(" at 2:9-"), "{}", message);
+        std::fs::write(&path, "{\"attack\": 0.1, \"cutof\": 800}").unwrap();
+        let message = execute(
+            &mut Environment::new(),
+            &load(", schema = {attack: 0, cutoff: 1000}"),
+            false,
+        )
+        .unwrap_err();
+        assert!(
+            message.contains("field `cutof`: unknown field"),
+            "{}",
+            message
+        );
+        assert!(message.contains(&path.display().to_string()), "{}", message);
+        assert!(message.contains(" at 2:9-"), "{}", message);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dump_errors() {
+        let message = execute(
+            &mut Environment::new(),
+            "debug_dump(Sin(440), 0.01, \"/tmp/dump.csv\");\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(
+            message.contains("cannot dump to /tmp/dump.csv"),
+            "{}",
+            message
+        );
+        let message = execute(
+            &mut Environment::new(),
+            "debug_dump(Sin(440), 0.01, \"cryss-missing/dump.csv\");\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(
+            message.contains("cannot write cryss-missing/dump.csv: "),
+            "{}",
+            message
+        );
+        assert!(message.contains(" at 1:1-"), "{}", message);
+    }
+
+    #[test]
+    fn render_progress() {
+        let path = std::env::temp_dir().join(format!("cryss-progress-{}.wav", std::process::id()));
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let sink = reports.clone();
+        let mut environment = Environment::new();
+        environment.set_progress(Box::new(move |progress| {
+            let peak = progress.peaks[0].1[0];
+            sink.borrow_mut()
+                .push((progress.rendered, progress.total, peak, progress.finished))
+        }));
+        execute(
+            &mut environment,
+            &format!(
+                "write(Sin(1000) * 0.5, 1, \"{}\", samplerate = 8192);\n",
+                path.display()
+            ),
+            false,
+        )
+        .unwrap();
+        progress::set(None);
+        std::fs::remove_file(&path).unwrap();
+        let reports = reports.borrow();
+        assert_eq!(reports.len(), 3);
+        assert_eq!((reports[0].0, reports[0].1, reports[0].3), (0.5, 1., false));
+        assert!((reports[0].2 - 0.5).abs() < 1e-3);
+        assert_eq!((reports[2].0, reports[2].3), (1., true));
+        // 最後のブロックは書き出し済みなので，ピークは 0
+        assert_eq!(reports[2].2, 0.);
+    }
+
+    #[test]
+    fn auto_length() {
+        let path = std::env::temp_dir().join(format!("cryss-auto-{}.wav", std::process::id()));
+        let environment = run(&format!(
+            "let note = Sin(440) * adsr(0.125, 0.125, 0.5, 0.25, length = 0.5) >> 0.25;\n\
+             let d = duration(note);\n\
+             write(note, auto, \"{0}\", samplerate = 8000, overwrite = true);\n\
+             let w = duration(read(\"{0}\"));\n",
+            path.display()
+        ));
+        assert_eq!(environment.get::<f64>("d"), Ok(1.));
+        assert_eq!(environment.get::<f64>("w"), Ok(1.));
+        std::fs::remove_file(&path).unwrap();
+        let message = execute(
+            &mut Environment::new(),
+            &format!("write(Sin(440), auto, \"{}\");\n", path.display()),
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("endless sound"), "{}", message);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn filters() {
+        let environment = run("let low = rms(lowpass(Sin(4000), 200), seconds = 0.5);\n\
+             let high = rms(highpass(Sin(4000), 200, q = 1), seconds = 0.5);\n\
+             let band = rms(bandpass(Sin(1000), 1000), seconds = 0.5);\n");
+        let get = |name: &str| environment.get::<f64>(name).unwrap();
+        assert!(get("low") < 0.01, "{}", get("low"));
+        assert!(
+            (get("high") - 0.5f64.sqrt()).abs() < 0.01,
+            "{}",
+            get("high")
+        );
+        assert!(
+            (get("band") - 0.5f64.sqrt()).abs() < 0.05,
+            "{}",
+            get("band")
+        );
+        let message = execute(
+            &mut Environment::new(),
+            "let s = lowpass(Sin(1), 0);\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("`cutoff` must be positive"), "{}", message);
+    }
+
+    #[test]
+    fn modulated_filters() {
+        let environment = run("let c = smooth(saw(1) * 0 + 200, 0.01);\n\
+             let low = rms(lowpass(Sin(4000), c), seconds = 0.5);\n\
+             let open = rms(lowpass(Sin(200), slew(saw(1) * 0 + 8000, 0.01, 0.1)), seconds = 0.5);\n\
+             let s = lowpass(saw(220), smooth(saw(1) * 100 + 300, 0.01), q = 2);\n\
+             let peak = peak(s, seconds = 0.5);\n");
+        let get = |name: &str| environment.get::<f64>(name).unwrap();
+        assert!(get("low") < 0.01, "{}", get("low"));
+        assert!(
+            (get("open") - 0.5f64.sqrt()).abs() < 0.05,
+            "{}",
+            get("open")
+        );
+        assert!(
+            get("peak") > 0. && get("peak").is_finite(),
+            "{}",
+            get("peak")
+        );
+        // 変調できない実数の引数に音を渡すと型エラー
+        for source in [
+            "let s = shift(Sin(1), saw(1));\n",
+            "let s = invert([60, 64, 67], saw(1));\n",
+            "let s = delay(Sin(1), saw(1));\n",
+            "let s = format(saw(1));\n",
+            "let s = lowpass(Sin(1), 200, q = saw(1));\n",
+        ] {
+            let message = execute(&mut Environment::new(), source, false).unwrap_err();
+            assert!(message.contains("type mismatch"), "{}: {}", source, message);
+        }
+    }
+
+    #[test]
+    fn clamped_envelopes() {
+        let environment = run("let up = Linear(2, t = 1, clamp = true) >> 1;\n\
+             let down = Linear(-1, from = 0.5, to = 1, hold = 0);\n\
+             let decay = Exp(-1, clamp = true);\n");
+        let render = |name: &str| environment.get::<Sound>(name).unwrap().render(3., 2.);
+        assert_eq!(render("up"), vec![0., 0., 0., 1., 2., 2.]);
+        assert_eq!(render("down"), vec![-0.5, -0.5, 0., 0., 0., 0.]);
+        assert_eq!(render("decay")[0], 1.);
+        assert!(execute(
+            &mut Environment::new(),
+            "let s = Linear(1, from = 2, to = 1);\n",
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn safety_limits() {
+        let message = execute(
+            &mut Environment::new(),
+            "let x = 1;\nlet s = Sin(-x);\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("must not be negative"), "{}", message);
+        assert!(message.contains(" at 2:9-"), "{}", message);
+        let message = execute(
+            &mut Environment::new(),
+            "write(Sin(5000), 0.01, \"nyquist.wav\", samplerate = 8000);\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("Nyquist"), "{}", message);
+        assert!(!std::path::Path::new("nyquist.wav").exists());
+        let message = execute(
+            &mut Environment::new(),
+            "write(0, 1e9, \"long.wav\");\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("too long"), "{}", message);
+    }
+
+    #[test]
+    fn integer_coercions() {
+        let message = execute(
+            &mut Environment::new(),
+            "let a = 1;\nlet s = format(1, width = -1);\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(
+            message.contains("width must not be negative"),
+            "{}",
+            message
+        );
+        assert!(message.contains(" at 2:9-"), "{}", message);
+        // `--strict-types` では小数部があるとエラー
+        crate::coercion::set_strict(true);
+        let message = execute(
+            &mut Environment::new(),
+            "let c = invert([60, 64, 67], 1.5);\n",
+            false,
+        )
+        .unwrap_err();
+        crate::coercion::set_strict(false);
+        assert!(
+            message.contains("inversion must be an integer"),
+            "{}",
+            message
+        );
+        assert!(message.contains(" at 1:9-"), "{}", message);
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut environment = run("let x = 1;\n");
+        execute(
+            &mut environment,
+            "def g() -> real { return 1; }\nlet s = Sin(440);\n:snapshot a\nx = 2;\nlet y = 3;\n\
+             def f() -> real { return 1; }\ndef g() -> real { return 2; }\n:restore a\nlet z = g();\n",
+            true,
+        )
+        .unwrap();
+        assert_eq!(environment.get::<f64>("x"), Ok(1.));
+        assert!(environment.get::<f64>("y").is_err());
+        assert!(environment.get::<Sound>("s").is_ok());
+        // 後から定義した関数は消え，定義し直した関数は元に戻る
+        assert_eq!(environment.get::<f64>("z"), Ok(1.));
+        let message = execute(&mut environment, "let w = f();\n", true).unwrap_err();
+        assert!(message.contains("undefined function f"), "{}", message);
+    }
+
+    #[test]
+    fn destructuring() {
+        let environment = run(
+            "let [a, b] = [1, 2, 3];\nlet {attack, decay} = {decay: 0.5, attack: 0.1};\nlet [s, t] = [Sin(1), 2];\n",
+        );
+        assert_eq!(environment.get::<f64>("a"), Ok(1.));
+        assert_eq!(environment.get::<f64>("b"), Ok(2.));
+        assert_eq!(environment.get::<f64>("attack"), Ok(0.1));
+        assert_eq!(environment.get::<f64>("decay"), Ok(0.5));
+        assert!(environment.get::<Sound>("s").is_ok());
+        assert!(environment.get::<Sound>("t").is_ok());
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let message = execute(
+            &mut Environment::new(),
+            "let a = [1, 2];\nlet x = a[5];\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("index 5 out of range"), "{}", message);
+        assert!(message.contains(" at 2:11-"), "{}", message);
+        let message =
+            execute(&mut Environment::new(), "let {a, z} = {a: 1};\n", false).unwrap_err();
+        assert!(message.contains("undefined field z"), "{}", message);
+        assert!(message.contains(" at 1:9-"), "{}", message);
+    }
+
+    #[test]
+    fn else_if() {
+        let environment = run(
+            "let x = 0.5;\nlet y = 0;\nif (x < 0) {\n    y = 1;\n} else if (x < 1) {\n    y = 2;\n} else {\n    y = 3;\n}\n",
+        );
+        assert_eq!(environment.get::<f64>("y"), Ok(2.));
+    }
+
+    #[test]
+    fn return_if() {
+        let environment =
+            run("let x = 1;\nreturn if x < 0;\nx = 2;\nreturn if (x == 2);\nx = 3;\n");
+        assert_eq!(environment.get::<f64>("x"), Ok(2.));
+    }
+
+    #[test]
+    fn integers() {
+        let environment = run(
+            "let i: int = 7 % 3;\nlet j: int = -1 % 3;\nlet k: int = 1 << 4;\nlet r = 7 / 2;\n\
+             let s = i + 0.5;\nlet n = 0;\nn = n + 0.5;\n\
+             def half(x: int) -> int { return x >> 1; }\nlet h: int = half(k);\nlet g = half(5);\n",
+        );
+        assert_eq!(environment.get::<i64>("i"), Ok(1));
+        assert_eq!(environment.get::<i64>("j"), Ok(2));
+        assert_eq!(environment.get::<i64>("k"), Ok(16));
+        assert_eq!(environment.get::<f64>("r"), Ok(3.5));
+        assert_eq!(environment.get::<f64>("s"), Ok(1.5));
+        // 型を書かなければ実数の変数
+        assert_eq!(environment.get::<f64>("n"), Ok(0.5));
+        assert_eq!(environment.get::<i64>("h"), Ok(8));
+        assert_eq!(environment.get::<f64>("g"), Ok(2.));
+        let message = execute(
+            &mut Environment::new(),
+            "let n: int = 0;\nn = 0.5;\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("type mismatch"), "{}", message);
+        for (source, expected) in [
+            (
+                "let x: int = 9223372036854775807;\nlet y = x + 1;\n",
+                "integer overflow",
+            ),
+            (
+                "let x: int = 1;\nlet y = x << 64;\n",
+                "cannot shift 1 << 64",
+            ),
+            (
+                "let x: int = 1;\nlet y = x % 0;\n",
+                "division by zero in 1 % 0",
+            ),
+        ] {
+            let message = execute(&mut Environment::new(), source, false).unwrap_err();
+            assert!(message.contains(expected), "{}", message);
+            assert!(message.contains(" at 2:9-"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn embedded_text() {
+        let path = std::env::temp_dir().join(format!("cryss-embed-{}.txt", std::process::id()));
+        std::fs::write(&path, "C4 {i}\nE4\n").unwrap();
+        let environment = run(&format!(
+            "let i = 1;\nlet notes = embed(\"{}\");\n",
+            path.display()
+        ));
+        assert_eq!(
+            environment.get::<String>("notes"),
+            Ok("C4 {i}\nE4\n".to_string())
+        );
+        std::fs::remove_file(&path).unwrap();
+        let message = execute(
+            &mut Environment::new(),
+            &format!("let notes = embed(\"{}\");\n", path.display()),
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("cannot read"), "{}", message);
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let environment = run(
+            "let s = 0.;\nfor (x in [1, 2, 3]) s += x;\ns *= 2.;\ns -= 1.;\ns /= 2.;\n\
+             let i: int = 7;\ni %= 4;\ni *= 3;\nlet p = 2.;\np ^= 3;\nlet a = \"x\";\na += 1;\n\
+             let mix = Sin(0);\nfor (f in [220, 330]) mix += Sin(f) * 0.5;\nlet d = duration(mix);\n",
+        );
+        assert_eq!(environment.get::<f64>("s"), Ok(5.5));
+        assert_eq!(environment.get::<i64>("i"), Ok(9));
+        assert_eq!(environment.get::<f64>("p"), Ok(8.));
+        assert_eq!(environment.get::<String>("a"), Ok("x1".to_string()));
+        assert!(execute(&mut Environment::new(), "f() += 1;\n", false).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let environment = run(
+            "let i = 3;\nlet a = f\"take_{i}_{{n}}.wav\";\nlet b = \"x\" + 0.5 + format(i, width = 2);\n\
+             let c = len(a);\nlet d = \"abc\" < \"abd\";\nlet e = \"{i} {{\";\nlet n = len([Sin(1)]);\n",
+        );
+        assert_eq!(
+            environment.get::<String>("a"),
+            Ok("take_3_{n}.wav".to_string())
+        );
+        assert_eq!(environment.get::<String>("b"), Ok("x0.503".to_string()));
+        assert_eq!(environment.get::<f64>("c"), Ok(14.));
+        assert_eq!(environment.get::<bool>("d"), Ok(true));
+        // 接頭辞 `f` のない文字列には埋め込まない
+        assert_eq!(environment.get::<String>("e"), Ok("{i} {{".to_string()));
+        assert_eq!(environment.get::<f64>("n"), Ok(1.));
+        let message =
+            execute(&mut Environment::new(), "let a = f\"{nope}\";\n", false).unwrap_err();
+        assert!(message.contains("nope"), "{}", message);
+        assert!(message.contains(" at 1:9-"), "{}", message);
+        assert!(execute(
+            &mut Environment::new(),
+            "let a = len_string(\"abc\");\n",
+            false
+        )
+        .is_err());
+        // 桁数と幅には上限がある
+        for source in [
+            "let s = format(1, width = 1e12);\n",
+            "let s = format(1, digits = 1e300);\n",
+        ] {
+            let message = execute(&mut Environment::new(), source, false).unwrap_err();
+            assert!(message.contains("is too large"), "{}", message);
+            assert!(message.contains(" at 1:9-"), "{}", message);
+        }
+        let environment = run("let s = format(1, digits = 2, width = 1000);\n");
+        assert_eq!(environment.get::<String>("s").map(|s| s.len()), Ok(1000));
+    }
+
+    #[test]
+    fn default_samplerate() {
+        let environment = run("let r = samplerate();\n");
+        assert_eq!(environment.get::<f64>("r"), Ok(44100.));
+        // 設定ファイルの `[render] samplerate` と同じ
+        let mut environment = Environment::new();
+        environment.set_samplerate(8000.);
+        execute(
+            &mut environment,
+            "let r = samplerate();\nlet name = format(samplerate()) + \".wav\";\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(environment.get::<f64>("r"), Ok(8000.));
+        assert_eq!(
+            environment.get::<String>("name"),
+            Ok("8000.wav".to_string())
+        );
+    }
+
+    #[test]
+    fn parameters() {
+        let mut environment = Environment::new();
+        environment.set_parameter("cutoff", 300.);
+        execute(
+            &mut environment,
+            "let cutoff = 800;\nlet q = cutoff * 2;\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(environment.get::<f64>("q"), Ok(600.));
+    }
+
+    #[test]
+    fn int_parameters() {
+        let mut environment = Environment::new();
+        environment.set_parameter("n", 3.);
+        execute(
+            &mut environment,
+            "let n: int = 4;\nlet m: int = n + 1;\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(environment.get::<i64>("m"), Ok(4));
+        for value in [3.5, f64::NAN, 1e19] {
+            let mut environment = Environment::new();
+            environment.set_parameter("n", value);
+            let message = execute(&mut environment, "let n: int = 4;\n", false).unwrap_err();
+            assert!(message.contains("must be an integer"), "{}", message);
+            assert!(message.contains(" at 1:5-"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn chained_comparison() {
+        let environment = run(
+            "let x = 0.5;\nlet a = 0 <= x <= 1;\nlet b = 0 < x < 0.5;\nlet c = 1 > x >= 0.5 > 0;\n",
+        );
+        assert_eq!(environment.get::<bool>("a"), Ok(true));
+        assert_eq!(environment.get::<bool>("b"), Ok(false));
+        assert_eq!(environment.get::<bool>("c"), Ok(true));
+    }
+
+    #[test]
+    fn user_defined_functions() {
+        let environment = run(
+            "let base = 2;\n\
+             def scale(x: real, by = base) -> real { let y = x * by; return y; }\n\
+             def fib(n: real) -> real {\n\
+                 return n if n < 2;\n\
+                 let a = fib(n - 1);\n\
+                 return a + fib(n - 2);\n\
+             }\n\
+             def ack(m: real, n: real) -> real {\n\
+                 return n + 1 if m == 0;\n\
+                 return ack(m - 1, 1) if n == 0;\n\
+                 return ack(m - 1, ack(m, n - 1));\n\
+             }\n\
+             let a = scale(3);\nlet b = scale(3, by = 0.5);\nlet c = fib(10);\nlet d = ack(2, 3);\n",
+        );
+        assert_eq!(environment.get::<f64>("a"), Ok(6.));
+        assert_eq!(environment.get::<f64>("b"), Ok(1.5));
+        assert_eq!(environment.get::<f64>("c"), Ok(55.));
+        assert_eq!(environment.get::<f64>("d"), Ok(9.));
+    }
+
+    #[test]
+    fn recursion_limit() {
+        // 既定の 200 段はテストのスレッドのスタックに収まらないことがある
+        function::set_recursion_limit(50);
+        let mut environment = Environment::new();
+        let message = execute(
+            &mut environment,
+            // 末尾呼び出しではないので段が重なる
+            "def down(n: real) -> real { return 1 + down(n - 1); }\nlet x = down(0);\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("recursion limit"), "{}", message);
+        assert!(message.contains("(depth 51)"), "{}", message);
+        assert!(message.contains("(45 more)"), "{}", message);
+        // 失敗した呼び出しのあとも呼べる
+        execute(
+            &mut environment,
+            "def one() -> real { return 1; }\nlet y = one();\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(environment.get::<f64>("y"), Ok(1.));
+        function::set_recursion_limit(function::DEFAULT_RECURSION_LIMIT);
+    }
+
+    #[test]
+    fn tail_calls() {
+        // 末尾呼び出しは段を重ねないので上限を超えて再帰できる
+        function::set_recursion_limit(50);
+        let environment = run("def sum(n: real, acc = 0) -> real {\n\
+               return acc if n == 0;\n\
+               if (n % 2 == 0) { return sum(n - 1, acc = acc + n); }\n\
+               return sum(n - 1, acc = acc + n);\n\
+             }\n\
+             let s = sum(1000);\n\
+             def wrap(n: real) -> real { return n if n == 0; return 0 + wrap(n - 1); }\n");
+        assert_eq!(environment.get::<f64>("s"), Ok(500500.));
+        // 値に手を加えるものは対象外
+        let mut environment = environment;
+        let message = execute(&mut environment, "let w = wrap(1000);\n", false).unwrap_err();
+        assert!(message.contains("recursion limit"), "{}", message);
+        function::set_recursion_limit(function::DEFAULT_RECURSION_LIMIT);
+    }
+
+    #[test]
+    fn closures() {
+        let environment = run(
+            "let k = 10;\nlet add = (x) => x + k;\nk = 20;\nlet a = map([1, 2], add);\n\
+             def twice(x: real, f = (y) => y * 2) -> real { return f(f(x)); }\n\
+             let b = twice(3);\nlet c = twice(3, f = add);\n\
+             let g = (a, b) => a - b;\ng = (a, b) => b - a;\nlet d = g(5, 3);\n\
+             let e = map_sounds([Sin(1)], (s: Sound) => s * 0.5);\n",
+        );
+        assert_eq!(environment.get::<Vec<f64>>("a"), Ok(vec![21., 22.]));
+        assert_eq!(environment.get::<f64>("b"), Ok(12.));
+        assert_eq!(environment.get::<f64>("c"), Ok(43.));
+        assert_eq!(environment.get::<f64>("d"), Ok(-2.));
+        assert_eq!(environment.get::<Vec<Sound>>("e").map(|e| e.len()), Ok(1));
+        let message = execute(
+            &mut Environment::new(),
+            "let f = map([1], (s: Sound) => s);\n",
+            false,
+        )
+        .unwrap_err();
+        assert!(message.contains("found fn(Sound) -> Sound"), "{}", message);
+    }
+
+    #[test]
+    fn array_iteration() {
+        let environment = run(
+            "let a = push([1, 2, 3], 10);\nlet n = len(a);\nlet s = 0;\n\
+             for (x in a) { if (x > 1) { s = s + x; } }\n\
+             let b = push_sounds([Sin(1)], Sin(2));\nlet m = 0;\nfor (o in b) m = m + len_sounds(b);\n\
+             def total(xs: real[]) -> real { let t = 0; for (x in xs) { t = t + x; } return t; }\n\
+             let t = total([4, 5, 6]);\n",
+        );
+        assert_eq!(environment.get::<Vec<f64>>("a"), Ok(vec![1., 2., 3., 10.]));
+        assert_eq!(environment.get::<f64>("n"), Ok(4.));
+        assert_eq!(environment.get::<f64>("s"), Ok(15.));
+        assert_eq!(environment.get::<f64>("m"), Ok(4.));
+        assert_eq!(environment.get::<f64>("t"), Ok(15.));
+        let message = execute(&mut Environment::new(), "for (x in 3) x?;\n", false).unwrap_err();
+        assert!(message.contains("cannot iterate over real"), "{}", message);
+    }
+}
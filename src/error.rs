@@ -1,22 +1,34 @@
 //! エラー出力のためのモジュール
 
 use crate::pos;
+use crate::preset::quote;
 use crate::types::Type;
+use std::cell::Cell;
 
+#[derive(Debug)]
 pub enum Error {
     UnexpectedCharacter(pos::Pos),
+    InvalidUtf8(pos::Pos),
     NoCharacterAfterBackSlash(pos::Pos),
+    InvalidHexEscape(pos::Range),
+    InvalidUnicodeEscape(pos::Range),
     UnterminatedComment(pos::Pos),
     UnterminatedStringLiteral(pos::Pos),
-    NoLineFeedAtEOF,
     IncompleteScientificNotation(pos::Range),
+    IncompleteRadixLiteral(pos::Range),
+    IntegerLiteralOutOfRange(pos::Range),
+    NumberLiteralOverflow(pos::Range),
+    NumberLiteralUnderflow(pos::Range),
+    EmbedFailure(pos::Range, String),
     SingleAmpersand(pos::Range),
-    SingleDot(pos::Range),
     ParseFloatFailure(pos::Range, std::num::ParseFloatError),
     UnclosedBracketUntil(pos::Range, pos::Range),
     UnclosedBracketUntilEOF(pos::Range),
     EmptyArgumentName(pos::Range),
     InvalidArgumentName(pos::Range, pos::Range),
+    InvalidFieldName(pos::Range, pos::Range),
+    EmptyField(pos::Range),
+    DuplicateField(String, pos::Range),
     UndefinedVariable(String, pos::Range),
     UndefinedFunction(String, pos::Range),
     EmptyOperandUnary(pos::Range),
@@ -24,6 +36,7 @@ pub enum Error {
     EmptyArgument(pos::Range),
     EmptyNamedArgument(pos::Range),
     EmptyParentheses(pos::Range, pos::Range),
+    EmptyIndex(pos::Range, pos::Range),
     EmptyRHS(pos::Range),
     EmptyExpressionReturn(pos::Range),
     TypeMismatchUnary(pos::Range, Type),
@@ -33,6 +46,9 @@ pub enum Error {
     WrongNumberOfArguments(pos::Range, usize, usize),
     UnusedNamedArguments(pos::Range, Vec<String>),
     TypeMismatchArgument(pos::Range, Type),
+    TypeMismatchElement(pos::Range, Type),
+    TypeMismatchDestructuring(pos::Range, Type),
+    TypeMismatchIteration(pos::Range, Type),
     LHSNotIdentifier(pos::Range, pos::Range),
     NoSemicolonAtEndOfStatement(pos::Range),
     UnexpectedToken(pos::Range),
@@ -40,192 +56,496 @@ pub enum Error {
     UnexpectedTokenAfterKeyword(pos::Range, pos::Range),
     UnexpectedEOFAfterKeyword(pos::Range),
     UnexpectedEOFAfterCondition(pos::Range, pos::Range),
+    ElseWithoutIf(pos::Range),
+    UnknownType(pos::Range),
+    UntypedParameter(String, pos::Range),
+    DuplicateParameter(String, pos::Range),
+    InvalidParameter(pos::Range),
     VoidRHS(pos::Range),
+    NestedSamples(pos::Range),
+    DuplicateSample(String, pos::Range),
+    SampleLoadFailure(pos::Range, String),
+    UnknownCommand(pos::Range),
+    UndefinedSnapshot(String, pos::Range),
+    ExportFailure(pos::Range, String),
+    RuntimeFailure(pos::Range, String),
+}
+
+thread_local! {
+    /// `write_diagnostic` で色をつけるか
+    static COLOR: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 端末に出すときは色をつける
+pub fn set_color(color: bool) {
+    COLOR.with(|cell| cell.set(color));
+}
+
+/// 実行中の失敗．`Environment::run` が受け止めて `Error::RuntimeFailure` にする
+pub struct RuntimeFailure(pub pos::Range, pub String);
+
+thread_local! {
+    /// 真なら，どの panic のメッセージも出さない（ `catch_quietly` ）
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `RuntimeFailure` はエラーとして報告するので，panic のメッセージを出さない
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if info.payload().downcast_ref::<RuntimeFailure>().is_none() && !QUIET.with(Cell::get) {
+            default(info)
+        }
+    }));
+}
+
+/// `f` の中の panic を，メッセージを出さずに受け止める．
+/// 対話環境で `RuntimeFailure` でない panic もエラーとして報告するため
+pub fn catch_quietly<T>(f: impl FnOnce() -> T) -> std::thread::Result<T> {
+    let quiet = QUIET.with(|quiet| quiet.replace(true));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    QUIET.with(|cell| cell.set(quiet));
+    result
 }
 
 impl Error {
-    pub fn print<Write: std::io::Write>(
-        &self,
-        w: &mut Write,
-        log: &[String],
-    ) -> Result<(), std::io::Error> {
-        write!(w, "error: ")?;
+    /// エラーの種類ごとの番号（ `E0001` など）．エディタなどの道具が頼るので変えない．
+    /// 新しい種類には続きの番号をつける
+    pub fn code(&self) -> &'static str {
         match self {
-            Error::UnexpectedCharacter(pos) => {
-                writeln!(w, "unexpected character at {}", pos)?;
-                pos.print(w, log)
-            }
-            Error::NoCharacterAfterBackSlash(pos) => {
-                writeln!(w, "no character after `\\` at {}", pos)?;
-                pos.print(w, log)
-            }
-            Error::UnterminatedComment(pos) => {
-                writeln!(w, "unterminated comment (started at {})", pos)?;
-                pos.print(w, log)
-            }
-            Error::UnterminatedStringLiteral(pos) => {
-                writeln!(w, "unterminated string literal (started at {})", pos)?;
-                pos.print(w, log)
-            }
-            Error::NoLineFeedAtEOF => {
-                writeln!(w, "no line feed at end of file")
-            }
-            Error::IncompleteScientificNotation(range) => {
-                writeln!(w, "incomplete scientific notation at {}", range)?;
-                range.print(w, log)
-            }
-            Error::SingleAmpersand(range) => {
-                writeln!(w, "single ampersand at {}", range)?;
-                range.print(w, log)
-            }
-            Error::SingleDot(range) => {
-                writeln!(w, "single dot at {}", range)?;
-                range.print(w, log)
-            }
-            Error::ParseFloatFailure(range, err) => {
-                writeln!(w, "failed to parse number at {} ({})", range, err)?;
-                range.print(w, log)
-            }
-            Error::UnclosedBracketUntil(open, range) => {
-                writeln!(w, "unexpected token at {}", range)?;
-                range.print(w, log)?;
-                writeln!(w, "note: bracket opened at {}", open)?;
-                open.print(w, log)
-            }
-            Error::UnclosedBracketUntilEOF(open) => {
-                writeln!(w, "unexpected end of file")?;
-                writeln!(w, "note: bracket opened at {}", open)?;
-                open.print(w, log)
-            }
-            Error::EmptyArgumentName(equal) => {
-                writeln!(w, "empty argument name before `=` at {}", equal)?;
-                equal.print(w, log)
-            }
-            Error::InvalidArgumentName(range, equal) => {
-                writeln!(w, "invalid argument name at {}", range)?;
-                range.print(w, log)?;
-                writeln!(w, "before `=` at {}", equal)?;
-                equal.print(w, log)
-            }
-            Error::UndefinedVariable(name, range) => {
-                writeln!(w, "undefined variable {} at {}", name, range)?;
-                range.print(w, log)
-            }
-            Error::UndefinedFunction(name, range) => {
-                writeln!(w, "undefined function {} at {}", name, range)?;
-                range.print(w, log)
-            }
-            Error::EmptyOperandUnary(range) => {
-                writeln!(w, "empty operand of unary operator at {}", range)?;
-                range.print(w, log)
-            }
-            Error::EmptyOperandRight(range) => {
-                writeln!(w, "empty operand after binary operator at {}", range)?;
-                range.print(w, log)
-            }
-            Error::EmptyArgument(range) => {
-                writeln!(w, "empty argument before comma at {}", range)?;
-                range.print(w, log)
-            }
-            Error::EmptyNamedArgument(range) => {
-                writeln!(w, "empty argument after equal at {}", range)?;
-                range.print(w, log)
-            }
-            Error::EmptyParentheses(open, close) => {
-                writeln!(
-                    w,
-                    "empty expression between opening parenthesis at {}",
-                    open
-                )?;
-                open.print(w, log)?;
-                writeln!(w, "and closing parenthesis at {}", close)?;
-                close.print(w, log)
-            }
-            Error::EmptyExpressionReturn(range) => {
-                writeln!(w, "empty expression after `return` at {}", range)?;
-                range.print(w, log)
-            }
-            Error::TypeMismatchUnary(range, ty) => {
-                writeln!(w, "type mismatch at {} (found {})", range, ty)?;
-                range.print(w, log)
-            }
-            Error::TypeMismatchBinary(left, left_ty, right, right_ty) => {
-                writeln!(w, "type mismatch at {} (found {})", left, left_ty)?;
-                left.print(w, log)?;
-                writeln!(w, "and {} (found {})", right, right_ty)?;
-                right.print(w, log)
-            }
-            Error::TypeMismatchCond(cond, ty) => {
-                writeln!(w, "type mismatch at {} (found {})", cond, ty)?;
-                cond.print(w, log)
-            }
-            Error::TypeMismatchReturn(range, ty) => {
-                writeln!(w, "type mismatch after at {} (found {})", range, ty)?;
-                range.print(w, log)
-            }
-            Error::WrongNumberOfArguments(range, expected, found) => {
-                writeln!(
-                    w,
+            Error::UnexpectedCharacter(..) => "E0001",
+            Error::InvalidUtf8(..) => "E0002",
+            Error::NoCharacterAfterBackSlash(..) => "E0003",
+            Error::InvalidHexEscape(..) => "E0004",
+            Error::InvalidUnicodeEscape(..) => "E0005",
+            Error::UnterminatedComment(..) => "E0006",
+            Error::UnterminatedStringLiteral(..) => "E0007",
+            Error::IncompleteScientificNotation(..) => "E0008",
+            Error::IncompleteRadixLiteral(..) => "E0009",
+            Error::IntegerLiteralOutOfRange(..) => "E0010",
+            Error::SingleAmpersand(..) => "E0011",
+            Error::ParseFloatFailure(..) => "E0012",
+            Error::UnclosedBracketUntil(..) => "E0013",
+            Error::UnclosedBracketUntilEOF(..) => "E0014",
+            Error::EmptyArgumentName(..) => "E0015",
+            Error::InvalidArgumentName(..) => "E0016",
+            Error::InvalidFieldName(..) => "E0017",
+            Error::EmptyField(..) => "E0018",
+            Error::DuplicateField(..) => "E0019",
+            Error::UndefinedVariable(..) => "E0020",
+            Error::UndefinedFunction(..) => "E0021",
+            Error::EmptyOperandUnary(..) => "E0022",
+            Error::EmptyOperandRight(..) => "E0023",
+            Error::EmptyArgument(..) => "E0024",
+            Error::EmptyNamedArgument(..) => "E0025",
+            Error::EmptyParentheses(..) => "E0026",
+            Error::EmptyIndex(..) => "E0027",
+            Error::EmptyRHS(..) => "E0028",
+            Error::EmptyExpressionReturn(..) => "E0029",
+            Error::TypeMismatchUnary(..) => "E0030",
+            Error::TypeMismatchBinary(..) => "E0031",
+            Error::TypeMismatchCond(..) => "E0032",
+            Error::TypeMismatchReturn(..) => "E0033",
+            Error::WrongNumberOfArguments(..) => "E0034",
+            Error::UnusedNamedArguments(..) => "E0035",
+            Error::TypeMismatchArgument(..) => "E0036",
+            Error::TypeMismatchElement(..) => "E0037",
+            Error::TypeMismatchDestructuring(..) => "E0038",
+            Error::TypeMismatchIteration(..) => "E0039",
+            Error::LHSNotIdentifier(..) => "E0040",
+            Error::NoSemicolonAtEndOfStatement(..) => "E0041",
+            Error::UnexpectedToken(..) => "E0042",
+            Error::NoSubstitutionAfterLet(..) => "E0043",
+            Error::UnexpectedTokenAfterKeyword(..) => "E0044",
+            Error::UnexpectedEOFAfterKeyword(..) => "E0045",
+            Error::UnexpectedEOFAfterCondition(..) => "E0046",
+            Error::ElseWithoutIf(..) => "E0047",
+            Error::UnknownType(..) => "E0048",
+            Error::UntypedParameter(..) => "E0049",
+            Error::DuplicateParameter(..) => "E0050",
+            Error::InvalidParameter(..) => "E0051",
+            Error::VoidRHS(..) => "E0052",
+            Error::NestedSamples(..) => "E0053",
+            Error::DuplicateSample(..) => "E0054",
+            Error::SampleLoadFailure(..) => "E0055",
+            Error::UnknownCommand(..) => "E0056",
+            Error::UndefinedSnapshot(..) => "E0057",
+            Error::ExportFailure(..) => "E0058",
+            Error::RuntimeFailure(..) => "E0059",
+            Error::NumberLiteralOverflow(..) => "E0060",
+            Error::NumberLiteralUnderflow(..) => "E0061",
+            Error::EmbedFailure(..) => "E0062",
+        }
+    }
+    /// 説明の行と，それぞれが指す位置．最初の行がエラーそのもので，残りは補足
+    fn labels(&self) -> Vec<(String, Option<Span<'_>>)> {
+        match self {
+            Error::UnexpectedCharacter(pos) => vec![
+                (format!("unexpected character at {}", pos), Some(pos.into())),
+            ],
+            Error::InvalidUtf8(pos) => vec![
+                (format!("invalid UTF-8 at {}", pos), Some(pos.into())),
+            ],
+            Error::NoCharacterAfterBackSlash(pos) => vec![
+                (format!("no character after `\\` at {}", pos), Some(pos.into())),
+            ],
+            Error::InvalidHexEscape(range) => vec![(
+                format!("invalid escape at {} (`\\x` takes two hex digits up to 7F)", range),
+                Some(range.into()),
+            )],
+            Error::InvalidUnicodeEscape(range) => vec![(
+                format!(
+                    "invalid escape at {} (`\\u{{...}}` takes 1 to 6 hex digits of a Unicode scalar value)",
+                    range
+                ),
+                Some(range.into()),
+            )],
+            Error::UnterminatedComment(pos) => vec![
+                (format!("unterminated comment (started at {})", pos), Some(pos.into())),
+            ],
+            Error::UnterminatedStringLiteral(pos) => vec![
+                (format!("unterminated string literal (started at {})", pos), Some(pos.into())),
+            ],
+            Error::IncompleteScientificNotation(range) => vec![
+                (format!("incomplete scientific notation at {}", range), Some(range.into())),
+            ],
+            Error::IncompleteRadixLiteral(range) => vec![
+                (format!("no digits after the radix prefix at {}", range), Some(range.into())),
+            ],
+            Error::IntegerLiteralOutOfRange(range) => vec![(
+                format!("integer literal out of range at {} (at most {})", range, i64::MAX),
+                Some(range.into()),
+            )],
+            Error::NumberLiteralOverflow(range) => vec![(
+                format!("number literal too large at {} (at most {:e})", range, f64::MAX),
+                Some(range.into()),
+            )],
+            Error::NumberLiteralUnderflow(range) => vec![(
+                format!(
+                    "number literal too small at {} (rounds to 0; the smallest is {:e})",
+                    range,
+                    f64::from_bits(1)
+                ),
+                Some(range.into()),
+            )],
+            Error::SingleAmpersand(range) => vec![
+                (format!("single ampersand at {}", range), Some(range.into())),
+            ],
+            Error::ParseFloatFailure(range, err) => vec![
+                (format!("failed to parse number at {} ({})", range, err), Some(range.into())),
+            ],
+            Error::UnclosedBracketUntil(open, range) => vec![
+                (format!("unexpected token at {}", range), Some(range.into())),
+                (format!("note: bracket opened at {}", open), Some(open.into())),
+            ],
+            Error::UnclosedBracketUntilEOF(open) => vec![
+                ("unexpected end of file".to_string(), None),
+                (format!("note: bracket opened at {}", open), Some(open.into())),
+            ],
+            Error::EmptyArgumentName(equal) => vec![
+                (format!("empty argument name before `=` at {}", equal), Some(equal.into())),
+            ],
+            Error::InvalidArgumentName(range, equal) => vec![
+                (format!("invalid argument name at {}", range), Some(range.into())),
+                (format!("before `=` at {}", equal), Some(equal.into())),
+            ],
+            Error::InvalidFieldName(dot, range) => vec![
+                (format!("invalid field name at {}", range), Some(range.into())),
+                (format!("after `.` at {}", dot), Some(dot.into())),
+            ],
+            Error::EmptyField(colon) => vec![
+                (format!("empty expression after `:` at {}", colon), Some(colon.into())),
+            ],
+            Error::DuplicateField(name, range) => vec![
+                (format!("duplicate field {} at {}", name, range), Some(range.into())),
+            ],
+            Error::UndefinedVariable(name, range) => vec![
+                (format!("undefined variable {} at {}", name, range), Some(range.into())),
+            ],
+            Error::UndefinedFunction(name, range) => vec![
+                (format!("undefined function {} at {}", name, range), Some(range.into())),
+            ],
+            Error::EmptyOperandUnary(range) => vec![
+                (format!("empty operand of unary operator at {}", range), Some(range.into())),
+            ],
+            Error::EmptyOperandRight(range) => vec![
+                (format!("empty operand after binary operator at {}", range), Some(range.into())),
+            ],
+            Error::EmptyArgument(range) => vec![
+                (format!("empty argument before comma at {}", range), Some(range.into())),
+            ],
+            Error::EmptyNamedArgument(range) => vec![
+                (format!("empty argument after equal at {}", range), Some(range.into())),
+            ],
+            Error::EmptyParentheses(open, close) => vec![
+                (
+                    format!("empty expression between opening parenthesis at {}", open),
+                    Some(open.into()),
+                ),
+                (format!("and closing parenthesis at {}", close), Some(close.into())),
+            ],
+            Error::EmptyIndex(open, close) => vec![
+                (format!("empty index between opening bracket at {}", open), Some(open.into())),
+                (format!("and closing bracket at {}", close), Some(close.into())),
+            ],
+            Error::EmptyExpressionReturn(range) => vec![
+                (format!("empty expression after `return` at {}", range), Some(range.into())),
+            ],
+            Error::TypeMismatchUnary(range, ty) => vec![
+                (format!("type mismatch at {} (found {})", range, ty), Some(range.into())),
+            ],
+            Error::TypeMismatchBinary(left, left_ty, right, right_ty) => vec![
+                (format!("type mismatch at {} (found {})", left, left_ty), Some(left.into())),
+                (format!("and {} (found {})", right, right_ty), Some(right.into())),
+            ],
+            Error::TypeMismatchCond(cond, ty) => vec![
+                (format!("type mismatch at {} (found {})", cond, ty), Some(cond.into())),
+            ],
+            Error::TypeMismatchReturn(range, ty) => vec![
+                (format!("type mismatch after at {} (found {})", range, ty), Some(range.into())),
+            ],
+            Error::WrongNumberOfArguments(range, expected, found) => vec![(
+                format!(
                     "wrong number of arguments at {} (expected {}, found {})",
-                    range, expected, found
-                )?;
-                range.print(w, log)
-            }
-            Error::UnusedNamedArguments(range, names) => {
-                writeln!(
-                    w,
-                    "unused named arguments ({}) at {}",
-                    names.join(", "),
+                    range,
+                    expected,
+                    found
+                ),
+                Some(range.into()),
+            )],
+            Error::UnusedNamedArguments(range, names) => vec![(
+                format!("unused named arguments ({}) at {}", names.join(", "), range),
+                Some(range.into()),
+            )],
+            Error::TypeMismatchArgument(arg, ty) => vec![
+                (format!("type mismatch at {} (found {})", arg, ty), Some(arg.into())),
+            ],
+            Error::TypeMismatchDestructuring(range, ty) => vec![
+                (format!("cannot destructure {} at {}", ty, range), Some(range.into())),
+            ],
+            Error::TypeMismatchIteration(range, ty) => vec![
+                (format!("cannot iterate over {} at {}", ty, range), Some(range.into())),
+            ],
+            Error::TypeMismatchElement(range, ty) => vec![(
+                format!("type mismatch at {} (found {}, expected real)", range, ty),
+                Some(range.into()),
+            )],
+            Error::NoSemicolonAtEndOfStatement(range) => vec![
+                (format!("no semicolon at end of statement ({})", range), Some(range.into())),
+            ],
+            Error::UnexpectedToken(range) => vec![
+                (format!("unexpected token at {}", range), Some(range.into())),
+            ],
+            Error::LHSNotIdentifier(range, equal) => vec![
+                (format!("identifier required at {}", range), Some(range.into())),
+                (format!("before `=` at {}", equal), Some(equal.into())),
+            ],
+            Error::EmptyRHS(equal) => vec![
+                (format!("empty expression after `=` at {}", equal), Some(equal.into())),
+            ],
+            Error::NoSubstitutionAfterLet(r#let) => vec![
+                (format!("no substitution after `let` at {}", r#let), Some(r#let.into())),
+            ],
+            Error::UnexpectedTokenAfterKeyword(keyword, token) => vec![
+                (format!("unexpected token at {}", token), Some(token.into())),
+                (format!("after keyword at {}", keyword), Some(keyword.into())),
+            ],
+            Error::UnexpectedEOFAfterKeyword(keyword) => vec![
+                (
+                    format!("unexpected end of file after keyword at {}", keyword),
+                    Some(keyword.into()),
+                ),
+            ],
+            Error::UnexpectedEOFAfterCondition(keyword, condition) => vec![
+                (
+                    format!("unexpected end of file after keyword at {}", keyword),
+                    Some(keyword.into()),
+                ),
+                (format!("and condition at {}", condition), Some(condition.into())),
+            ],
+            Error::ElseWithoutIf(range) => vec![
+                (format!("`else` without a preceding `if` at {}", range), Some(range.into())),
+            ],
+            Error::VoidRHS(range) => vec![
+                (format!("void expression at rhs {}", range), Some(range.into())),
+            ],
+            Error::NestedSamples(range) => vec![
+                (format!("`samples` must be at top level ({})", range), Some(range.into())),
+            ],
+            Error::UnknownType(range) => vec![(
+                format!(
+                    "unknown type at {} (expected real, boolean, Sound, string, real[], Sound[] or record)",
                     range
-                )?;
-                range.print(w, log)
-            }
-            Error::TypeMismatchArgument(arg, ty) => {
-                writeln!(w, "type mismatch at {} (found {})", arg, ty)?;
-                arg.print(w, log)
-            }
-            Error::NoSemicolonAtEndOfStatement(range) => {
-                writeln!(w, "no semicolon at end of statement ({})", range)?;
-                range.print(w, log)
-            }
-            Error::UnexpectedToken(range) => {
-                writeln!(w, "unexpected token at {}", range)?;
-                range.print(w, log)
-            }
-            Error::LHSNotIdentifier(range, equal) => {
-                writeln!(w, "identifier required at {}", range)?;
-                range.print(w, log)?;
-                writeln!(w, "before `=` at {}", equal)?;
-                equal.print(w, log)
-            }
-            Error::EmptyRHS(equal) => {
-                writeln!(w, "empty expression after `=` at {}", equal)?;
-                equal.print(w, log)
-            }
-            Error::NoSubstitutionAfterLet(r#let) => {
-                writeln!(w, "no substitution after `let` at {}", r#let)?;
-                r#let.print(w, log)
-            }
-            Error::UnexpectedTokenAfterKeyword(keyword, token) => {
-                writeln!(w, "unexpected token at {}", token)?;
-                token.print(w, log)?;
-                writeln!(w, "after keyword at {}", keyword)?;
-                keyword.print(w, log)
-            }
-            Error::UnexpectedEOFAfterKeyword(keyword) => {
-                writeln!(w, "unexpected end of file after keyword at {}", keyword)?;
-                keyword.print(w, log)
-            }
-            Error::UnexpectedEOFAfterCondition(keyword, condition) => {
-                writeln!(w, "unexpected end of file after keyword at {}", keyword)?;
-                keyword.print(w, log)?;
-                writeln!(w, "and condition at {}", condition)?;
-                condition.print(w, log)
-            }
-            Error::VoidRHS(range) => {
-                writeln!(w, "void expression at rhs {}", range)?;
-                range.print(w, log)
+                ),
+                Some(range.into()),
+            )],
+            Error::UntypedParameter(name, range) => vec![(
+                format!(
+                    "parameter {} at {} needs a type (`{}: real`) or a default value",
+                    name,
+                    range,
+                    name
+                ),
+                Some(range.into()),
+            )],
+            Error::DuplicateParameter(name, range) => vec![
+                (format!("duplicate parameter {} at {}", name, range), Some(range.into())),
+            ],
+            Error::InvalidParameter(range) => vec![
+                (format!("invalid parameter at {} (expected a name)", range), Some(range.into())),
+            ],
+            Error::UnknownCommand(range) => vec![(
+                format!(
+                    "unknown command at {} (expected `:snapshot <name>`, `:restore <name>`, `:history` or `:export <path>`)",
+                    range
+                ),
+                Some(range.into()),
+            )],
+            Error::UndefinedSnapshot(name, range) => vec![
+                (format!("undefined snapshot {} at {}", name, range), Some(range.into())),
+            ],
+            Error::RuntimeFailure(range, message) => vec![
+                (format!("{} at {}", message, range), Some(range.into())),
+            ],
+            Error::ExportFailure(range, message) => vec![
+                (format!("{} at {}", message, range), Some(range.into())),
+            ],
+            Error::EmbedFailure(range, message) => vec![
+                (format!("{} at {}", message, range), Some(range.into())),
+            ],
+            Error::DuplicateSample(name, range) => vec![
+                (format!("duplicate sample `{}` at {}", name, range), Some(range.into())),
+            ],
+            Error::SampleLoadFailure(range, message) => vec![
+                (format!("{} (declared at {})", message, range), Some(range.into())),
+            ],
+        }
+    }
+    /// `error: ` に続けて説明の行と，該当する行に `^` を引いたものを書く
+    pub fn write_diagnostic<W: std::io::Write>(
+        &self,
+        w: &mut W,
+        log: &[String],
+    ) -> Result<(), std::io::Error> {
+        let color = COLOR.with(Cell::get);
+        if color {
+            write!(w, "\x1b[1;31merror\x1b[0m: ")?;
+        } else {
+            write!(w, "error: ")?;
+        }
+        for (message, span) in self.labels() {
+            writeln!(w, "{}", message)?;
+            if let Some(span) = span {
+                span.print(w, log, color)?;
             }
         }
+        Ok(())
+    }
+    /// 1 行の JSON ．位置は 1 から数える行と列（バイト）で， `end` はその手前まで
+    /// （1 文字を指すエラーでは `start` と同じ）
+    pub fn to_json(&self) -> String {
+        let labels = self
+            .labels()
+            .iter()
+            .map(|(message, span)| {
+                let span = match span {
+                    Some(span) => span.json(),
+                    None => "null".to_string(),
+                };
+                format!("{{\"message\":{},\"span\":{}}}", quote(message), span)
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"code\":{},\"message\":{},\"labels\":[{}]}}",
+            quote(self.code()),
+            quote(&self.to_string()),
+            labels.join(",")
+        )
+    }
+}
+
+/// 最初の説明の行（位置は `行:列` で含む）
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.labels()[0].0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// エラーが指す位置
+#[derive(Clone, Copy)]
+enum Span<'a> {
+    Pos(&'a pos::Pos),
+    Range(&'a pos::Range),
+}
+
+impl<'a> From<&'a pos::Pos> for Span<'a> {
+    fn from(pos: &'a pos::Pos) -> Span<'a> {
+        Span::Pos(pos)
+    }
+}
+
+impl<'a> From<&'a pos::Range> for Span<'a> {
+    fn from(range: &'a pos::Range) -> Span<'a> {
+        Span::Range(range)
+    }
+}
+
+impl Span<'_> {
+    fn print<W: std::io::Write>(
+        &self,
+        w: &mut W,
+        log: &[String],
+        color: bool,
+    ) -> Result<(), std::io::Error> {
+        match self {
+            Span::Pos(pos) => pos.print(w, log, color),
+            Span::Range(range) => range.print(w, log, color),
+        }
+    }
+    fn json(&self) -> String {
+        let pos = |pos: &pos::Pos| {
+            format!(
+                "{{\"line\":{},\"column\":{}}}",
+                pos.line() + 1,
+                pos.byte() + 1
+            )
+        };
+        let (start, end) = match self {
+            Span::Pos(p) => (pos(p), pos(p)),
+            Span::Range(range) => (pos(range.start()), pos(range.end())),
+        };
+        format!("{{\"start\":{},\"end\":{}}}", start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer, parser};
+
+    #[test]
+    fn diagnostics() {
+        let mut lexer = lexer::Lexer::new(Box::new(std::io::Cursor::new("x = (1;\n")), false);
+        let mut log = Vec::new();
+        let err = match parser::parse_statement(&mut lexer, &mut log) {
+            Err(err) => err,
+            Ok(_) => panic!("parsed an unclosed bracket"),
+        };
+        assert_eq!(err.code(), "E0013");
+        assert_eq!(err.to_string(), "unexpected token at 1:7-1:7");
+        let mut text = Vec::new();
+        err.write_diagnostic(&mut text, &log).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "error: unexpected token at 1:7-1:7\n  |\n1 | x = (1;\n  |       ^\n\
+             note: bracket opened at 1:5-1:5\n  |\n1 | x = (1;\n  |     ^\n"
+        );
+        assert_eq!(
+            err.to_json(),
+            "{\"code\":\"E0013\",\"message\":\"unexpected token at 1:7-1:7\",\"labels\":[\
+             {\"message\":\"unexpected token at 1:7-1:7\",\"span\":{\"start\":{\"line\":1,\"column\":7},\"end\":{\"line\":1,\"column\":8}}},\
+             {\"message\":\"note: bracket opened at 1:5-1:5\",\"span\":{\"start\":{\"line\":1,\"column\":5},\"end\":{\"line\":1,\"column\":6}}}]}"
+        );
     }
 }
@@ -0,0 +1,133 @@
+//! 書き出しなどの出来事の記録（ `--log-file` ）
+//!
+//! 1 行に 1 つの JSON オブジェクトで，どれも `time` （ UTC の日時）， `elapsed` （開始からの秒数），
+//! `event` （種類）を持つ．`--log-file` がなければ何もしない
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+
+struct Log {
+    file: std::io::BufWriter<std::fs::File>,
+    start: Instant,
+}
+
+/// 音声ファイルを読み込むスレッドからも記録する
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+
+/// 項目の値
+pub enum Field<'a> {
+    String(&'a str),
+    Number(f64),
+}
+
+/// `path` に記録し始める
+pub fn open(path: &str) -> Result<(), String> {
+    let file =
+        std::fs::File::create(path).map_err(|err| format!("cannot create {}: {}", path, err))?;
+    *LOG.lock().unwrap() = Some(Log {
+        file: std::io::BufWriter::new(file),
+        start: Instant::now(),
+    });
+    Ok(())
+}
+
+/// 種類 `event` の出来事を `fields` とともに記録する
+pub fn record(event: &str, fields: &[(&str, Field)]) {
+    if let Some(log) = &mut *LOG.lock().unwrap() {
+        let mut line = format!(
+            "{{\"time\":{},\"elapsed\":{},\"event\":{}",
+            string(&timestamp(SystemTime::now())),
+            number(log.start.elapsed().as_secs_f64()),
+            string(event)
+        );
+        for (key, value) in fields {
+            let value = match value {
+                Field::String(s) => string(s),
+                Field::Number(x) => number(*x),
+            };
+            line += &format!(",{}:{}", string(key), value);
+        }
+        line += "}";
+        // 記録に失敗しても描画は続ける
+        let _ = writeln!(log.file, "{}", line).and_then(|()| log.file.flush());
+    }
+}
+
+/// 警告を標準エラー出力に出し，記録する
+pub fn warning(message: &str) {
+    eprintln!("warning: {}", message);
+    record("warning", &[("message", Field::String(message))]);
+}
+
+fn string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            '\t' => ret += "\\t",
+            c if (c as u32) < 0x20 => ret += &format!("\\u{:04x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret + "\""
+}
+
+/// JSON には NaN や無限大がないので `null` にする
+fn number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// `2021-03-04T05:06:07.089Z` の形
+fn timestamp(time: SystemTime) -> String {
+    let since = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since.as_secs();
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    // 0000-03-01 から数えた日数を 400 年周期で年月日に直す
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60,
+        since.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        assert_eq!(string("a\"b\\\n"), "\"a\\\"b\\\\\\n\"");
+        assert_eq!(number(f64::NAN), "null");
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(951_782_400_123);
+        assert_eq!(timestamp(time), "2000-02-29T00:00:00.123Z");
+        assert_eq!(
+            timestamp(SystemTime::UNIX_EPOCH),
+            "1970-01-01T00:00:00.000Z"
+        );
+    }
+}
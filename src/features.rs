@@ -0,0 +1,27 @@
+//! このバイナリに含まれる機能（ `cryss features` ， `has_feature("flac")` ）
+//!
+//! 切り替えられるものは cargo の feature で，WAV の読み書きと `play` はいつも含まれる
+
+/// 機能の名前と，含まれているか
+pub const FEATURES: &[(&str, bool)] = &[
+    ("wav", true),
+    ("playback", true),
+    ("aiff", cfg!(feature = "aiff")),
+    ("flac", cfg!(feature = "flac")),
+    ("record", cfg!(feature = "record")),
+];
+
+/// 機能 `name` が含まれているか．知らない名前は含まれていないとみなす
+pub fn has(name: &str) -> bool {
+    FEATURES
+        .iter()
+        .any(|&(feature, enabled)| feature == name && enabled)
+}
+
+/// `cryss features` の出力．一行に一つ，含まれていないものには `-` をつける
+pub fn report() -> String {
+    FEATURES
+        .iter()
+        .map(|&(name, enabled)| format!("{}{}\n", if enabled { "+" } else { "-" }, name))
+        .collect()
+}
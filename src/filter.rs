@@ -0,0 +1,269 @@
+//! フィルタ（双二次フィルタ）と，それを組み合わせた効果
+
+use crate::dynamics::Dynamics;
+use crate::sound::Sound;
+use std::f64::consts::PI;
+
+/// 双二次フィルタ（直接形 I ）
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// 係数 `a` は `a0` で割ったもので， `a0` は含めない
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+        Biquad {
+            b,
+            a,
+            x: [0.; 2],
+            y: [0.; 2],
+        }
+    }
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+    /// 状態を保ったまま係数を `other` のものにする（周波数を変調するフィルタ）
+    pub fn retune(&mut self, other: &Biquad) {
+        self.b = other.b;
+        self.a = other.a;
+    }
+}
+
+/// 音にかけるフィルタの種類．周波数は Hz
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Lowpass {
+        frequency: f64,
+        q: f64,
+    },
+    Highpass {
+        frequency: f64,
+        q: f64,
+    },
+    /// 帯域通過（ピークの利得が 1 ）
+    Bandpass {
+        frequency: f64,
+        q: f64,
+    },
+    Allpass {
+        frequency: f64,
+        q: f64,
+    },
+}
+
+/// 周波数を変調するフィルタ（ `Sound::ModulatedFilter` ）の余韻を見積もる周波数．可聴域の下限
+pub const LOWEST_FREQUENCY: f64 = 20.;
+
+impl Filter {
+    /// 周波数だけを `frequency` にしたもの
+    pub fn with_frequency(self, frequency: f64) -> Filter {
+        match self {
+            Filter::Lowpass { q, .. } => Filter::Lowpass { frequency, q },
+            Filter::Highpass { q, .. } => Filter::Highpass { frequency, q },
+            Filter::Bandpass { q, .. } => Filter::Bandpass { frequency, q },
+            Filter::Allpass { q, .. } => Filter::Allpass { frequency, q },
+        }
+    }
+    /// 標本化周波数 `samplerate` での係数（ RBJ の Audio EQ Cookbook ）
+    pub fn biquad(&self, samplerate: f64) -> Biquad {
+        let (frequency, q) = match *self {
+            Filter::Lowpass { frequency, q }
+            | Filter::Highpass { frequency, q }
+            | Filter::Bandpass { frequency, q }
+            | Filter::Allpass { frequency, q } => (frequency, q),
+        };
+        let w0 = 2. * PI * frequency.clamp(0., samplerate / 2.) / samplerate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2. * q);
+        let b = match self {
+            Filter::Lowpass { .. } => [(1. - cos) / 2., 1. - cos, (1. - cos) / 2.],
+            Filter::Highpass { .. } => [(1. + cos) / 2., -1. - cos, (1. + cos) / 2.],
+            Filter::Bandpass { .. } => [alpha, 0., -alpha],
+            Filter::Allpass { .. } => [1. - alpha, -2. * cos, 1. + alpha],
+        };
+        let a0 = 1. + alpha;
+        Biquad::new(
+            [b[0] / a0, b[1] / a0, b[2] / a0],
+            [-2. * cos / a0, (1. - alpha) / a0],
+        )
+    }
+}
+
+impl Filter {
+    /// インパルス応答が -60 dB まで減衰する時間（秒）のおおよその値
+    pub fn ring_time(&self) -> f64 {
+        let (frequency, q) = match *self {
+            Filter::Lowpass { frequency, q }
+            | Filter::Highpass { frequency, q }
+            | Filter::Bandpass { frequency, q }
+            | Filter::Allpass { frequency, q } => (frequency, q),
+        };
+        // 極の減衰の時定数は 2Q / ω0
+        1000f64.ln() * q / (PI * frequency)
+    }
+}
+
+/// `sound` に `filters` を順にかける
+fn chain(sound: Sound, filters: impl IntoIterator<Item = Filter>) -> Sound {
+    filters
+        .into_iter()
+        .fold(sound, |sound, filter| Sound::Filter {
+            sound: sound.into(),
+            filter,
+        })
+}
+
+/// `crossovers` （ Hz ，昇順）で帯域を分ける．Linkwitz–Riley （ 4 次）の分割で，
+/// 低い帯域には高いクロスオーバーと同じ位相の全域通過をかけるので，足し合わせると平坦になる
+pub fn split_bands(sound: Sound, crossovers: &[f64]) -> Result<Vec<Sound>, String> {
+    if crossovers
+        .iter()
+        .any(|frequency| frequency.is_nan() || *frequency <= 0.)
+        || crossovers.windows(2).any(|pair| pair[0] >= pair[1])
+    {
+        return Err(format!(
+            "crossover frequencies must be positive and ascending (got {:?})",
+            crossovers
+        ));
+    }
+    // Butterworth の 2 次を 2 段重ねると Linkwitz–Riley の 4 次になる
+    let q = std::f64::consts::FRAC_1_SQRT_2;
+    let butterworth = |filter: fn(f64, f64) -> Filter, frequency| {
+        let filter = filter(frequency, q);
+        vec![filter, filter]
+    };
+    let lowpass = |frequency, q| Filter::Lowpass { frequency, q };
+    let highpass = |frequency, q| Filter::Highpass { frequency, q };
+    Ok((0..=crossovers.len())
+        .map(|i| {
+            let mut filters: Vec<Filter> = crossovers[..i]
+                .iter()
+                .flat_map(|&frequency| butterworth(highpass, frequency))
+                .collect();
+            if let Some(&frequency) = crossovers.get(i) {
+                filters.extend(butterworth(lowpass, frequency));
+            }
+            filters.extend(
+                crossovers
+                    .iter()
+                    .skip(i + 1)
+                    .map(|&frequency| Filter::Allpass { frequency, q }),
+            );
+            chain(sound.clone(), filters)
+        })
+        .collect())
+}
+
+/// ボコーダーの帯域の下端と上端（ Hz ）
+const VOCODER_LOW: f64 = 100.;
+const VOCODER_HIGH: f64 = 8000.;
+/// ボコーダーの包絡線の追従の時間（秒）
+const VOCODER_ATTACK: f64 = 0.005;
+const VOCODER_RELEASE: f64 = 0.02;
+
+/// チャンネルボコーダー．帯域を対数で `bands` 個に等分し，帯域ごとに
+/// `carrier` の成分へ `modulator` の同じ帯域の包絡線をかけて足す
+pub fn vocode(carrier: Sound, modulator: Sound, bands: usize) -> Sound {
+    let ratio = (VOCODER_HIGH / VOCODER_LOW).powf(1. / bands as f64);
+    // 隣の帯域と -3 dB で接する Q
+    let q = ratio.sqrt() / (ratio - 1.);
+    (0..bands)
+        .map(|i| {
+            let filter = Filter::Bandpass {
+                frequency: VOCODER_LOW * ratio.powf(i as f64 + 0.5),
+                q,
+            };
+            let band = |sound: &Sound| Sound::Filter {
+                sound: sound.clone().into(),
+                filter,
+            };
+            let envelope = Sound::Dynamics {
+                sound: band(&modulator).into(),
+                sidechain: band(&modulator).into(),
+                dynamics: Dynamics::Follow {
+                    attack: VOCODER_ATTACK,
+                    release: VOCODER_RELEASE,
+                },
+            };
+            Sound::Mul(band(&carrier).into(), envelope.into())
+        })
+        .fold(Sound::Const(0.), |sum, band| {
+            Sound::Add(sum.into(), band.into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis;
+    use num::complex::Complex64;
+
+    fn sin(frequency: f64) -> Sound {
+        Sound::Sin {
+            frequency,
+            phase: 0.,
+        }
+    }
+
+    #[test]
+    fn bandpass() {
+        let filtered = |frequency| {
+            let sound = Sound::Filter {
+                sound: sin(frequency).into(),
+                filter: Filter::Bandpass {
+                    frequency: 1000.,
+                    q: 2.,
+                },
+            };
+            analysis::peak(&sound.render(1., 48000.)[24000..])
+        };
+        assert!((filtered(1000.) - 1.).abs() < 1e-3);
+        assert!(filtered(100.) < 0.1);
+        assert!(filtered(10000.) < 0.1);
+    }
+
+    #[test]
+    fn bands_sum_to_allpass() {
+        let samplerate = 48000.;
+        let impulse = Sound::Samples {
+            samples: vec![1.].into(),
+            samplerate,
+            offset: 0.,
+            looping: None,
+        };
+        let bands = split_bands(impulse, &[200., 2000.]).unwrap();
+        assert_eq!(bands.len(), 3);
+        // インパルス応答の和の振幅特性が平坦
+        let mut sum = vec![Complex64::new(0., 0.); 8192];
+        for band in bands {
+            for (sum, x) in sum
+                .iter_mut()
+                .zip(band.render(8192. / samplerate, samplerate))
+            {
+                *sum += x;
+            }
+        }
+        analysis::fft(&mut sum);
+        assert!(sum[..4096].iter().all(|x| (x.norm() - 1.).abs() < 1e-6));
+        assert!(split_bands(Sound::Const(0.), &[2000., 200.]).is_err());
+    }
+
+    #[test]
+    fn vocoder_follows_modulator() {
+        let samplerate = 48000.;
+        let carrier = Sound::Rand;
+        let silent = vocode(carrier.clone(), Sound::Const(0.), 8);
+        assert_eq!(analysis::peak(&silent.render(0.1, samplerate)), 0.);
+        let voiced = vocode(carrier, sin(440.), 8);
+        assert!(analysis::rms(&voiced.render(0.5, samplerate)[4800..]) > 0.01);
+    }
+}
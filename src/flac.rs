@@ -0,0 +1,385 @@
+//! FLAC の復号（ `flac` フィーチャー）
+//!
+//! 固定予測と線形予測のサブフレーム，チャンネル間の相関除去に対応する．CRC は確かめない
+
+use crate::samples::Decoded;
+
+/// ビッグエンディアンのビット列を先頭から読む
+struct Bits<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn new(bytes: &'a [u8]) -> Bits<'a> {
+        Bits { bytes, position: 0 }
+    }
+    fn bit(&mut self) -> Result<u32, String> {
+        let byte = self
+            .bytes
+            .get(self.position / 8)
+            .ok_or_else(|| "unexpected end of stream".to_string())?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Ok(u32::from(bit))
+    }
+    /// `n` ビット（ 64 以下）の符号なし整数
+    fn unsigned(&mut self, n: u32) -> Result<u64, String> {
+        (0..n).try_fold(0, |x, _| Ok(x << 1 | u64::from(self.bit()?)))
+    }
+    /// `n` ビットの 2 の補数
+    fn signed(&mut self, n: u32) -> Result<i64, String> {
+        let x = self.unsigned(n)? as i64;
+        Ok(if n > 0 && x >> (n - 1) & 1 == 1 {
+            x - (1 << n)
+        } else {
+            x
+        })
+    }
+    /// 1 が現れるまでの 0 の数
+    fn unary(&mut self) -> Result<u64, String> {
+        let mut n = 0;
+        while self.bit()? == 0 {
+            n += 1;
+        }
+        Ok(n)
+    }
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len() * 8
+    }
+}
+
+/// ストリームの情報（ `STREAMINFO` ）
+struct Info {
+    samplerate: u64,
+    channels: usize,
+    bits: u32,
+    /// 全体の標本数（チャンネルあたり）．0 ならば不明
+    frames: u64,
+}
+
+/// FLAC ファイルの中身を復号する．2 チャンネル以上なら先頭の 2 チャンネル
+pub fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    let mut bits = Bits::new(bytes.strip_prefix(b"fLaC").ok_or("not a FLAC file")?);
+    let mut info = None;
+    loop {
+        let last = bits.bit()? == 1;
+        let kind = bits.unsigned(7)?;
+        let length = bits.unsigned(24)? as usize;
+        if kind == 0 {
+            bits.unsigned(16 + 16 + 24 + 24)?;
+            info = Some(Info {
+                samplerate: bits.unsigned(20)?,
+                channels: bits.unsigned(3)? as usize + 1,
+                bits: bits.unsigned(5)? as u32 + 1,
+                frames: bits.unsigned(36)?,
+            });
+            bits.unsigned(64)?;
+            bits.unsigned(64)?;
+        } else {
+            bits.position += length * 8;
+        }
+        if last {
+            break;
+        }
+    }
+    let info = info.ok_or_else(|| "no STREAMINFO block".to_string())?;
+    let mut channels = vec![Vec::new(); info.channels];
+    while !bits.is_empty() {
+        for (channel, samples) in channels.iter_mut().zip(frame(&mut bits, &info)?) {
+            channel.extend(samples);
+        }
+    }
+    let scale = 2f64.powi(info.bits as i32 - 1);
+    let frames = match info.frames {
+        0 => usize::MAX,
+        frames => frames as usize,
+    };
+    Ok(Decoded {
+        channels: channels
+            .into_iter()
+            .take(2)
+            .map(|samples| {
+                samples
+                    .into_iter()
+                    .take(frames)
+                    .map(|x| x as f64 / scale)
+                    .collect()
+            })
+            .collect(),
+        samplerate: info.samplerate as f64,
+        loop_points: None,
+    })
+}
+
+/// 1 フレームを復号して，チャンネルごとの標本を返す
+fn frame(bits: &mut Bits, info: &Info) -> Result<Vec<Vec<i64>>, String> {
+    if bits.unsigned(14)? != 0b11_1111_1111_1110 {
+        return Err("lost frame sync".to_string());
+    }
+    bits.unsigned(2)?;
+    let block_size = bits.unsigned(4)?;
+    let samplerate = bits.unsigned(4)?;
+    let assignment = bits.unsigned(4)?;
+    let sample_size = bits.unsigned(3)?;
+    bits.unsigned(1)?;
+    // UTF-8 と同じ形で符号化したフレーム番号
+    let first = bits.unsigned(8)?;
+    bits.unsigned(8 * (first as u8).leading_ones().saturating_sub(1))?;
+    let block_size = match block_size {
+        1 => 192,
+        2..=5 => 576 << (block_size - 2),
+        6 => bits.unsigned(8)? + 1,
+        7 => bits.unsigned(16)? + 1,
+        8..=15 => 256 << (block_size - 8),
+        _ => return Err("invalid block size".to_string()),
+    } as usize;
+    match samplerate {
+        12 => {
+            bits.unsigned(8)?;
+        }
+        13 | 14 => {
+            bits.unsigned(16)?;
+        }
+        15 => return Err("invalid sample rate".to_string()),
+        _ => {}
+    }
+    let sample_size = match sample_size {
+        0 => info.bits,
+        1 => 8,
+        2 => 12,
+        4 => 16,
+        5 => 20,
+        6 => 24,
+        7 => 32,
+        _ => return Err("invalid sample size".to_string()),
+    };
+    // CRC-8
+    bits.unsigned(8)?;
+    let (channels, side) = match assignment {
+        0..=7 => (assignment as usize + 1, None),
+        8..=10 => (2, Some(assignment)),
+        _ => return Err("invalid channel assignment".to_string()),
+    };
+    let mut subframes = (0..channels)
+        .map(|channel| {
+            // 差分のチャンネルは 1 ビット多い
+            let extra = match (side, channel) {
+                (Some(8), 1) | (Some(9), 0) | (Some(10), 1) => 1,
+                _ => 0,
+            };
+            subframe(bits, sample_size + extra, block_size)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    bits.align();
+    // CRC-16
+    bits.unsigned(16)?;
+    if let Some(side) = side {
+        let (left, right) = subframes.split_at_mut(1);
+        for (a, b) in left[0].iter_mut().zip(right[0].iter_mut()) {
+            let (l, r) = match side {
+                8 => (*a, *a - *b),
+                9 => (*a + *b, *b),
+                _ => {
+                    let mid = *a << 1 | (*b & 1);
+                    ((mid + *b) >> 1, (mid - *b) >> 1)
+                }
+            };
+            *a = l;
+            *b = r;
+        }
+    }
+    Ok(subframes)
+}
+
+fn subframe(bits: &mut Bits, sample_size: u32, block_size: usize) -> Result<Vec<i64>, String> {
+    bits.unsigned(1)?;
+    let kind = bits.unsigned(6)?;
+    let wasted = if bits.bit()? == 1 {
+        bits.unary()? as u32 + 1
+    } else {
+        0
+    };
+    let sample_size = sample_size - wasted;
+    let mut samples = match kind {
+        0 => vec![bits.signed(sample_size)?; block_size],
+        1 => (0..block_size)
+            .map(|_| bits.signed(sample_size))
+            .collect::<Result<_, _>>()?,
+        8..=12 => {
+            let coefficients: &[i64] = match kind - 8 {
+                0 => &[],
+                1 => &[1],
+                2 => &[2, -1],
+                3 => &[3, -3, 1],
+                _ => &[4, -6, 4, -1],
+            };
+            predict(bits, sample_size, block_size, coefficients)?
+        }
+        32..=63 => {
+            let order = kind as usize - 31;
+            let mut warmup = (0..order)
+                .map(|_| bits.signed(sample_size))
+                .collect::<Result<Vec<_>, _>>()?;
+            let precision = bits.unsigned(4)? as u32 + 1;
+            let shift = bits.signed(5)?.max(0) as u32;
+            let coefficients = (0..order)
+                .map(|_| bits.signed(precision))
+                .collect::<Result<Vec<_>, _>>()?;
+            let residual = residual(bits, block_size, order)?;
+            warmup.reserve(block_size);
+            restore(&mut warmup, &residual, &coefficients, shift);
+            warmup
+        }
+        _ => return Err(format!("invalid subframe type {}", kind)),
+    };
+    if wasted > 0 {
+        for x in &mut samples {
+            *x <<= wasted;
+        }
+    }
+    Ok(samples)
+}
+
+/// 固定予測のサブフレーム
+fn predict(
+    bits: &mut Bits,
+    sample_size: u32,
+    block_size: usize,
+    coefficients: &[i64],
+) -> Result<Vec<i64>, String> {
+    let mut samples = (0..coefficients.len())
+        .map(|_| bits.signed(sample_size))
+        .collect::<Result<Vec<_>, _>>()?;
+    let residual = residual(bits, block_size, coefficients.len())?;
+    samples.reserve(block_size);
+    restore(&mut samples, &residual, coefficients, 0);
+    Ok(samples)
+}
+
+/// 予測に残差を足して標本を復元する．`coefficients[j]` は `j + 1` 個前の標本に掛ける
+fn restore(samples: &mut Vec<i64>, residual: &[i64], coefficients: &[i64], shift: u32) {
+    for r in residual {
+        let n = samples.len();
+        let prediction: i64 = coefficients
+            .iter()
+            .enumerate()
+            .map(|(j, c)| c * samples[n - 1 - j])
+            .sum();
+        samples.push((prediction >> shift) + r);
+    }
+}
+
+/// ライス符号の残差
+fn residual(bits: &mut Bits, block_size: usize, order: usize) -> Result<Vec<i64>, String> {
+    let parameter_bits = match bits.unsigned(2)? {
+        0 => 4,
+        1 => 5,
+        _ => return Err("invalid residual coding method".to_string()),
+    };
+    let partition_order = bits.unsigned(4)?;
+    let partitions = 1 << partition_order;
+    let mut residual = Vec::with_capacity(block_size);
+    for i in 0..partitions {
+        let count = (block_size >> partition_order)
+            .checked_sub(if i == 0 { order } else { 0 })
+            .ok_or_else(|| "invalid partition order".to_string())?;
+        let parameter = bits.unsigned(parameter_bits)? as u32;
+        if parameter == (1 << parameter_bits) - 1 {
+            // エスケープ：符号化せずに並べる
+            let size = bits.unsigned(5)? as u32;
+            for _ in 0..count {
+                residual.push(bits.signed(size)?);
+            }
+        } else {
+            for _ in 0..count {
+                let x = bits.unary()? << parameter | bits.unsigned(parameter)?;
+                residual.push((x >> 1) as i64 ^ -((x & 1) as i64));
+            }
+        }
+    }
+    Ok(residual)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ビット列を組み立てる
+    #[derive(Default)]
+    struct Writer {
+        bits: Vec<bool>,
+    }
+
+    impl Writer {
+        fn put(&mut self, n: u32, x: i64) {
+            for i in (0..n).rev() {
+                self.bits.push(x >> i & 1 == 1);
+            }
+        }
+        fn bytes(mut self) -> Vec<u8> {
+            while !self.bits.len().is_multiple_of(8) {
+                self.bits.push(false);
+            }
+            self.bits
+                .chunks(8)
+                .map(|byte| byte.iter().fold(0, |x, bit| x << 1 | *bit as u8))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn decode_stream() {
+        let mut w = Writer::default();
+        // STREAMINFO ： 8 kHz ， 2 チャンネル， 16 ビット， 4 標本
+        w.put(1, 1);
+        w.put(7, 0);
+        w.put(24, 34);
+        w.put(16 + 16, 0);
+        w.put(24 + 24, 0);
+        w.put(20, 8000);
+        w.put(3, 1);
+        w.put(5, 15);
+        w.put(36, 4);
+        w.put(64, 0);
+        w.put(64, 0);
+        // フレーム：左と差分，ブロックは 4 標本（ 8 ビットで指定）
+        w.put(14, 0b11_1111_1111_1110);
+        w.put(2, 0);
+        w.put(4, 6);
+        w.put(4, 0);
+        w.put(4, 8);
+        w.put(3, 0);
+        w.put(1, 0);
+        w.put(8, 0);
+        w.put(8, 3);
+        w.put(8, 0);
+        // 左：固定予測 2 次，最初の 2 標本は 100 と 200 ，残差は 0 と 1 （パラメータ 1 ）
+        w.put(1, 0);
+        w.put(6, 10);
+        w.put(1, 0);
+        w.put(16, 100);
+        w.put(16, 200);
+        w.put(2, 0);
+        w.put(4, 0);
+        w.put(4, 1);
+        w.put(2, 0b10);
+        w.put(2, 0b11);
+        // 差分：定数 -8 （ 17 ビット）
+        w.put(1, 0);
+        w.put(6, 0);
+        w.put(1, 0);
+        w.put(17, -8);
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend(w.bytes());
+        bytes.extend(&[0, 0]);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.samplerate, 8000.);
+        let scale = |xs: &[f64]| xs.iter().map(|x| x * 32768.).collect::<Vec<_>>();
+        assert_eq!(scale(&decoded.channels[0]), vec![100., 200., 300., 399.]);
+        assert_eq!(scale(&decoded.channels[1]), vec![108., 208., 308., 407.]);
+    }
+}
@@ -0,0 +1,129 @@
+//! `cryss fmt` ：行末の空白を除いて整える．`--annotate` では台本を実行し，
+//! トップレベルで代入した変数の値を行末に `//= 名前 = 値` の形で書き添える
+//!
+//! 書き添えた注釈は次に整えるときに除くので，何度かけても同じ結果になる
+
+use crate::environment::Environment;
+use crate::lexer::Lexer;
+use crate::parser;
+use crate::syntax::{Pattern, Statement};
+use crate::value::Value;
+use std::collections::BTreeMap;
+
+/// 注釈の始まり
+const MARKER: &str = "  //= ";
+
+/// `source` を整えた台本．`environment` があればそこで実行して注釈をつける
+pub fn format(source: &str, environment: Option<&mut Environment>) -> Result<String, String> {
+    let mut annotations: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut log = Vec::new();
+    if let Some(environment) = environment {
+        let mut lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_string())), false);
+        loop {
+            let statement = match parser::parse_statement(&mut lexer, &mut log) {
+                Ok(Some(statement)) => statement,
+                Ok(None) => break,
+                Err(err) => return Err(message(err, &log)),
+            };
+            let names = assigned(&statement);
+            environment
+                .run(statement)
+                .map_err(|err| message(err, &log))?;
+            let values = names.into_iter().filter_map(|name| {
+                let value = describe(environment.variable(&name)?)?;
+                Some(format!("{} = {}", name, value))
+            });
+            annotations
+                .entry(lexer.end().line())
+                .or_default()
+                .extend(values);
+        }
+    }
+    Ok(source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = match line.find(MARKER) {
+                Some(index) => &line[..index],
+                None => line,
+            }
+            .trim_end();
+            match annotations.get(&i) {
+                Some(values) if !values.is_empty() => {
+                    format!("{}{}{}\n", line, MARKER, values.join(", "))
+                }
+                _ => format!("{}\n", line),
+            }
+        })
+        .collect())
+}
+
+fn message(err: crate::error::Error, log: &[String]) -> String {
+    let mut message = Vec::new();
+    err.write_diagnostic(&mut message, log)
+        .expect("cannot print error message");
+    String::from_utf8_lossy(&message).into_owned()
+}
+
+/// トップレベルの文で値が決まる変数（ブロックの中は数えない）
+fn assigned(statement: &Statement) -> Vec<String> {
+    match statement {
+        Statement::Declaration(_, name, ..) | Statement::Substitution(_, name, _) => {
+            vec![name.clone()]
+        }
+        Statement::Destructuring(_, Pattern::Array(names) | Pattern::Record(names), _) => {
+            names.iter().map(|(_, name)| name.clone()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 注釈に書く値．音や関数は書かない
+fn describe(value: &Value) -> Option<String> {
+    let list = |items: Vec<String>| items.join(", ");
+    Some(match value {
+        Value::Real(rc) => rc.get().to_string(),
+        Value::Int(rc) => rc.get().to_string(),
+        Value::Boolean(rc) => rc.get().to_string(),
+        Value::String(rc) => format!("{:?}", rc.borrow()),
+        Value::Array(rc) => format!(
+            "[{}]",
+            list(rc.borrow().iter().map(f64::to_string).collect())
+        ),
+        Value::Record(rc) => format!(
+            "{{{}}}",
+            list(
+                rc.borrow()
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect()
+            )
+        ),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotates_constants() {
+        let source = "let a = 440 * 2;   \nlet s = Sin(a);\nlet [b, c] = [1, 2]; a = a + b;\nif (true) { let d = 1; }\n";
+        let annotated = format(source, Some(&mut Environment::new())).unwrap();
+        assert_eq!(
+            annotated,
+            "let a = 440 * 2;  //= a = 880\nlet s = Sin(a);\n\
+             let [b, c] = [1, 2]; a = a + b;  //= b = 1, c = 2, a = 881\nif (true) { let d = 1; }\n"
+        );
+        // 注釈を付け直しても増えず，注釈なしで整えれば消える
+        assert_eq!(
+            format(&annotated, Some(&mut Environment::new())).unwrap(),
+            annotated
+        );
+        assert_eq!(
+            format(&annotated, None).unwrap(),
+            "let a = 440 * 2;\nlet s = Sin(a);\nlet [b, c] = [1, 2]; a = a + b;\nif (true) { let d = 1; }\n"
+        );
+    }
+}
@@ -1,12 +1,78 @@
-use crate::program::{Argument, RealExpression};
-use crate::sound::Sound;
+use crate::buffer;
+use crate::bundle;
+use crate::chapters;
+use crate::chord;
+use crate::coercion;
+use crate::dither::Dither;
+use crate::dump;
+use crate::dynamics::{Compressor, Dynamics, Gate};
+use crate::effects::Effect;
+use crate::envelope::Adsr;
+use crate::error::RuntimeFailure;
+use crate::events::{self, Field};
+use crate::features;
+use crate::filter::{self, Filter};
+use crate::graph;
+#[cfg(feature = "record")]
+use crate::input::AudioInput;
+use crate::limits;
+use crate::master::{self, Master};
+use crate::meter;
+use crate::multisample;
+use crate::normalize::{self, Normalizer};
+use crate::oscillator::{self, Waveform};
+use crate::output::{AudioBackend, Encoding, Output, Sink};
+use crate::paths;
+use crate::pos;
+use crate::preset;
+use crate::probe::Probes;
+use crate::program::{
+    self, Argument, ArrayExpression, BooleanExpression, Evaluatable, Exit, IntExpression,
+    RealExpression, RecordExpression, SoundArrayExpression, SoundExpression, Statement,
+    StringExpression, VoidExpression,
+};
+use crate::progress::{self, RenderProgress};
+use crate::resynth;
+use crate::samples;
+use crate::sound::{self, Sound, SoundIter, Steps};
+use crate::spatial;
+use crate::tail::{self, Tail};
+use crate::timeline::Timeline;
+use crate::types::{Signature, Type};
 use crate::value::Value;
+use crate::wav;
 
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::f64::consts::TAU;
 use std::rc::Rc;
+use std::time::Instant;
 type RcCell<T> = Rc<Cell<T>>;
 type RcRefCell<T> = Rc<RefCell<T>>;
 
+/// `unison` の声部の数の上限
+const MAX_VOICES: f64 = 64.;
+
+/// ユーザ定義関数の呼び出しの深さの既定の上限（ `--recursion-limit` ）
+pub const DEFAULT_RECURSION_LIMIT: usize = 200;
+
+/// 上限を超えたときに見せる呼び出しの連なりの先頭と末尾の数
+const SHOWN_CALLS: usize = 3;
+
+thread_local! {
+    static RECURSION_LIMIT: Cell<usize> = const { Cell::new(DEFAULT_RECURSION_LIMIT) };
+    /// 実行中のユーザ定義関数の名前（外側から順に）
+    static CALLS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_recursion_limit(limit: usize) {
+    RECURSION_LIMIT.with(|cell| cell.set(limit));
+}
+
+/// 標本化周波数が指定されていないときの値
+pub const DEFAULT_SAMPLERATE: f64 = 44100.;
+
+#[derive(Clone)]
 pub struct Function {
     pub body: Body,
     pub arguments: Vec<Value>,
@@ -14,6 +80,169 @@ pub struct Function {
 }
 
 impl Function {
+    /// 位置引数の型と戻り値の型
+    pub fn signature(&self) -> Signature {
+        Signature {
+            parameters: self.arguments.iter().map(Value::ty).collect(),
+            ret: match self.body {
+                Body::Real(_) => Type::Real,
+                Body::Int(_) => Type::Int,
+                Body::Array(_) => Type::Array,
+                Body::Record(_) => Type::Record,
+                Body::SoundArray(_) => Type::SoundArray,
+                Body::Boolean(_) => Type::Boolean,
+                Body::Sound(_) => Type::Sound,
+                Body::String(_) => Type::String,
+                Body::Void(_) => Type::Void,
+            },
+        }
+    }
+    /// 関数の値として呼ぶ前に，位置引数に `arguments` を書き込み，名前つき引数を既定値にする
+    pub fn bind(&self, arguments: &[Value]) {
+        for (parameter, argument) in self.arguments.iter().zip(arguments) {
+            parameter.assign(argument);
+        }
+        program::bind(
+            self.named_arguments
+                .iter()
+                .map(|(_, argument)| argument.clone())
+                .collect(),
+        );
+    }
+    /// 関数の値の入った変数 `function` の呼び出し `f(x)` ．
+    /// 型はコンパイルするときに入っている関数で決まり，呼ぶのは実行するときに入っている関数
+    pub fn dynamic(function: RcRefCell<Rc<Function>>) -> Function {
+        let current = function.borrow().clone();
+        let arguments: Vec<_> = current.arguments.iter().map(Value::deep_copy).collect();
+        let dynamic = Rc::new(Dynamic {
+            function,
+            arguments: arguments.clone(),
+        });
+        let body = match current.body {
+            Body::Real(_) => Body::Real(Rc::new(RealFunction::Dynamic(dynamic))),
+            Body::Int(_) => Body::Int(Rc::new(IntFunction::Dynamic(dynamic))),
+            Body::Array(_) => Body::Array(Rc::new(ArrayFunction::Dynamic(dynamic))),
+            Body::Record(_) => Body::Record(Rc::new(RecordFunction::Dynamic(dynamic))),
+            Body::SoundArray(_) => Body::SoundArray(Rc::new(SoundArrayFunction::Dynamic(dynamic))),
+            Body::Boolean(_) => Body::Boolean(Rc::new(BooleanFunction::Dynamic(dynamic))),
+            Body::Sound(_) => Body::Sound(Rc::new(SoundFunction::Dynamic(dynamic))),
+            Body::String(_) => Body::String(Rc::new(StringFunction::Dynamic(dynamic))),
+            Body::Void(_) => Body::Void(Rc::new(VoidFunction::Dynamic(dynamic))),
+        };
+        Function {
+            body,
+            arguments,
+            named_arguments: Vec::new(),
+        }
+    }
+    pub fn map() -> Function {
+        let array = Rc::new(RefCell::new(Vec::new()));
+        let function = Rc::new(RefCell::new(Rc::new(Function::primitive_real_1(|x| x))));
+        Function {
+            arguments: vec![
+                Value::Array(array.clone()),
+                Value::Function(function.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Array(Rc::new(ArrayFunction::Map(array, function))),
+        }
+    }
+    pub fn map_sounds() -> Function {
+        let sounds = Rc::new(RefCell::new(Vec::new()));
+        let function = Rc::new(RefCell::new(Rc::new(Function::route(|sound| sound))));
+        Function {
+            arguments: vec![
+                Value::SoundArray(sounds.clone()),
+                Value::Function(function.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::SoundArray(Rc::new(SoundArrayFunction::Map(sounds, function))),
+        }
+    }
+    /// 組み込み関数 `name` の最初の引数に型 `ty` の値を渡したときに代わりに呼ぶもの
+    pub fn overload(name: &str, ty: &Type) -> Option<Function> {
+        match (name, ty) {
+            ("len", Type::String) => Some(Function::string_len()),
+            ("len", Type::SoundArray) => Some(Function::len_sounds()),
+            _ => None,
+        }
+    }
+    /// 配列の長さ
+    pub fn len() -> Function {
+        let array = Rc::new(RefCell::new(Vec::new()));
+        Function {
+            arguments: vec![Value::Array(array.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::Len(array))),
+        }
+    }
+    pub fn len_sounds() -> Function {
+        let sounds = Rc::new(RefCell::new(Vec::new()));
+        Function {
+            arguments: vec![Value::SoundArray(sounds.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::SoundLen(sounds))),
+        }
+    }
+    /// 文字列の長さ（文字数）． `len` を文字列で呼んだもの（ `Function::overload` ）
+    pub fn string_len() -> Function {
+        let string = Rc::new(RefCell::new(String::new()));
+        Function {
+            arguments: vec![Value::String(string.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::StringLen(string))),
+        }
+    }
+    /// 数を文字列にする．名前つき引数 `digits` は小数点以下の桁数（省略すると必要なだけ），
+    /// `width` は 0 で埋める幅
+    pub fn format() -> Function {
+        let x = Rc::new(Cell::new(0.));
+        let digits = Rc::new(Cell::new(f64::NAN));
+        let width = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Real(x.clone())],
+            named_arguments: vec![
+                (
+                    "digits".to_string(),
+                    Argument::Real(digits.clone(), RealExpression::Const(f64::NAN)),
+                ),
+                (
+                    "width".to_string(),
+                    Argument::Real(width.clone(), RealExpression::Const(0.)),
+                ),
+            ],
+            body: Body::String(Rc::new(StringFunction::Format { x, digits, width })),
+        }
+    }
+    /// 末尾に `x` を加えた配列（元の配列は変えない）
+    pub fn push() -> Function {
+        let array = Rc::new(RefCell::new(Vec::new()));
+        let x = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Array(array.clone()), Value::Real(x.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Array(Rc::new(ArrayFunction::Push(array, x))),
+        }
+    }
+    pub fn push_sounds() -> Function {
+        let sounds = Rc::new(RefCell::new(Vec::new()));
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![
+                Value::SoundArray(sounds.clone()),
+                Value::Sound(sound.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::SoundArray(Rc::new(SoundArrayFunction::Push(sounds, sound))),
+        }
+    }
+    pub fn primitive_real_0(fnc: fn() -> f64) -> Function {
+        Function {
+            arguments: Vec::new(),
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::Primitive0(fnc))),
+        }
+    }
     pub fn primitive_real_1(fnc: fn(f64) -> f64) -> Function {
         let x = Rc::new(Cell::new(0.));
         Function {
@@ -31,146 +260,3312 @@ impl Function {
             body: Body::Real(Rc::new(RealFunction::Primitive2(fnc, x, y))),
         }
     }
-    pub fn sin() -> Function {
+    pub fn time_at_bar(timeline: RcRefCell<Timeline>) -> Function {
         let x = Rc::new(Cell::new(0.));
         Function {
             arguments: vec![Value::Real(x.clone())],
             named_arguments: Vec::new(),
-            body: Body::Sound(Rc::new(SoundFunction::Sin(x))),
+            body: Body::Real(Rc::new(RealFunction::TimeAtBar(timeline, x))),
         }
     }
-    pub fn exp() -> Function {
+    pub fn time_at_beat(timeline: RcRefCell<Timeline>) -> Function {
         let x = Rc::new(Cell::new(0.));
         Function {
             arguments: vec![Value::Real(x.clone())],
             named_arguments: Vec::new(),
-            body: Body::Sound(Rc::new(SoundFunction::Exp(x))),
+            body: Body::Real(Rc::new(RealFunction::TimeAtBeat(timeline, x))),
         }
     }
-    pub fn linear() -> Function {
-        let x1 = Rc::new(Cell::new(0.));
-        let t1 = Rc::new(Cell::new(0.));
+    pub fn mtof() -> Function {
+        Function::primitive_real_1(|note| 440. * 2f64.powf((note - 69.) / 12.))
+    }
+    pub fn voice_lead() -> Function {
+        let prev = Rc::new(RefCell::new(Vec::new()));
+        let next = Rc::new(RefCell::new(Vec::new()));
         Function {
-            arguments: vec![Value::Real(x1.clone())],
+            arguments: vec![Value::Array(prev.clone()), Value::Array(next.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Array(Rc::new(ArrayFunction::VoiceLead(prev, next))),
+        }
+    }
+    pub fn invert() -> Function {
+        let chord = Rc::new(RefCell::new(Vec::new()));
+        let n = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Array(chord.clone()), Value::Real(n.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Array(Rc::new(ArrayFunction::Invert(chord, n))),
+        }
+    }
+    pub fn drop() -> Function {
+        let chord = Rc::new(RefCell::new(Vec::new()));
+        let n = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Array(chord.clone()), Value::Real(n.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Array(Rc::new(ArrayFunction::Drop(chord, n))),
+        }
+    }
+    pub fn spread() -> Function {
+        let chord = Rc::new(RefCell::new(Vec::new()));
+        Function {
+            arguments: vec![Value::Array(chord.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Array(Rc::new(ArrayFunction::Spread(chord))),
+        }
+    }
+    pub fn save_preset() -> Function {
+        let record = Rc::new(RefCell::new(Vec::new()));
+        let filename = Rc::new(RefCell::new("".to_string()));
+        Function {
+            arguments: vec![
+                Value::Record(record.clone()),
+                Value::String(filename.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::SavePreset(record, filename))),
+        }
+    }
+    pub fn load_preset() -> Function {
+        let filename = Rc::new(RefCell::new("".to_string()));
+        let schema = Rc::new(RefCell::new(Vec::new()));
+        Function {
+            arguments: vec![Value::String(filename.clone())],
             named_arguments: vec![(
-                "t".to_string(),
-                Argument::Real(t1.clone(), RealExpression::Const(1.)),
+                "schema".to_string(),
+                Argument::Record(schema.clone(), RecordExpression::Literal(Vec::new())),
             )],
-            body: Body::Sound(Rc::new(SoundFunction::Linear(x1, t1))),
+            body: Body::Record(Rc::new(RecordFunction::LoadPreset(filename, schema))),
         }
     }
-    pub fn write() -> Function {
+    pub fn render() -> Function {
         let sound = Rc::new(RefCell::new(Sound::Const(0.)));
-        let time = Rc::new(Cell::new(0.));
-        let filename = Rc::new(RefCell::new("".to_string()));
+        let seconds = Rc::new(Cell::new(0.));
         let samplerate = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(seconds.clone())],
+            named_arguments: vec![(
+                "samplerate".to_string(),
+                Argument::Real(
+                    samplerate.clone(),
+                    RealExpression::Const(DEFAULT_SAMPLERATE),
+                ),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Render(sound, seconds, samplerate))),
+        }
+    }
+    /// 音を解析して実数を返す関数．
+    /// 名前つき引数 `seconds` で解析する長さを指定する（省略すると音の長さ）
+    pub fn analysis(fnc: fn(&[f64], f64) -> f64) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let seconds = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: vec![(
+                "seconds".to_string(),
+                Argument::Real(seconds.clone(), RealExpression::Const(f64::INFINITY)),
+            )],
+            body: Body::Real(Rc::new(RealFunction::Analysis(fnc, sound, seconds))),
+        }
+    }
+    /// 音を解析して配列を返す関数．名前つき引数は `analysis` と同じ
+    pub fn array_analysis(fnc: fn(&[f64], f64) -> Vec<f64>) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let seconds = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: vec![(
+                "seconds".to_string(),
+                Argument::Real(seconds.clone(), RealExpression::Const(f64::INFINITY)),
+            )],
+            body: Body::Array(Rc::new(ArrayFunction::Analysis(fnc, sound, seconds))),
+        }
+    }
+    pub fn duration() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::Duration(sound))),
+        }
+    }
+    /// `write` などで `samplerate` を省略したときの標本化周波数（設定ファイルで変わる）
+    pub fn samplerate(samplerate: RcCell<f64>) -> Function {
+        Function {
+            arguments: Vec::new(),
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::Samplerate(samplerate))),
+        }
+    }
+    /// 環境を作ってからの経過時間（秒）
+    pub fn render_time(start: Instant) -> Function {
+        Function {
+            arguments: Vec::new(),
+            named_arguments: Vec::new(),
+            body: Body::Real(Rc::new(RealFunction::RenderTime(start))),
+        }
+    }
+    /// このバイナリに機能 `name` が含まれているか（ `mod features` ）
+    pub fn has_feature() -> Function {
+        let name = Rc::new(RefCell::new("".to_string()));
+        Function {
+            arguments: vec![Value::String(name.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Boolean(Rc::new(BooleanFunction::HasFeature(name))),
+        }
+    }
+    pub fn version() -> Function {
+        Function {
+            arguments: Vec::new(),
+            named_arguments: Vec::new(),
+            body: Body::String(Rc::new(StringFunction::Version)),
+        }
+    }
+    pub fn pan() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let position = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Sound(position.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Pan(sound, position))),
+        }
+    }
+    /// チャンネルを `route` で組み替える（ `left` ， `mono` など）
+    pub fn route(route: fn(Sound) -> Sound) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Route(sound, route))),
+        }
+    }
+    /// 音をそのまま通して，ピークと RMS を名前 `name` で記録する（ `mod probe` ）
+    pub fn probe(probes: RcRefCell<Probes>) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let name = Rc::new(RefCell::new("".to_string()));
+        let waveform = Rc::new(Cell::new(false));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::String(name.clone())],
+            named_arguments: vec![(
+                "waveform".to_string(),
+                Argument::Boolean(
+                    waveform.clone(),
+                    BooleanExpression::Reference(Rc::new(Cell::new(false))),
+                ),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Probe {
+                sound,
+                name,
+                waveform,
+                probes,
+            })),
+        }
+    }
+    /// コンプレッサー．名前つき引数 `sidechain` を省略すると `sound` 自身で検出する．
+    /// `lookahead` 秒だけ先読みし，その分だけ遅延する
+    pub fn compress() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let sidechain = Rc::new(RefCell::new(Sound::Const(0.)));
+        let threshold = Rc::new(Cell::new(0.));
+        let ratio = Rc::new(Cell::new(0.));
+        let attack = Rc::new(Cell::new(0.));
+        let release = Rc::new(Cell::new(0.));
+        let makeup = Rc::new(Cell::new(0.));
+        let lookahead = Rc::new(Cell::new(0.));
+        let real =
+            |rc: &RcCell<f64>, value| Argument::Real(rc.clone(), RealExpression::Const(value));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: vec![
+                ("threshold".to_string(), real(&threshold, -20.)),
+                ("ratio".to_string(), real(&ratio, 4.)),
+                ("attack".to_string(), real(&attack, 0.01)),
+                ("release".to_string(), real(&release, 0.1)),
+                ("makeup".to_string(), real(&makeup, 0.)),
+                ("lookahead".to_string(), real(&lookahead, 0.)),
+                // 名前つき引数は位置引数の後に設定されるので，既定値は `sound` の値になる
+                (
+                    "sidechain".to_string(),
+                    Argument::Sound(sidechain.clone(), SoundExpression::Reference(sound.clone())),
+                ),
+            ],
+            body: Body::Sound(Rc::new(SoundFunction::Compress {
+                sound,
+                sidechain,
+                threshold,
+                ratio,
+                attack,
+                release,
+                makeup,
+                lookahead,
+            })),
+        }
+    }
+    /// 音の振幅のエンベロープ（制御用の信号）
+    pub fn follow() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let attack = Rc::new(Cell::new(0.));
+        let release = Rc::new(Cell::new(0.));
         Function {
             arguments: vec![
                 Value::Sound(sound.clone()),
-                Value::Real(time.clone()),
-                Value::String(filename.clone()),
+                Value::Real(attack.clone()),
+                Value::Real(release.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Follow(sound, attack, release))),
+        }
+    }
+    /// ゲート．`compress` と同じく名前つき引数 `sidechain` で検出用の信号を指定できる
+    pub fn gate() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let sidechain = Rc::new(RefCell::new(Sound::Const(0.)));
+        let threshold = Rc::new(Cell::new(0.));
+        let attack = Rc::new(Cell::new(0.));
+        let hold = Rc::new(Cell::new(0.));
+        let release = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::Real(threshold.clone()),
+                Value::Real(attack.clone()),
+                Value::Real(hold.clone()),
+                Value::Real(release.clone()),
             ],
             named_arguments: vec![(
-                "samplerate".to_string(),
-                Argument::Real(samplerate.clone(), RealExpression::Const(44100.)),
+                "sidechain".to_string(),
+                Argument::Sound(sidechain.clone(), SoundExpression::Reference(sound.clone())),
             )],
-            body: Body::Void(Rc::new(VoidFunction::Write(
-                sound, time, filename, samplerate,
-            ))),
+            body: Body::Sound(Rc::new(SoundFunction::Gate {
+                sound,
+                sidechain,
+                threshold,
+                attack,
+                hold,
+                release,
+            })),
         }
     }
-}
-
-pub enum Body {
-    Real(Rc<RealFunction>),
-    Boolean(Rc<BooleanFunction>), // ユーザ定義関数ができたら使う
-    Sound(Rc<SoundFunction>),
-    String(Rc<StringFunction>), // ユーザ定義関数ができたら使う
-    Void(Rc<VoidFunction>),
-}
-
-pub enum RealFunction {
-    Primitive1(fn(f64) -> f64, RcCell<f64>),
-    Primitive2(fn(f64, f64) -> f64, RcCell<f64>, RcCell<f64>),
-}
-
-impl RealFunction {
-    pub fn evaluate(&self) -> f64 {
-        match self {
-            RealFunction::Primitive1(fnc, x) => fnc(x.get()),
-            RealFunction::Primitive2(fnc, x, y) => fnc(x.get(), y.get()),
+    /// 録音の音高と音量で波形 `oscillator` を鳴らし直す
+    pub fn track_and_resynth() -> Function {
+        let buffer = Rc::new(RefCell::new(Sound::Const(0.)));
+        let oscillator = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![
+                Value::Sound(buffer.clone()),
+                Value::Sound(oscillator.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::TrackAndResynth(buffer, oscillator))),
         }
     }
-}
-
-pub enum BooleanFunction {}
-impl BooleanFunction {
-    pub fn evaluate(&self) -> bool {
-        todo!();
+    /// `samples { }` で宣言した音
+    /// `samples { }` で読み込んだ音．`loop_start` と `loop_end` （秒）を省略すると
+    /// ファイルのループ区間を使い， `looping = false` ならばループしない
+    pub fn sample(registry: RcRefCell<samples::Registry>) -> Function {
+        let name = Rc::new(RefCell::new("".to_string()));
+        let loop_start = Rc::new(Cell::new(0.));
+        let loop_end = Rc::new(Cell::new(0.));
+        let looping = Rc::new(Cell::new(true));
+        Function {
+            arguments: vec![Value::String(name.clone())],
+            named_arguments: vec![
+                (
+                    "loop_start".to_string(),
+                    Argument::Real(loop_start.clone(), RealExpression::Const(f64::NAN)),
+                ),
+                (
+                    "loop_end".to_string(),
+                    Argument::Real(loop_end.clone(), RealExpression::Const(f64::NAN)),
+                ),
+                (
+                    "looping".to_string(),
+                    Argument::Boolean(
+                        looping.clone(),
+                        BooleanExpression::Reference(Rc::new(Cell::new(true))),
+                    ),
+                ),
+            ],
+            body: Body::Sound(Rc::new(SoundFunction::Sample {
+                registry,
+                name,
+                loop_start,
+                loop_end,
+                looping,
+            })),
+        }
     }
-}
-
-pub enum SoundFunction {
-    Sin(RcCell<f64>),
-    Linear(RcCell<f64>, RcCell<f64>),
-    Exp(RcCell<f64>),
-}
-
-impl SoundFunction {
-    pub fn evaluate(&self) -> Sound {
-        match self {
-            SoundFunction::Sin(frequency) => Sound::Sin {
-                frequency: frequency.get(),
-                phase: 0.,
-            },
-            SoundFunction::Linear(x1, t1) => Sound::Linear {
-                slope: x1.get() / t1.get(),
-                intercept: 0.,
-            },
-            SoundFunction::Exp(time) => Sound::Exp {
-                slope: time.get().recip(),
-                intercept: 0.,
-            },
+    /// 音声ファイルを読んだ音
+    pub fn read() -> Function {
+        let filename = Rc::new(RefCell::new("".to_string()));
+        Function {
+            arguments: vec![Value::String(filename.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Read(filename))),
         }
     }
-}
-
-pub enum StringFunction {}
-impl StringFunction {
-    pub fn evaluate(&self) -> String {
-        todo!();
+    /// 標本のレコードから高さ `note` の音を選ぶ（ `mod multisample` ）．
+    /// 同じ高さで呼ぶたびにラウンドロビンの次の音になる
+    pub fn multisample() -> Function {
+        let zones = Rc::new(RefCell::new(Vec::new()));
+        let note = Rc::new(Cell::new(0.));
+        let velocity = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::SoundRecord(zones.clone()), Value::Real(note.clone())],
+            named_arguments: vec![(
+                "velocity".to_string(),
+                Argument::Real(velocity.clone(), RealExpression::Const(1.)),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Multisample {
+                zones,
+                note,
+                velocity,
+                rounds: Rc::new(RefCell::new(HashMap::new())),
+            })),
+        }
     }
-}
-
-pub enum VoidFunction {
-    Write(
-        RcRefCell<Sound>,
-        RcCell<f64>,
-        RcRefCell<String>,
-        RcCell<f64>,
-    ),
-}
-impl VoidFunction {
-    pub fn evaluate(&self) {
-        match self {
-            VoidFunction::Write(sound, time, filename, samplerate) => {
-                let samplerate = samplerate.get();
-                let mut iter = sound.borrow().clone().iter(samplerate);
-                let spec = hound::WavSpec {
-                    channels: 1,
-                    sample_rate: samplerate as u32,
-                    bits_per_sample: 32,
-                    sample_format: hound::SampleFormat::Int,
-                };
-                let mut writer = hound::WavWriter::create(&*filename.borrow(), spec).unwrap();
-                let amplitude = std::i32::MAX as f64;
-                for _ in 0..(time.get() * samplerate) as i64 {
-                    writer
-                        .write_sample((amplitude * iter.next()) as i32)
-                        .unwrap();
-                }
-                writer.finalize().unwrap();
-            }
+    /// クロスオーバー周波数の配列で帯域を分ける
+    pub fn split_bands() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let crossovers = Rc::new(RefCell::new(Vec::new()));
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::Array(crossovers.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::SoundArray(Rc::new(SoundArrayFunction::SplitBands(sound, crossovers))),
+        }
+    }
+    /// 分けた帯域を足し合わせる
+    pub fn merge_bands() -> Function {
+        let bands = Rc::new(RefCell::new(Vec::new()));
+        Function {
+            arguments: vec![Value::SoundArray(bands.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::MergeBands(bands))),
         }
     }
+    /// オシレーター `oscillator` を `voices` 個に分けて `detune` セントの幅に散らし，左右に `spread` （ 0 から 1 ）だけ広げる．
+    /// 名前つき引数 `seed` で各声部の位相が決まる．省略すると毎回違う
+    pub fn unison() -> Function {
+        let oscillator = Rc::new(RefCell::new(Sound::Const(0.)));
+        let voices = Rc::new(Cell::new(0.));
+        let detune = Rc::new(Cell::new(0.));
+        let spread = Rc::new(Cell::new(0.));
+        let seed = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(oscillator.clone()),
+                Value::Real(voices.clone()),
+                Value::Real(detune.clone()),
+                Value::Real(spread.clone()),
+            ],
+            named_arguments: vec![(
+                "seed".to_string(),
+                Argument::Real(seed.clone(), RealExpression::Const(f64::NAN)),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Unison {
+                oscillator,
+                voices,
+                detune,
+                spread,
+                seed,
+            })),
+        }
+    }
+    /// 長さの決まっている音を順に鳴らす．名前つき引数 `glide` は音の変わり目を滑らせる時定数（秒）で，
+    /// 周波数の列をオシレーターに渡せばポルタメントになる
+    pub fn seq() -> Function {
+        let sounds = Rc::new(RefCell::new(Vec::new()));
+        let glide = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::SoundArray(sounds.clone())],
+            named_arguments: vec![(
+                "glide".to_string(),
+                Argument::Real(glide.clone(), RealExpression::Const(0.)),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Seq(sounds, glide))),
+        }
+    }
+    /// 長さの決まっている音を `count` 回続けて鳴らす
+    pub fn repeat() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let count = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(count.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Repeat(sound, count))),
+        }
+    }
+    /// `t` 秒遅らせる（ `sound >> t` と同じ）
+    pub fn shift() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let t = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(t.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Shift(sound, t))),
+        }
+    }
+    /// チャンネルボコーダー
+    pub fn vocode() -> Function {
+        let carrier = Rc::new(RefCell::new(Sound::Const(0.)));
+        let modulator = Rc::new(RefCell::new(Sound::Const(0.)));
+        let bands = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(carrier.clone()),
+                Value::Sound(modulator.clone()),
+                Value::Real(bands.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Vocode(carrier, modulator, bands))),
+        }
+    }
+    pub fn binaural() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let azimuth = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(azimuth.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Binaural(sound, azimuth))),
+        }
+    }
+    /// 長さの決まった音を逆にする
+    pub fn reverse() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Reverse(sound))),
+        }
+    }
+    /// 長さの決まった音の時刻 `[start, end)` （秒）を切り出す
+    pub fn trim() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let start = Rc::new(Cell::new(0.));
+        let end = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::Real(start.clone()),
+                Value::Real(end.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Trim(sound, start, end))),
+        }
+    }
+    /// 長さの決まった音を 2 つつなぐ
+    pub fn append() -> Function {
+        let a = Rc::new(RefCell::new(Sound::Const(0.)));
+        let b = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(a.clone()), Value::Sound(b.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Append(a, b))),
+        }
+    }
+    /// 長さの決まった音を，初めて鳴らすときに描いて使い回す．
+    /// `render` と同じく時刻 0 から描くので，ずらしてもそれより前は鳴らない
+    pub fn freeze() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Freeze(sound))),
+        }
+    }
+    /// 長さの決まった音の両端を `ms` ミリ秒ずつフェードする（クリック除け）
+    pub fn fade_edges() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let ms = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(ms.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::FadeEdges(sound, ms))),
+        }
+    }
+    /// 正弦波．名前つき引数 `phase` は初期位相（ 1 周期を 1 とする）．周波数は音でもよい（周波数変調）
+    pub fn sin() -> Function {
+        let x = Rc::new(RefCell::new(Sound::Const(0.)));
+        let phase = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(x.clone())],
+            named_arguments: vec![phase_argument(&phase)],
+            body: Body::Sound(Rc::new(SoundFunction::Sin(x, phase))),
+        }
+    }
+    /// 基本波形のオシレーター．矩形波と鋸歯状波は名前つき引数 `bandlimit` （既定で true ）で折り返しを抑え，
+    /// 矩形波は `duty` （既定で 0.5 ）で 1 周期のうち高い部分の割合を指定する．`phase` と周波数は `Sin` と同じ
+    pub fn oscillator(waveform: Waveform) -> Function {
+        let frequency = Rc::new(RefCell::new(Sound::Const(0.)));
+        let phase = Rc::new(Cell::new(0.));
+        let duty = Rc::new(Cell::new(0.5));
+        let bandlimit = Rc::new(Cell::new(true));
+        let mut named_arguments = vec![phase_argument(&phase)];
+        if let Waveform::Square { .. } = waveform {
+            named_arguments.push((
+                "duty".to_string(),
+                Argument::Real(duty.clone(), RealExpression::Const(0.5)),
+            ));
+        }
+        if waveform != Waveform::Triangle {
+            named_arguments.push((
+                "bandlimit".to_string(),
+                Argument::Boolean(
+                    bandlimit.clone(),
+                    BooleanExpression::Reference(Rc::new(Cell::new(true))),
+                ),
+            ));
+        }
+        Function {
+            arguments: vec![Value::Sound(frequency.clone())],
+            named_arguments,
+            body: Body::Sound(Rc::new(SoundFunction::Oscillator {
+                waveform,
+                frequency,
+                phase,
+                duty,
+                bandlimit,
+            })),
+        }
+    }
+    /// 双二次フィルタ `filter(cutoff, q)` をかける．名前つき引数 `q` の既定は Butterworth の 1/√2
+    pub fn filter(filter: fn(f64, f64) -> Filter) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let cutoff = Rc::new(Cell::new(0.));
+        let q = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(cutoff.clone())],
+            named_arguments: vec![(
+                "q".to_string(),
+                Argument::Real(
+                    q.clone(),
+                    RealExpression::Const(std::f64::consts::FRAC_1_SQRT_2),
+                ),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Filter {
+                sound,
+                cutoff,
+                q,
+                filter,
+            })),
+        }
+    }
+    /// `time` 秒のディレイ．名前つき引数 `feedback` で繰り返し， `mix` で原音との割合を決める
+    pub fn delay() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let time = Rc::new(Cell::new(0.));
+        let feedback = Rc::new(Cell::new(0.));
+        let mix = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(time.clone())],
+            named_arguments: vec![
+                (
+                    "feedback".to_string(),
+                    Argument::Real(feedback.clone(), RealExpression::Const(0.5)),
+                ),
+                (
+                    "mix".to_string(),
+                    Argument::Real(mix.clone(), RealExpression::Const(0.5)),
+                ),
+            ],
+            body: Body::Sound(Rc::new(SoundFunction::Delay {
+                sound,
+                time,
+                feedback,
+                mix,
+            })),
+        }
+    }
+    /// 時定数 `time` 秒で制御信号をならす（段差によるクリックを防ぐ）
+    pub fn smooth() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let time = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(time.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Lag {
+                sound,
+                rise: time.clone(),
+                fall: time,
+            })),
+        }
+    }
+    /// 上がるときは時定数 `rise` 秒，下がるときは `fall` 秒で制御信号を追う
+    pub fn slew() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let rise = Rc::new(Cell::new(0.));
+        let fall = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::Real(rise.clone()),
+                Value::Real(fall.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Lag { sound, rise, fall })),
+        }
+    }
+    /// Freeverb 型の残響．名前つき引数 `size` ， `damp` ， `mix` はどれも 0 から 1
+    pub fn reverb() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let size = Rc::new(Cell::new(0.));
+        let damp = Rc::new(Cell::new(0.));
+        let mix = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone())],
+            named_arguments: vec![
+                (
+                    "size".to_string(),
+                    Argument::Real(size.clone(), RealExpression::Const(0.5)),
+                ),
+                (
+                    "damp".to_string(),
+                    Argument::Real(damp.clone(), RealExpression::Const(0.5)),
+                ),
+                (
+                    "mix".to_string(),
+                    Argument::Real(mix.clone(), RealExpression::Const(0.3)),
+                ),
+            ],
+            body: Body::Sound(Rc::new(SoundFunction::Reverb {
+                sound,
+                size,
+                damp,
+                mix,
+            })),
+        }
+    }
+    /// ハードシンク．オシレーター `master` が 1 周期進むごとに `slave` を最初から鳴らし直す
+    pub fn sync() -> Function {
+        let master = Rc::new(RefCell::new(Sound::Const(0.)));
+        let slave = Rc::new(RefCell::new(Sound::Const(0.)));
+        Function {
+            arguments: vec![Value::Sound(master.clone()), Value::Sound(slave.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Sound(Rc::new(SoundFunction::Sync(master, slave))),
+        }
+    }
+    /// `input` から `seconds` 秒録音した音．名前つき引数 `gate` （ dBFS ）があれば，それより小さい部分を消す
+    #[cfg(feature = "record")]
+    pub fn record(
+        input: RcRefCell<Box<dyn AudioInput>>,
+        default_samplerate: RcCell<f64>,
+    ) -> Function {
+        let seconds = Rc::new(Cell::new(0.));
+        let samplerate = Rc::new(Cell::new(0.));
+        let gate = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Real(seconds.clone())],
+            named_arguments: vec![
+                (
+                    "samplerate".to_string(),
+                    Argument::Real(
+                        samplerate.clone(),
+                        RealExpression::Reference(default_samplerate),
+                    ),
+                ),
+                (
+                    "gate".to_string(),
+                    Argument::Real(gate.clone(), RealExpression::Const(f64::NAN)),
+                ),
+            ],
+            body: Body::Sound(Rc::new(SoundFunction::Record {
+                input,
+                seconds,
+                samplerate,
+                gate,
+            })),
+        }
+    }
+    /// 白色雑音．名前つき引数 `seed` を省略すると毎回違う雑音になる
+    pub fn noise() -> Function {
+        let seed = Rc::new(Cell::new(0.));
+        Function {
+            arguments: Vec::new(),
+            named_arguments: vec![(
+                "seed".to_string(),
+                Argument::Real(seed.clone(), RealExpression::Const(f64::NAN)),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Noise(seed))),
+        }
+    }
+    pub fn exp() -> Function {
+        let x = Rc::new(Cell::new(0.));
+        let clamp = ClampArguments::new();
+        Function {
+            arguments: vec![Value::Real(x.clone())],
+            named_arguments: clamp.named_arguments(),
+            body: Body::Sound(Rc::new(SoundFunction::Exp(x, clamp))),
+        }
+    }
+    pub fn linear() -> Function {
+        let x1 = Rc::new(Cell::new(0.));
+        let t1 = Rc::new(Cell::new(0.));
+        let clamp = ClampArguments::new();
+        let mut named_arguments = vec![(
+            "t".to_string(),
+            Argument::Real(t1.clone(), RealExpression::Const(1.)),
+        )];
+        named_arguments.extend(clamp.named_arguments());
+        Function {
+            arguments: vec![Value::Real(x1.clone())],
+            named_arguments,
+            body: Body::Sound(Rc::new(SoundFunction::Linear(x1, t1, clamp))),
+        }
+    }
+    /// ADSR エンベロープ．名前つき引数 `length` で離鍵までの秒数を指定する（省略すると離さない）
+    pub fn adsr() -> Function {
+        let attack = Rc::new(Cell::new(0.));
+        let decay = Rc::new(Cell::new(0.));
+        let sustain = Rc::new(Cell::new(0.));
+        let release = Rc::new(Cell::new(0.));
+        let length = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Real(attack.clone()),
+                Value::Real(decay.clone()),
+                Value::Real(sustain.clone()),
+                Value::Real(release.clone()),
+            ],
+            named_arguments: vec![(
+                "length".to_string(),
+                Argument::Real(length.clone(), RealExpression::Const(f64::NAN)),
+            )],
+            body: Body::Sound(Rc::new(SoundFunction::Adsr {
+                attack,
+                decay,
+                sustain,
+                release,
+                length,
+            })),
+        }
+    }
+    /// ステップシーケンサ．パターンの 1 文字を `step` 秒として鳴らす．
+    /// `step` は秒で固定で，テンポ（ `tempo_change` ， `tempo_ramp` ）には従わない．
+    /// テンポに合わせるには `time_at_beat` で求めた長さを渡す（テンポが変わる箇所では分けて並べる）
+    pub fn steps() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let pattern = Rc::new(RefCell::new("".to_string()));
+        let step = Rc::new(Cell::new(0.));
+        let chance = Rc::new(Cell::new(0.));
+        let every = Rc::new(Cell::new(0.));
+        let variation = Rc::new(RefCell::new("".to_string()));
+        let jitter = Rc::new(Cell::new(0.));
+        let velocity_jitter = Rc::new(Cell::new(0.));
+        let seed = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::String(pattern.clone()),
+                Value::Real(step.clone()),
+            ],
+            named_arguments: vec![
+                (
+                    "chance".to_string(),
+                    Argument::Real(chance.clone(), RealExpression::Const(1.)),
+                ),
+                (
+                    "every".to_string(),
+                    Argument::Real(every.clone(), RealExpression::Const(0.)),
+                ),
+                (
+                    "variation".to_string(),
+                    Argument::String(variation.clone(), StringExpression::Const("".to_string())),
+                ),
+                (
+                    "jitter".to_string(),
+                    Argument::Real(jitter.clone(), RealExpression::Const(0.)),
+                ),
+                (
+                    "velocity_jitter".to_string(),
+                    Argument::Real(velocity_jitter.clone(), RealExpression::Const(0.)),
+                ),
+                (
+                    "seed".to_string(),
+                    Argument::Real(seed.clone(), RealExpression::Const(0.)),
+                ),
+            ],
+            body: Body::Sound(Rc::new(SoundFunction::Steps {
+                sound,
+                pattern,
+                step,
+                chance,
+                every,
+                variation,
+                jitter,
+                velocity_jitter,
+                seed,
+            })),
+        }
+    }
+    pub fn write(
+        timeline: RcRefCell<Timeline>,
+        master: RcRefCell<Master>,
+        output: RcRefCell<dyn Output>,
+        default_samplerate: RcCell<f64>,
+        default_overwrite: RcCell<bool>,
+        out: RcRefCell<Option<String>>,
+    ) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let time = Rc::new(Cell::new(0.));
+        let filename = Rc::new(RefCell::new("".to_string()));
+        let samplerate = Rc::new(Cell::new(0.));
+        let metadata = MetadataArguments::new();
+        let master = MasterArgument::new(master);
+        let tail = TailArgument::new();
+        let encoding = EncodingArguments::new();
+        let normalize = NormalizeArguments::new();
+        let file = FileArguments::new(default_overwrite, out);
+        let mut named_arguments = vec![
+            (
+                "samplerate".to_string(),
+                Argument::Real(
+                    samplerate.clone(),
+                    RealExpression::Reference(default_samplerate),
+                ),
+            ),
+            master.named_argument(),
+            tail.named_argument(),
+        ];
+        named_arguments.extend(encoding.named_arguments());
+        named_arguments.extend(metadata.named_arguments());
+        named_arguments.extend(normalize.named_arguments());
+        named_arguments.extend(file.named_arguments());
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::Real(time.clone()),
+                Value::String(filename.clone()),
+            ],
+            named_arguments,
+            body: Body::Void(Rc::new(VoidFunction::Write(
+                timeline, sound, time, filename, samplerate, metadata, master, tail, encoding,
+                normalize, file, output,
+            ))),
+        }
+    }
+    /// `sound` を `seconds` 秒だけ `backend` で鳴らす．鳴らし終えるまで戻らない
+    pub fn play(
+        master: RcRefCell<Master>,
+        backend: RcRefCell<Box<dyn AudioBackend>>,
+        default_samplerate: RcCell<f64>,
+    ) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let seconds = Rc::new(Cell::new(0.));
+        let samplerate = Rc::new(Cell::new(0.));
+        let master = MasterArgument::new(master);
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(seconds.clone())],
+            named_arguments: vec![
+                (
+                    "samplerate".to_string(),
+                    Argument::Real(
+                        samplerate.clone(),
+                        RealExpression::Reference(default_samplerate),
+                    ),
+                ),
+                master.named_argument(),
+            ],
+            body: Body::Void(Rc::new(VoidFunction::Play {
+                sound,
+                seconds,
+                samplerate,
+                master,
+                backend,
+            })),
+        }
+    }
+    /// 標本列（と名前つき引数 `spectrum` があればスペクトル）を CSV に書き出す
+    pub fn debug_dump() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let seconds = Rc::new(Cell::new(0.));
+        let filename = Rc::new(RefCell::new("".to_string()));
+        let spectrum = Rc::new(RefCell::new("".to_string()));
+        let block = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::Real(seconds.clone()),
+                Value::String(filename.clone()),
+            ],
+            named_arguments: vec![
+                (
+                    "spectrum".to_string(),
+                    Argument::String(spectrum.clone(), StringExpression::Const("".to_string())),
+                ),
+                (
+                    "block".to_string(),
+                    Argument::Real(block.clone(), RealExpression::Const(1024.)),
+                ),
+            ],
+            body: Body::Void(Rc::new(VoidFunction::DebugDump {
+                sound,
+                seconds,
+                filename,
+                spectrum,
+                block,
+            })),
+        }
+    }
+    pub fn show() -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let seconds = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Sound(sound.clone()), Value::Real(seconds.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::Show(sound, seconds))),
+        }
+    }
+    /// `probe` の記録を表示する
+    pub fn probes(probes: RcRefCell<Probes>) -> Function {
+        Function {
+            arguments: Vec::new(),
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::Probes(probes))),
+        }
+    }
+    pub fn marker(timeline: RcRefCell<Timeline>) -> Function {
+        let name = Rc::new(RefCell::new("".to_string()));
+        let time = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::String(name.clone()), Value::Real(time.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::Marker(timeline, name, time))),
+        }
+    }
+    pub fn region(timeline: RcRefCell<Timeline>) -> Function {
+        let name = Rc::new(RefCell::new("".to_string()));
+        let start = Rc::new(Cell::new(0.));
+        let end = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::String(name.clone()),
+                Value::Real(start.clone()),
+                Value::Real(end.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::Region(timeline, name, start, end))),
+        }
+    }
+    /// マーカーと区間をトラックにした CUE シート．
+    /// 名前つき引数 `file` は音声ファイルの名前（省略すると拡張子を `.wav` にしたもの）
+    pub fn write_cue(timeline: RcRefCell<Timeline>) -> Function {
+        let filename = Rc::new(RefCell::new("".to_string()));
+        let file = Rc::new(RefCell::new("".to_string()));
+        Function {
+            arguments: vec![Value::String(filename.clone())],
+            named_arguments: vec![(
+                "file".to_string(),
+                Argument::String(file.clone(), StringExpression::Const("".to_string())),
+            )],
+            body: Body::Void(Rc::new(VoidFunction::WriteCue(timeline, filename, file))),
+        }
+    }
+    /// マーカーと区間をチャプターにした JSON
+    pub fn write_chapters(timeline: RcRefCell<Timeline>) -> Function {
+        let filename = Rc::new(RefCell::new("".to_string()));
+        Function {
+            arguments: vec![Value::String(filename.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::WriteChapters(timeline, filename))),
+        }
+    }
+    pub fn tempo_change(timeline: RcRefCell<Timeline>) -> Function {
+        let bar = Rc::new(Cell::new(0.));
+        let bpm = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::Real(bar.clone()), Value::Real(bpm.clone())],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::TempoChange(timeline, bar, bpm))),
+        }
+    }
+    pub fn tempo_ramp(timeline: RcRefCell<Timeline>) -> Function {
+        let from = Rc::new(Cell::new(0.));
+        let to = Rc::new(Cell::new(0.));
+        let bpm = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![
+                Value::Real(from.clone()),
+                Value::Real(to.clone()),
+                Value::Real(bpm.clone()),
+            ],
+            named_arguments: Vec::new(),
+            body: Body::Void(Rc::new(VoidFunction::TempoRamp(timeline, from, to, bpm))),
+        }
+    }
+    pub fn write_region(
+        timeline: RcRefCell<Timeline>,
+        master: RcRefCell<Master>,
+        output: RcRefCell<dyn Output>,
+        default_samplerate: RcCell<f64>,
+        default_overwrite: RcCell<bool>,
+        out: RcRefCell<Option<String>>,
+    ) -> Function {
+        let sound = Rc::new(RefCell::new(Sound::Const(0.)));
+        let name = Rc::new(RefCell::new("".to_string()));
+        let filename = Rc::new(RefCell::new("".to_string()));
+        let samplerate = Rc::new(Cell::new(0.));
+        let master = MasterArgument::new(master);
+        let tail = TailArgument::new();
+        let encoding = EncodingArguments::new();
+        let normalize = NormalizeArguments::new();
+        let file = FileArguments::new(default_overwrite, out);
+        let mut named_arguments = vec![
+            (
+                "samplerate".to_string(),
+                Argument::Real(
+                    samplerate.clone(),
+                    RealExpression::Reference(default_samplerate),
+                ),
+            ),
+            master.named_argument(),
+            tail.named_argument(),
+        ];
+        named_arguments.extend(encoding.named_arguments());
+        named_arguments.extend(normalize.named_arguments());
+        named_arguments.extend(file.named_arguments());
+        Function {
+            arguments: vec![
+                Value::Sound(sound.clone()),
+                Value::String(name.clone()),
+                Value::String(filename.clone()),
+            ],
+            named_arguments,
+            body: Body::Void(Rc::new(VoidFunction::WriteRegion(
+                timeline, sound, name, filename, samplerate, master, tail, encoding, normalize,
+                file, output,
+            ))),
+        }
+    }
+    /// `write` などでかけるマスター段を選ぶ
+    pub fn set_master(master: RcRefCell<Master>) -> Function {
+        let name = Rc::new(RefCell::new("".to_string()));
+        let ceiling = Rc::new(Cell::new(0.));
+        let release = Rc::new(Cell::new(0.));
+        Function {
+            arguments: vec![Value::String(name.clone())],
+            named_arguments: vec![
+                (
+                    "ceiling".to_string(),
+                    Argument::Real(
+                        ceiling.clone(),
+                        RealExpression::Const(master::DEFAULT_CEILING_DB),
+                    ),
+                ),
+                (
+                    "release".to_string(),
+                    Argument::Real(
+                        release.clone(),
+                        RealExpression::Const(master::DEFAULT_RELEASE),
+                    ),
+                ),
+            ],
+            body: Body::Void(Rc::new(VoidFunction::SetMaster(
+                master, name, ceiling, release,
+            ))),
+        }
+    }
+    pub fn write_stems(
+        timeline: RcRefCell<Timeline>,
+        output: RcRefCell<dyn Output>,
+        default_samplerate: RcCell<f64>,
+        default_overwrite: RcCell<bool>,
+    ) -> Function {
+        let tracks = Rc::new(RefCell::new(Vec::new()));
+        let time = Rc::new(Cell::new(0.));
+        let directory = Rc::new(RefCell::new("".to_string()));
+        let samplerate = Rc::new(Cell::new(0.));
+        let tail = TailArgument::new();
+        let encoding = EncodingArguments::new();
+        // ステムはディレクトリに書き出すので `--out` には従わない
+        let file = FileArguments::new(default_overwrite, Rc::new(RefCell::new(None)));
+        let mut named_arguments = vec![
+            (
+                "samplerate".to_string(),
+                Argument::Real(
+                    samplerate.clone(),
+                    RealExpression::Reference(default_samplerate),
+                ),
+            ),
+            tail.named_argument(),
+        ];
+        named_arguments.extend(encoding.named_arguments());
+        named_arguments.extend(file.named_arguments());
+        Function {
+            arguments: vec![
+                Value::SoundRecord(tracks.clone()),
+                Value::Real(time.clone()),
+                Value::String(directory.clone()),
+            ],
+            named_arguments,
+            body: Body::Void(Rc::new(VoidFunction::WriteStems(
+                timeline, tracks, time, directory, samplerate, tail, encoding, file, output,
+            ))),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Body {
+    Real(Rc<RealFunction>),
+    Int(Rc<IntFunction>),
+    Array(Rc<ArrayFunction>),
+    Record(Rc<RecordFunction>),
+    SoundArray(Rc<SoundArrayFunction>),
+    Boolean(Rc<BooleanFunction>),
+    Sound(Rc<SoundFunction>),
+    String(Rc<StringFunction>),
+    Void(Rc<VoidFunction>),
+}
+
+impl Body {
+    /// 実数の引数 `cell` に音を渡せるか．実数を返す関数は標本ごとに呼ぶ（ `Sound::Apply` ）
+    pub fn modulatable(&self, cell: &RcCell<f64>) -> bool {
+        match self {
+            Body::Real(_) => true,
+            Body::Sound(body) => body.modulatable(cell),
+            _ => false,
+        }
+    }
+}
+
+/// ユーザ定義関数（ `def` ）．`Expr` は戻り値の式の型
+///
+/// 本体は定義したときに一度だけコンパイルし，そのときのスコープの変数を共有する．
+/// 引数と局所変数は関数ごとに 1 組しかないので，再帰呼び出しの前後で退避して戻す
+pub struct UserDefined<Expr: Evaluatable> {
+    name: String,
+    /// 関数名の位置．実行時のエラーはここで報告する
+    range: pos::Range,
+    /// 呼び出し側が書き込む引数（位置引数，名前つき引数の順）
+    arguments: Vec<Value>,
+    /// 本体から見える引数．呼び出すたびに `arguments` を写す
+    parameters: Vec<Value>,
+    /// 退避する変数（引数と局所変数）
+    frame: RefCell<Vec<Value>>,
+    body: RefCell<Option<Statement<Expr>>>,
+    /// 実行中の呼び出しの数
+    depth: Cell<usize>,
+}
+
+/// 関数の値の呼び出し（ `Function::dynamic` ）．
+/// 呼び出し側は `arguments` に書き込み，呼ぶときにそのとき入っている関数に写す
+pub struct Dynamic {
+    function: RcRefCell<Rc<Function>>,
+    arguments: Vec<Value>,
+}
+
+impl Dynamic {
+    fn current(&self) -> Rc<Function> {
+        self.function.borrow().clone()
+    }
+    /// 引数を写した関数
+    fn call(&self) -> Rc<Function> {
+        let function = self.current();
+        function.bind(&self.arguments);
+        function
+    }
+}
+
+/// 呼び出しを終えたら（ panic でも）実行中の呼び出しから外す
+struct Activation<'a>(&'a Cell<usize>);
+
+impl Drop for Activation<'_> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+        CALLS.with(|calls| calls.borrow_mut().pop());
+    }
+}
+
+/// `f -> f -> ... (n more) -> f` ．長ければ先頭と末尾だけ
+fn call_chain(calls: &[String]) -> String {
+    if calls.len() <= 2 * SHOWN_CALLS {
+        return calls.join(" -> ");
+    }
+    format!(
+        "{} -> ... ({} more) -> {}",
+        calls[..SHOWN_CALLS].join(" -> "),
+        calls.len() - 2 * SHOWN_CALLS,
+        calls[calls.len() - SHOWN_CALLS..].join(" -> ")
+    )
+}
+
+impl<Expr: Evaluatable + Clone> UserDefined<Expr> {
+    pub fn new(
+        name: String,
+        range: pos::Range,
+        arguments: Vec<Value>,
+        parameters: Vec<Value>,
+    ) -> UserDefined<Expr> {
+        UserDefined {
+            name,
+            range,
+            arguments,
+            frame: RefCell::new(parameters.clone()),
+            parameters,
+            body: RefCell::new(None),
+            depth: Cell::new(0),
+        }
+    }
+    /// 本体を設定する．本体で代入する変数のうち `captured` （定義したスコープ）にないものが局所変数．
+    /// 自分自身への末尾呼び出しは呼び出しを重ねずにやり直す（ `Statement::eliminate_tail_calls` ）
+    pub fn set_body(&self, body: Statement<Expr>, captured: &HashMap<String, Value>) {
+        let body = body.eliminate_tail_calls(self);
+        let mut targets = Vec::new();
+        body.targets(&mut targets);
+        self.frame.borrow_mut().extend(
+            targets
+                .into_iter()
+                .filter(|target| !captured.values().any(|value| value.ptr_eq(target))),
+        );
+        *self.body.borrow_mut() = Some(body);
+    }
+    fn fail(&self, message: String) -> ! {
+        std::panic::panic_any(RuntimeFailure(self.range.clone(), message))
+    }
+    /// 本体を実行する．`return` せずに終わったら `None`
+    pub fn call(&self) -> Option<Expr::Output> {
+        let depth = CALLS.with(|calls| {
+            let mut calls = calls.borrow_mut();
+            calls.push(self.name.clone());
+            calls.len()
+        });
+        self.depth.set(self.depth.get() + 1);
+        let activation = Activation(&self.depth);
+        let limit = RECURSION_LIMIT.with(Cell::get);
+        if depth > limit {
+            let chain = CALLS.with(|calls| call_chain(&calls.borrow()));
+            self.fail(format!(
+                "recursion limit ({}) exceeded in `{}` (depth {}): {}",
+                limit, self.name, depth, chain
+            ));
+        }
+        let saved = (self.depth.get() > 1).then(|| {
+            self.frame
+                .borrow()
+                .iter()
+                .map(Value::deep_copy)
+                .collect::<Vec<_>>()
+        });
+        let body = self
+            .body
+            .borrow()
+            .clone()
+            .expect("function body is not compiled");
+        let ret = loop {
+            for (parameter, argument) in self.parameters.iter().zip(&self.arguments) {
+                parameter.assign(argument);
+            }
+            match body.clone().exit() {
+                Some(Exit::Return(value)) => break Some(value),
+                Some(Exit::TailCall) => {}
+                None => break None,
+            }
+        };
+        drop(activation);
+        if let Some(saved) = saved {
+            for (value, saved) in self.frame.borrow().iter().zip(&saved) {
+                value.assign(saved);
+            }
+        }
+        ret
+    }
+    /// 値を返す関数の `call`
+    pub fn value(&self) -> Expr::Output {
+        self.call().unwrap_or_else(|| {
+            self.fail(format!(
+                "function `{}` ended without returning a value",
+                self.name
+            ))
+        })
+    }
+}
+
+pub enum RealFunction {
+    Primitive0(fn() -> f64),
+    Primitive1(fn(f64) -> f64, RcCell<f64>),
+    Primitive2(fn(f64, f64) -> f64, RcCell<f64>, RcCell<f64>),
+    TimeAtBar(RcRefCell<Timeline>, RcCell<f64>),
+    TimeAtBeat(RcRefCell<Timeline>, RcCell<f64>),
+    Analysis(fn(&[f64], f64) -> f64, RcRefCell<Sound>, RcCell<f64>),
+    Duration(RcRefCell<Sound>),
+    Len(RcRefCell<Vec<f64>>),
+    SoundLen(RcRefCell<Vec<Sound>>),
+    StringLen(RcRefCell<String>),
+    RenderTime(Instant),
+    Samplerate(RcCell<f64>),
+    UserDefined(Rc<UserDefined<RealExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl RealFunction {
+    /// 評価できない引数ならば `Err` ．`evaluate` の前に呼ぶ
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            RealFunction::Analysis(_, sound, seconds)
+                if sound.borrow().duration().is_none() && !bounded(seconds.get()) =>
+            {
+                return Err("cannot analyze an infinite sound (specify `seconds`)".to_string());
+            }
+            RealFunction::Dynamic(dynamic) => match &dynamic.current().body {
+                Body::Real(function) => function.check()?,
+                _ => unreachable!(),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+    pub fn evaluate(&self) -> f64 {
+        match self {
+            RealFunction::Primitive0(fnc) => fnc(),
+            RealFunction::Primitive1(fnc, x) => fnc(x.get()),
+            RealFunction::Primitive2(fnc, x, y) => fnc(x.get(), y.get()),
+            RealFunction::TimeAtBar(timeline, x) => timeline.borrow().time_at_bar(x.get()),
+            RealFunction::TimeAtBeat(timeline, x) => timeline.borrow().time_at_beat(x.get()),
+            RealFunction::Analysis(fnc, sound, seconds) => {
+                let (samples, samplerate) = analyzed(&sound.borrow(), seconds.get())
+                    .expect("checked by `RealFunction::check`");
+                fnc(&samples, samplerate)
+            }
+            RealFunction::Duration(sound) => sound.borrow().duration().unwrap_or(f64::INFINITY),
+            RealFunction::Len(array) => array.borrow().len() as f64,
+            RealFunction::SoundLen(sounds) => sounds.borrow().len() as f64,
+            RealFunction::StringLen(string) => string.borrow().chars().count() as f64,
+            RealFunction::RenderTime(start) => start.elapsed().as_secs_f64(),
+            RealFunction::Samplerate(samplerate) => samplerate.get(),
+            RealFunction::UserDefined(function) => function.value(),
+            RealFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Real(function) => function.evaluate(),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+/// 秒数が有限か．実数の式は無限大を `f64::MAX` に丸めるので，それも無限とみなす
+fn bounded(seconds: f64) -> bool {
+    seconds < f64::MAX
+}
+
+/// 解析する標本列と標本化周波数．
+/// 長さは `seconds` と音の長さの短い方．どちらも無限ならば `Err`
+fn analyzed(sound: &Sound, seconds: f64) -> Result<(Vec<f64>, f64), String> {
+    let samplerate = match sound {
+        Sound::Samples { samplerate, .. } => *samplerate,
+        _ => DEFAULT_SAMPLERATE,
+    };
+    let seconds = match sound.duration() {
+        Some(duration) => duration.min(seconds),
+        None if bounded(seconds) => seconds,
+        None => return Err("cannot analyze an infinite sound (specify `seconds`)".to_string()),
+    };
+    Ok((sound.clone().render(seconds, samplerate), samplerate))
+}
+
+pub enum ArrayFunction {
+    /// 各要素を関数に通す
+    Map(RcRefCell<Vec<f64>>, RcRefCell<Rc<Function>>),
+    VoiceLead(RcRefCell<Vec<f64>>, RcRefCell<Vec<f64>>),
+    Invert(RcRefCell<Vec<f64>>, RcCell<f64>),
+    Drop(RcRefCell<Vec<f64>>, RcCell<f64>),
+    Spread(RcRefCell<Vec<f64>>),
+    Push(RcRefCell<Vec<f64>>, RcCell<f64>),
+    Analysis(fn(&[f64], f64) -> Vec<f64>, RcRefCell<Sound>, RcCell<f64>),
+    UserDefined(Rc<UserDefined<ArrayExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl ArrayFunction {
+    pub fn evaluate(&self) -> Result<Vec<f64>, String> {
+        Ok(match self {
+            ArrayFunction::VoiceLead(prev, next) => {
+                chord::voice_lead(&prev.borrow(), &next.borrow())
+            }
+            ArrayFunction::Invert(chord, n) => {
+                chord::invert(&chord.borrow(), coercion::integer(n.get(), "inversion")?)
+            }
+            ArrayFunction::Drop(chord, n) => {
+                chord::drop(&chord.borrow(), coercion::integer(n.get(), "drop")?)
+            }
+            ArrayFunction::Spread(chord) => chord::spread(&chord.borrow()),
+            ArrayFunction::Push(array, x) => {
+                let mut array = array.borrow().clone();
+                array.push(x.get());
+                array
+            }
+            ArrayFunction::Analysis(fnc, sound, seconds) => {
+                let (samples, samplerate) = analyzed(&sound.borrow(), seconds.get())?;
+                fnc(&samples, samplerate)
+            }
+            ArrayFunction::UserDefined(function) => function.value(),
+            ArrayFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Array(function) => function.evaluate()?,
+                _ => unreachable!(),
+            },
+            ArrayFunction::Map(array, function) => {
+                let function = function.borrow().clone();
+                let array = array.borrow().clone();
+                array
+                    .into_iter()
+                    .map(|x| {
+                        function.bind(&[Value::from(x)]);
+                        match &function.body {
+                            Body::Real(function) => {
+                                function.check()?;
+                                Ok(function.evaluate())
+                            }
+                            _ => unreachable!(),
+                        }
+                    })
+                    .collect::<Result<_, String>>()?
+            }
+        })
+    }
+}
+
+pub enum SoundArrayFunction {
+    Map(RcRefCell<Vec<Sound>>, RcRefCell<Rc<Function>>),
+    SplitBands(RcRefCell<Sound>, RcRefCell<Vec<f64>>),
+    Push(RcRefCell<Vec<Sound>>, RcRefCell<Sound>),
+    UserDefined(Rc<UserDefined<SoundArrayExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl SoundArrayFunction {
+    pub fn evaluate(&self) -> Result<Vec<Sound>, String> {
+        Ok(match self {
+            SoundArrayFunction::SplitBands(sound, crossovers) => {
+                filter::split_bands(sound.borrow().clone(), &crossovers.borrow())?
+            }
+            SoundArrayFunction::Push(sounds, sound) => {
+                let mut sounds = sounds.borrow().clone();
+                sounds.push(sound.borrow().clone());
+                sounds
+            }
+            SoundArrayFunction::UserDefined(function) => function.value(),
+            SoundArrayFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::SoundArray(function) => function.evaluate()?,
+                _ => unreachable!(),
+            },
+            SoundArrayFunction::Map(sounds, function) => {
+                let function = function.borrow().clone();
+                let sounds = sounds.borrow().clone();
+                sounds
+                    .into_iter()
+                    .map(|sound| {
+                        function.bind(&[Value::from(sound)]);
+                        match &function.body {
+                            Body::Sound(function) => {
+                                function.check()?;
+                                function.evaluate()
+                            }
+                            _ => unreachable!(),
+                        }
+                    })
+                    .collect::<Result<_, String>>()?
+            }
+        })
+    }
+}
+
+pub enum RecordFunction {
+    LoadPreset(RcRefCell<String>, RcRefCell<Vec<(String, f64)>>),
+    UserDefined(Rc<UserDefined<RecordExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl RecordFunction {
+    /// 読み込みに失敗したらエラーを返す（呼び出し位置をつけて報告する）
+    pub fn evaluate(&self) -> Result<Vec<(String, f64)>, String> {
+        match self {
+            RecordFunction::LoadPreset(filename, schema) => {
+                let filename = paths::resolve_lossy(&filename.borrow());
+                let json = std::fs::read_to_string(&filename)
+                    .map_err(|err| format!("cannot read {}: {}", filename, err))?;
+                preset::from_json(&json, &schema.borrow())
+                    .map_err(|err| format!("{}:{}", filename, err))
+            }
+            RecordFunction::UserDefined(function) => Ok(function.value()),
+            RecordFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Record(function) => function.evaluate(),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+/// 整数を返す関数（ `def f() -> int` ）
+pub enum IntFunction {
+    UserDefined(Rc<UserDefined<IntExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl IntFunction {
+    pub fn evaluate(&self) -> i64 {
+        match self {
+            IntFunction::UserDefined(function) => function.value(),
+            IntFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Int(function) => function.evaluate(),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+pub enum BooleanFunction {
+    HasFeature(RcRefCell<String>),
+    UserDefined(Rc<UserDefined<BooleanExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl BooleanFunction {
+    pub fn evaluate(&self) -> bool {
+        match self {
+            BooleanFunction::HasFeature(name) => features::has(&name.borrow()),
+            BooleanFunction::UserDefined(function) => function.value(),
+            BooleanFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Boolean(function) => function.evaluate(),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+pub enum SoundFunction {
+    Sin(RcRefCell<Sound>, RcCell<f64>),
+    Linear(RcCell<f64>, RcCell<f64>, ClampArguments),
+    Exp(RcCell<f64>, ClampArguments),
+    Oscillator {
+        /// 矩形波の `duty` は引数で置き換える
+        waveform: Waveform,
+        frequency: RcRefCell<Sound>,
+        phase: RcCell<f64>,
+        duty: RcCell<f64>,
+        bandlimit: RcCell<bool>,
+    },
+    Noise(RcCell<f64>),
+    Sync(RcRefCell<Sound>, RcRefCell<Sound>),
+    #[cfg(feature = "record")]
+    Record {
+        input: RcRefCell<Box<dyn AudioInput>>,
+        seconds: RcCell<f64>,
+        samplerate: RcCell<f64>,
+        gate: RcCell<f64>,
+    },
+    Filter {
+        sound: RcRefCell<Sound>,
+        cutoff: RcCell<f64>,
+        q: RcCell<f64>,
+        filter: fn(f64, f64) -> Filter,
+    },
+    Delay {
+        sound: RcRefCell<Sound>,
+        time: RcCell<f64>,
+        feedback: RcCell<f64>,
+        mix: RcCell<f64>,
+    },
+    Lag {
+        sound: RcRefCell<Sound>,
+        rise: RcCell<f64>,
+        fall: RcCell<f64>,
+    },
+    Reverb {
+        sound: RcRefCell<Sound>,
+        size: RcCell<f64>,
+        damp: RcCell<f64>,
+        mix: RcCell<f64>,
+    },
+    Render(RcRefCell<Sound>, RcCell<f64>, RcCell<f64>),
+    Steps {
+        sound: RcRefCell<Sound>,
+        pattern: RcRefCell<String>,
+        step: RcCell<f64>,
+        chance: RcCell<f64>,
+        every: RcCell<f64>,
+        variation: RcRefCell<String>,
+        jitter: RcCell<f64>,
+        velocity_jitter: RcCell<f64>,
+        seed: RcCell<f64>,
+    },
+    Adsr {
+        attack: RcCell<f64>,
+        decay: RcCell<f64>,
+        sustain: RcCell<f64>,
+        release: RcCell<f64>,
+        length: RcCell<f64>,
+    },
+    Pan(RcRefCell<Sound>, RcRefCell<Sound>),
+    Route(RcRefCell<Sound>, fn(Sound) -> Sound),
+    Read(RcRefCell<String>),
+    Probe {
+        sound: RcRefCell<Sound>,
+        name: RcRefCell<String>,
+        waveform: RcCell<bool>,
+        probes: RcRefCell<Probes>,
+    },
+    Compress {
+        sound: RcRefCell<Sound>,
+        sidechain: RcRefCell<Sound>,
+        threshold: RcCell<f64>,
+        ratio: RcCell<f64>,
+        attack: RcCell<f64>,
+        release: RcCell<f64>,
+        makeup: RcCell<f64>,
+        lookahead: RcCell<f64>,
+    },
+    Follow(RcRefCell<Sound>, RcCell<f64>, RcCell<f64>),
+    Gate {
+        sound: RcRefCell<Sound>,
+        sidechain: RcRefCell<Sound>,
+        threshold: RcCell<f64>,
+        attack: RcCell<f64>,
+        hold: RcCell<f64>,
+        release: RcCell<f64>,
+    },
+    Binaural(RcRefCell<Sound>, RcCell<f64>),
+    Reverse(RcRefCell<Sound>),
+    Trim(RcRefCell<Sound>, RcCell<f64>, RcCell<f64>),
+    Append(RcRefCell<Sound>, RcRefCell<Sound>),
+    FadeEdges(RcRefCell<Sound>, RcCell<f64>),
+    Freeze(RcRefCell<Sound>),
+    Vocode(RcRefCell<Sound>, RcRefCell<Sound>, RcCell<f64>),
+    TrackAndResynth(RcRefCell<Sound>, RcRefCell<Sound>),
+    MergeBands(RcRefCell<Vec<Sound>>),
+    Seq(RcRefCell<Vec<Sound>>, RcCell<f64>),
+    Unison {
+        oscillator: RcRefCell<Sound>,
+        voices: RcCell<f64>,
+        detune: RcCell<f64>,
+        spread: RcCell<f64>,
+        seed: RcCell<f64>,
+    },
+    Repeat(RcRefCell<Sound>, RcCell<f64>),
+    Shift(RcRefCell<Sound>, RcCell<f64>),
+    Multisample {
+        zones: RcRefCell<Vec<(String, Sound)>>,
+        note: RcCell<f64>,
+        velocity: RcCell<f64>,
+        /// 高さごとに次に鳴らすラウンドロビンの番号
+        rounds: RcRefCell<HashMap<i64, usize>>,
+    },
+    Sample {
+        registry: RcRefCell<samples::Registry>,
+        name: RcRefCell<String>,
+        loop_start: RcCell<f64>,
+        loop_end: RcCell<f64>,
+        looping: RcCell<bool>,
+    },
+    UserDefined(Rc<UserDefined<SoundExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+
+impl SoundFunction {
+    /// 実数の引数 `cell` の代わりに，標本ごとに変わる音を渡せるか（フィルタの `cutoff` ）
+    pub fn modulatable(&self, cell: &RcCell<f64>) -> bool {
+        match self {
+            SoundFunction::Filter { cutoff, .. } => Rc::ptr_eq(cutoff, cell),
+            _ => false,
+        }
+    }
+    /// `modulatable` な引数に音 `sounds` を渡して音を作る
+    pub fn modulate(&self, sounds: Vec<(RcCell<f64>, Sound)>) -> Result<Sound, String> {
+        match (self, &sounds[..]) {
+            (
+                SoundFunction::Filter {
+                    sound, q, filter, ..
+                },
+                [(_, cutoff)],
+            ) => {
+                if limits::finite("q", q.get())? <= 0. {
+                    return Err(format!("`q` must be positive (got {})", q.get()));
+                }
+                Ok(Sound::ModulatedFilter {
+                    sound: sound.borrow().clone().into(),
+                    cutoff: cutoff.clone().into(),
+                    filter: filter(filter::LOWEST_FREQUENCY, q.get()),
+                })
+            }
+            _ => unreachable!(),
+        }
+    }
+    /// 引数が音を作れる値か確かめる（ `limits` ）
+    pub fn check(&self) -> Result<(), String> {
+        let nonzero = |name: &str, value: f64| {
+            if limits::finite(name, value)? == 0. {
+                return Err(format!("`{}` must not be zero", name));
+            }
+            Ok(())
+        };
+        match self {
+            SoundFunction::Sin(frequency, phase) => {
+                limits::finite("phase", phase.get())?;
+                if let Sound::Const(frequency) = *frequency.borrow() {
+                    limits::frequency("frequency", frequency)?;
+                }
+            }
+            SoundFunction::Linear(x1, t1, clamp) => {
+                limits::finite("value", x1.get())?;
+                nonzero("t", t1.get())?;
+                clamp.check()?;
+            }
+            SoundFunction::Exp(time, clamp) => {
+                nonzero("time", time.get())?;
+                clamp.check()?;
+            }
+            SoundFunction::Oscillator {
+                frequency,
+                phase,
+                duty,
+                ..
+            } => {
+                if let Sound::Const(frequency) = *frequency.borrow() {
+                    limits::frequency("frequency", frequency)?;
+                }
+                limits::finite("phase", phase.get())?;
+                if !(0. < duty.get() && duty.get() < 1.) {
+                    return Err(format!(
+                        "`duty` must be between 0 and 1 (got {})",
+                        duty.get()
+                    ));
+                }
+            }
+            SoundFunction::Sync(master, _) if oscillation(&master.borrow()).is_none() => {
+                return Err(
+                    "the master of `sync` must be an oscillator (Sin, square, saw or triangle)"
+                        .to_string(),
+                );
+            }
+            SoundFunction::Filter { cutoff, q, .. } => {
+                if limits::frequency("cutoff", cutoff.get())? == 0. {
+                    return Err("`cutoff` must be positive".to_string());
+                }
+                if limits::finite("q", q.get())? <= 0. {
+                    return Err(format!("`q` must be positive (got {})", q.get()));
+                }
+            }
+            SoundFunction::Seq(sounds, glide) => {
+                limits::seconds("glide", glide.get())?;
+                if let Some(i) = sounds.borrow().iter().position(|s| s.duration().is_none()) {
+                    return Err(format!(
+                        "`seq` needs sounds of finite length (sound {} is infinite)",
+                        i
+                    ));
+                }
+            }
+            SoundFunction::Repeat(sound, count) => match sound.borrow().duration() {
+                Some(duration) => {
+                    limits::seconds("count * duration", count.get().max(0.) * duration)?;
+                }
+                None => return Err("`repeat` needs a sound of finite length".to_string()),
+            },
+            SoundFunction::Shift(_, t) => {
+                limits::finite("t", t.get())?;
+            }
+            SoundFunction::Unison {
+                oscillator,
+                voices,
+                detune,
+                spread,
+                seed,
+            } => {
+                if oscillation(&oscillator.borrow()).is_none()
+                    && !matches!(*oscillator.borrow(), Sound::Fm { .. })
+                {
+                    return Err(
+                        "`unison` needs an oscillator (Sin, square, saw or triangle)".to_string(),
+                    );
+                }
+                if !(1. ..=MAX_VOICES).contains(&voices.get()) {
+                    return Err(format!(
+                        "`voices` must be between 1 and {} (got {})",
+                        MAX_VOICES,
+                        voices.get()
+                    ));
+                }
+                if limits::finite("detune", detune.get())? < 0. {
+                    return Err(format!(
+                        "`detune` must not be negative (got {} cents)",
+                        detune.get()
+                    ));
+                }
+                limits::unit("spread", spread.get())?;
+                if !seed.get().is_nan() {
+                    limits::finite("seed", seed.get())?;
+                }
+            }
+            // `smooth` は上りと下りで同じ引数を使う
+            SoundFunction::Lag { rise, fall, .. } if Rc::ptr_eq(rise, fall) => {
+                limits::seconds("time", rise.get())?;
+            }
+            SoundFunction::Lag { rise, fall, .. } => {
+                limits::seconds("rise", rise.get())?;
+                limits::seconds("fall", fall.get())?;
+            }
+            SoundFunction::Delay {
+                time,
+                feedback,
+                mix,
+                ..
+            } => {
+                if limits::seconds("time", time.get())? == 0. {
+                    return Err("`time` must be positive".to_string());
+                }
+                if limits::finite("feedback", feedback.get())?.abs() >= 1. {
+                    return Err(format!(
+                        "`feedback` must be between -1 and 1 exclusive (got {})",
+                        feedback.get()
+                    ));
+                }
+                limits::unit("mix", mix.get())?;
+            }
+            SoundFunction::Reverb {
+                size, damp, mix, ..
+            } => {
+                limits::unit("size", size.get())?;
+                limits::unit("damp", damp.get())?;
+                limits::unit("mix", mix.get())?;
+            }
+            SoundFunction::Noise(seed) if !seed.get().is_nan() => {
+                limits::finite("seed", seed.get())?;
+            }
+            SoundFunction::Render(_, seconds, samplerate) => {
+                limits::seconds("seconds", seconds.get())?;
+                limits::samplerate(samplerate.get())?;
+            }
+            #[cfg(feature = "record")]
+            SoundFunction::Record {
+                seconds,
+                samplerate,
+                gate,
+                ..
+            } => {
+                limits::seconds("seconds", seconds.get())?;
+                limits::samplerate(samplerate.get())?;
+                if !gate.get().is_nan() {
+                    limits::finite("gate", gate.get())?;
+                }
+            }
+            SoundFunction::Steps { step, .. } => {
+                limits::seconds("step", step.get())?;
+            }
+            SoundFunction::Adsr {
+                attack,
+                decay,
+                sustain,
+                release,
+                length,
+            } => {
+                limits::seconds("attack", attack.get())?;
+                limits::seconds("decay", decay.get())?;
+                limits::finite("sustain", sustain.get())?;
+                limits::seconds("release", release.get())?;
+                if !length.get().is_nan() {
+                    limits::seconds("length", length.get())?;
+                }
+            }
+            SoundFunction::Compress {
+                attack,
+                release,
+                lookahead,
+                ..
+            } => {
+                limits::seconds("attack", attack.get())?;
+                limits::seconds("release", release.get())?;
+                limits::finite("lookahead", lookahead.get())?;
+            }
+            SoundFunction::Follow(_, attack, release) => {
+                limits::seconds("attack", attack.get())?;
+                limits::seconds("release", release.get())?;
+            }
+            SoundFunction::Gate {
+                attack,
+                hold,
+                release,
+                ..
+            } => {
+                limits::seconds("attack", attack.get())?;
+                limits::seconds("hold", hold.get())?;
+                limits::seconds("release", release.get())?;
+            }
+            SoundFunction::Binaural(_, azimuth) => {
+                limits::finite("azimuth", azimuth.get())?;
+            }
+            SoundFunction::Reverse(sound) => buffer::check("buffer", &sound.borrow())?,
+            SoundFunction::Freeze(sound) => buffer::check("sound", &sound.borrow())?,
+            SoundFunction::Trim(sound, start, end) => {
+                buffer::check("buffer", &sound.borrow())?;
+                if limits::seconds("end", end.get())? < limits::seconds("start", start.get())? {
+                    return Err(format!(
+                        "`end` must not be before `start` (got {} s and {} s)",
+                        end.get(),
+                        start.get()
+                    ));
+                }
+            }
+            SoundFunction::Append(a, b) => {
+                buffer::check("a", &a.borrow())?;
+                buffer::check("b", &b.borrow())?;
+            }
+            SoundFunction::FadeEdges(sound, ms) => {
+                buffer::check("buffer", &sound.borrow())?;
+                if limits::finite("ms", ms.get())? < 0. {
+                    return Err(format!("`ms` must not be negative (got {} ms)", ms.get()));
+                }
+            }
+            // 引数を写すのはここ（ `evaluate` の前に必ず呼ばれる）
+            SoundFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Sound(function) => function.check()?,
+                _ => unreachable!(),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+    /// 失敗したら `Err` ．引数の範囲は `check` で確かめてある
+    pub fn evaluate(&self) -> Result<Sound, String> {
+        Ok(match self {
+            SoundFunction::Sin(frequency, phase) => match *frequency.borrow() {
+                Sound::Const(frequency) => Sound::Sin {
+                    frequency,
+                    phase: TAU * phase.get(),
+                },
+                ref frequency => Sound::Fm {
+                    waveform: None,
+                    frequency: frequency.clone().into(),
+                    phase: phase.get().rem_euclid(1.),
+                    bandlimit: false,
+                    offset: 0.,
+                },
+            },
+            SoundFunction::Linear(x1, t1, clamp) => {
+                let slope = x1.get() / t1.get();
+                clamp.apply(
+                    Sound::Linear {
+                        slope,
+                        intercept: 0.,
+                    },
+                    |t| slope * t,
+                    t1.get(),
+                )
+            }
+            SoundFunction::Exp(time, clamp) => {
+                let slope = time.get().recip();
+                clamp.apply(
+                    Sound::Exp {
+                        slope,
+                        intercept: 0.,
+                    },
+                    |t| (slope * t).exp(),
+                    f64::INFINITY,
+                )
+            }
+            SoundFunction::Render(sound, seconds, samplerate) => {
+                let sound = sound.borrow().clone();
+                let render = |sound: Sound| Sound::Samples {
+                    samples: sound.render(seconds.get(), samplerate.get()).into(),
+                    samplerate: samplerate.get(),
+                    offset: 0.,
+                    looping: None,
+                };
+                if sound.channels() == 2 {
+                    Sound::Stereo(
+                        render(sound.clone().channel(0)).into(),
+                        render(sound.channel(1)).into(),
+                    )
+                } else {
+                    render(sound)
+                }
+            }
+            SoundFunction::Oscillator {
+                waveform,
+                frequency,
+                phase,
+                duty,
+                bandlimit,
+            } => {
+                let waveform = match waveform {
+                    Waveform::Square { .. } => Waveform::Square { duty: duty.get() },
+                    waveform => *waveform,
+                };
+                match *frequency.borrow() {
+                    Sound::Const(frequency) => Sound::Oscillator {
+                        waveform,
+                        frequency,
+                        phase: phase.get().rem_euclid(1.),
+                        bandlimit: bandlimit.get(),
+                    },
+                    ref frequency => Sound::Fm {
+                        waveform: Some(waveform),
+                        frequency: frequency.clone().into(),
+                        phase: phase.get().rem_euclid(1.),
+                        bandlimit: bandlimit.get(),
+                        offset: 0.,
+                    },
+                }
+            }
+            SoundFunction::Filter {
+                sound,
+                cutoff,
+                q,
+                filter,
+            } => Sound::Filter {
+                sound: sound.borrow().clone().into(),
+                filter: filter(cutoff.get(), q.get()),
+            },
+            SoundFunction::Delay {
+                sound,
+                time,
+                feedback,
+                mix,
+            } => Sound::Effect {
+                sound: sound.borrow().clone().into(),
+                effect: Effect::Delay {
+                    time: time.get(),
+                    feedback: feedback.get(),
+                    mix: mix.get(),
+                },
+            },
+            SoundFunction::Lag { sound, rise, fall } => Sound::Effect {
+                sound: sound.borrow().clone().into(),
+                effect: Effect::Lag {
+                    rise: rise.get(),
+                    fall: fall.get(),
+                },
+            },
+            SoundFunction::Reverb {
+                sound,
+                size,
+                damp,
+                mix,
+            } => Sound::Effect {
+                sound: sound.borrow().clone().into(),
+                effect: Effect::Reverb {
+                    size: size.get(),
+                    damp: damp.get(),
+                    mix: mix.get(),
+                },
+            },
+            SoundFunction::Sync(master, slave) => {
+                let (frequency, phase) = oscillation(&master.borrow()).unwrap();
+                Sound::Sync {
+                    frequency,
+                    phase,
+                    slave: slave.borrow().clone().into(),
+                }
+            }
+            SoundFunction::Noise(seed) => Sound::Noise {
+                seed: if seed.get().is_nan() {
+                    rand::random()
+                } else {
+                    seed.get() as u64
+                },
+                offset: 0.,
+            },
+            SoundFunction::Adsr {
+                attack,
+                decay,
+                sustain,
+                release,
+                length,
+            } => Sound::Adsr {
+                envelope: Adsr {
+                    attack: attack.get(),
+                    decay: decay.get(),
+                    sustain: sustain.get(),
+                    release: release.get(),
+                    length: if length.get().is_nan() {
+                        f64::INFINITY
+                    } else {
+                        length.get()
+                    },
+                },
+                offset: 0.,
+            },
+            SoundFunction::Pan(sound, position) => {
+                spatial::pan(sound.borrow().clone(), position.borrow().clone())
+            }
+            SoundFunction::Route(sound, route) => route(sound.borrow().clone()),
+            #[cfg(feature = "record")]
+            SoundFunction::Record {
+                input,
+                seconds,
+                samplerate,
+                gate,
+            } => {
+                let recorded = Sound::Samples {
+                    samples: input
+                        .borrow_mut()
+                        .capture(seconds.get(), samplerate.get())?
+                        .into(),
+                    samplerate: samplerate.get(),
+                    offset: 0.,
+                    looping: None,
+                };
+                if gate.get().is_nan() {
+                    return Ok(recorded);
+                }
+                // 話し声や楽器の切れ目で途切れない程度に保つ
+                Sound::Dynamics {
+                    sound: recorded.clone().into(),
+                    sidechain: recorded.into(),
+                    dynamics: Dynamics::Gate(Gate {
+                        threshold: gate.get(),
+                        attack: 0.001,
+                        hold: 0.05,
+                        release: 0.05,
+                    }),
+                }
+            }
+            SoundFunction::Read(filename) => {
+                samples::read(&paths::resolve_lossy(&filename.borrow()))?
+            }
+            SoundFunction::Probe {
+                sound,
+                name,
+                waveform,
+                probes,
+            } => Sound::Probe {
+                sound: sound.borrow().clone().into(),
+                probe: probes.borrow_mut().get(&name.borrow(), waveform.get()),
+            },
+            SoundFunction::Compress {
+                sound,
+                sidechain,
+                threshold,
+                ratio,
+                attack,
+                release,
+                makeup,
+                lookahead,
+            } => {
+                // 先読みの分だけ音を遅らせ，その遅延を報告する
+                let lookahead = lookahead.get().max(0.);
+                let compressed = Sound::Dynamics {
+                    sound: sound.borrow().clone().shift(-lookahead).into(),
+                    sidechain: sidechain.borrow().clone().into(),
+                    dynamics: Dynamics::Compress(Compressor {
+                        threshold: threshold.get(),
+                        ratio: ratio.get(),
+                        attack: attack.get(),
+                        release: release.get(),
+                        makeup: makeup.get(),
+                    }),
+                };
+                if lookahead > 0. {
+                    Sound::Latency {
+                        sound: compressed.into(),
+                        latency: lookahead,
+                    }
+                } else {
+                    compressed
+                }
+            }
+            SoundFunction::Follow(sound, attack, release) => Sound::Dynamics {
+                sound: sound.borrow().clone().into(),
+                sidechain: sound.borrow().clone().into(),
+                dynamics: Dynamics::Follow {
+                    attack: attack.get(),
+                    release: release.get(),
+                },
+            },
+            SoundFunction::Gate {
+                sound,
+                sidechain,
+                threshold,
+                attack,
+                hold,
+                release,
+            } => Sound::Dynamics {
+                sound: sound.borrow().clone().into(),
+                sidechain: sidechain.borrow().clone().into(),
+                dynamics: Dynamics::Gate(Gate {
+                    threshold: threshold.get(),
+                    attack: attack.get(),
+                    hold: hold.get(),
+                    release: release.get(),
+                }),
+            },
+            SoundFunction::Binaural(sound, azimuth) => {
+                spatial::binaural(sound.borrow().clone(), azimuth.get())
+            }
+            SoundFunction::Reverse(sound) => buffer::reverse(&sound.borrow()),
+            SoundFunction::Trim(sound, start, end) => {
+                buffer::trim(&sound.borrow(), start.get(), end.get())
+            }
+            SoundFunction::Append(a, b) => buffer::append(&a.borrow(), &b.borrow()),
+            SoundFunction::FadeEdges(sound, ms) => buffer::fade_edges(&sound.borrow(), ms.get()),
+            SoundFunction::Freeze(sound) => Sound::Frozen {
+                sound: sound.borrow().clone().into(),
+                frozen: Rc::new(RefCell::new(None)),
+                channel: None,
+                offset: 0.,
+            },
+            SoundFunction::TrackAndResynth(buffer, oscillator) => {
+                let buffer = buffer.borrow().clone().channel(0);
+                if buffer.duration().is_none() {
+                    return Err(
+                        "cannot track an infinite sound (record it with `render`)".to_string()
+                    );
+                }
+                let (samples, samplerate) = analyzed(&buffer, f64::INFINITY)?;
+                resynth::track_and_resynth(&samples, samplerate, oscillator.borrow().clone())
+            }
+            SoundFunction::Multisample {
+                zones,
+                note,
+                velocity,
+                rounds,
+            } => {
+                let mut rounds = rounds.borrow_mut();
+                let round = rounds.entry(note.get().round() as i64).or_insert(0);
+                *round += 1;
+                multisample::build(&zones.borrow(), note.get(), velocity.get(), *round - 1)?
+            }
+            SoundFunction::Sample {
+                registry,
+                name,
+                loop_start,
+                loop_end,
+                looping,
+            } => {
+                let name = name.borrow();
+                let sound = match registry.borrow().get(&*name) {
+                    Some(sound) => sound.clone(),
+                    None => {
+                        return Err(format!(
+                            "undefined sample `{}` (declare it in `samples {{ }}`)",
+                            name
+                        ))
+                    }
+                };
+                if !looping.get() {
+                    return Ok(sound.with_looping(None));
+                }
+                let (start, end) = (loop_start.get(), loop_end.get());
+                if start.is_nan() && end.is_nan() {
+                    return Ok(sound);
+                }
+                // 片方だけならば，もう片方はファイルのループ区間か音の端
+                let length = sound.clone().with_looping(None).duration().unwrap_or(0.);
+                let (file_start, file_end) = sound.looping().unwrap_or((0., length));
+                let start = if start.is_nan() { file_start } else { start };
+                let end = if end.is_nan() { file_end } else { end };
+                if !(0. <= start && start < end) {
+                    return Err(format!(
+                        "invalid loop points {} to {} for `{}`",
+                        start, end, name
+                    ));
+                }
+                sound.with_looping(Some((start, end)))
+            }
+            SoundFunction::MergeBands(bands) => bands
+                .borrow()
+                .iter()
+                .cloned()
+                .reduce(|sum, band| Sound::Add(sum.into(), band.into()))
+                .unwrap_or(Sound::Const(0.)),
+            SoundFunction::Seq(sounds, glide) => {
+                let sound = sound::seq(sounds.borrow().iter().cloned());
+                if glide.get() > 0. {
+                    Sound::Effect {
+                        sound: sound.into(),
+                        effect: Effect::Lag {
+                            rise: glide.get(),
+                            fall: glide.get(),
+                        },
+                    }
+                } else {
+                    sound
+                }
+            }
+            SoundFunction::Repeat(sound, count) => {
+                let sound = sound.borrow();
+                let count = coercion::count(count.get(), "count")?;
+                // 長さ 0 の音は何回並べても長さ 0
+                if sound.duration() == Some(0.) {
+                    return Ok(sound::seq(None));
+                }
+                sound::seq(std::iter::repeat_n(sound.clone(), count))
+            }
+            SoundFunction::Shift(sound, t) => sound.borrow().clone().shift(-t.get()),
+            SoundFunction::Unison {
+                oscillator,
+                voices,
+                detune,
+                spread,
+                seed,
+            } => oscillator::unison(
+                &oscillator.borrow(),
+                coercion::count(voices.get(), "voices")?,
+                detune.get(),
+                spread.get(),
+                if seed.get().is_nan() {
+                    rand::random()
+                } else {
+                    seed.get() as u64
+                },
+            )
+            .unwrap(),
+            SoundFunction::Vocode(carrier, modulator, bands) => {
+                let bands = bands.get();
+                if bands.is_nan() || bands < 1. {
+                    return Err(format!(
+                        "the number of vocoder bands must be positive (got {})",
+                        bands
+                    ));
+                }
+                filter::vocode(
+                    carrier.borrow().clone(),
+                    modulator.borrow().clone(),
+                    coercion::count(bands, "the number of vocoder bands")?,
+                )
+            }
+            SoundFunction::Steps {
+                sound,
+                pattern,
+                step,
+                chance,
+                every,
+                variation,
+                jitter,
+                velocity_jitter,
+                seed,
+            } => Sound::Steps {
+                sound: sound.borrow().clone().into(),
+                steps: Rc::new(Steps {
+                    pattern: parse_pattern(&pattern.borrow()),
+                    variation: match coercion::count(every.get(), "every")? {
+                        0 => None,
+                        every => Some((every, parse_pattern(&variation.borrow()))),
+                    },
+                    step: step.get(),
+                    chance: chance.get(),
+                    jitter: jitter.get().abs(),
+                    velocity_jitter: velocity_jitter.get().abs(),
+                    seed: seed.get() as u64,
+                }),
+                offset: 0.,
+            },
+            SoundFunction::UserDefined(function) => function.value(),
+            SoundFunction::Dynamic(dynamic) => match &dynamic.current().body {
+                Body::Sound(function) => function.evaluate()?,
+                _ => unreachable!(),
+            },
+        })
+    }
+}
+
+/// オシレーターの周波数と位相（ 1 周期を 1 とする）
+fn oscillation(sound: &Sound) -> Option<(f64, f64)> {
+    match *sound {
+        Sound::Sin { frequency, phase } => Some((frequency, (phase / TAU).rem_euclid(1.))),
+        Sound::Oscillator {
+            frequency, phase, ..
+        } => Some((frequency, phase)),
+        _ => None,
+    }
+}
+
+/// 初期位相の名前つき引数 `phase`
+fn phase_argument(phase: &RcCell<f64>) -> (String, Argument) {
+    (
+        "phase".to_string(),
+        Argument::Real(phase.clone(), RealExpression::Const(0.)),
+    )
+}
+
+/// ステップシーケンサのパターン文字列を各ステップの音量にする．
+///
+/// - `x` `X` : 音量 1
+/// - `1`-`9` : 音量 1/9 - 1
+/// - 空白と `|` : 読み飛ばす（区切り用）
+/// - それ以外（ `.` `-` など） : 休符
+fn parse_pattern(pattern: &str) -> Vec<f64> {
+    pattern
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '|')
+        .map(|c| match c {
+            'x' | 'X' => 1.,
+            '1'..='9' => c.to_digit(10).unwrap() as f64 / 9.,
+            _ => 0.,
+        })
+        .collect()
+}
+
+pub enum StringFunction {
+    Version,
+    Format {
+        x: RcCell<f64>,
+        digits: RcCell<f64>,
+        width: RcCell<f64>,
+    },
+    UserDefined(Rc<UserDefined<StringExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+impl StringFunction {
+    pub fn evaluate(&self) -> Result<String, String> {
+        Ok(match self {
+            StringFunction::Version => env!("CARGO_PKG_VERSION").to_string(),
+            StringFunction::Format { x, digits, width } => {
+                let string = match digits.get() {
+                    digits if digits.is_nan() => x.get().to_string(),
+                    digits => {
+                        let digits = limits::digits("digits", coercion::count(digits, "digits")?)?;
+                        format!("{:.*}", digits, x.get())
+                    }
+                };
+                let width = limits::digits("width", coercion::count(width.get(), "width")?)?;
+                let (sign, digits) = match string.strip_prefix('-') {
+                    Some(digits) => ("-", digits),
+                    None => ("", string.as_str()),
+                };
+                let zeros = width.saturating_sub(sign.len() + digits.len());
+                format!("{}{}{}", sign, "0".repeat(zeros), digits)
+            }
+            StringFunction::UserDefined(function) => function.value(),
+            StringFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::String(function) => function.evaluate()?,
+                _ => unreachable!(),
+            },
+        })
+    }
+}
+
+/// `write` の名前つき引数 `title` `artist` `comment` `time_reference`
+pub struct MetadataArguments {
+    title: RcRefCell<String>,
+    artist: RcRefCell<String>,
+    comment: RcRefCell<String>,
+    time_reference: RcCell<f64>,
+}
+impl MetadataArguments {
+    fn new() -> MetadataArguments {
+        MetadataArguments {
+            title: Rc::new(RefCell::new("".to_string())),
+            artist: Rc::new(RefCell::new("".to_string())),
+            comment: Rc::new(RefCell::new("".to_string())),
+            time_reference: Rc::new(Cell::new(0.)),
+        }
+    }
+    fn named_arguments(&self) -> Vec<(String, Argument)> {
+        let string = |rc: &RcRefCell<String>| {
+            Argument::String(rc.clone(), StringExpression::Const("".to_string()))
+        };
+        vec![
+            ("title".to_string(), string(&self.title)),
+            ("artist".to_string(), string(&self.artist)),
+            ("comment".to_string(), string(&self.comment)),
+            // 指定されなければ bext チャンクは書かない
+            (
+                "time_reference".to_string(),
+                Argument::Real(self.time_reference.clone(), RealExpression::Const(f64::NAN)),
+            ),
+        ]
+    }
+    fn get(&self) -> wav::Metadata {
+        let time_reference = self.time_reference.get();
+        wav::Metadata {
+            title: self.title.borrow().clone(),
+            artist: self.artist.borrow().clone(),
+            comment: self.comment.borrow().clone(),
+            time_reference: if time_reference.is_nan() {
+                None
+            } else {
+                Some(time_reference)
+            },
+        }
+    }
+}
+
+/// `write` の名前つき引数 `master` ．空ならば `set_master` で選んだものを使う
+pub struct MasterArgument {
+    global: RcRefCell<Master>,
+    name: RcRefCell<String>,
+}
+impl MasterArgument {
+    fn new(global: RcRefCell<Master>) -> MasterArgument {
+        MasterArgument {
+            global,
+            name: Rc::new(RefCell::new("".to_string())),
+        }
+    }
+    fn named_argument(&self) -> (String, Argument) {
+        (
+            "master".to_string(),
+            Argument::String(self.name.clone(), StringExpression::Const("".to_string())),
+        )
+    }
+    fn get(&self) -> Result<Master, String> {
+        match self.name.borrow().as_str() {
+            "" => Ok(self.global.borrow().clone()),
+            name => Master::from_name(name, master::DEFAULT_CEILING_DB, master::DEFAULT_RELEASE),
+        }
+    }
+}
+
+/// 名前つき引数 `tail` （ `""` ， `"auto"` ，または秒数）
+pub struct TailArgument {
+    tail: RcRefCell<String>,
+}
+impl TailArgument {
+    fn new() -> TailArgument {
+        TailArgument {
+            tail: Rc::new(RefCell::new("".to_string())),
+        }
+    }
+    fn named_argument(&self) -> (String, Argument) {
+        (
+            "tail".to_string(),
+            Argument::String(self.tail.clone(), StringExpression::Const("".to_string())),
+        )
+    }
+    fn get(&self) -> Result<Tail, String> {
+        Tail::parse(&self.tail.borrow())
+    }
+}
+
+/// 名前つき引数 `clamp` ， `from` ， `to` と `hold` ．どれかを指定すると，時刻 `[from, to)` の外では
+/// 端の値を保つ（終わりの後は `hold` があればその値）．`from` の既定は 0 ，`to` の既定は関数ごとの終わり
+pub struct ClampArguments {
+    clamp: RcCell<bool>,
+    from: RcCell<f64>,
+    to: RcCell<f64>,
+    hold: RcCell<f64>,
+}
+impl ClampArguments {
+    fn new() -> ClampArguments {
+        ClampArguments {
+            clamp: Rc::new(Cell::new(false)),
+            from: Rc::new(Cell::new(0.)),
+            to: Rc::new(Cell::new(0.)),
+            hold: Rc::new(Cell::new(0.)),
+        }
+    }
+    fn named_arguments(&self) -> Vec<(String, Argument)> {
+        let real = |name: &str, rc: &RcCell<f64>| {
+            (
+                name.to_string(),
+                Argument::Real(rc.clone(), RealExpression::Const(f64::NAN)),
+            )
+        };
+        vec![
+            (
+                "clamp".to_string(),
+                Argument::Boolean(
+                    self.clamp.clone(),
+                    BooleanExpression::Reference(Rc::new(Cell::new(false))),
+                ),
+            ),
+            real("from", &self.from),
+            real("to", &self.to),
+            real("hold", &self.hold),
+        ]
+    }
+    fn check(&self) -> Result<(), String> {
+        let (from, to) = (self.from.get(), self.to.get());
+        if from > to {
+            return Err(format!("`from` ({} s) is after `to` ({} s)", from, to));
+        }
+        for (name, value) in [("from", from), ("to", to), ("hold", self.hold.get())] {
+            if !value.is_nan() {
+                limits::finite(name, value)?;
+            }
+        }
+        Ok(())
+    }
+    /// 時刻 `t` の値が `at(t)` で，既定では `end` に終わる `sound`
+    fn apply(&self, sound: Sound, at: impl Fn(f64) -> f64, end: f64) -> Sound {
+        let given = |value: f64| !value.is_nan();
+        let (from, to, hold) = (self.from.get(), self.to.get(), self.hold.get());
+        if !(self.clamp.get() || given(from) || given(to) || given(hold)) {
+            return sound;
+        }
+        let from = if given(from) { from } else { 0. };
+        let to = if given(to) { to } else { end };
+        Sound::Clamp {
+            sound: sound.into(),
+            from,
+            to,
+            before: at(from),
+            after: if given(hold) { hold } else { at(to) },
+            offset: 0.,
+        }
+    }
+}
+
+/// 名前つき引数 `bits` （ 16 ， 24 ， 32 ．0 ならば設定ファイルの `bits` ）， `float` （ 32 ビット浮動小数点数）と
+/// `dither` （ `"none"` ， `"tpdf"` ， `"shaped"` ．空ならば設定ファイルの `dither` ）
+pub struct EncodingArguments {
+    bits: RcCell<f64>,
+    float: RcCell<bool>,
+    dither: RcRefCell<String>,
+}
+impl EncodingArguments {
+    fn new() -> EncodingArguments {
+        EncodingArguments {
+            bits: Rc::new(Cell::new(0.)),
+            float: Rc::new(Cell::new(false)),
+            dither: Rc::new(RefCell::new("".to_string())),
+        }
+    }
+    fn named_arguments(&self) -> Vec<(String, Argument)> {
+        vec![
+            (
+                "bits".to_string(),
+                Argument::Real(self.bits.clone(), RealExpression::Const(0.)),
+            ),
+            (
+                "float".to_string(),
+                Argument::Boolean(
+                    self.float.clone(),
+                    BooleanExpression::Reference(Rc::new(Cell::new(false))),
+                ),
+            ),
+            (
+                "dither".to_string(),
+                Argument::String(self.dither.clone(), StringExpression::Const("".to_string())),
+            ),
+        ]
+    }
+    fn get(&self) -> Result<Encoding, String> {
+        let bits = match self.bits.get() {
+            0. => None,
+            x @ (16. | 24. | 32.) => Some(x as u16),
+            x => {
+                return Err(format!(
+                    "unsupported bit depth {} (expected 16, 24 or 32)",
+                    x
+                ))
+            }
+        };
+        let float = self.float.get();
+        if float && bits.is_some_and(|bits| bits != 32) {
+            return Err(format!(
+                "float output is always 32-bit (got bits = {})",
+                self.bits.get()
+            ));
+        }
+        let dither = match self.dither.borrow().as_str() {
+            "" => None,
+            name => Some(Dither::from_name(name)?),
+        };
+        Ok(Encoding {
+            bits,
+            float,
+            dither,
+        })
+    }
+}
+
+/// 名前つき引数 `normalize` （目標の統合ラウドネス，既定では正規化しない）と `true_peak`
+pub struct NormalizeArguments {
+    loudness: RcCell<f64>,
+    true_peak: RcCell<f64>,
+}
+impl NormalizeArguments {
+    fn new() -> NormalizeArguments {
+        NormalizeArguments {
+            loudness: Rc::new(Cell::new(0.)),
+            true_peak: Rc::new(Cell::new(0.)),
+        }
+    }
+    fn named_arguments(&self) -> Vec<(String, Argument)> {
+        vec![
+            (
+                "normalize".to_string(),
+                Argument::Real(self.loudness.clone(), RealExpression::Const(f64::NAN)),
+            ),
+            (
+                "true_peak".to_string(),
+                Argument::Real(
+                    self.true_peak.clone(),
+                    RealExpression::Const(normalize::DEFAULT_TRUE_PEAK),
+                ),
+            ),
+        ]
+    }
+    fn get(&self) -> Option<normalize::Target> {
+        let loudness = self.loudness.get();
+        if loudness.is_nan() {
+            None
+        } else {
+            Some(normalize::Target {
+                loudness,
+                true_peak: self.true_peak.get(),
+            })
+        }
+    }
+}
+
+/// 名前つき引数 `mkdirs` と `overwrite` ．
+/// `mkdirs` が真ならば書き出し先のディレクトリがなければ作り，
+/// `overwrite` が偽ならば既存のファイルに書き出そうとするとエラーにする
+pub struct FileArguments {
+    mkdirs: RcCell<bool>,
+    overwrite: RcCell<bool>,
+    default_overwrite: RcCell<bool>,
+    out: RcRefCell<Option<String>>,
+}
+impl FileArguments {
+    /// `overwrite` を省略したときは `default_overwrite` （ `--force` ）に従う．
+    /// `out` （ `--out` ）があれば台本のファイル名の代わりに使う
+    fn new(default_overwrite: RcCell<bool>, out: RcRefCell<Option<String>>) -> FileArguments {
+        FileArguments {
+            mkdirs: Rc::new(Cell::new(true)),
+            overwrite: Rc::new(Cell::new(false)),
+            default_overwrite,
+            out,
+        }
+    }
+    fn named_arguments(&self) -> Vec<(String, Argument)> {
+        vec![
+            (
+                "mkdirs".to_string(),
+                Argument::Boolean(
+                    self.mkdirs.clone(),
+                    BooleanExpression::Reference(Rc::new(Cell::new(true))),
+                ),
+            ),
+            (
+                "overwrite".to_string(),
+                Argument::Boolean(
+                    self.overwrite.clone(),
+                    BooleanExpression::Reference(self.default_overwrite.clone()),
+                ),
+            ),
+        ]
+    }
+    fn mkdirs(&self) -> bool {
+        self.mkdirs.get()
+    }
+    fn overwrite(&self) -> bool {
+        self.overwrite.get()
+    }
+    fn filename(&self, filename: &str) -> String {
+        self.out
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| filename.to_string())
+    }
+}
+
+/// ファイル名の `~` を展開し， `{n}` を，まだ書き出していない最小の番号（ 1 から）に置き換える．
+/// 相対パスの置き場所は `output` が決める
+fn numbered(output: &dyn Output, filename: &str) -> String {
+    let filename = paths::expand(filename).to_string_lossy().into_owned();
+    if !filename.contains("{n}") {
+        return filename;
+    }
+    (1..)
+        .map(|n| filename.replace("{n}", &n.to_string()))
+        .find(|candidate| !output.exists(candidate))
+        .unwrap()
+}
+
+pub enum VoidFunction {
+    Write(
+        RcRefCell<Timeline>,
+        RcRefCell<Sound>,
+        RcCell<f64>,
+        RcRefCell<String>,
+        RcCell<f64>,
+        MetadataArguments,
+        MasterArgument,
+        TailArgument,
+        EncodingArguments,
+        NormalizeArguments,
+        FileArguments,
+        RcRefCell<dyn Output>,
+    ),
+    Marker(RcRefCell<Timeline>, RcRefCell<String>, RcCell<f64>),
+    Play {
+        sound: RcRefCell<Sound>,
+        seconds: RcCell<f64>,
+        samplerate: RcCell<f64>,
+        master: MasterArgument,
+        backend: RcRefCell<Box<dyn AudioBackend>>,
+    },
+    Probes(RcRefCell<Probes>),
+    SavePreset(RcRefCell<Vec<(String, f64)>>, RcRefCell<String>),
+    WriteCue(RcRefCell<Timeline>, RcRefCell<String>, RcRefCell<String>),
+    WriteChapters(RcRefCell<Timeline>, RcRefCell<String>),
+    Show(RcRefCell<Sound>, RcCell<f64>),
+    DebugDump {
+        sound: RcRefCell<Sound>,
+        seconds: RcCell<f64>,
+        filename: RcRefCell<String>,
+        spectrum: RcRefCell<String>,
+        block: RcCell<f64>,
+    },
+    Region(
+        RcRefCell<Timeline>,
+        RcRefCell<String>,
+        RcCell<f64>,
+        RcCell<f64>,
+    ),
+    TempoChange(RcRefCell<Timeline>, RcCell<f64>, RcCell<f64>),
+    TempoRamp(RcRefCell<Timeline>, RcCell<f64>, RcCell<f64>, RcCell<f64>),
+    WriteRegion(
+        RcRefCell<Timeline>,
+        RcRefCell<Sound>,
+        RcRefCell<String>,
+        RcRefCell<String>,
+        RcCell<f64>,
+        MasterArgument,
+        TailArgument,
+        EncodingArguments,
+        NormalizeArguments,
+        FileArguments,
+        RcRefCell<dyn Output>,
+    ),
+    SetMaster(
+        RcRefCell<Master>,
+        RcRefCell<String>,
+        RcCell<f64>,
+        RcCell<f64>,
+    ),
+    WriteStems(
+        RcRefCell<Timeline>,
+        RcRefCell<Vec<(String, Sound)>>,
+        RcCell<f64>,
+        RcRefCell<String>,
+        RcCell<f64>,
+        TailArgument,
+        EncodingArguments,
+        FileArguments,
+        RcRefCell<dyn Output>,
+    ),
+    UserDefined(Rc<UserDefined<VoidExpression>>),
+    Dynamic(Rc<Dynamic>),
+}
+impl VoidFunction {
+    /// 書き出しに失敗したらエラーを返す（呼び出し位置をつけて報告する）
+    pub fn evaluate(&self) -> Result<(), String> {
+        match self {
+            VoidFunction::Write(
+                timeline,
+                sound,
+                time,
+                filename,
+                samplerate,
+                metadata,
+                master,
+                tail,
+                encoding,
+                normalize,
+                file,
+                output,
+            ) => {
+                let timeline = timeline.borrow();
+                let time = length([&*sound.borrow()], time.get())?;
+                let window = timeline
+                    .window(time)
+                    .map_err(|name| format!("undefined marker or region `{}`", name))?;
+                let (window, samplerate) = timeline.preview(window, samplerate.get());
+                let mut output = output.borrow_mut();
+                let filename = numbered(&*output, &file.filename(&filename.borrow()));
+                write_wavs(
+                    &mut *output,
+                    vec![(filename, sound.borrow().clone())],
+                    window,
+                    samplerate,
+                    &metadata.get(),
+                    &master.get()?,
+                    tail.get()?,
+                    encoding.get()?,
+                    normalize.get(),
+                    file.mkdirs(),
+                    file.overwrite(),
+                )?;
+            }
+            VoidFunction::Play {
+                sound,
+                seconds,
+                samplerate,
+                master,
+                backend,
+            } => play(
+                &mut **backend.borrow_mut(),
+                sound.borrow().clone(),
+                length([&*sound.borrow()], seconds.get())?,
+                samplerate.get(),
+                &master.get()?,
+            )?,
+            VoidFunction::Probes(probes) => print!("{}", probes.borrow().report()),
+            VoidFunction::SavePreset(record, filename) => {
+                let filename = paths::resolve_lossy(&filename.borrow());
+                std::fs::write(&filename, preset::to_json(&record.borrow()))
+                    .map_err(|err| format!("cannot write {}: {}", filename, err))?;
+            }
+            VoidFunction::WriteCue(timeline, filename, file) => {
+                let file = match file.borrow().as_str() {
+                    "" => std::path::Path::new(&*filename.borrow())
+                        .with_extension("wav")
+                        .file_name()
+                        .map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+                    file => file.to_string(),
+                };
+                let cue = chapters::cue(&timeline.borrow().chapters()?, &file)?;
+                let filename = paths::resolve_lossy(&filename.borrow());
+                std::fs::write(&filename, cue)
+                    .map_err(|err| format!("cannot write {}: {}", filename, err))?;
+            }
+            VoidFunction::WriteChapters(timeline, filename) => {
+                let json = chapters::json(&timeline.borrow().chapters()?);
+                let filename = paths::resolve_lossy(&filename.borrow());
+                std::fs::write(&filename, json)
+                    .map_err(|err| format!("cannot write {}: {}", filename, err))?;
+            }
+            VoidFunction::Show(sound, seconds) => {
+                let samples = sound
+                    .borrow()
+                    .clone()
+                    .render(seconds.get(), DEFAULT_SAMPLERATE);
+                println!("{}", meter::show(&samples));
+            }
+            VoidFunction::DebugDump {
+                sound,
+                seconds,
+                filename,
+                spectrum,
+                block,
+            } => {
+                let sound = sound.borrow().clone();
+                let channels: Vec<_> = (0..sound.channels())
+                    .map(|i| {
+                        sound
+                            .clone()
+                            .channel(i)
+                            .render(seconds.get(), DEFAULT_SAMPLERATE)
+                    })
+                    .collect();
+                let write = |filename: &str, csv: String| {
+                    // 台本のディレクトリの外には書かない
+                    if !bundle::is_relative(std::path::Path::new(filename)) {
+                        return Err(format!(
+                            "cannot dump to {} (only paths inside the script directory are allowed)",
+                            filename
+                        ));
+                    }
+                    std::fs::write(paths::resolve(filename), csv)
+                        .map_err(|err| format!("cannot write {}: {}", filename, err))
+                };
+                write(
+                    &filename.borrow(),
+                    dump::samples_csv(&channels, DEFAULT_SAMPLERATE),
+                )?;
+                if !spectrum.borrow().is_empty() {
+                    let mono = sound.render(seconds.get(), DEFAULT_SAMPLERATE);
+                    let csv = dump::spectra_csv(
+                        &mono,
+                        DEFAULT_SAMPLERATE,
+                        coercion::count(block.get(), "block")?,
+                    )?;
+                    write(&spectrum.borrow(), csv)?;
+                }
+            }
+            VoidFunction::Marker(timeline, name, time) => timeline
+                .borrow_mut()
+                .add_marker(name.borrow().clone(), time.get()),
+            VoidFunction::Region(timeline, name, start, end) => {
+                timeline
+                    .borrow_mut()
+                    .add_region(name.borrow().clone(), start.get(), end.get())
+            }
+            VoidFunction::TempoChange(timeline, bar, bpm) => {
+                timeline.borrow_mut().tempo_change(bar.get(), bpm.get())
+            }
+            VoidFunction::TempoRamp(timeline, from, to, bpm) => {
+                timeline
+                    .borrow_mut()
+                    .tempo_ramp(from.get(), to.get(), bpm.get())
+            }
+            VoidFunction::WriteRegion(
+                timeline,
+                sound,
+                name,
+                filename,
+                samplerate,
+                master,
+                tail,
+                encoding,
+                normalize,
+                file,
+                output,
+            ) => {
+                let timeline = timeline.borrow();
+                let window = timeline
+                    .region(&name.borrow())
+                    .ok_or_else(|| format!("undefined marker or region `{}`", name.borrow()))?;
+                let (window, samplerate) = timeline.preview(window, samplerate.get());
+                let mut output = output.borrow_mut();
+                let filename = numbered(&*output, &file.filename(&filename.borrow()));
+                write_wavs(
+                    &mut *output,
+                    vec![(filename, sound.borrow().clone())],
+                    window,
+                    samplerate,
+                    &wav::Metadata::default(),
+                    &master.get()?,
+                    tail.get()?,
+                    encoding.get()?,
+                    normalize.get(),
+                    file.mkdirs(),
+                    file.overwrite(),
+                )?;
+            }
+            VoidFunction::WriteStems(
+                timeline,
+                tracks,
+                time,
+                directory,
+                samplerate,
+                tail,
+                encoding,
+                file,
+                output,
+            ) => {
+                let timeline = timeline.borrow();
+                let time = length(tracks.borrow().iter().map(|(_, sound)| sound), time.get())?;
+                let window = timeline
+                    .window(time)
+                    .map_err(|name| format!("undefined marker or region `{}`", name))?;
+                let (window, samplerate) = timeline.preview(window, samplerate.get());
+                let directory = paths::expand(&directory.borrow());
+                let mut output = output.borrow_mut();
+                if file.mkdirs() {
+                    output.create_dir_all(&directory)?;
+                }
+                let tracks = tracks
+                    .borrow()
+                    .iter()
+                    .map(|(name, sound)| {
+                        let path = directory.join(format!("{}.wav", name));
+                        (path.to_string_lossy().into_owned(), sound.clone())
+                    })
+                    .collect();
+                // ステムはマスター段の前の音
+                write_wavs(
+                    &mut *output,
+                    tracks,
+                    window,
+                    samplerate,
+                    &wav::Metadata::default(),
+                    &Master::None,
+                    tail.get()?,
+                    encoding.get()?,
+                    None,
+                    false,
+                    file.overwrite(),
+                )?;
+            }
+            VoidFunction::SetMaster(master, name, ceiling, release) => {
+                *master.borrow_mut() =
+                    Master::from_name(&name.borrow(), ceiling.get(), release.get())?;
+            }
+            VoidFunction::UserDefined(function) => {
+                function.call();
+            }
+            VoidFunction::Dynamic(dynamic) => match &dynamic.call().body {
+                Body::Void(function) => function.evaluate()?,
+                _ => unreachable!(),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// 書き出す長さ．`seconds` が `auto` （ NaN ）ならば `sounds` の長さの最大値
+fn length<'a>(sounds: impl IntoIterator<Item = &'a Sound>, seconds: f64) -> Result<f64, String> {
+    if !seconds.is_nan() {
+        return Ok(seconds);
+    }
+    sounds
+        .into_iter()
+        .try_fold(0., |max: f64, sound| Some(sound.duration()?.max(max)))
+        .ok_or_else(|| {
+            "cannot infer the length of an endless sound (give it in seconds)".to_string()
+        })
+}
+
+/// `sound` を `seconds` 秒だけ鳴らす
+fn play(
+    backend: &mut dyn AudioBackend,
+    sound: Sound,
+    seconds: f64,
+    samplerate: f64,
+    master: &Master,
+) -> Result<(), String> {
+    limits::seconds("seconds", seconds)?;
+    limits::samplerate(samplerate)?;
+    limits::check(&sound, samplerate)?;
+    let channels = sound.channels();
+    let mut sink = backend.open(channels, samplerate)?;
+    let sound = sound.clone().shift(sound.latency());
+    let mut iters: Vec<_> = (0..channels)
+        .map(|i| sound.clone().channel(i).iter(samplerate))
+        .collect();
+    let mut processor = master.processor(samplerate);
+    let mut frame = Vec::new();
+    for _ in 0..(seconds * samplerate) as i64 {
+        frame.clear();
+        frame.extend(iters.iter_mut().map(SoundIter::next));
+        processor.process(&mut frame);
+        sink.write(&frame)?;
+    }
+    sink.finalize()
+}
+
+/// 複数の音の `[start, end)` の部分を 1 回の走査でそれぞれ `output` に書き出す．
+/// どれも同じ長さ，同じ開始時刻になる．
+/// 余韻を書き出すときは `end` で音源を止め，その後を `tail` に従って続ける．
+/// `encoding` に従って標本を書き， `normalize` があればラウドネスを正規化する．
+/// `mkdirs` ならば書き出し先のディレクトリを作り， `overwrite` でなければ既存のファイルがあるとエラーにする
+#[allow(clippy::too_many_arguments)]
+fn write_wavs(
+    output: &mut dyn Output,
+    tracks: Vec<(String, Sound)>,
+    (start, end): (f64, f64),
+    samplerate: f64,
+    metadata: &wav::Metadata,
+    master: &Master,
+    tail: Tail,
+    encoding: Encoding,
+    normalize: Option<normalize::Target>,
+    mkdirs: bool,
+    overwrite: bool,
+) -> Result<(), String> {
+    if let Some(format) = graph::explaining() {
+        for (filename, sound) in &tracks {
+            print!("{}", graph::explain(filename, sound, samplerate, format));
+        }
+        return Ok(());
+    }
+    limits::seconds("duration", end - start)?;
+    limits::samplerate(samplerate)?;
+    for (filename, sound) in &tracks {
+        limits::check(sound, samplerate).map_err(|err| format!("{}: {}", filename, err))?;
+    }
+    let started = std::time::Instant::now();
+    let mut tracks = tracks
+        .into_iter()
+        .map(|(filename, sound)| {
+            let channels = sound.channels();
+            if !overwrite && output.exists(&filename) {
+                return Err(format!(
+                    "{} already exists (use `overwrite = true` or --force)",
+                    filename
+                ));
+            }
+            if mkdirs {
+                if let Some(parent) = std::path::Path::new(&filename).parent() {
+                    output.create_dir_all(parent)?;
+                }
+            }
+            let writer = output.create(&filename, channels, samplerate, metadata, &encoding)?;
+            let writer: Box<dyn Sink> = match normalize {
+                Some(target) => Box::new(Normalizer::new(
+                    writer, &filename, channels, samplerate, target,
+                )),
+                None => writer,
+            };
+            // 遅延のある処理を含めば，その分だけ早めて時刻を合わせる
+            let sound = sound.clone().shift(start + sound.latency());
+            let sound = match tail {
+                Tail::None => sound,
+                _ => sound.cut(end - start),
+            };
+            let iters: Vec<_> = (0..channels)
+                .map(|i| sound.clone().channel(i).iter(samplerate))
+                .collect();
+            let limit = tail.limit(sound.tail());
+            let summary = (filename, channels, 0., 0);
+            Ok((writer, iters, master.processor(samplerate), limit, summary))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let length = ((end - start) * samplerate) as i64;
+    let limit = tracks
+        .iter()
+        .map(|(_, _, _, limit, _)| *limit)
+        .fold(0., f64::max);
+    let hold = (tail::HOLD * samplerate) as i64;
+    let mut quiet = 0;
+    let mut frame = Vec::new();
+    let total = length + (limit * samplerate) as i64;
+    // 進み具合を知らせるときだけ，ブロックごとのピークを測る
+    let reporting = progress::active();
+    let mut levels: Vec<Vec<f64>> = tracks
+        .iter()
+        .map(|(_, iters, _, _, _)| vec![0.; if reporting { iters.len() } else { 0 }])
+        .collect();
+    let names: Vec<_> = tracks
+        .iter()
+        .map(|(_, _, _, _, (filename, ..))| filename.clone())
+        .collect();
+    let mut rendered = 0;
+    let report = |levels: &mut [Vec<f64>], rendered: i64, finished: bool| {
+        let peaks = names
+            .iter()
+            .cloned()
+            .zip(levels.iter_mut().map(|levels| {
+                let zeros = vec![0.; levels.len()];
+                std::mem::replace(levels, zeros)
+            }))
+            .collect();
+        progress::report(RenderProgress {
+            rendered: rendered as f64 / samplerate,
+            total: total as f64 / samplerate,
+            peaks,
+            finished,
+        });
+    };
+    for i in 0..total {
+        let mut audible = false;
+        for ((writer, iters, processor, _, (_, _, peak, frames)), levels) in
+            tracks.iter_mut().zip(&mut levels)
+        {
+            frame.clear();
+            frame.extend(iters.iter_mut().map(SoundIter::next));
+            processor.process(&mut frame);
+            writer.write(&frame)?;
+            *peak = frame.iter().fold(*peak, |peak: f64, x| peak.max(x.abs()));
+            *frames += 1;
+            audible |= frame.iter().any(|x| x.abs() >= tail::THRESHOLD);
+            for (level, x) in levels.iter_mut().zip(&frame) {
+                *level = level.max(x.abs());
+            }
+        }
+        rendered = i + 1;
+        if reporting && rendered % progress::BLOCK == 0 {
+            report(&mut levels, rendered, false);
+        }
+        // 余韻がしきい値を下回り続けたら止める
+        if tail == Tail::Auto && i >= length {
+            quiet = if audible { 0 } else { quiet + 1 };
+            if quiet >= hold {
+                break;
+            }
+        }
+    }
+    if reporting {
+        report(&mut levels, rendered, true);
+    }
+    for (writer, _, _, _, (filename, channels, peak, frames)) in tracks {
+        writer.finalize()?;
+        events::record(
+            "output",
+            &[
+                ("file", Field::String(&filename)),
+                ("channels", Field::Number(channels as f64)),
+                ("samplerate", Field::Number(samplerate)),
+                ("duration", Field::Number(frames as f64 / samplerate)),
+                ("peak", Field::Number(peak)),
+                ("seconds", Field::Number(started.elapsed().as_secs_f64())),
+            ],
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,208 @@
+//! 音の出力を記録済みのスナップショットと比べるテスト用の道具（ `test-util` フィーチャー）
+//!
+//! スナップショットは `golden/<名前>.txt` に置く．1 行目が標本列のハッシュ，
+//! 続く行がブロックごとのピークと実効値．
+//! 環境変数 `UPDATE_GOLDEN` を設定して実行すると，比べる代わりにスナップショットを書き直す
+
+use crate::analysis;
+use crate::environment::Environment;
+use crate::lexer::Lexer;
+use crate::parser;
+use crate::sound::Sound;
+use std::path::PathBuf;
+
+/// スナップショットを取るときの標本化周波数
+pub const SAMPLERATE: f64 = 44100.;
+/// エンベロープのブロック数
+const BLOCKS: usize = 16;
+
+/// 比べ方
+#[derive(Clone, Copy, Debug)]
+pub enum Mode {
+    /// 標本列のハッシュが一致しなければならない
+    Exact,
+    /// ブロックごとのピークと実効値の差がそれぞれ `tolerance` 以下ならよい
+    Tolerance(f64),
+}
+
+/// スクリプトを実行し，変数 `variable` の音を `seconds` 秒描画する
+pub fn render(source: &str, variable: &str, seconds: f64) -> Vec<f64> {
+    let mut lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_string())), false);
+    let mut log = Vec::new();
+    let mut environment = Environment::new();
+    loop {
+        let result = match parser::parse_statement(&mut lexer, &mut log) {
+            Ok(Some(statement)) => environment.run(statement),
+            Ok(None) => break,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            let mut message = Vec::new();
+            err.write_diagnostic(&mut message, &log)
+                .expect("cannot print error message");
+            panic!("{}", String::from_utf8_lossy(&message));
+        }
+    }
+    environment
+        .get::<Sound>(variable)
+        .unwrap_or_else(|err| panic!("`{}`: {}", variable, err))
+        .render(seconds, SAMPLERATE)
+}
+
+/// 標本列のスナップショット
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    hash: u64,
+    blocks: Vec<(f64, f64)>,
+}
+
+impl Snapshot {
+    pub fn new(samples: &[f64]) -> Snapshot {
+        // FNV-1a
+        let hash = samples
+            .iter()
+            .flat_map(|x| x.to_bits().to_le_bytes())
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        let blocks = (0..BLOCKS)
+            .map(|i| {
+                let block = &samples[i * samples.len() / BLOCKS..(i + 1) * samples.len() / BLOCKS];
+                (analysis::peak(block), analysis::rms(block))
+            })
+            .collect();
+        Snapshot { hash, blocks }
+    }
+    pub fn parse(text: &str) -> Option<Snapshot> {
+        let mut lines = text.lines();
+        let hash = u64::from_str_radix(lines.next()?.strip_prefix("hash ")?, 16).ok()?;
+        let blocks = lines
+            .map(|line| {
+                let mut words = line.split_whitespace();
+                let peak = words.next()?.parse().ok()?;
+                let rms = words.next()?.parse().ok()?;
+                Some((peak, rms))
+            })
+            .collect::<Option<_>>()?;
+        Some(Snapshot { hash, blocks })
+    }
+    /// スナップショットと比べ，食い違いがあればその説明を返す
+    pub fn compare(&self, expected: &Snapshot, mode: Mode) -> Result<(), String> {
+        match mode {
+            Mode::Exact if self.hash != expected.hash => Err(format!(
+                "hash {:016x} differs from {:016x}",
+                self.hash, expected.hash
+            )),
+            Mode::Exact => Ok(()),
+            Mode::Tolerance(tolerance) => {
+                if self.blocks.len() != expected.blocks.len() {
+                    return Err(format!(
+                        "{} blocks differ from {} blocks",
+                        self.blocks.len(),
+                        expected.blocks.len()
+                    ));
+                }
+                for (i, (actual, expected)) in self.blocks.iter().zip(&expected.blocks).enumerate()
+                {
+                    if (actual.0 - expected.0).abs() > tolerance
+                        || (actual.1 - expected.1).abs() > tolerance
+                    {
+                        return Err(format!(
+                            "block {}: peak/rms {:?} differs from {:?}",
+                            i, actual, expected
+                        ));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "hash {:016x}", self.hash)?;
+        for (peak, rms) in &self.blocks {
+            writeln!(f, "{} {}", peak, rms)?;
+        }
+        Ok(())
+    }
+}
+
+/// `samples` を `golden/<name>.txt` のスナップショットと比べる．食い違えば panic する
+pub fn assert_golden(name: &str, samples: &[f64], mode: Mode) {
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "golden",
+        &format!("{}.txt", name),
+    ]
+    .iter()
+    .collect();
+    let actual = Snapshot::new(samples);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).expect("cannot create golden/");
+        std::fs::write(&path, actual.to_string())
+            .unwrap_or_else(|err| panic!("cannot write {}: {}", path.display(), err));
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| Snapshot::parse(&text))
+        .unwrap_or_else(|| {
+            panic!(
+                "cannot read snapshot {} (run with UPDATE_GOLDEN=1 to create it)",
+                path.display()
+            )
+        });
+    if let Err(message) = actual.compare(&expected, mode) {
+        panic!("golden `{}`: {}", name, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `sample` にある例のうち，乱数を使わないもの
+    const EXAMPLES: &[(&str, &str, f64)] = &[
+        ("saw", "let Saw = Linear(440) % 1;\n", 1.),
+        ("square", "let Square = floor(Linear(2 * 440) % 2);\n", 1.),
+        (
+            "adsr",
+            "let Adsr = .5 * (Sin(440) + Sin(660)) * min(1, Linear(50)) \
+             * (.9 * Exp(-.1) + .1) >> /50 * min(1, Exp(-.1) >> 2);\n",
+            3.,
+        ),
+        ("kick", "let Kick = 1.6e+4 * Sin(5e-3) * Exp(-5e-3);\n", 1.),
+        (
+            "fm",
+            "let Fm = sin(Linear(2 * PI * 220) + 3 * Sin(440));\n",
+            3.,
+        ),
+    ];
+
+    #[test]
+    fn examples() {
+        for (name, source, seconds) in EXAMPLES {
+            let variable = source[4..].split_whitespace().next().unwrap();
+            let samples = render(source, variable, *seconds);
+            assert_golden(name, &samples, Mode::Tolerance(1e-6));
+        }
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let samples: Vec<f64> = (0..100).map(|i| (i as f64 * 0.1).sin()).collect();
+        let snapshot = Snapshot::new(&samples);
+        let parsed = Snapshot::parse(&snapshot.to_string()).unwrap();
+        assert_eq!(parsed, snapshot);
+        assert!(parsed.compare(&snapshot, Mode::Exact).is_ok());
+
+        let mut louder = samples.clone();
+        louder[5] *= 1.01;
+        let louder = Snapshot::new(&louder);
+        assert!(louder.compare(&snapshot, Mode::Exact).is_err());
+        assert!(louder.compare(&snapshot, Mode::Tolerance(0.1)).is_ok());
+        assert!(louder.compare(&snapshot, Mode::Tolerance(1e-6)).is_err());
+    }
+}
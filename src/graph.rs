@@ -0,0 +1,362 @@
+//! 書き出す音の処理のグラフ（ `--explain-graph text | dot` ）
+//!
+//! 音の木の節に，入力を先にして（帰りがけ順に）番号をつける．この順番がそのまま 1 フレームごとの計算の順番．
+//! 費用は 1 標本あたりのおおよその重さ（加算が 1 ，正弦波が 4 ）で，どこが重いかを比べる目安にすぎない．
+//! 遅延はその節までに積み重なった処理の遅延で，書き出すときにはこの分だけ早めて揃える
+
+use crate::dynamics::Dynamics;
+use crate::effects::Effect;
+use crate::filter::Filter;
+use crate::sound::{self, Sound};
+use std::cell::Cell;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    /// Graphviz
+    Dot,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, String> {
+        match name {
+            "text" => Ok(Format::Text),
+            "dot" => Ok(Format::Dot),
+            _ => Err(format!(
+                "unknown graph format `{}` (expected `text` or `dot`)",
+                name
+            )),
+        }
+    }
+}
+
+thread_local! {
+    /// 書き出す代わりにグラフを出力するか
+    static EXPLAIN: Cell<Option<Format>> = const { Cell::new(None) };
+}
+
+pub fn set_explain(format: Option<Format>) {
+    EXPLAIN.with(|cell| cell.set(format));
+}
+
+pub fn explaining() -> Option<Format> {
+    EXPLAIN.with(Cell::get)
+}
+
+struct Node {
+    label: String,
+    /// 入力の節の番号
+    inputs: Vec<usize>,
+    cost: f64,
+    /// この節までの遅延（秒）
+    latency: f64,
+}
+
+/// `sound` とその入力を `nodes` に加えて， `sound` の番号を返す
+fn collect(sound: &Sound, nodes: &mut Vec<Node>) -> usize {
+    let (label, children, cost): (String, Vec<&Sound>, f64) = match sound {
+        Sound::Const(value) => (format!("const {}", value), vec![], 0.),
+        Sound::Linear { slope, intercept } => {
+            (format!("linear {}t + {}", slope, intercept), vec![], 1.)
+        }
+        Sound::Sin { frequency, phase } => {
+            (format!("sin {} Hz phase {}", frequency, phase), vec![], 4.)
+        }
+        Sound::Exp { slope, intercept } => (format!("exp {}t + {}", slope, intercept), vec![], 4.),
+        Sound::Begin(time) => (format!("begin {} s", time), vec![], 0.),
+        Sound::End(time) => (format!("end {} s", time), vec![], 0.),
+        Sound::Rand => ("rand".to_string(), vec![], 2.),
+        Sound::Oscillator {
+            waveform,
+            frequency,
+            bandlimit,
+            ..
+        } => (
+            format!(
+                "{} {} Hz{}",
+                waveform.name(),
+                frequency,
+                if *bandlimit { " band-limited" } else { "" }
+            ),
+            vec![],
+            if *bandlimit { 4. } else { 2. },
+        ),
+        Sound::Noise { seed, .. } => (format!("noise seed {}", seed), vec![], 2.),
+        Sound::Sync {
+            frequency, slave, ..
+        } => (format!("sync {} Hz", frequency), vec![slave], 2.),
+        Sound::Fm {
+            waveform,
+            frequency,
+            ..
+        } => (
+            format!(
+                "{} fm",
+                waveform.map_or_else(|| "sin".to_string(), |waveform| waveform.name())
+            ),
+            vec![frequency],
+            4.,
+        ),
+        Sound::Minus(sound) => ("minus".to_string(), vec![sound], 1.),
+        Sound::Reciprocal(sound) => ("reciprocal".to_string(), vec![sound], 2.),
+        Sound::Add(left, right) => ("add".to_string(), vec![left, right], 1.),
+        Sound::Sub(left, right) => ("sub".to_string(), vec![left, right], 1.),
+        Sound::Mul(left, right) => ("mul".to_string(), vec![left, right], 1.),
+        Sound::Div(left, right) => ("div".to_string(), vec![left, right], 2.),
+        Sound::Pow(left, right) => ("pow".to_string(), vec![left, right], 8.),
+        Sound::Rem(left, right) => ("rem".to_string(), vec![left, right], 1.),
+        Sound::Apply(_, _, sounds) => (
+            "function".to_string(),
+            sounds.iter().map(|(_, sound)| sound).collect(),
+            2.,
+        ),
+        Sound::Steps { sound, steps, .. } => (
+            format!("steps {} x {} s", steps.pattern.len(), steps.step),
+            vec![sound],
+            2.,
+        ),
+        Sound::Samples {
+            samples,
+            samplerate,
+            looping,
+            ..
+        } => (
+            format!(
+                "samples {} s at {} Hz{}",
+                samples.len() as f64 / samplerate,
+                samplerate,
+                if looping.is_some() { " looped" } else { "" }
+            ),
+            vec![],
+            if looping.is_some() { 4. } else { 2. },
+        ),
+        Sound::Clamp {
+            sound,
+            from,
+            to,
+            before,
+            after,
+            ..
+        } => (
+            format!("clamp {} s to {} s hold {} / {}", from, to, before, after),
+            vec![sound],
+            1.,
+        ),
+        Sound::Adsr { envelope, .. } => (
+            format!(
+                "adsr {} {} {} {} length {}",
+                envelope.attack,
+                envelope.decay,
+                envelope.sustain,
+                envelope.release,
+                envelope.length
+            ),
+            vec![],
+            2.,
+        ),
+        Sound::Stereo(left, right) => ("stereo".to_string(), vec![left, right], 0.),
+        Sound::PanLaw { position, right } => {
+            let name = if *right { "pan right" } else { "pan left" };
+            (name.to_string(), vec![position], 4.)
+        }
+        Sound::Latency { sound, latency } => (format!("latency {} s", latency), vec![sound], 0.),
+        Sound::Dynamics {
+            sound,
+            sidechain,
+            dynamics,
+        } => {
+            let name = match dynamics {
+                Dynamics::Follow { .. } => "follow",
+                Dynamics::Compress(_) => "compress",
+                Dynamics::Gate(_) => "gate",
+            };
+            (name.to_string(), vec![sound, sidechain], 8.)
+        }
+        Sound::Filter { sound, filter } => {
+            let (name, frequency, q) = filter_parameters(filter);
+            (
+                format!("{} {} Hz q {}", name, frequency, q),
+                vec![sound],
+                5.,
+            )
+        }
+        // 標本ごとに係数を計算し直す
+        Sound::ModulatedFilter {
+            sound,
+            cutoff,
+            filter,
+        } => {
+            let (name, _, q) = filter_parameters(filter);
+            (
+                format!("{} modulated q {}", name, q),
+                vec![sound, cutoff],
+                25.,
+            )
+        }
+        Sound::Effect { sound, effect } => {
+            let cost = match effect {
+                Effect::Delay { .. } => 2.,
+                Effect::Reverb { .. } => 30.,
+                Effect::Lag { .. } => 1.,
+            };
+            (effect.name(), vec![sound], cost)
+        }
+        Sound::Probe { sound, probe } => (format!("probe {}", probe.name()), vec![sound], 1.),
+        // 描くのは 1 度だけなので，鳴らす費用は標本列と同じ
+        Sound::Frozen { sound, .. } => ("freeze".to_string(), vec![sound], 2.),
+        // 同時に鳴るのは 1 つだけ
+        Sound::Seq { sounds, .. } => (
+            format!("seq {} sounds", sounds.len()),
+            sounds.iter().map(|(_, sound)| sound).collect(),
+            1.,
+        ),
+        // 鳴らすのは選ばれた 1 つだけ
+        Sound::Multisample {
+            layers,
+            velocity,
+            round,
+        } => (
+            format!("multisample velocity {} round {}", velocity, round),
+            sound::played(layers, *velocity, *round)
+                .into_iter()
+                .collect(),
+            0.,
+        ),
+    };
+    let inputs = children
+        .into_iter()
+        .map(|child| collect(child, nodes))
+        .collect();
+    nodes.push(Node {
+        label,
+        inputs,
+        cost,
+        latency: sound.latency(),
+    });
+    nodes.len() - 1
+}
+
+/// フィルタの名前，周波数と Q
+fn filter_parameters(filter: &Filter) -> (&'static str, f64, f64) {
+    match *filter {
+        Filter::Lowpass { frequency, q } => ("lowpass", frequency, q),
+        Filter::Highpass { frequency, q } => ("highpass", frequency, q),
+        Filter::Bandpass { frequency, q } => ("bandpass", frequency, q),
+        Filter::Allpass { frequency, q } => ("allpass", frequency, q),
+    }
+}
+
+/// ファイル `name` に書き出す音 `sound` のグラフ
+pub fn explain(name: &str, sound: &Sound, samplerate: f64, format: Format) -> String {
+    let mut nodes = Vec::new();
+    collect(sound, &mut nodes);
+    let cost: f64 = nodes.iter().map(|node| node.cost).sum();
+    let latency = sound.latency();
+    match format {
+        Format::Text => {
+            let mut text = format!(
+                "graph of {} ({} channels, {} Hz)\n",
+                name,
+                sound.channels(),
+                samplerate
+            );
+            for (i, node) in nodes.iter().enumerate() {
+                let inputs: Vec<_> = node.inputs.iter().map(|j| format!("#{}", j)).collect();
+                let mut line = format!(
+                    "  #{:<4}{:<36}{:<14}cost {}",
+                    i,
+                    node.label,
+                    inputs.join(" "),
+                    node.cost
+                );
+                if node.latency > 0. {
+                    line += &format!(", latency {} s", node.latency);
+                }
+                text += line.trim_end();
+                text.push('\n');
+            }
+            text + &format!(
+                "total cost {} per sample, latency {} s ({} samples)\n",
+                cost,
+                latency,
+                (latency * samplerate).round()
+            )
+        }
+        Format::Dot => {
+            let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+            let mut dot = format!("digraph {} {{\n  rankdir=LR;\n", quote(name));
+            for (i, node) in nodes.iter().enumerate() {
+                let mut label = format!("#{} {}\\ncost {}", i, node.label, node.cost);
+                if node.latency > 0. {
+                    label += &format!("\\nlatency {} s", node.latency);
+                }
+                dot += &format!(
+                    "  n{} [shape=box, label=\"{}\"];\n",
+                    i,
+                    label.replace('"', "\\\"")
+                );
+            }
+            for (i, node) in nodes.iter().enumerate() {
+                for j in &node.inputs {
+                    dot += &format!("  n{} -> n{};\n", j, i);
+                }
+            }
+            dot + &format!(
+                "  label={};\n}}\n",
+                quote(&format!(
+                    "total cost {} per sample, latency {} s",
+                    cost, latency
+                ))
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch() -> Sound {
+        let tone = Sound::Mul(
+            Box::new(Sound::Sin {
+                frequency: 440.,
+                phase: 0.,
+            }),
+            Box::new(Sound::Const(0.5)),
+        );
+        Sound::Latency {
+            sound: Box::new(Sound::Filter {
+                sound: Box::new(tone),
+                filter: Filter::Lowpass {
+                    frequency: 1000.,
+                    q: 0.5,
+                },
+            }),
+            latency: 0.01,
+        }
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            explain("out.wav", &patch(), 100., Format::Text),
+            "graph of out.wav (1 channels, 100 Hz)\n\
+             \x20 #0   sin 440 Hz phase 0                                cost 4\n\
+             \x20 #1   const 0.5                                         cost 0\n\
+             \x20 #2   mul                                 #0 #1         cost 1\n\
+             \x20 #3   lowpass 1000 Hz q 0.5               #2            cost 5\n\
+             \x20 #4   latency 0.01 s                      #3            cost 0, latency 0.01 s\n\
+             total cost 10 per sample, latency 0.01 s (1 samples)\n"
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let dot = explain("a\"b.wav", &patch(), 100., Format::Dot);
+        assert!(dot.starts_with("digraph \"a\\\"b.wav\" {\n"), "{}", dot);
+        for edge in &["n0 -> n2;", "n1 -> n2;", "n2 -> n3;", "n3 -> n4;"] {
+            assert!(dot.contains(edge), "{}", dot);
+        }
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,194 @@
+//! 録音（ `record(seconds)` ，フィーチャー `record` ）
+//!
+//! 外部のコマンドが標準出力に書く WAV を読む．既定では `arecord` （ macOS では SoX の `rec` ）で，
+//! 設定ファイルの `recorder` で変える．コマンドの `{samplerate}` は録音する標本化周波数に置き換える
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+/// `record` の録音元．`Environment::set_input` で差し替える
+pub trait AudioInput {
+    /// 標本化周波数 `samplerate` で `seconds` 秒録音する．2 チャンネル以上なら平均してモノラルにする
+    fn capture(&mut self, seconds: f64, samplerate: f64) -> Result<Vec<f64>, String>;
+}
+
+/// 既定の入力デバイスから録音するコマンド
+pub struct Recorder {
+    command: Vec<String>,
+}
+
+impl Default for Recorder {
+    fn default() -> Recorder {
+        let command = if cfg!(target_os = "macos") {
+            "rec -q -t wav -b 16 -c 1 -r {samplerate} -"
+        } else {
+            "arecord -q -t wav -f S16_LE -c 1 -r {samplerate}"
+        };
+        Recorder::new(command).unwrap()
+    }
+}
+
+impl Recorder {
+    /// 空白で区切ったコマンド
+    pub fn new(command: &str) -> Result<Recorder, String> {
+        let command: Vec<_> = command.split_whitespace().map(str::to_string).collect();
+        if command.is_empty() {
+            return Err("empty recorder command".to_string());
+        }
+        Ok(Recorder { command })
+    }
+}
+
+/// WAV の `fmt ` チャンクの中身のうち使うもの
+struct Format {
+    channels: usize,
+    samplerate: u32,
+    bits: u16,
+    float: bool,
+}
+
+/// `reader` から `data` チャンクの手前までを読む
+fn read_header(reader: &mut impl Read) -> Result<Format, String> {
+    let mut bytes = |n: usize| -> Result<Vec<u8>, String> {
+        let mut buffer = vec![0; n];
+        reader
+            .read_exact(&mut buffer)
+            .map_err(|err| format!("cannot read the WAV header: {}", err))?;
+        Ok(buffer)
+    };
+    let riff = bytes(12)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err("the recorder did not write a WAV stream".to_string());
+    }
+    let mut format = None;
+    loop {
+        let chunk = bytes(8)?;
+        let length = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+        if &chunk[..4] == b"data" {
+            break;
+        }
+        let body = bytes(length + length % 2)?;
+        if &chunk[..4] == b"fmt " && length >= 16 {
+            let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+            format = Some(Format {
+                // WAVE_FORMAT_EXTENSIBLE はサブフォーマットの先頭を見る
+                float: match u16_at(0) {
+                    0xFFFE if length >= 26 => u16_at(24) == 3,
+                    tag => tag == 3,
+                },
+                channels: u16_at(2) as usize,
+                samplerate: u32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+                bits: u16_at(14),
+            });
+        }
+    }
+    format.ok_or_else(|| "no `fmt ` chunk in the recorded WAV".to_string())
+}
+
+impl Format {
+    fn supported(&self) -> Result<(), String> {
+        match (self.float, self.bits) {
+            (false, 16) | (false, 24) | (false, 32) | (true, 32) => Ok(()),
+            (float, bits) => Err(format!(
+                "unsupported recorded format ({}-bit {})",
+                bits,
+                if float { "float" } else { "integer" }
+            )),
+        }
+    }
+}
+
+/// `bytes` を `format` の標本として読む．`Format::supported` で確かめておく
+fn decode(bytes: &[u8], format: &Format) -> f64 {
+    match (format.float, format.bits) {
+        (false, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 32768.,
+        (false, 24) => {
+            (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f64 / 8388608.
+        }
+        (false, 32) => {
+            i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / 2147483648.
+        }
+        (true, 32) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        _ => unreachable!(),
+    }
+}
+
+impl AudioInput for Recorder {
+    fn capture(&mut self, seconds: f64, samplerate: f64) -> Result<Vec<f64>, String> {
+        let rate = (samplerate as u32).to_string();
+        let command: Vec<_> = self
+            .command
+            .iter()
+            .map(|word| word.replace("{samplerate}", &rate))
+            .collect();
+        let name = command.join(" ");
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                format!(
+                    "cannot start `{}`: {} (set `recorder` in cryss.toml)",
+                    name, err
+                )
+            })?;
+        let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+        let result = (|| {
+            let format = read_header(&mut stdout)?;
+            if format.samplerate != samplerate as u32 {
+                return Err(format!(
+                    "`{}` recorded at {} Hz (expected {} Hz)",
+                    name, format.samplerate, samplerate
+                ));
+            }
+            format.supported()?;
+            let width = (format.bits / 8) as usize;
+            let mut frame = vec![0; width * format.channels.max(1)];
+            let frames = (seconds * samplerate) as usize;
+            let mut samples = Vec::with_capacity(frames);
+            while samples.len() < frames {
+                stdout.read_exact(&mut frame).map_err(|err| {
+                    format!(
+                        "`{}` stopped after {} s: {}",
+                        name,
+                        samples.len() as f64 / samplerate,
+                        err
+                    )
+                })?;
+                let sum: f64 = frame
+                    .chunks(width)
+                    .map(|bytes| decode(bytes, &format))
+                    .sum();
+                samples.push(sum / format.channels.max(1) as f64);
+            }
+            Ok(samples)
+        })();
+        // 録音を止める（もう終わっていれば何もしない）
+        let _ = child.kill();
+        let _ = child.wait();
+        result
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorder_command() {
+        let path = std::env::temp_dir().join(format!("cryss-recorder-{}.wav", std::process::id()));
+        // 16 ビット，2 チャンネル，8 Hz の 4 フレーム
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x02\0\x08\0\0\0\x20\0\0\0\x04\0\x10\0data\x10\0\0\0"
+            .to_vec();
+        for (left, right) in [(16384i16, 0i16), (-16384, -16384), (0, 0), (8192, 8192)] {
+            wav.extend(&left.to_le_bytes());
+            wav.extend(&right.to_le_bytes());
+        }
+        std::fs::write(&path, &wav).unwrap();
+        let mut recorder = Recorder::new(&format!("cat {}", path.display())).unwrap();
+        assert_eq!(recorder.capture(0.5, 8.), Ok(vec![0.25, -0.5, 0., 0.25]));
+        assert!(recorder.capture(1., 8.).unwrap_err().contains("stopped"));
+        assert!(recorder.capture(0.5, 16.).unwrap_err().contains("at 8 Hz"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
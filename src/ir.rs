@@ -0,0 +1,618 @@
+//! 構文解析まで済ませた台本（ `.crsc` ， `cryss compile` ）
+//!
+//! 形式は，見出し行 `cryss-ir <形式の版> <cryss の版>` のあとに，
+//! 台本の各行（エラーの表示に使う）と文の木を並べたもの．
+//! 数はすべてリトルエンディアン．版が違うものは読まずにコンパイルし直してもらう
+
+use crate::lexer::Lexer;
+use crate::parser;
+use crate::pos::{Pos, Range};
+use crate::syntax::{Expression, Node, Parameter, Pattern, SampleEntry, Statement};
+use crate::types::Type;
+use std::convert::TryFrom;
+
+const MAGIC: &str = "cryss-ir ";
+
+/// 形式を変えたら増やす
+const FORMAT: u32 = 7;
+
+/// 構文解析の済んだ台本
+pub struct Compiled {
+    /// 台本の各行
+    pub log: Vec<String>,
+    pub statements: Vec<Statement>,
+}
+
+/// `bytes` が `.crsc` の中身か
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC.as_bytes())
+}
+
+fn header() -> String {
+    format!("{}{} {}\n", MAGIC, FORMAT, env!("CARGO_PKG_VERSION"))
+}
+
+impl Compiled {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder(header().into_bytes());
+        encoder.len(self.log.len());
+        self.log.iter().for_each(|line| encoder.string(line));
+        encoder.len(self.statements.len());
+        self.statements
+            .iter()
+            .for_each(|statement| encoder.statement(statement));
+        encoder.0
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Compiled, String> {
+        let newline = bytes
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .filter(|_| is_compiled(bytes))
+            .ok_or_else(|| "not a compiled cryss script".to_string())?;
+        let found = String::from_utf8_lossy(&bytes[..=newline]);
+        if found != header() {
+            return Err(format!(
+                "compiled by another version of cryss (`{}`, expected `{}`); compile it again",
+                found.trim_end(),
+                header().trim_end()
+            ));
+        }
+        let mut decoder = Decoder(&bytes[newline + 1..]);
+        let log = decoder.list(Decoder::string)?;
+        let statements = decoder.list(Decoder::statement)?;
+        if !decoder.0.is_empty() {
+            return Err("trailing bytes in compiled script".to_string());
+        }
+        Ok(Compiled { log, statements })
+    }
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn len(&mut self, len: usize) {
+        self.0.extend(&(len as u64).to_le_bytes());
+    }
+    fn string(&mut self, string: &str) {
+        self.len(string.len());
+        self.0.extend(string.as_bytes());
+    }
+    fn range(&mut self, range: &Range) {
+        for pos in &[range.start(), range.end()] {
+            self.len(pos.line());
+            self.len(pos.byte());
+        }
+    }
+    fn expressions(&mut self, expressions: &[Expression]) {
+        self.len(expressions.len());
+        expressions
+            .iter()
+            .for_each(|expression| self.expression(expression));
+    }
+    fn optional(&mut self, expression: &Option<Expression>) {
+        match expression {
+            Some(expression) => {
+                self.0.push(1);
+                self.expression(expression);
+            }
+            None => self.0.push(0),
+        }
+    }
+    fn expression(&mut self, expression: &Expression) {
+        self.range(&expression.range);
+        let binary = |tag, (a, b): (&Expression, &Expression), encoder: &mut Encoder| {
+            encoder.0.push(tag);
+            encoder.expression(a);
+            encoder.expression(b);
+        };
+        match &expression.node {
+            Node::Identifier(name) => {
+                self.0.push(0);
+                self.string(name);
+            }
+            Node::Invocation(name, arguments, named) => {
+                self.0.push(1);
+                self.string(name);
+                self.expressions(arguments);
+                // 同じ台本からは同じバイト列にする
+                let mut named: Vec<_> = named.iter().collect();
+                named.sort_by_key(|(name, _)| *name);
+                self.len(named.len());
+                for (name, expression) in named {
+                    self.string(name);
+                    self.expression(expression);
+                }
+            }
+            Node::Parameter(name) => {
+                self.0.push(2);
+                self.string(name);
+            }
+            Node::Number(x) => {
+                self.0.push(3);
+                self.0.extend(&x.to_le_bytes());
+            }
+            Node::String(string) => {
+                self.0.push(4);
+                self.string(string);
+            }
+            Node::Print(a) => {
+                self.0.push(5);
+                self.expression(a);
+            }
+            Node::Index(a, b) => binary(6, (a, b), self),
+            Node::Field(a, name) => {
+                self.0.push(7);
+                self.expression(a);
+                self.string(name);
+            }
+            Node::Minus(a) => {
+                self.0.push(8);
+                self.expression(a);
+            }
+            Node::Reciprocal(a) => {
+                self.0.push(9);
+                self.expression(a);
+            }
+            Node::Not(a) => {
+                self.0.push(10);
+                self.expression(a);
+            }
+            Node::Add(a, b) => binary(11, (a, b), self),
+            Node::Sub(a, b) => binary(12, (a, b), self),
+            Node::Mul(a, b) => binary(13, (a, b), self),
+            Node::Div(a, b) => binary(14, (a, b), self),
+            Node::Rem(a, b) => binary(15, (a, b), self),
+            Node::Pow(a, b) => binary(16, (a, b), self),
+            Node::LeftShift(a, b) => binary(17, (a, b), self),
+            Node::RightShift(a, b) => binary(18, (a, b), self),
+            Node::Less(a, b) => binary(19, (a, b), self),
+            Node::Greater(a, b) => binary(20, (a, b), self),
+            Node::LessEqual(a, b) => binary(21, (a, b), self),
+            Node::GreaterEqual(a, b) => binary(22, (a, b), self),
+            Node::Equal(a, b) => binary(23, (a, b), self),
+            Node::NotEqual(a, b) => binary(24, (a, b), self),
+            Node::And(a, b) => binary(25, (a, b), self),
+            Node::Or(a, b) => binary(26, (a, b), self),
+            Node::Group(a) => {
+                self.0.push(27);
+                self.expression(a);
+            }
+            Node::Score(rows) => {
+                self.0.push(28);
+                self.len(rows.len());
+                rows.iter().for_each(|row| self.expressions(row));
+            }
+            Node::Record(fields) => {
+                self.0.push(29);
+                self.len(fields.len());
+                for (name, expression) in fields {
+                    self.string(name);
+                    self.expression(expression);
+                }
+            }
+            Node::Lambda(parameters, body) => {
+                self.0.push(30);
+                self.parameters(parameters);
+                self.expression(body);
+            }
+            Node::Int(x) => {
+                self.0.push(31);
+                self.0.extend(&x.to_le_bytes());
+            }
+            Node::FormatString(string) => {
+                self.0.push(32);
+                self.string(string);
+            }
+        }
+    }
+    fn parameters(&mut self, parameters: &[Parameter]) {
+        self.len(parameters.len());
+        for parameter in parameters {
+            self.range(&parameter.range);
+            self.string(&parameter.name);
+            match &parameter.ty {
+                Some(ty) => {
+                    self.0.push(1);
+                    self.ty(ty);
+                }
+                None => self.0.push(0),
+            }
+            self.optional(&parameter.default);
+        }
+    }
+    fn names(&mut self, names: &[(Range, String)]) {
+        self.len(names.len());
+        for (range, name) in names {
+            self.range(range);
+            self.string(name);
+        }
+    }
+    fn ty(&mut self, ty: &Type) {
+        self.0.push(match ty {
+            Type::Real => 0,
+            Type::Boolean => 1,
+            Type::Sound => 2,
+            Type::String => 3,
+            Type::Array => 4,
+            Type::Record => 5,
+            Type::SoundRecord => 6,
+            Type::SoundArray => 7,
+            Type::Void => 8,
+            Type::Int => 9,
+            // 関数の型は台本に書けない
+            Type::Function(_) => unreachable!(),
+        });
+    }
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression(expression) => {
+                self.0.push(0);
+                self.optional(expression);
+            }
+            Statement::Substitution(range, name, expression) => {
+                self.0.push(1);
+                self.range(range);
+                self.string(name);
+                self.expression(expression);
+            }
+            Statement::Declaration(range, name, ty, expression) => {
+                self.0.push(2);
+                self.range(range);
+                self.string(name);
+                match ty {
+                    Some(ty) => {
+                        self.0.push(1);
+                        self.ty(ty);
+                    }
+                    None => self.0.push(0),
+                }
+                self.expression(expression);
+            }
+            Statement::Destructuring(range, pattern, expression) => {
+                self.0.push(3);
+                self.range(range);
+                match pattern {
+                    Pattern::Array(names) => {
+                        self.0.push(0);
+                        self.names(names);
+                    }
+                    Pattern::Record(names) => {
+                        self.0.push(1);
+                        self.names(names);
+                    }
+                }
+                self.expression(expression);
+            }
+            Statement::Block(statements) => {
+                self.0.push(4);
+                self.len(statements.len());
+                statements
+                    .iter()
+                    .for_each(|statement| self.statement(statement));
+            }
+            Statement::If(range, condition, then, otherwise) => {
+                self.0.push(5);
+                self.range(range);
+                self.expression(condition);
+                self.statement(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        self.0.push(1);
+                        self.statement(otherwise);
+                    }
+                    None => self.0.push(0),
+                }
+            }
+            Statement::While(condition, body) => {
+                self.0.push(6);
+                self.expression(condition);
+                self.statement(body);
+            }
+            Statement::Break(range) => {
+                self.0.push(7);
+                self.range(range);
+            }
+            Statement::Continue(range) => {
+                self.0.push(8);
+                self.range(range);
+            }
+            Statement::Return(range, expression) => {
+                self.0.push(9);
+                self.range(range);
+                self.optional(expression);
+            }
+            Statement::Definition(range, name, parameters, ty, body) => {
+                self.0.push(10);
+                self.range(range);
+                self.string(name);
+                self.parameters(parameters);
+                self.ty(ty);
+                self.statement(body);
+            }
+            Statement::Samples(range, entries) => {
+                self.0.push(11);
+                self.range(range);
+                self.len(entries.len());
+                for entry in entries {
+                    self.range(&entry.range);
+                    self.string(&entry.name);
+                    self.string(&entry.path);
+                    match &entry.sha256 {
+                        Some(sha256) => {
+                            self.0.push(1);
+                            self.string(sha256);
+                        }
+                        None => self.0.push(0),
+                    }
+                }
+            }
+            Statement::Command(range, command) => {
+                self.0.push(12);
+                self.range(range);
+                self.string(command);
+            }
+            Statement::For(range, name, array, body) => {
+                self.0.push(13);
+                self.range(range);
+                self.string(name);
+                self.expression(array);
+                self.statement(body);
+            }
+        }
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+fn truncated() -> String {
+    "truncated compiled script".to_string()
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err(truncated());
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+    fn tag(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn len(&mut self) -> Result<usize, String> {
+        let bytes = <[u8; 8]>::try_from(self.take(8)?).unwrap();
+        usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| truncated())
+    }
+    fn list<T>(
+        &mut self,
+        item: impl Fn(&mut Decoder<'a>) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let len = self.len()?;
+        // 壊れた長さで大きな領域を確保しないように，残りのバイト数で抑える
+        let mut vec = Vec::with_capacity(len.min(self.0.len()));
+        for _ in 0..len {
+            vec.push(item(self)?);
+        }
+        Ok(vec)
+    }
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "invalid UTF-8".to_string())
+    }
+    fn range(&mut self) -> Result<Range, String> {
+        let start = Pos::new(self.len()?, self.len()?);
+        let end = Pos::new(self.len()?, self.len()?);
+        if start > end {
+            return Err("invalid range in compiled script".to_string());
+        }
+        Ok(Range::new(start, end))
+    }
+    fn boxed(&mut self) -> Result<Box<Expression>, String> {
+        self.expression().map(Box::new)
+    }
+    fn optional(&mut self) -> Result<Option<Expression>, String> {
+        match self.tag()? {
+            0 => Ok(None),
+            _ => self.expression().map(Some),
+        }
+    }
+    fn expression(&mut self) -> Result<Expression, String> {
+        let range = self.range()?;
+        let tag = self.tag()?;
+        let node = match tag {
+            0 => Node::Identifier(self.string()?),
+            1 => {
+                let name = self.string()?;
+                let arguments = self.list(Decoder::expression)?;
+                let named = self.list(|decoder| Ok((decoder.string()?, decoder.expression()?)))?;
+                Node::Invocation(name, arguments, named.into_iter().collect())
+            }
+            2 => Node::Parameter(self.string()?),
+            3 => Node::Number(f64::from_le_bytes(
+                <[u8; 8]>::try_from(self.take(8)?).unwrap(),
+            )),
+            4 => Node::String(self.string()?),
+            5 => Node::Print(self.boxed()?),
+            7 => Node::Field(self.boxed()?, self.string()?),
+            8 => Node::Minus(self.boxed()?),
+            9 => Node::Reciprocal(self.boxed()?),
+            10 => Node::Not(self.boxed()?),
+            27 => Node::Group(self.boxed()?),
+            28 => Node::Score(self.list(|decoder| decoder.list(Decoder::expression))?),
+            29 => {
+                Node::Record(self.list(|decoder| Ok((decoder.string()?, decoder.expression()?)))?)
+            }
+            30 => Node::Lambda(self.parameters()?, self.boxed()?),
+            31 => Node::Int(i64::from_le_bytes(
+                <[u8; 8]>::try_from(self.take(8)?).unwrap(),
+            )),
+            32 => Node::FormatString(self.string()?),
+            6 | 11..=26 => {
+                let (a, b) = (self.boxed()?, self.boxed()?);
+                match tag {
+                    6 => Node::Index(a, b),
+                    11 => Node::Add(a, b),
+                    12 => Node::Sub(a, b),
+                    13 => Node::Mul(a, b),
+                    14 => Node::Div(a, b),
+                    15 => Node::Rem(a, b),
+                    16 => Node::Pow(a, b),
+                    17 => Node::LeftShift(a, b),
+                    18 => Node::RightShift(a, b),
+                    19 => Node::Less(a, b),
+                    20 => Node::Greater(a, b),
+                    21 => Node::LessEqual(a, b),
+                    22 => Node::GreaterEqual(a, b),
+                    23 => Node::Equal(a, b),
+                    24 => Node::NotEqual(a, b),
+                    25 => Node::And(a, b),
+                    _ => Node::Or(a, b),
+                }
+            }
+            tag => return Err(format!("unknown expression tag {}", tag)),
+        };
+        Ok(Expression::new(range, node))
+    }
+    fn parameters(&mut self) -> Result<Vec<Parameter>, String> {
+        self.list(|decoder| {
+            Ok(Parameter {
+                range: decoder.range()?,
+                name: decoder.string()?,
+                ty: match decoder.tag()? {
+                    0 => None,
+                    _ => Some(decoder.ty()?),
+                },
+                default: decoder.optional()?,
+            })
+        })
+    }
+    fn ty(&mut self) -> Result<Type, String> {
+        Ok(match self.tag()? {
+            0 => Type::Real,
+            1 => Type::Boolean,
+            2 => Type::Sound,
+            3 => Type::String,
+            4 => Type::Array,
+            5 => Type::Record,
+            6 => Type::SoundRecord,
+            7 => Type::SoundArray,
+            8 => Type::Void,
+            9 => Type::Int,
+            tag => return Err(format!("unknown type tag {}", tag)),
+        })
+    }
+    fn names(&mut self) -> Result<Vec<(Range, String)>, String> {
+        self.list(|decoder| Ok((decoder.range()?, decoder.string()?)))
+    }
+    fn statement(&mut self) -> Result<Statement, String> {
+        Ok(match self.tag()? {
+            0 => Statement::Expression(self.optional()?),
+            1 => Statement::Substitution(self.range()?, self.string()?, self.expression()?),
+            2 => Statement::Declaration(
+                self.range()?,
+                self.string()?,
+                match self.tag()? {
+                    0 => None,
+                    _ => Some(self.ty()?),
+                },
+                self.expression()?,
+            ),
+            3 => {
+                let range = self.range()?;
+                let pattern = match self.tag()? {
+                    0 => Pattern::Array(self.names()?),
+                    _ => Pattern::Record(self.names()?),
+                };
+                Statement::Destructuring(range, pattern, self.expression()?)
+            }
+            4 => Statement::Block(self.list(Decoder::statement)?),
+            5 => {
+                let range = self.range()?;
+                let condition = self.expression()?;
+                let then = Box::new(self.statement()?);
+                let otherwise = match self.tag()? {
+                    0 => None,
+                    _ => Some(Box::new(self.statement()?)),
+                };
+                Statement::If(range, condition, then, otherwise)
+            }
+            6 => Statement::While(self.expression()?, Box::new(self.statement()?)),
+            7 => Statement::Break(self.range()?),
+            8 => Statement::Continue(self.range()?),
+            9 => Statement::Return(self.range()?, self.optional()?),
+            10 => {
+                let range = self.range()?;
+                let name = self.string()?;
+                let parameters = self.parameters()?;
+                let ty = self.ty()?;
+                Statement::Definition(range, name, parameters, ty, Box::new(self.statement()?))
+            }
+            11 => Statement::Samples(
+                self.range()?,
+                self.list(|decoder| {
+                    Ok(SampleEntry {
+                        range: decoder.range()?,
+                        name: decoder.string()?,
+                        path: decoder.string()?,
+                        sha256: match decoder.tag()? {
+                            0 => None,
+                            _ => Some(decoder.string()?),
+                        },
+                    })
+                })?,
+            ),
+            12 => Statement::Command(self.range()?, self.string()?),
+            13 => Statement::For(
+                self.range()?,
+                self.string()?,
+                self.expression()?,
+                Box::new(self.statement()?),
+            ),
+            tag => return Err(format!("unknown statement tag {}", tag)),
+        })
+    }
+}
+
+/// 台本 `source` を最後まで構文解析する
+pub fn compile(source: Box<dyn std::io::BufRead>) -> Result<Compiled, String> {
+    let mut lexer = Lexer::new(source, false);
+    let mut log = Vec::new();
+    let mut statements = Vec::new();
+    loop {
+        match parser::parse_statement(&mut lexer, &mut log) {
+            Ok(Some(statement)) => statements.push(statement),
+            Ok(None) => return Ok(Compiled { log, statements }),
+            Err(err) => {
+                let mut message = Vec::new();
+                err.write_diagnostic(&mut message, &log)
+                    .expect("cannot print error message");
+                return Err(String::from_utf8_lossy(&message).trim_end().to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let source = "samples { \"k\": \"kick.wav\" }\nlet {a, b} = {a: 1, b: [2, 3; 4]};\n\
+            if (!(b[0] <= 2)) { let c = -a ^ 2 << 1; } else { c? ; }\n\
+            while (a < 3 && true) { a = a + 1; break; }\nfor (x in b) a = a + x;\n\
+            def f(x: Sound[], y = 2, n: int) -> real { return y; }\nlet g = (x, s: Sound) => x;\nlet i: int = 1;\nwrite(Sin(440 * a), 0.1, \"x.wav\", mkdirs = false);\n";
+        let compiled = compile(Box::new(std::io::Cursor::new(source.to_string()))).unwrap();
+        let expected = format!("{:?}", compiled.statements);
+        let bytes = compiled.to_bytes();
+        let compiled = Compiled::from_bytes(&bytes).unwrap();
+        assert_eq!(format!("{:?}", compiled.statements), expected);
+        assert_eq!(compiled.log.concat(), source);
+        assert_eq!(compiled.to_bytes(), bytes);
+        assert!(Compiled::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let other = String::from_utf8_lossy(&bytes).replacen("cryss-ir 7", "cryss-ir 0", 1);
+        let message = Compiled::from_bytes(other.as_bytes()).err().unwrap();
+        assert!(message.contains("compile it again"), "{}", message);
+    }
+}
@@ -14,8 +14,86 @@ use std::collections::VecDeque;
 struct Inner {
     /// これが空でないなら，ブロックコメントの途中
     comment: Vec<pos::Pos>,
-    /// これが Some なら，文字列リテラルの途中
-    string: Option<(pos::Pos, String)>,
+    /// これが Some なら，文字列リテラルの途中．
+    /// 文字列は行をまたいでよく，改行もそのまま文字列に入る
+    string: Option<(pos::Pos, String, Prefix)>,
+}
+
+/// 文字列リテラルの接頭辞
+#[derive(Clone, Copy, PartialEq)]
+enum Prefix {
+    None,
+    /// `r"..."` ．エスケープしない
+    Raw,
+    /// `f"..."` ．`{name}` に変数の値を埋め込む（ `Token::FormatString` ）
+    Format,
+}
+
+/// 文字列の中のエスケープ．`start` はバックスラッシュの位置で， `iter` はその直後から読む
+fn escape(
+    line_num: usize,
+    start: usize,
+    iter: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<char, Error> {
+    let pos = |byte| pos::Pos::new(line_num, byte);
+    let (index, c) = iter
+        .next()
+        .ok_or_else(|| Error::NoCharacterAfterBackSlash(pos(start)))?;
+    Ok(match c {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        '0' => '\0',
+        'x' => {
+            let (digits, end) = hex_digits(iter, index + 1, 2);
+            let range = pos::Range::new(pos(start), pos(end));
+            match u8::from_str_radix(&digits, 16) {
+                Ok(byte) if digits.len() == 2 && byte.is_ascii() => byte as char,
+                _ => return Err(Error::InvalidHexEscape(range)),
+            }
+        }
+        'u' => {
+            let invalid = |end| Error::InvalidUnicodeEscape(pos::Range::new(pos(start), pos(end)));
+            if iter.next_if(|&(_, c)| c == '{').is_none() {
+                return Err(invalid(index + 1));
+            }
+            let (digits, end) = hex_digits(iter, index + 2, 6);
+            if iter.next_if(|&(_, c)| c == '}').is_none() {
+                return Err(invalid(end));
+            }
+            u32::from_str_radix(&digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| invalid(end + 1))?
+        }
+        // バックスラッシュの直後の文字をそのまま
+        // `"` や `'` のエスケープを含む
+        c => c,
+    })
+}
+
+/// 数値リテラルから桁区切りの `_` を除いたもの
+fn digits(literal: &str) -> String {
+    literal.replace('_', "")
+}
+
+/// 16 進の数字を `max` 個まで読み，読み終えた位置（何も読まなければ `end` ）と一緒に返す
+fn hex_digits(
+    iter: &mut std::iter::Peekable<std::str::CharIndices>,
+    mut end: usize,
+    max: usize,
+) -> (String, usize) {
+    let mut digits = String::new();
+    while digits.len() < max {
+        match iter.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+            Some((i, c)) => {
+                digits.push(c);
+                end = i + 1;
+            }
+            None => break,
+        }
+    }
+    (digits, end)
 }
 
 impl Inner {
@@ -39,9 +117,8 @@ impl Inner {
     ///
     /// トークンが区切れるとき，新しいトークンが始まるとき：前のトークンを `queue` に push する．
     ///
-    /// ファイルの末尾以外では，行は必ず `\n` で終わる（ `std::io::BufRead::read_line` の仕様）．
-    /// ファイルの末尾は `\n` で終わっていなければならない．
-    /// もしトークンの途中でファイルが終了したらエラーを返す
+    /// 行は必ず `\n` で終わる（ファイルの末尾で `\n` がなければ `Lexer::read` が補う）ので，
+    /// 行末でトークンが途中になることはない
     fn run(
         &mut self,
         line_num: usize,
@@ -79,25 +156,29 @@ impl Inner {
                 continue;
             }
             if c == '"' {
-                if let Some((start, string)) = self.string.take() {
+                if let Some((start, string, prefix)) = self.string.take() {
                     // 文字列の終わり．
                     // 次のループで queue に push してもらう
-                    prev = Some((start, State::String(string)));
+                    prev = Some((start, State::String(string, prefix == Prefix::Format)));
                     continue;
                 }
-            } else if let Some((_, string)) = &mut self.string {
+                if let Some((start, State::Identifier)) = &prev {
+                    let prefix = match &line[start.byte()..index] {
+                        "r" => Some(Prefix::Raw),
+                        "f" => Some(Prefix::Format),
+                        _ => None,
+                    };
+                    if let Some(prefix) = prefix {
+                        // 生文字列か埋め込みのある文字列の始まり
+                        self.string = Some((start.clone(), String::new(), prefix));
+                        prev = None;
+                        continue;
+                    }
+                }
+            } else if let Some((_, string, prefix)) = &mut self.string {
                 // 文字列の途中．
                 string.push(match c {
-                    '\\' => match iter.next().ok_or(Error::NoCharacterAfterBackSlash(pos))?.1 {
-                        // エスケープ
-                        'n' => '\n',
-                        'r' => '\r',
-                        't' => '\t',
-                        '0' => '\0',
-                        // バックスラッシュの直後の文字を push
-                        // `"` や `'` のエスケープを含む
-                        c => c,
-                    },
+                    '\\' if *prefix != Prefix::Raw => escape(line_num, index, &mut iter)?,
                     c => c,
                 });
                 continue;
@@ -111,23 +192,49 @@ impl Inner {
                         (State::Parameter, 'a'..='z' | 'A'..='Z' | '_' | '$' | '0'..='9') => {
                             State::Parameter
                         }
-                        (State::Integer, '0'..='9') => State::Integer,
+                        (State::Integer, '0'..='9' | '_') => State::Integer,
+                        (State::Integer, 'x' | 'o' | 'b') if &line[start.byte()..index] == "0" => {
+                            State::RadixPrefix(match c {
+                                'x' => 16,
+                                'o' => 8,
+                                _ => 2,
+                            })
+                        }
+                        (State::RadixPrefix(radix) | State::Radix(radix), c)
+                            if c.is_digit(radix) || c == '_' =>
+                        {
+                            State::Radix(radix)
+                        }
                         (State::Integer, '.') => State::Decimal,
-                        (State::Dot | State::Decimal, '0'..='9') => State::Decimal,
+                        (State::Dot, '0'..='9') => State::Decimal,
+                        (State::Decimal, '0'..='9' | '_') => State::Decimal,
                         (State::Integer | State::Decimal, 'e' | 'E') => State::ScientificIncomplete,
                         (State::ScientificIncomplete, '+' | '-') => State::ScientificSign,
                         (
                             State::ScientificIncomplete | State::ScientificSign | State::Scientific,
                             '0'..='9',
                         ) => State::Scientific,
+                        (State::Scientific, '_') => State::Scientific,
                         (State::Equal, '=') => State::DoubleEqual,
                         (State::Equal, '>') => State::EqualGreater,
                         (State::Hyphen, '>') => State::HyphenGreater,
+                        (State::Plus, '=') => State::PlusEqual,
+                        (State::Hyphen, '=') => State::HyphenEqual,
+                        (State::Asterisk, '=') => State::AsteriskEqual,
+                        (State::Slash, '=') => State::SlashEqual,
+                        (State::Percent, '=') => State::PercentEqual,
+                        (State::Circumflex, '=') => State::CircumflexEqual,
                         (State::Exclamation, '=') => State::ExclamationEqual,
                         (State::Ampersand, '&') => State::DoubleAmpersand,
                         (State::Bar, '|') => State::DoubleBar,
                         (State::Less, '<') => State::DoubleLess,
+                        (State::Less, '=') => State::LessEqual,
                         (State::Greater, '>') => State::DoubleGreater,
+                        (State::Greater, '=') => State::GreaterEqual,
+                        (State::Hash, _) => {
+                            // `#` から行末まではラインコメント（ `//` と同じ）
+                            return Ok(());
+                        }
                         (State::Slash, '/') => {
                             // この行はこれ以降ラインコメント．
                             // `/` の直前のトークンは push 済みなので
@@ -153,6 +260,7 @@ impl Inner {
                                     "for" => Token::KeywordFor,
                                     "let" => Token::KeywordLet,
                                     "def" => Token::KeywordDef,
+                                    "samples" => Token::KeywordSamples,
                                     "break" => Token::KeywordBreak,
                                     "continue" => Token::KeywordContinue,
                                     "return" => Token::KeywordReturn,
@@ -161,8 +269,38 @@ impl Inner {
                                 State::Parameter => {
                                     Token::Parameter(line[start.byte()..index].to_string())
                                 }
-                                State::Integer | State::Decimal | State::Scientific => {
-                                    match line[start.byte()..index].parse() {
+                                State::Integer => {
+                                    match digits(&line[start.byte()..index]).parse::<i64>() {
+                                        Ok(value) => Token::Int(value),
+                                        Err(_) => {
+                                            return Err(Error::IntegerLiteralOutOfRange(
+                                                pos::Range::new(start, pos),
+                                            ))
+                                        }
+                                    }
+                                }
+                                State::Decimal | State::Scientific => {
+                                    let literal = digits(&line[start.byte()..index]);
+                                    match literal.parse::<f64>() {
+                                        Ok(value) if value.is_infinite() => {
+                                            return Err(Error::NumberLiteralOverflow(
+                                                pos::Range::new(start, pos),
+                                            ))
+                                        }
+                                        // 仮数部が 0 でないのに 0 になった
+                                        Ok(value)
+                                            if value == 0.
+                                                && literal
+                                                    .split(['e', 'E'])
+                                                    .next()
+                                                    .unwrap()
+                                                    .bytes()
+                                                    .any(|b| matches!(b, b'1'..=b'9')) =>
+                                        {
+                                            return Err(Error::NumberLiteralUnderflow(
+                                                pos::Range::new(start, pos),
+                                            ))
+                                        }
                                         Ok(value) => Token::Number(value),
                                         Err(err) => {
                                             return Err(Error::ParseFloatFailure(
@@ -177,13 +315,41 @@ impl Inner {
                                         pos::Range::new(start, pos),
                                     ));
                                 }
-                                State::String(string) => Token::String(string),
+                                State::RadixPrefix(_) => {
+                                    return Err(Error::IncompleteRadixLiteral(pos::Range::new(
+                                        start, pos,
+                                    )));
+                                }
+                                State::Radix(radix) => {
+                                    let text = digits(&line[start.byte() + 2..index]);
+                                    match i64::from_str_radix(&text, radix) {
+                                        Ok(value) => Token::Int(value),
+                                        Err(_) if text.is_empty() => {
+                                            return Err(Error::IncompleteRadixLiteral(
+                                                pos::Range::new(start, pos),
+                                            ))
+                                        }
+                                        Err(_) => {
+                                            return Err(Error::IntegerLiteralOutOfRange(
+                                                pos::Range::new(start, pos),
+                                            ))
+                                        }
+                                    }
+                                }
+                                State::String(string, false) => Token::String(string),
+                                State::String(string, true) => Token::FormatString(string),
                                 State::Plus => Token::Plus,
                                 State::Hyphen => Token::Hyphen,
                                 State::Asterisk => Token::Asterisk,
                                 State::Slash => Token::Slash,
                                 State::Percent => Token::Percent,
                                 State::Circumflex => Token::Circumflex,
+                                State::PlusEqual => Token::PlusEqual,
+                                State::HyphenEqual => Token::HyphenEqual,
+                                State::AsteriskEqual => Token::AsteriskEqual,
+                                State::SlashEqual => Token::SlashEqual,
+                                State::PercentEqual => Token::PercentEqual,
+                                State::CircumflexEqual => Token::CircumflexEqual,
                                 State::Equal => Token::Equal,
                                 State::EqualGreater => Token::EqualGreater,
                                 State::HyphenGreater => Token::HyphenGreater,
@@ -191,8 +357,10 @@ impl Inner {
                                 State::Exclamation => Token::Exclamation,
                                 State::ExclamationEqual => Token::ExclamationEqual,
                                 State::Less => Token::Less,
+                                State::LessEqual => Token::LessEqual,
                                 State::DoubleLess => Token::DoubleLess,
                                 State::Greater => Token::Greater,
+                                State::GreaterEqual => Token::GreaterEqual,
                                 State::DoubleGreater => Token::DoubleGreater,
                                 State::DoubleAmpersand => Token::DoubleAmpersand,
                                 State::Bar => Token::Bar,
@@ -210,9 +378,8 @@ impl Inner {
                                 State::Ampersand => {
                                     return Err(Error::SingleAmpersand(pos::Range::new(start, pos)))
                                 }
-                                State::Dot => {
-                                    return Err(Error::SingleDot(pos::Range::new(start, pos)))
-                                }
+                                State::Dot => Token::Dot,
+                                State::Hash => unreachable!("`#` always starts a line comment"),
                             };
                             // queue への push_back を行うのはここ 1 箇所だけ．
                             queue.push_back((pos::Range::new(start, pos.clone()), token));
@@ -226,11 +393,8 @@ impl Inner {
                 None => self.begin(pos, c)?,
             };
         }
-        if prev.is_some() {
-            Err(Error::NoLineFeedAtEOF)
-        } else {
-            Ok(())
-        }
+        debug_assert!(prev.is_none());
+        Ok(())
     }
     /// None からの遷移
     fn begin(&mut self, pos: pos::Pos, c: char) -> Result<Option<(pos::Pos, State)>, Error> {
@@ -240,7 +404,7 @@ impl Inner {
             '0'..='9' => State::Integer,
             '"' => {
                 // self.string が None でなくなることで，オートマトンの遷移から抜ける
-                self.string = Some((pos, String::new()));
+                self.string = Some((pos, String::new(), Prefix::None));
                 // 文字列リテラルの終了後に None が入っているように
                 return Ok(None);
             }
@@ -267,6 +431,7 @@ impl Inner {
             ']' => State::ClosingBracket,
             '{' => State::OpeningBrace,
             '}' => State::ClosingBrace,
+            '#' => State::Hash,
             _ if c.is_ascii_whitespace() => return Ok(None),
             _ => return Err(Error::UnexpectedCharacter(pos)),
         };
@@ -288,14 +453,21 @@ enum State {
     /// - None + `$` -> `Parameter`
     /// - `Parameter` + [`a`-`z` `A`-`Z` `_` `$` `0`-`9`] -> `Parameter`
     Parameter,
-    /// 数値リテラル．
+    /// 数値リテラル．`_` は桁の区切りで読み飛ばす
     /// - None + [`0`-`9`] -> `Integer`
-    /// - `Integer` + [`0`-`9`] -> `Integer`
+    /// - `Integer` + [`0`-`9` `_`] -> `Integer`
     Integer,
+    /// 基数の接頭辞（ `0x` `0o` `0b` ）まで読んだ整数リテラル
+    /// - `Integer` （ `0` だけ）+ [`x` `o` `b`] -> `RadixPrefix`
+    RadixPrefix(u32),
+    /// 16 進・ 8 進・ 2 進の整数リテラル
+    /// - `RadixPrefix` + [その基数の数字 `_`] -> `Radix`
+    /// - `Radix` + [その基数の数字 `_`] -> `Radix`
+    Radix(u32),
     /// 小数点を含む数値リテラル．
     /// - `Integer` + `.` -> `Decimal`
     /// - `Dot` + [`0`-`9`] -> `Decimal`
-    /// - `Decimal` + [`0`-`9`] -> `Decimal`
+    /// - `Decimal` + [`0`-`9` `_`] -> `Decimal`
     Decimal,
     /// 指数表記の途中（ e まで）
     /// - `Integer` + [`e` `E`] -> `ScientificIncomplete`
@@ -307,11 +479,11 @@ enum State {
     /// 指数表記の数値リテラル
     /// - `ScientificIncomplete` + [`0`-`9`] -> `Scientific`
     /// - `ScientificSign` + [`0`-`9`] -> `Scientific`
-    /// - `Scientific` + [`0`-`9`] -> `Scientific`
+    /// - `Scientific` + [`0`-`9` `_`] -> `Scientific`
     Scientific,
-    /// 文字列リテラル．
+    /// 文字列リテラル．真なら `f"..."` ．
     /// ただしオートマトンには含まれない
-    String(String),
+    String(String, bool),
     Plus,
     Hyphen,
     HyphenGreater,
@@ -319,6 +491,14 @@ enum State {
     Slash,
     Percent,
     Circumflex,
+    /// 複合代入 `+=` `-=` `*=` `/=` `%=` `^=`
+    /// - `Plus` + `=` -> `PlusEqual` （ほかも同様）
+    PlusEqual,
+    HyphenEqual,
+    AsteriskEqual,
+    SlashEqual,
+    PercentEqual,
+    CircumflexEqual,
     Equal,
     EqualGreater,
     DoubleEqual,
@@ -326,8 +506,10 @@ enum State {
     ExclamationEqual,
     Less,
     DoubleLess,
+    LessEqual,
     Greater,
     DoubleGreater,
+    GreaterEqual,
     /// 単独の `&`
     Ampersand,
     DoubleAmpersand,
@@ -336,7 +518,7 @@ enum State {
     Colon,
     Semicolon,
     Comma,
-    /// 単独の `.`
+    /// 数値リテラルでない `.`
     Dot,
     Question,
     OpeningParenthesis,
@@ -345,19 +527,28 @@ enum State {
     ClosingBracket,
     OpeningBrace,
     ClosingBrace,
+    /// ラインコメントの始まりの `#` ．次の文字で行を読み終える
+    Hash,
 }
 
 use std::io::BufRead;
 
+/// UTF-8 の BOM
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
 /// 内部で `Inner::run()` を呼び出す
 pub struct Lexer {
     /// 標準入力，ファイル入力どちらも可
     reader: Box<dyn BufRead>,
-    /// プロンプト文字 `> ` を出力するか否か
+    /// 対話環境として読むか否か（メタコマンドを読む）
     prompt: bool,
+    /// 偽なら，対話環境でもプロンプト文字 `> ` は `reader` が出す
+    print_prompt: bool,
     inner: Inner,
     /// トークンの入っているキュー
     queue: VecDeque<(pos::Range, Token)>,
+    /// 最後に `next` で返したトークンの終わり
+    end: pos::Pos,
 }
 
 impl Lexer {
@@ -365,16 +556,38 @@ impl Lexer {
         Lexer {
             reader,
             prompt,
+            print_prompt: prompt,
             inner: Inner::new(),
             queue: VecDeque::new(),
+            end: pos::Pos::new(0, 0),
         }
     }
+    /// プロンプトは `reader` が出す（ `line_editor::LineEditor` ）
+    #[cfg_attr(not(feature = "line-editing"), allow(dead_code))]
+    pub fn reader_prompts(mut self) -> Lexer {
+        self.print_prompt = false;
+        self
+    }
+    /// 最後に読んだトークンの終わり
+    pub fn end(&self) -> pos::Pos {
+        self.end.clone()
+    }
+    /// 読みかけのトークンを捨てる．対話環境でエラーの後に次の行から読み直すため
+    pub fn discard(&mut self) {
+        self.inner = Inner::new();
+        self.queue.clear();
+    }
 }
 
 impl Lexer {
+    /// 1 行読んで字句解析する．
+    ///
+    /// 行末の `\r\n` は `\n` として，先頭の BOM はないものとして扱う．
+    /// 末尾に改行のないファイルは，改行があるものとして読む．
+    /// UTF-8 として正しくない行は，（置き換え文字にして）ログに残したうえでエラーを返す
     pub fn read(&mut self, log: &mut Vec<String>) -> Result<bool, Error> {
-        let mut line = String::new();
-        if self.prompt {
+        let mut bytes = Vec::new();
+        if self.print_prompt {
             // 対話環境ではプロンプトを出す
             // ファイルから読むときは出さない
             use std::io::Write;
@@ -383,16 +596,51 @@ impl Lexer {
         }
         if self
             .reader
-            .read_line(&mut line)
+            .read_until(b'\n', &mut bytes)
             .expect("failed to read input")
             > 0
         {
-            let result = self.inner.run(log.len(), &line, &mut self.queue);
-            log.push(line);
+            if bytes.ends_with(b"\r\n") {
+                bytes.truncate(bytes.len() - 2);
+                bytes.push(b'\n');
+            } else if !bytes.ends_with(b"\n") {
+                bytes.push(b'\n');
+            }
+            if log.is_empty() && bytes.starts_with(BOM) {
+                bytes.drain(..BOM.len());
+            }
+            let result = match String::from_utf8(bytes) {
+                Ok(line) => {
+                    let result = if self.prompt
+                        && line.starts_with(':')
+                        && self.queue.is_empty()
+                        && self.inner.comment.is_empty()
+                        && self.inner.string.is_none()
+                    {
+                        // 対話環境で文の始めに `:` から始まる行はメタコマンド
+                        let range = pos::Range::new(
+                            pos::Pos::new(log.len(), 0),
+                            pos::Pos::new(log.len(), line.trim_end().len()),
+                        );
+                        let command = line[1..].trim().to_string();
+                        self.queue.push_back((range, Token::Command(command)));
+                        Ok(())
+                    } else {
+                        self.inner.run(log.len(), &line, &mut self.queue)
+                    };
+                    log.push(line);
+                    result
+                }
+                Err(err) => {
+                    let pos = pos::Pos::new(log.len(), err.utf8_error().valid_up_to());
+                    log.push(String::from_utf8_lossy(err.as_bytes()).into_owned());
+                    Err(Error::InvalidUtf8(pos))
+                }
+            };
             result.map(|()| true)
         } else if let Some(pos) = self.inner.comment.pop() {
             Err(Error::UnterminatedComment(pos))
-        } else if let Some((pos, _)) = self.inner.string.take() {
+        } else if let Some((pos, _, _)) = self.inner.string.take() {
             Err(Error::UnterminatedStringLiteral(pos))
         } else {
             Ok(false)
@@ -409,7 +657,10 @@ impl Lexer {
     pub fn next(&mut self, log: &mut Vec<String>) -> Result<Option<(pos::Range, Token)>, Error> {
         Ok(loop {
             match self.queue.pop_front() {
-                Some(token) => break Some(token),
+                Some(token) => {
+                    self.end = token.0.end().clone();
+                    break Some(token);
+                }
                 None => {
                     if !self.read(log)? {
                         break None;
@@ -462,6 +713,117 @@ mod tests {
         TestHelper::new(s)
     }
 
+    fn tokens(bytes: &'static [u8]) -> Result<Vec<Token>, Error> {
+        let mut lex = Lexer::new(Box::new(bytes), false);
+        let mut log = Vec::new();
+        let mut tokens = Vec::new();
+        while let Some((_, token)) = lex.next(&mut log)? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn crlf() {
+        // Windows で編集した台本（ BOM つき， CRLF ）
+        let tokens = tokens(b"\xef\xbb\xbflet a = \"x\r\ny\";\r\n// c\r\n");
+        let expected = vec![
+            Token::KeywordLet,
+            Token::Identifier("a".to_string()),
+            Token::Equal,
+            Token::String("x\ny".to_string()),
+            Token::Semicolon,
+        ];
+        assert!(matches!(tokens, Ok(tokens) if tokens == expected));
+    }
+
+    #[test]
+    fn no_line_feed_at_eof() {
+        let expected = vec![Token::Identifier("x".to_string()), Token::Semicolon];
+        assert!(matches!(tokens(b"x;"), Ok(tokens) if tokens == expected));
+        let expected = vec![Token::Number(1.5)];
+        assert!(matches!(tokens(b"// c\n1.5"), Ok(tokens) if tokens == expected));
+        assert!(matches!(
+            tokens(b"\"x"),
+            Err(Error::UnterminatedStringLiteral(_))
+        ));
+        assert!(matches!(
+            tokens(b"/* x"),
+            Err(Error::UnterminatedComment(_))
+        ));
+    }
+
+    #[test]
+    fn escapes() {
+        let expected = vec![Token::String("A\u{3042}\u{1f600}\"".to_string())];
+        let source = b"\"\\x41\\u{3042}\\u{1F600}\\\"\"";
+        assert!(matches!(tokens(source), Ok(tokens) if tokens == expected));
+        let range = |source| match tokens(source) {
+            Err(Error::InvalidHexEscape(range) | Error::InvalidUnicodeEscape(range)) => {
+                (range.start().byte(), range.end().byte())
+            }
+            _ => panic!("not an invalid escape"),
+        };
+        assert_eq!(range(b"\"ab\\x4\""), (3, 6));
+        assert_eq!(range(b"\"\\x80\""), (1, 5));
+        assert_eq!(range(b"\"\\u3042\""), (1, 3));
+        assert_eq!(range(b"\"\\u{3042\""), (1, 8));
+        assert_eq!(range(b"\"\\u{d800}\""), (1, 9));
+    }
+
+    #[test]
+    fn raw_and_multiline_strings() {
+        let expected = vec![
+            Token::String("C:\\x\\n".to_string()),
+            Token::Identifier("r".to_string()),
+            Token::String("a".to_string()),
+        ];
+        assert!(matches!(tokens(b"r\"C:\\x\\n\" r \"a\""), Ok(tokens) if tokens == expected));
+        let expected = vec![
+            Token::FormatString("{a}\n".to_string()),
+            Token::Identifier("f".to_string()),
+            Token::String("b".to_string()),
+        ];
+        assert!(matches!(tokens(b"f\"{a}\\n\" f \"b\""), Ok(tokens) if tokens == expected));
+        let mut lex = Lexer::new(Box::new(&b"x = r\"a\n\\b\";"[..]), false);
+        let mut log = Vec::new();
+        let mut next = || match lex.next(&mut log) {
+            Ok(Some(next)) => next,
+            _ => panic!("no token"),
+        };
+        next();
+        next();
+        let (range, token) = next();
+        assert_eq!(token, Token::String("a\n\\b".to_string()));
+        assert_eq!(range.to_string(), "1:5-2:3");
+    }
+
+    #[test]
+    fn hash_comment() {
+        let expected = vec![
+            Token::Identifier("a".to_string()),
+            Token::Semicolon,
+            Token::String("#".to_string()),
+        ];
+        let source = b"#!/usr/bin/env cryss\na; # comment\n\"#\"#\n/* # */";
+        assert!(matches!(tokens(source), Ok(tokens) if tokens == expected));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut lex = Lexer::new(Box::new(&b"let a = 1;\nlet \xff = 2;\n"[..]), false);
+        let mut log = Vec::new();
+        let err = loop {
+            match lex.next(&mut log) {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("no error"),
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::InvalidUtf8(pos) if pos == pos::Pos::new(1, 4)));
+        assert_eq!(log[1], "let \u{fffd} = 2;\n");
+    }
+
     fn nearly(actual: f64, expected: f64, err: f64) -> bool {
         (expected - err < actual) && (actual < expected + err)
     }
@@ -493,7 +855,48 @@ mod tests {
     #[test]
     fn number_integer() {
         let mut h = helper(r#"123 "#);
-        assert!(matches!(h.next(), Ok(Some((_, Token::Number(v)))) if nearly(v, 123.0, 0.05)));
+        assert!(matches!(h.next(), Ok(Some((_, Token::Int(123))))));
+        // i64 に収まらない整数は 16 進などと同じくエラー．実数にするなら小数点をつける
+        let mut h = helper(r#"99999999999999999999 "#);
+        assert!(matches!(h.next(), Err(Error::IntegerLiteralOutOfRange(_))));
+        let mut h = helper(r#"99999999999999999999. "#);
+        assert!(matches!(h.next(), Ok(Some((_, Token::Number(v)))) if nearly(v, 1e20, 1e5)));
+    }
+
+    #[test]
+    fn number_radix_and_separators() {
+        let expected = vec![
+            Token::Int(255),
+            Token::Int(8),
+            Token::Int(5),
+            Token::Int(1_000_000),
+            Token::Number(1_000.5),
+        ];
+        let source = b"0xfF 0o1_0 0b101 1_000_000 1_000.5";
+        assert!(matches!(tokens(source), Ok(tokens) if tokens == expected));
+        assert!(matches!(
+            tokens(b"0x;"),
+            Err(Error::IncompleteRadixLiteral(_))
+        ));
+        assert!(matches!(
+            tokens(b"0x8000000000000000"),
+            Err(Error::IntegerLiteralOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn number_out_of_range() {
+        assert!(matches!(
+            tokens(b"1e999;"),
+            Err(Error::NumberLiteralOverflow(_))
+        ));
+        assert!(matches!(
+            tokens(b"1_0e-999;"),
+            Err(Error::NumberLiteralUnderflow(_))
+        ));
+        // 非正規化数や 0 そのものは読める
+        let expected = vec![Token::Number(5e-324), Token::Number(0.), Token::Semicolon];
+        assert!(matches!(tokens(b"5e-324 0.0e-999;"), Ok(tokens) if tokens == expected));
     }
 
     #[test]
@@ -531,6 +934,7 @@ mod tests {
             ("for ", Token::KeywordFor),
             ("return ", Token::KeywordReturn),
             ("def ", Token::KeywordDef),
+            ("samples ", Token::KeywordSamples),
         ];
 
         keywords.iter().for_each(|(op, tk)| {
@@ -549,6 +953,12 @@ mod tests {
             ("/ ", Token::Slash),
             ("% ", Token::Percent),
             ("^ ", Token::Circumflex),
+            ("+= ", Token::PlusEqual),
+            ("-= ", Token::HyphenEqual),
+            ("*= ", Token::AsteriskEqual),
+            ("/= ", Token::SlashEqual),
+            ("%= ", Token::PercentEqual),
+            ("^= ", Token::CircumflexEqual),
             ("= ", Token::Equal),
             ("=> ", Token::EqualGreater),
             ("== ", Token::DoubleEqual),
@@ -557,6 +967,8 @@ mod tests {
             ("< ", Token::Less),
             ("<< ", Token::DoubleLess),
             ("> ", Token::Greater),
+            ("<= ", Token::LessEqual),
+            (">= ", Token::GreaterEqual),
             (">> ", Token::DoubleGreater),
             // ("& ", Token::Ampersand), // there are no SingleAmpersand token.
             ("&& ", Token::DoubleAmpersand),
@@ -565,6 +977,7 @@ mod tests {
             (": ", Token::Colon),
             ("; ", Token::Semicolon),
             (", ", Token::Comma),
+            (". ", Token::Dot),
             ("? ", Token::Question),
             ("( ", Token::OpeningParenthesis),
             (") ", Token::ClosingParenthesis),
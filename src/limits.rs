@@ -0,0 +1,196 @@
+//! 音を作るときの引数の安全確認
+//!
+//! 周波数や時間が有限でなかったり負だったりすると，黙って無音や雑音，巨大なファイルになる．
+//! 組み込み関数の引数は `SoundFunction::check` で，標本化周波数に依存する上限は書き出すときに `check` で確かめる
+
+use crate::filter::Filter;
+use crate::sound::Sound;
+
+/// 書き出したり鳴らしたりできる最長の時間（秒）
+pub const MAX_SECONDS: f64 = 60. * 60.;
+
+/// 標本化周波数の上限（ Hz ）
+pub const MAX_SAMPLERATE: f64 = 768000.;
+
+/// 数を文字列にするときの桁数と幅（ `format` ）の上限
+pub const MAX_DIGITS: usize = 1000;
+
+/// `value` が有限であることを確かめる
+pub fn finite(name: &str, value: f64) -> Result<f64, String> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("`{}` must be finite (got {})", name, value))
+    }
+}
+
+/// 0 以上の有限の周波数
+pub fn frequency(name: &str, value: f64) -> Result<f64, String> {
+    if finite(name, value)? < 0. {
+        return Err(format!(
+            "`{}` must not be negative (got {} Hz)",
+            name, value
+        ));
+    }
+    Ok(value)
+}
+
+/// 0 以上 1 以下の割合
+pub fn unit(name: &str, value: f64) -> Result<f64, String> {
+    if !(0. ..=1.).contains(&finite(name, value)?) {
+        return Err(format!(
+            "`{}` must be between 0 and 1 (got {})",
+            name, value
+        ));
+    }
+    Ok(value)
+}
+
+/// `MAX_DIGITS` 以下の桁数
+pub fn digits(name: &str, value: usize) -> Result<usize, String> {
+    if value > MAX_DIGITS {
+        return Err(format!(
+            "`{}` is too large ({}, at most {})",
+            name, value, MAX_DIGITS
+        ));
+    }
+    Ok(value)
+}
+
+/// 0 以上 `MAX_SECONDS` 以下の時間
+pub fn seconds(name: &str, value: f64) -> Result<f64, String> {
+    if finite(name, value)? < 0. {
+        return Err(format!("`{}` must not be negative (got {} s)", name, value));
+    }
+    if value > MAX_SECONDS {
+        return Err(format!(
+            "`{}` is too long ({} s, at most {} s)",
+            name, value, MAX_SECONDS
+        ));
+    }
+    Ok(value)
+}
+
+/// 正で `MAX_SAMPLERATE` 以下の標本化周波数
+pub fn samplerate(value: f64) -> Result<f64, String> {
+    if !(finite("samplerate", value)? > 0. && value <= MAX_SAMPLERATE) {
+        return Err(format!(
+            "`samplerate` must be between 0 and {} Hz (got {})",
+            MAX_SAMPLERATE, value
+        ));
+    }
+    Ok(value)
+}
+
+/// `sound` の正弦波とフィルタの周波数がナイキスト周波数を超えないことを確かめる
+pub fn check(sound: &Sound, samplerate: f64) -> Result<(), String> {
+    let nyquist = samplerate / 2.;
+    match sound {
+        Sound::Sin { frequency, .. } if frequency.abs() > nyquist => Err(format!(
+            "sin frequency {} Hz is above the Nyquist frequency {} Hz",
+            frequency, nyquist
+        )),
+        Sound::Oscillator {
+            waveform,
+            frequency,
+            ..
+        } if *frequency > nyquist => Err(format!(
+            "{} frequency {} Hz is above the Nyquist frequency {} Hz",
+            waveform.name(),
+            frequency,
+            nyquist
+        )),
+        Sound::Filter { sound, filter } => {
+            let (Filter::Lowpass { frequency, .. }
+            | Filter::Highpass { frequency, .. }
+            | Filter::Bandpass { frequency, .. }
+            | Filter::Allpass { frequency, .. }) = *filter;
+            if frequency >= nyquist {
+                return Err(format!(
+                    "filter frequency {} Hz is not below the Nyquist frequency {} Hz",
+                    frequency, nyquist
+                ));
+            }
+            check(sound, samplerate)
+        }
+        Sound::Minus(sound)
+        | Sound::Reciprocal(sound)
+        | Sound::Steps { sound, .. }
+        | Sound::Latency { sound, .. }
+        | Sound::Probe { sound, .. }
+        | Sound::Frozen { sound, .. }
+        | Sound::Clamp { sound, .. }
+        | Sound::Effect { sound, .. }
+        | Sound::Fm {
+            frequency: sound, ..
+        }
+        | Sound::Sync { slave: sound, .. }
+        | Sound::PanLaw {
+            position: sound, ..
+        } => check(sound, samplerate),
+        Sound::Add(left, right)
+        | Sound::Sub(left, right)
+        | Sound::Mul(left, right)
+        | Sound::Div(left, right)
+        | Sound::Pow(left, right)
+        | Sound::Rem(left, right)
+        | Sound::Stereo(left, right)
+        | Sound::Dynamics {
+            sound: left,
+            sidechain: right,
+            ..
+        }
+        | Sound::ModulatedFilter {
+            sound: left,
+            cutoff: right,
+            ..
+        } => check(left, samplerate).and_then(|()| check(right, samplerate)),
+        Sound::Apply(_, _, sounds) => sounds
+            .iter()
+            .try_for_each(|(_, sound)| check(sound, samplerate)),
+        Sound::Seq { sounds, .. } => sounds
+            .iter()
+            .try_for_each(|(_, sound)| check(sound, samplerate)),
+        Sound::Multisample { layers, .. } => layers
+            .iter()
+            .flat_map(|(_, sounds)| sounds)
+            .try_for_each(|sound| check(sound, samplerate)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        assert_eq!(frequency("frequency", 440.), Ok(440.));
+        assert!(frequency("frequency", -1.).is_err());
+        assert!(frequency("frequency", f64::NAN).is_err());
+        assert!(seconds("seconds", MAX_SECONDS + 1.).is_err());
+        assert_eq!(unit("mix", 1.), Ok(1.));
+        assert!(unit("mix", -0.5).is_err());
+        assert!(samplerate(0.).is_err());
+        let tone = |frequency| Sound::Sin {
+            frequency,
+            phase: 0.,
+        };
+        assert!(check(&tone(4000.), 8000.).is_ok());
+        assert!(check(&tone(-5000.), 8000.).is_err());
+        let filtered = Sound::Mul(
+            Box::new(Sound::Const(0.5)),
+            Box::new(Sound::Filter {
+                sound: Box::new(tone(100.)),
+                filter: Filter::Lowpass {
+                    frequency: 4000.,
+                    q: 0.5,
+                },
+            }),
+        );
+        assert_eq!(
+            check(&filtered, 8000.),
+            Err("filter frequency 4000 Hz is not below the Nyquist frequency 4000 Hz".to_string())
+        );
+    }
+}
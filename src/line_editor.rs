@@ -0,0 +1,89 @@
+//! 対話環境の行編集と履歴（フィーチャー `line-editing` ）
+//!
+//! rustyline で 1 行ずつ読み， `Lexer` には `BufRead` として渡す．プロンプトもここで出す．
+//! 履歴は `$XDG_STATE_HOME/cryss/history` か `~/.local/state/cryss/history` に残す
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{BufRead, Read};
+use std::path::PathBuf;
+
+pub struct LineEditor {
+    editor: DefaultEditor,
+    /// 読んだ行（改行つき）と，そのうち `Lexer` に渡した長さ
+    line: String,
+    consumed: usize,
+    history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    /// 端末を使えなければ `None` （ふつうの標準入力から読む）
+    pub fn new() -> Option<LineEditor> {
+        let mut editor = DefaultEditor::new().ok()?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // 初めて起動したときは履歴がない
+            let _ = editor.load_history(path);
+        }
+        Some(LineEditor {
+            editor,
+            line: String::new(),
+            consumed: 0,
+            history,
+        })
+    }
+}
+
+/// 履歴のファイル
+fn history_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(base) if !base.is_empty() => PathBuf::from(base),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("cryss").join("history"))
+}
+
+impl BufRead for LineEditor {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.consumed == self.line.len() {
+            self.consumed = 0;
+            self.line = match self.editor.readline("> ") {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = self.editor.add_history_entry(line.as_str());
+                    }
+                    line + "\n"
+                }
+                // Ctrl-C は打ちかけの行を捨てる
+                Err(ReadlineError::Interrupted) => "\n".to_string(),
+                Err(ReadlineError::Eof) => String::new(),
+                Err(err) => return Err(std::io::Error::other(err)),
+            };
+        }
+        Ok(&self.line.as_bytes()[self.consumed..])
+    }
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount;
+    }
+}
+
+impl Read for LineEditor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl Drop for LineEditor {
+    fn drop(&mut self) {
+        if let Some(path) = &self.history {
+            if let Some(directory) = path.parent() {
+                let _ = std::fs::create_dir_all(directory);
+            }
+            let _ = self.editor.save_history(path);
+        }
+    }
+}
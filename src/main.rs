@@ -1,53 +1,633 @@
 //! CReate Your Sound from Scratch
 
+#[cfg(feature = "aiff")]
+mod aiff;
+mod analysis;
+mod buffer;
+mod bundle;
+mod cache;
+mod chapters;
+mod chord;
+mod coercion;
 mod compiler;
+mod config;
+mod dither;
+mod dump;
+mod dynamics;
+mod effects;
+mod envelope;
 mod environment;
 mod error;
+mod events;
+mod features;
+mod filter;
+#[cfg(feature = "flac")]
+mod flac;
+mod format;
 mod function;
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+mod golden;
+mod graph;
+#[cfg(feature = "record")]
+mod input;
+mod ir;
 mod lexer;
+mod limits;
+#[cfg(feature = "line-editing")]
+mod line_editor;
+mod master;
+mod meter;
+mod multisample;
+mod normalize;
+mod oscillator;
+mod output;
 mod parser;
+mod paths;
 mod pos;
+mod preset;
+mod probe;
 mod program;
+mod progress;
+mod repl;
+mod resynth;
+mod samples;
+mod session;
 mod sound;
+mod spatial;
+mod sweep;
 mod syntax;
+mod tail;
+mod template;
+mod timeline;
 mod token;
 mod types;
 mod value;
+mod wav;
 
 fn main() {
     let matches = clap::App::new("cryss")
         .arg(clap::Arg::with_name("input"))
+        .arg(
+            clap::Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .help("Renders from the given marker or region"),
+        )
+        .arg(
+            clap::Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .help("Renders until the given marker or region"),
+        )
+        .arg(
+            clap::Arg::with_name("preview")
+                .long("preview")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Writes only the first seconds of each output (e.g. `10s`)"),
+        )
+        .arg(
+            clap::Arg::with_name("preview-samplerate")
+                .long("preview-samplerate")
+                .takes_value(true)
+                .requires("preview")
+                .help("Lowers the sample rate of the preview"),
+        )
+        .arg(
+            clap::Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .help("Uses the given [profile.<name>] of the configuration files"),
+        )
+        .arg(
+            clap::Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .help("Records render events to the given file as JSON lines"),
+        )
+        .arg(
+            clap::Arg::with_name("force")
+                .long("force")
+                .help("Overwrites existing output files unless `overwrite = false` is given"),
+        )
+        .arg(
+            clap::Arg::with_name("explain-graph")
+                .long("explain-graph")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["text", "dot"])
+                .help("Prints the graph of each output instead of writing it"),
+        )
+        .arg(
+            clap::Arg::with_name("error-format")
+                .long("error-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .help("Prints errors as text or as one JSON object per line (for editors)"),
+        )
+        .arg(
+            clap::Arg::with_name("probe-report")
+                .long("probe-report")
+                .help("Prints the peak and RMS recorded by probe() after running the script"),
+        )
+        .arg(
+            clap::Arg::with_name("relative-to-cwd")
+                .long("relative-to-cwd")
+                .help("Resolves relative paths in the script from the current directory instead of the script's"),
+        )
+        .arg(
+            clap::Arg::with_name("recursion-limit")
+                .long("recursion-limit")
+                .takes_value(true)
+                .value_name("DEPTH")
+                .help("Maximum depth of calls to functions defined with `def` [default: 200]"),
+        )
+        .arg(
+            clap::Arg::with_name("strict-types")
+                .long("strict-types")
+                .help("Fails instead of truncating reals used as integers (indices, counts)"),
+        )
+        .arg(
+            clap::Arg::with_name("param")
+                .long("param")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME=VALUE")
+                .help("Defines a variable, overriding `let NAME = ...` at the top level of the script"),
+        )
+        .arg(
+            clap::Arg::with_name("out")
+                .long("out")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes to the given file instead of the filenames in write() ({NAME} is replaced by --param values)"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("new")
+                .about("Writes a commented starter script")
+                .arg(
+                    clap::Arg::with_name("template")
+                        .required(true)
+                        .possible_values(&template::names()),
+                )
+                .arg(
+                    clap::Arg::with_name("directory")
+                        .long("directory")
+                        .short("d")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Writes the files into the given directory"),
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .long("config")
+                        .help("Also writes cryss.toml"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("bundle")
+                .about("Packs a script and the files it reads into a single file")
+                .arg(clap::Arg::with_name("script").required(true))
+                .arg(
+                    clap::Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("Writes the bundle to the given file (default: <script>.crb)"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("compile")
+                .about("Parses a script ahead of time so that it starts faster")
+                .arg(clap::Arg::with_name("script").required(true))
+                .arg(
+                    clap::Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("Writes the compiled script to the given file (default: <script>.crsc)"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("fmt")
+                .about("Removes trailing whitespace from a script in place")
+                .arg(clap::Arg::with_name("script").required(true))
+                .arg(
+                    clap::Arg::with_name("annotate")
+                        .long("annotate")
+                        .help("Runs the script and appends the values of top-level variables as `//=` comments"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("sweep")
+                .about("Renders a script once for each combination of parameter values")
+                .arg(clap::Arg::with_name("script").required(true))
+                .arg(
+                    clap::Arg::with_name("param")
+                        .long("param")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .value_name("NAME=VALUES")
+                        .help("Values of a variable: FROM..TO:COUNT, A,B,C or a single value"),
+                )
+                .arg(
+                    clap::Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("FILE")
+                        .help("Output file of each run, such as `sweep_{cutoff}.wav`"),
+                )
+                .arg(
+                    clap::Arg::with_name("jobs")
+                        .long("jobs")
+                        .short("j")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Number of runs at the same time"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("features")
+                .about("Prints the capabilities this binary was built with (`-` if not included)"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("cache")
+                .about("Manages the cache of decoded audio files")
+                .subcommand(
+                    clap::SubCommand::with_name("clean").about("Removes all cached files"),
+                )
+                .setting(clap::AppSettings::SubcommandRequired),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("run")
+                .about("Extracts a bundle and runs its script there, or runs a compiled script")
+                .arg(clap::Arg::with_name("bundle").required(true))
+                .arg(
+                    clap::Arg::with_name("directory")
+                        .long("directory")
+                        .short("d")
+                        .takes_value(true)
+                        .help("Extracts into the given directory (default: <bundle> without extension)"),
+                ),
+        )
         .get_matches();
 
-    let mut lexer = match matches.value_of("input") {
-        Some(filename) => lexer::Lexer::new(
-            Box::new(std::io::BufReader::new(
-                std::fs::File::open(filename).expect("cannot open the input file"),
-            )),
-            false,
-        ),
-        None => lexer::Lexer::new(Box::new(std::io::BufReader::new(std::io::stdin())), true),
+    if let Some(matches) = matches.subcommand_matches("new") {
+        match template::create(
+            matches.value_of("template").unwrap(),
+            std::path::Path::new(matches.value_of("directory").unwrap()),
+            matches.is_present("config"),
+        ) {
+            Ok(files) => files
+                .iter()
+                .for_each(|path| eprintln!("created {}", path.display())),
+            Err(message) => fail(&message),
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("bundle") {
+        let script = std::path::Path::new(matches.value_of("script").unwrap());
+        let output = matches
+            .value_of("output")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| script.with_extension("crb"));
+        let bundle = bundle::Bundle::collect(script).unwrap_or_else(|message| fail(&message));
+        std::fs::write(&output, bundle.to_bytes())
+            .unwrap_or_else(|err| fail(&format!("cannot write {}: {}", output.display(), err)));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("compile") {
+        let script = std::path::Path::new(matches.value_of("script").unwrap());
+        let output = matches
+            .value_of("output")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| script.with_extension("crsc"));
+        let file = std::fs::File::open(script)
+            .unwrap_or_else(|err| fail(&format!("cannot read {}: {}", script.display(), err)));
+        // `embed` は台本の置き場所から読む
+        paths::set_base(Some(paths::script_directory(script)));
+        let compiled =
+            ir::compile(Box::new(std::io::BufReader::new(file))).unwrap_or_else(|message| {
+                // 構文エラーの表示には `error:` がついている
+                eprintln!("{}", message);
+                std::process::exit(1)
+            });
+        std::fs::write(&output, compiled.to_bytes())
+            .unwrap_or_else(|err| fail(&format!("cannot write {}: {}", output.display(), err)));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let script = std::path::Path::new(matches.value_of("script").unwrap());
+        let source = std::fs::read_to_string(script)
+            .unwrap_or_else(|err| fail(&format!("cannot read {}: {}", script.display(), err)));
+        // 書き出しや再生はせずに実行する
+        let mut environment = matches.is_present("annotate").then(|| {
+            error::install_panic_hook();
+            paths::set_base(Some(paths::script_directory(script)));
+            let mut environment = environment::Environment::with_output(std::rc::Rc::new(
+                std::cell::RefCell::new(output::Discard),
+            ));
+            environment.set_backend(Box::new(output::Discard));
+            environment
+        });
+        let formatted = format::format(&source, environment.as_mut()).unwrap_or_else(|message| {
+            eprint!("{}", message);
+            std::process::exit(1)
+        });
+        std::fs::write(script, formatted)
+            .unwrap_or_else(|err| fail(&format!("cannot write {}: {}", script.display(), err)));
+        return;
+    }
+    if matches.subcommand_matches("features").is_some() {
+        print!("{}", features::report());
+        return;
+    }
+    if let Some(sweep) = matches.subcommand_matches("sweep") {
+        let parameters = sweep
+            .values_of("param")
+            .unwrap()
+            .map(sweep::values)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|message| fail(&message));
+        let jobs = sweep.value_of("jobs").unwrap();
+        let jobs = jobs
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("invalid number of jobs `{}`", jobs)));
+        // 台本の実行に関わるオプションは各回に引き継ぐ
+        let mut command = vec![std::env::current_exe()
+            .unwrap_or_else(|err| fail(&format!("cannot find the executable: {}", err)))
+            .into()];
+        for flag in ["force", "strict-types", "relative-to-cwd"] {
+            if matches.is_present(flag) {
+                command.push(format!("--{}", flag).into());
+            }
+        }
+        for option in ["profile", "error-format"] {
+            if let Some(value) = matches.value_of(option) {
+                command.extend([format!("--{}", option).into(), value.into()]);
+            }
+        }
+        command.push(sweep.value_of_os("script").unwrap().to_owned());
+        let combinations = sweep::combinations(&parameters);
+        let failed = sweep::run(
+            &command,
+            &combinations,
+            sweep.value_of("out").unwrap(),
+            jobs,
+        );
+        if failed > 0 {
+            fail(&format!("{} of {} runs failed", failed, combinations.len()));
+        }
+        return;
+    }
+    let input = match matches.subcommand_matches("run") {
+        Some(matches) => {
+            let path = std::path::Path::new(matches.value_of("bundle").unwrap());
+            let bytes = std::fs::read(path)
+                .unwrap_or_else(|err| fail(&format!("cannot read {}: {}", path.display(), err)));
+            // コンパイル済みの台本はその場で実行する
+            if ir::is_compiled(&bytes) {
+                Some(path.to_path_buf())
+            } else {
+                let directory = matches
+                    .value_of("directory")
+                    .map(std::path::PathBuf::from)
+                    .unwrap_or_else(|| path.with_extension(""));
+                let bundle =
+                    bundle::Bundle::from_bytes(&bytes).unwrap_or_else(|message| fail(&message));
+                let script = bundle
+                    .extract(&directory)
+                    .unwrap_or_else(|message| fail(&message));
+                // 台本の中の相対パスは展開した場所から解決する
+                std::env::set_current_dir(&directory).unwrap_or_else(|err| {
+                    fail(&format!("cannot enter {}: {}", directory.display(), err))
+                });
+                Some(script.file_name().unwrap().into())
+            }
+        }
+        None => matches.value_of_os("input").map(std::path::PathBuf::from),
     };
-    let mut log = Vec::new();
-
-    let mut environment = environment::Environment::new();
-
-    loop {
-        match parser::parse_statement(&mut lexer, &mut log) {
-            Ok(Some(statement)) => match environment.run(statement) {
-                Ok(()) => {}
-                Err(err) => {
-                    break err
-                        .print(&mut std::io::stderr(), &log)
-                        .expect("cannot print error message")
-                }
-            },
-            Ok(None) => break,
-            Err(err) => {
-                break err
-                    .print(&mut std::io::stderr(), &log)
-                    .expect("cannot print error message")
+
+    // 利用者の設定，台本の隣の設定，コマンドライン引数の順に重ねる
+    let script_directory = input
+        .as_deref()
+        .map(paths::script_directory)
+        .unwrap_or_default();
+    let configs: Vec<_> = [
+        config::user_config_path(),
+        Some(script_directory.join(config::SCRIPT_CONFIG)),
+    ]
+    .iter()
+    .flatten()
+    .filter_map(|path| config::Config::load(path).unwrap_or_else(|message| fail(&message)))
+    .collect();
+    let settings = config::resolve(&configs, matches.value_of("profile"))
+        .unwrap_or_else(|message| fail(&message));
+    let cache_settings = config::resolve_cache(&configs);
+    let cache_directory = cache_settings
+        .directory
+        .map(std::path::PathBuf::from)
+        .or_else(cache::Cache::default_directory);
+    if matches.subcommand_matches("cache").is_some() {
+        // `cache clean`
+        let directory = cache_directory.unwrap_or_else(|| fail("cannot find the cache directory"));
+        let removed = cache::clean(&directory).unwrap_or_else(|message| fail(&message));
+        eprintln!("removed {} bytes from {}", removed, directory.display());
+        return;
+    }
+
+    if let Some(path) = matches.value_of("log-file") {
+        events::open(path).unwrap_or_else(|message| fail(&message));
+    }
+    let script = input
+        .as_ref()
+        .map_or("-".into(), |path| path.to_string_lossy().into_owned());
+    events::record("start", &[("script", events::Field::String(&script))]);
+    let interactive = input.is_none();
+    let mut compiled = None;
+    let lexer = match input {
+        Some(filename) => {
+            let bytes = std::fs::read(&filename).expect("cannot open the input file");
+            if ir::is_compiled(&bytes) {
+                compiled = Some(ir::Compiled::from_bytes(&bytes).unwrap_or_else(|message| {
+                    fail(&format!("{}: {}", filename.display(), message))
+                }));
             }
+            lexer::Lexer::new(Box::new(std::io::Cursor::new(bytes)), false)
         }
+        None => stdin_lexer(),
+    };
+
+    coercion::set_strict(matches.is_present("strict-types"));
+    if let Some(limit) = matches.value_of("recursion-limit") {
+        function::set_recursion_limit(
+            limit
+                .parse()
+                .unwrap_or_else(|_| fail(&format!("invalid recursion limit `{}`", limit))),
+        );
+    }
+    graph::set_explain(
+        matches
+            .value_of("explain-graph")
+            .map(|name| graph::Format::from_name(name).unwrap_or_else(|message| fail(&message))),
+    );
+    error::install_panic_hook();
+    let json = matches.value_of("error-format") == Some("json");
+    error::set_color(
+        !json
+            && std::io::IsTerminal::is_terminal(&std::io::stderr())
+            && std::env::var_os("NO_COLOR").is_none(),
+    );
+    let dither = match &settings.dither {
+        Some(name) => dither::Dither::from_name(name).unwrap_or_else(|err| fail(&err)),
+        None => dither::Dither::None,
+    };
+    if !matches.is_present("relative-to-cwd") {
+        paths::set_base(Some(script_directory.clone()));
+    }
+    let output = output::WavFiles::new(
+        settings.bits.unwrap_or(32),
+        dither,
+        paths::resolve(settings.output_dir.as_deref().unwrap_or("")),
+    );
+    let mut environment =
+        environment::Environment::with_output(std::rc::Rc::new(std::cell::RefCell::new(output)));
+    environment.set_window(
+        matches
+            .value_of("from")
+            .map(str::to_string)
+            .or(settings.from),
+        matches.value_of("to").map(str::to_string).or(settings.to),
+    );
+    if let Some(samplerate) = settings.samplerate {
+        environment.set_samplerate(samplerate);
+    }
+    environment.set_overwrite(matches.is_present("force"));
+    let parameters = matches
+        .values_of("param")
+        .into_iter()
+        .flatten()
+        .map(sweep::parameter)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|message| fail(&message));
+    for (name, value) in &parameters {
+        environment.set_parameter(name, *value);
+    }
+    environment.set_out(
+        matches
+            .value_of("out")
+            .map(|out| sweep::expand(out, &parameters)),
+    );
+    if let Some(command) = &settings.player {
+        let player = output::Player::new(command).unwrap_or_else(|message| fail(&message));
+        environment.set_backend(Box::new(player));
     }
+    #[cfg(feature = "record")]
+    if let Some(command) = &settings.recorder {
+        let recorder = input::Recorder::new(command).unwrap_or_else(|message| fail(&message));
+        environment.set_input(Box::new(recorder));
+    }
+    if std::io::IsTerminal::is_terminal(&std::io::stderr()) {
+        environment.set_progress(progress::terminal());
+    }
+    if let Some(threads) = settings.threads {
+        environment.set_threads(threads);
+    }
+    if let Some(directory) = cache_directory {
+        let max_size = cache_settings
+            .max_size
+            .map_or(cache::DEFAULT_MAX_SIZE, |megabytes| {
+                (megabytes * 1024. * 1024.) as u64
+            });
+        environment.set_cache(cache::Cache::new(directory, max_size));
+    }
+    if let Some(seconds) = matches.value_of("preview") {
+        let seconds = seconds
+            .strip_suffix('s')
+            .unwrap_or(seconds)
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("invalid preview length `{}`", seconds)));
+        let samplerate = matches.value_of("preview-samplerate").map(|samplerate| {
+            samplerate
+                .parse()
+                .unwrap_or_else(|_| fail(&format!("invalid sample rate `{}`", samplerate)))
+        });
+        environment.set_preview(seconds, samplerate);
+    }
+
+    // コンパイル済みの台本は構文解析を飛ばして最初のエラーまで実行する
+    if let Some(compiled) = compiled {
+        let mut failed = false;
+        for statement in compiled.statements {
+            if let Err(err) = environment.run(statement) {
+                report(err, &compiled.log, json);
+                failed = true;
+                break;
+            }
+        }
+        if matches.is_present("probe-report") {
+            eprint!("{}", environment.probe_report());
+        }
+        events::record("finish", &[]);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // 対話環境ではエラーがあっても続ける．台本のエラーは終了コード 1 で伝える
+    let failed = repl::Repl::new(lexer, interactive)
+        .run(&mut environment, |err, log| report(err, log, json));
+    if matches.is_present("probe-report") {
+        eprint!("{}", environment.probe_report());
+    }
+    events::record("finish", &[]);
+    if failed && !interactive {
+        std::process::exit(1);
+    }
+}
+
+/// 対話環境の入力．端末ならば行編集と履歴を使う（フィーチャー `line-editing` ）
+fn stdin_lexer() -> lexer::Lexer {
+    #[cfg(feature = "line-editing")]
+    {
+        use std::io::IsTerminal;
+        if std::io::stdin().is_terminal() {
+            if let Some(editor) = line_editor::LineEditor::new() {
+                return lexer::Lexer::new(Box::new(editor), true).reader_prompts();
+            }
+        }
+    }
+    lexer::Lexer::new(Box::new(std::io::BufReader::new(std::io::stdin())), true)
+}
+
+/// エラーを表示して記録する．`json` なら 1 行の JSON で表示する．
+/// 記録には該当する行を含めない
+fn report(err: error::Error, log: &[String], json: bool) {
+    if json {
+        eprintln!("{}", err.to_json());
+    } else {
+        let mut message = Vec::new();
+        err.write_diagnostic(&mut message, log)
+            .expect("cannot print error message");
+        eprint!("{}", String::from_utf8_lossy(&message));
+    }
+    events::record(
+        "error",
+        &[
+            ("code", events::Field::String(err.code())),
+            ("message", events::Field::String(&err.to_string())),
+        ],
+    );
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1)
 }
@@ -0,0 +1,126 @@
+//! 書き出す直前にかけるマスター段
+
+/// `set_master` で選ぶマスター段
+#[derive(Clone, Debug, PartialEq)]
+pub enum Master {
+    /// 何もしない
+    None,
+    /// ピークリミッター．`ceiling` は振幅，`release` は秒
+    Limiter { ceiling: f64, release: f64 },
+}
+
+/// 既定のリミッターの天井（ -1 dBFS ）と戻りの時間
+pub const DEFAULT_CEILING_DB: f64 = -1.;
+pub const DEFAULT_RELEASE: f64 = 0.05;
+
+impl Default for Master {
+    fn default() -> Master {
+        Master::Limiter {
+            ceiling: db_to_amplitude(DEFAULT_CEILING_DB),
+            release: DEFAULT_RELEASE,
+        }
+    }
+}
+
+pub fn db_to_amplitude(db: f64) -> f64 {
+    10f64.powf(db / 20.)
+}
+
+impl Master {
+    /// 名前（ `limiter` か `none` ）から作る
+    pub fn from_name(name: &str, ceiling_db: f64, release: f64) -> Result<Master, String> {
+        match name {
+            "limiter" => Ok(Master::Limiter {
+                ceiling: db_to_amplitude(ceiling_db),
+                release,
+            }),
+            "none" => Ok(Master::None),
+            _ => Err(format!(
+                "unknown master `{}` (expected `limiter` or `none`)",
+                name
+            )),
+        }
+    }
+    pub fn processor(&self, samplerate: f64) -> Processor {
+        match self {
+            Master::None => Processor {
+                ceiling: f64::INFINITY,
+                coefficient: 0.,
+                gain: 1.,
+            },
+            Master::Limiter { ceiling, release } => Processor {
+                ceiling: *ceiling,
+                coefficient: (-1. / (release * samplerate)).exp(),
+                gain: 1.,
+            },
+        }
+    }
+}
+
+/// フレームを 1 つずつ処理する状態
+pub struct Processor {
+    ceiling: f64,
+    coefficient: f64,
+    gain: f64,
+}
+
+impl Processor {
+    /// 1 フレーム（全チャンネルの標本）を処理する．
+    /// 利得はチャンネルで共通で，アタックは即座に，戻りは指数的に．
+    /// 出力の絶対値は天井を超えない
+    pub fn process(&mut self, frame: &mut [f64]) {
+        let peak = frame.iter().fold(0., |peak: f64, x| peak.max(x.abs()));
+        let target = (self.ceiling / peak).min(1.);
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + (self.gain - target) * self.coefficient
+        };
+        frame.iter_mut().for_each(|x| *x *= self.gain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(processor: &mut Processor, x: f64) -> f64 {
+        let mut frame = [x];
+        processor.process(&mut frame);
+        frame[0]
+    }
+
+    #[test]
+    fn limiter() {
+        let mut processor = Master::default().processor(1000.);
+        let ceiling = db_to_amplitude(DEFAULT_CEILING_DB);
+        // 小さい音はそのまま
+        assert_eq!(process(&mut processor, 0.5), 0.5);
+        // 大きい音は天井で抑える
+        assert!((process(&mut processor, 2.) - ceiling).abs() < 1e-12);
+        assert!((process(&mut processor, -4.) + ceiling).abs() < 1e-12);
+        // 戻りの途中ではまだ小さい
+        let released = process(&mut processor, 0.5);
+        assert!(released < 0.5 && released > 0.);
+        for _ in 0..1000 {
+            process(&mut processor, 0.5);
+        }
+        assert!((process(&mut processor, 0.5) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn linked_stereo() {
+        let mut processor = Master::default().processor(1000.);
+        let mut frame = [0.5, 2.];
+        processor.process(&mut frame);
+        // 左右の比は保たれる
+        assert!((frame[1] / frame[0] - 4.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn none() {
+        let mut processor = Master::from_name("none", 0., 0.).unwrap().processor(1000.);
+        assert_eq!(process(&mut processor, 3.), 3.);
+        assert!(Master::from_name("compressor", 0., 0.).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! 端末に出すための ASCII の波形とレベルメーター
+
+use crate::analysis;
+
+/// 波形の幅と高さ（文字数）
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 9;
+/// メーターの目盛りの下限（dBFS）
+const FLOOR: f64 = -60.;
+
+/// 標本列を `width` 列に分け，各列の最小値から最大値までを `#` で塗る
+///
+/// 上端が 1 ，下端が -1 ，はみ出した値は端に丸める
+pub fn waveform(samples: &[f64], width: usize, height: usize) -> Vec<String> {
+    let columns: Vec<Option<(f64, f64)>> = (0..width)
+        .map(|i| {
+            let chunk = &samples[i * samples.len() / width..(i + 1) * samples.len() / width];
+            chunk.iter().fold(None, |acc, &x| {
+                let x = x.clamp(-1., 1.);
+                Some(acc.map_or((x, x), |(min, max): (f64, f64)| (min.min(x), max.max(x))))
+            })
+        })
+        .collect();
+    (0..height)
+        .map(|row| {
+            // 行 row が受け持つ値の範囲
+            let top = 1. - 2. * row as f64 / height as f64;
+            let bottom = 1. - 2. * (row + 1) as f64 / height as f64;
+            columns
+                .iter()
+                .map(|column| match column {
+                    Some((min, max)) if *min <= top && bottom <= *max => '#',
+                    _ if bottom <= 0. && 0. <= top => '-',
+                    _ => ' ',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// 振幅 `level` を dBFS の目盛りで表示する．1 を超えたら `!` をつける
+pub fn meter(level: f64, width: usize) -> String {
+    let db = 20. * level.log10();
+    let filled = ((1. - db / FLOOR).clamp(0., 1.) * width as f64).round() as usize;
+    format!(
+        "[{}{}]{} {:6.1} dBFS",
+        "#".repeat(filled),
+        " ".repeat(width - filled),
+        if level > 1. { '!' } else { ' ' },
+        db.max(FLOOR)
+    )
+}
+
+/// 波形とピーク，実効値のメーターをまとめた表示
+pub fn show(samples: &[f64]) -> String {
+    let mut lines = waveform(samples, WIDTH, HEIGHT);
+    lines.push(format!(
+        "peak {}",
+        meter(analysis::peak(samples), WIDTH - 15)
+    ));
+    lines.push(format!(
+        "rms  {}",
+        meter(analysis::rms(samples), WIDTH - 15)
+    ));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveform_square() {
+        let samples: Vec<f64> = (0..8).map(|i| if i < 4 { 1. } else { -0.5 }).collect();
+        assert_eq!(waveform(&samples, 2, 5), vec!["# ", "  ", "--", " #", "  "]);
+        assert_eq!(waveform(&[], 3, 3), vec!["   ", "---", "   "]);
+    }
+
+    #[test]
+    fn meter_levels() {
+        assert_eq!(meter(1., 4), "[####]     0.0 dBFS");
+        assert_eq!(meter(0.1, 4), "[### ]   -20.0 dBFS");
+        assert_eq!(meter(2., 4), "[####]!    6.0 dBFS");
+        assert_eq!(meter(0., 4), "[    ]   -60.0 dBFS");
+    }
+}
@@ -0,0 +1,148 @@
+//! 複数の標本から音の高さと音量で選んで鳴らす楽器（ `multisample` ）
+//!
+//! レコードのフィールド名で標本の置き場所を決める．
+//! `c4` は元の高さが C4 （ MIDI ノート番号 60 ）の標本で， `fs3` のように `s` をつけると半音上．
+//! `c4_2` は 2 番目に大きい音量の層， `c4_2_1` はその層で順番に（ラウンドロビンで）鳴らす 1 つ目．
+//! 音の高さは元の高さが一番近い標本を速さを変えて合わせ，音量 0〜1 を層の数で等分して層を選ぶ
+
+use crate::sound::Sound;
+use std::collections::BTreeMap;
+
+/// フィールド名の意味
+#[derive(Debug, PartialEq)]
+struct Zone {
+    /// 元の高さ（ MIDI ノート番号）
+    root: i64,
+    layer: u32,
+    round: u32,
+}
+
+fn parse_zone(name: &str) -> Result<Zone, String> {
+    let invalid = || {
+        format!(
+            "invalid multisample zone `{}` (expected a note name like `c4`, `fs3_2` or `a2_1_3`)",
+            name
+        )
+    };
+    let mut parts = name.split('_');
+    let note = parts.next().unwrap_or_default();
+    let mut chars = note.chars();
+    let pitch_class = match chars.next() {
+        Some('c') => 0,
+        Some('d') => 2,
+        Some('e') => 4,
+        Some('f') => 5,
+        Some('g') => 7,
+        Some('a') => 9,
+        Some('b') => 11,
+        _ => return Err(invalid()),
+    };
+    let rest = chars.as_str();
+    let (sharp, octave) = match rest.strip_prefix('s') {
+        Some(octave) => (1, octave),
+        None => (0, rest),
+    };
+    let octave: i64 = octave.parse().map_err(|_| invalid())?;
+    let number = |parts: &mut std::str::Split<char>| match parts.next() {
+        Some(n) => n.parse().map_err(|_| invalid()),
+        None => Ok(1),
+    };
+    let layer = number(&mut parts)?;
+    let round = number(&mut parts)?;
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(Zone {
+        root: 12 * (octave + 1) + pitch_class + sharp,
+        layer,
+        round,
+    })
+}
+
+/// 高さ `note` ，音量 `velocity` で鳴らす音．`round` 番目の音から順番に鳴らす
+pub fn build(
+    zones: &[(String, Sound)],
+    note: f64,
+    velocity: f64,
+    round: usize,
+) -> Result<Sound, String> {
+    let mut parsed = Vec::new();
+    for (name, sound) in zones {
+        parsed.push((parse_zone(name)?, sound));
+    }
+    // 元の高さが一番近いもの（同じなら低いもの）
+    let root = parsed
+        .iter()
+        .map(|(zone, _)| zone.root)
+        .min_by(|a, b| {
+            (*a as f64 - note)
+                .abs()
+                .partial_cmp(&(*b as f64 - note).abs())
+                .unwrap()
+                .then(a.cmp(b))
+        })
+        .ok_or_else(|| "multisample needs at least one zone".to_string())?;
+    let ratio = 2f64.powf((note - root as f64) / 12.);
+    let mut layers: BTreeMap<u32, BTreeMap<u32, Sound>> = BTreeMap::new();
+    for (zone, sound) in parsed {
+        if zone.root == root {
+            layers
+                .entry(zone.layer)
+                .or_default()
+                .insert(zone.round, sound.clone().transpose(ratio));
+        }
+    }
+    let count = layers.len() as f64;
+    let layers = layers
+        .into_values()
+        .enumerate()
+        .map(|(i, rounds)| (i as f64 / count, rounds.into_values().collect()))
+        .collect::<Vec<_>>();
+    Ok(Sound::Multisample {
+        layers: layers.into(),
+        velocity,
+        round,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zones() {
+        let zone = |root, layer, round| Zone { root, layer, round };
+        assert_eq!(parse_zone("c4"), Ok(zone(60, 1, 1)));
+        assert_eq!(parse_zone("fs3_2"), Ok(zone(54, 2, 1)));
+        assert_eq!(parse_zone("a0_1_3"), Ok(zone(21, 1, 3)));
+        assert!(parse_zone("h4").is_err());
+        assert!(parse_zone("c4_1_2_3").is_err());
+    }
+
+    #[test]
+    fn layers_and_rounds() {
+        let constant = |x: f64| Sound::Samples {
+            samples: vec![x; 4].into(),
+            samplerate: 4.,
+            offset: 0.,
+            looping: None,
+        };
+        let zones: Vec<_> = [("c4_1_1", 1.), ("c4_1_2", 2.), ("c4_2", 3.), ("c5", 4.)]
+            .iter()
+            .map(|(name, x)| (name.to_string(), constant(*x)))
+            .collect();
+        let first = |note, velocity, round| {
+            build(&zones, note, velocity, round)
+                .unwrap()
+                .render(0.25, 4.)[0]
+        };
+        assert_eq!(first(62., 0.2, 0), 1.);
+        assert_eq!(first(62., 0.2, 1), 2.);
+        assert_eq!(first(62., 0.2, 2), 1.);
+        assert_eq!(first(62., 0.5, 1), 3.);
+        assert_eq!(first(70., 1., 0), 4.);
+        // 長 2 度上は速く鳴る
+        let sound = build(&zones, 62., 1., 0).unwrap();
+        assert!((sound.duration().unwrap() - 2f64.powf(-2. / 12.)).abs() < 1e-9);
+    }
+}
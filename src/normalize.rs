@@ -0,0 +1,154 @@
+//! 書き出しのラウドネス正規化（ `write(..., normalize = -14)` ）
+//!
+//! 書き出す音をいったんすべてためて統合ラウドネスを測り，目標に合わせて増幅してから書く．
+//! 真のピークが上限を超えるならば，上限に収まるところまでしか増幅しない
+
+use crate::analysis;
+use crate::events::{self, Field};
+use crate::master::db_to_amplitude;
+use crate::output::Sink;
+
+/// 真のピークの上限の既定値（ dBTP ）
+pub const DEFAULT_TRUE_PEAK: f64 = -1.;
+
+/// 目標の統合ラウドネス（ LUFS ）と真のピークの上限（ dBTP ）
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Target {
+    pub loudness: f64,
+    pub true_peak: f64,
+}
+
+/// 書き出しを正規化する `Sink`
+pub struct Normalizer {
+    sink: Box<dyn Sink>,
+    name: String,
+    target: Target,
+    samplerate: f64,
+    /// チャンネルごとの標本列
+    channels: Vec<Vec<f64>>,
+}
+
+impl Normalizer {
+    pub fn new(
+        sink: Box<dyn Sink>,
+        name: &str,
+        channels: usize,
+        samplerate: f64,
+        target: Target,
+    ) -> Normalizer {
+        Normalizer {
+            sink,
+            name: name.to_string(),
+            target,
+            samplerate,
+            channels: vec![Vec::new(); channels],
+        }
+    }
+    /// 目標に合わせる倍率と，正規化前の統合ラウドネス
+    fn gain(&self) -> (f64, f64) {
+        let channels: Vec<&[f64]> = self.channels.iter().map(Vec::as_slice).collect();
+        let loudness = analysis::lufs_integrated_channels(&channels, self.samplerate);
+        // 無音や短すぎる音は測れないのでそのまま
+        if !loudness.is_finite() {
+            events::warning(&format!(
+                "{}: cannot measure loudness; not normalized",
+                self.name
+            ));
+            return (1., loudness);
+        }
+        let gain = db_to_amplitude(self.target.loudness - loudness);
+        let true_peak = channels
+            .iter()
+            .map(|samples| analysis::true_peak(samples))
+            .fold(0., f64::max);
+        let ceiling = db_to_amplitude(self.target.true_peak);
+        if true_peak * gain <= ceiling {
+            return (gain, loudness);
+        }
+        let gain = ceiling / true_peak;
+        events::warning(&format!(
+            "{}: true-peak ceiling {} dBTP limits loudness to {:.1} LUFS",
+            self.name,
+            self.target.true_peak,
+            loudness + 20. * gain.log10()
+        ));
+        (gain, loudness)
+    }
+}
+
+impl Sink for Normalizer {
+    fn write(&mut self, frame: &[f64]) -> Result<(), String> {
+        for (samples, x) in self.channels.iter_mut().zip(frame) {
+            samples.push(*x);
+        }
+        Ok(())
+    }
+    fn finalize(mut self: Box<Self>) -> Result<(), String> {
+        let (gain, loudness) = self.gain();
+        events::record(
+            "normalize",
+            &[
+                ("file", Field::String(&self.name)),
+                ("loudness", Field::Number(loudness)),
+                ("gain", Field::Number(20. * gain.log10())),
+            ],
+        );
+        let len = self.channels.iter().map(Vec::len).min().unwrap_or(0);
+        let mut frame = Vec::new();
+        for i in 0..len {
+            frame.clear();
+            frame.extend(self.channels.iter().map(|samples| samples[i] * gain));
+            self.sink.write(&frame)?;
+        }
+        self.sink.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Encoding, Memory, Output};
+    use crate::wav;
+
+    fn normalize(target: Target) -> Vec<f64> {
+        let mut memory = Memory::default();
+        let sink = memory
+            .create(
+                "a.wav",
+                1,
+                8000.,
+                &wav::Metadata::default(),
+                &Encoding::default(),
+            )
+            .unwrap();
+        let mut normalizer = Box::new(Normalizer::new(sink, "a.wav", 1, 8000., target));
+        for i in 0..16000 {
+            let x = 0.1 * (std::f64::consts::TAU * 1000. * i as f64 / 8000.).sin();
+            normalizer.write(&[x]).unwrap();
+        }
+        normalizer.finalize().unwrap();
+        let recording = memory.get("a.wav").unwrap();
+        recording.samples.iter().map(|x| *x as f64).collect()
+    }
+
+    #[test]
+    fn loudness_and_ceiling() {
+        let samples = normalize(Target {
+            loudness: -14.,
+            true_peak: DEFAULT_TRUE_PEAK,
+        });
+        let loudness = analysis::lufs_integrated(&samples, 8000.);
+        assert!((loudness + 14.).abs() < 0.1, "{}", loudness);
+        // 上限のほうが先に効く
+        let samples = normalize(Target {
+            loudness: 0.,
+            true_peak: -6.,
+        });
+        let true_peak = analysis::true_peak(&samples);
+        assert!(
+            (true_peak - db_to_amplitude(-6.)).abs() < 1e-3,
+            "{}",
+            true_peak
+        );
+    }
+}
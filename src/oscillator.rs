@@ -0,0 +1,242 @@
+//! 基本波形のオシレーター（ `square` ， `saw` ， `triangle` ）と雑音（ `noise` ），ユニゾン（ `unison` ）
+//!
+//! 位相は 1 周期を 1 とした `[0, 1)` ．`bandlimit` ならば不連続点を PolyBLEP でならして折り返し雑音を減らす．
+//! 三角波は倍音が 12 dB/oct で減るので折り返しは小さく，そのまま鳴らす
+
+use crate::sound::Sound;
+use crate::spatial;
+use std::f64::consts::TAU;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    /// 1 周期のうち `duty` の間だけ 1 ，残りは -1
+    Square {
+        duty: f64,
+    },
+    Saw,
+    Triangle,
+}
+
+/// 位相 `t` の近くにある不連続点（位相 0 ）の PolyBLEP の補正．`dt` は 1 標本で進む位相
+fn blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2. * x - x * x - 1.
+    } else if t > 1. - dt {
+        let x = (t - 1.) / dt;
+        x * x + 2. * x + 1.
+    } else {
+        0.
+    }
+}
+
+impl Waveform {
+    pub fn name(&self) -> String {
+        match self {
+            Waveform::Square { duty } => format!("square duty {}", duty),
+            Waveform::Saw => "saw".to_string(),
+            Waveform::Triangle => "triangle".to_string(),
+        }
+    }
+    /// 位相 `phase` の値．`dt` は 1 標本で進む位相で，0 ならば帯域制限しない
+    pub fn value(&self, phase: f64, dt: f64) -> f64 {
+        let blep = |t: f64| if dt > 0. { blep(t, dt) } else { 0. };
+        match *self {
+            Waveform::Square { duty } => {
+                let naive = if phase < duty { 1. } else { -1. };
+                naive + blep(phase) - blep((phase - duty).rem_euclid(1.))
+            }
+            Waveform::Saw => 2. * phase - 1. - blep(phase),
+            // 正弦波と同じく 0 から上がり始める
+            Waveform::Triangle => 1. - 4. * ((phase + 0.25).rem_euclid(1.) - 0.5).abs(),
+        }
+    }
+}
+
+/// シード `seed` の `index` 番目の白色雑音の標本．`[-1, 1)` で一様
+///
+/// 標本番号だけから決まるので，どこから鳴らしても同じ結果になる
+pub fn noise(seed: u64, index: i64) -> f64 {
+    // SplitMix64
+    let mut z = seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 52) as f64 - 1.
+}
+
+/// オシレーター `sound` の周波数を `ratio` 倍し，位相を `phase` （ 1 周期を 1 とする）にした音
+fn retune(sound: &Sound, ratio: f64, phase: f64) -> Option<Sound> {
+    Some(match sound {
+        Sound::Sin { frequency, .. } => Sound::Sin {
+            frequency: frequency * ratio,
+            phase: TAU * phase,
+        },
+        Sound::Oscillator {
+            waveform,
+            frequency,
+            bandlimit,
+            ..
+        } => Sound::Oscillator {
+            waveform: *waveform,
+            frequency: frequency * ratio,
+            phase,
+            bandlimit: *bandlimit,
+        },
+        Sound::Fm {
+            waveform,
+            frequency,
+            bandlimit,
+            offset,
+            ..
+        } => Sound::Fm {
+            waveform: *waveform,
+            frequency: Sound::Mul(frequency.clone(), Sound::Const(ratio).into()).into(),
+            phase,
+            bandlimit: *bandlimit,
+            offset: *offset,
+        },
+        _ => return None,
+    })
+}
+
+/// オシレーター `sound` を `voices` 個に分け，全体で `detune` セントの幅に散らして
+/// 低いものから左（ -`spread` ）から右（ `spread` ）に並べる．位相は `seed` から決める．
+/// 音量は声部の数の平方根で割る．オシレーターでなければ `None`
+pub fn unison(sound: &Sound, voices: usize, detune: f64, spread: f64, seed: u64) -> Option<Sound> {
+    let gain = Sound::Const((voices as f64).sqrt().recip());
+    let sum = (0..voices)
+        .map(|i| {
+            let x = if voices > 1 {
+                2. * i as f64 / (voices - 1) as f64 - 1.
+            } else {
+                0.
+            };
+            let ratio = 2f64.powf(x * detune / 2. / 1200.);
+            let phase = (noise(seed, i as i64) + 1.) / 2.;
+            let voice = retune(sound, ratio, phase)?;
+            Some(spatial::pan(voice, Sound::Const(x * spread)))
+        })
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .reduce(|sum, voice| Sound::Add(sum.into(), voice.into()))?;
+    Some(Sound::Mul(sum.into(), gain.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms() {
+        let values = |waveform: Waveform| -> Vec<f64> {
+            [0., 0.125, 0.25, 0.5, 0.75]
+                .iter()
+                .map(|&phase| waveform.value(phase, 0.))
+                .collect()
+        };
+        assert_eq!(
+            values(Waveform::Square { duty: 0.25 }),
+            vec![1., 1., -1., -1., -1.]
+        );
+        assert_eq!(values(Waveform::Saw), vec![-1., -0.75, -0.5, 0., 0.5]);
+        assert_eq!(values(Waveform::Triangle), vec![0., 0.5, 1., 0., -1.]);
+        // 不連続点の両側を寄せる
+        let saw = |phase| Waveform::Saw.value(phase, 0.1);
+        assert_eq!(saw(0.5), 0.);
+        assert!(saw(0.) > -0.5 && saw(0.99) < 0.5);
+    }
+
+    #[test]
+    fn hard_sync() {
+        let sync = Sound::Sync {
+            frequency: 2.,
+            phase: 0.,
+            slave: Box::new(Sound::Oscillator {
+                waveform: Waveform::Saw,
+                frequency: 1.5,
+                phase: 0.,
+                bandlimit: false,
+            }),
+        };
+        let samples = sync.clone().render(1., 8.);
+        assert_eq!(samples, [-1., -0.625, -0.25, 0.125].repeat(2));
+        assert_eq!(sync.shift(0.25).render(0.75, 8.), samples[2..]);
+    }
+
+    #[test]
+    fn frequency_modulation() {
+        let fm = |frequency| Sound::Fm {
+            waveform: None,
+            frequency: Box::new(frequency),
+            phase: 0.25,
+            bandlimit: false,
+            offset: 0.,
+        };
+        let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9);
+        let constant = fm(Sound::Const(1.)).render(1., 4.);
+        assert!(close(&constant, &[1., 0., -1., 0.]));
+        // 周波数が 1 から 3 に上がるので，位相は 1 秒で 2 周期進む
+        let sweep = fm(Sound::Linear {
+            slope: 2.,
+            intercept: 1.,
+        });
+        let samples = sweep.clone().render(1., 1000.);
+        assert!(close(
+            &sweep.clone().shift(0.25).render(0.5, 1000.),
+            &samples[250..750]
+        ));
+        let delayed = sweep.shift(-0.5).render(1.5, 1000.);
+        assert!(close(&delayed[500..], &samples));
+    }
+
+    #[test]
+    fn detuned_voices() {
+        let saw = Sound::Oscillator {
+            waveform: Waveform::Saw,
+            frequency: 2.,
+            phase: 0.,
+            bandlimit: false,
+        };
+        assert!(unison(&Sound::Const(1.), 3, 10., 1., 0).is_none());
+        // 1 オクターブの幅の 3 声を左端，中央，右端に置く
+        let sound = unison(&saw, 3, 2400., 1., 7).unwrap();
+        let left = sound.clone().channel(0).render(1., 16.);
+        let right = sound.channel(1).render(1., 16.);
+        let voice = |i: i64, ratio: f64, gain: f64| {
+            let phase = (noise(7, i) + 1.) / 2.;
+            let voice = retune(&saw, ratio, phase).unwrap().render(1., 16.);
+            voice.into_iter().map(move |x| x * gain / 3f64.sqrt())
+        };
+        let center = std::f64::consts::FRAC_1_SQRT_2;
+        let expected_left: Vec<_> = voice(0, 0.5, 1.)
+            .zip(voice(1, 1., center))
+            .map(|(a, b)| a + b)
+            .collect();
+        let expected_right: Vec<_> = voice(1, 1., center)
+            .zip(voice(2, 2., 1.))
+            .map(|(a, b)| a + b)
+            .collect();
+        let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-9);
+        assert!(close(&left, &expected_left));
+        assert!(close(&right, &expected_right));
+    }
+
+    #[test]
+    fn reproducible_noise() {
+        let sound = Sound::Noise {
+            seed: 1,
+            offset: 0.,
+        };
+        let samples = sound.clone().render(1., 1000.);
+        assert!(samples.iter().all(|x| (-1. ..1.).contains(x)));
+        let mean = samples.iter().sum::<f64>() / 1000.;
+        assert!(mean.abs() < 0.1, "{}", mean);
+        assert_eq!(sound.shift(0.5).render(0.5, 1000.), samples[500..]);
+        let other = Sound::Noise {
+            seed: 2,
+            offset: 0.,
+        };
+        assert_ne!(other.render(1., 1000.), samples);
+    }
+}
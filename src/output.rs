@@ -0,0 +1,594 @@
+//! `write` などの書き出し先．既定は WAV ファイルで，テストや埋め込み先ではメモリに置き換えられる
+//! （ `Memory` は `test-util` フィーチャー）．`cryss fmt --annotate` では何も残さない `Discard` に置き換える．
+//! `play` の再生先（ `AudioBackend` ）も同じ `Sink` で受け取る
+
+use crate::dither::{Dither, Quantizer};
+use crate::wav;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+#[cfg(any(test, feature = "test-util"))]
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// 標本の書き方．`None` の項目は書き出し先の既定に従う
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Encoding {
+    /// 整数の量子化ビット数
+    pub bits: Option<u16>,
+    /// 32 ビット浮動小数点数で書く（ディザーはかけない）
+    pub float: bool,
+    pub dither: Option<Dither>,
+}
+
+/// 書き出し先．`Environment::with_output` で差し替える
+pub trait Output {
+    /// 名前 `name` の書き出しを始める
+    fn create(
+        &mut self,
+        name: &str,
+        channels: usize,
+        samplerate: f64,
+        metadata: &wav::Metadata,
+        encoding: &Encoding,
+    ) -> Result<Box<dyn Sink>, String>;
+    /// `write_stems` の書き出し先のディレクトリを作る
+    fn create_dir_all(&mut self, path: &Path) -> Result<(), String>;
+    /// 名前 `name` の書き出しがすでにあるか
+    fn exists(&self, name: &str) -> bool;
+}
+
+/// `play` の再生先．`Environment::set_backend` で差し替える
+pub trait AudioBackend {
+    /// 再生を始める．`Sink::finalize` は鳴らし終えるまで待つ
+    fn open(&mut self, channels: usize, samplerate: f64) -> Result<Box<dyn Sink>, String>;
+}
+
+/// 1 つの書き出し
+pub trait Sink {
+    /// 1 フレーム（全チャンネルの標本）を書く
+    fn write(&mut self, frame: &[f64]) -> Result<(), String>;
+    fn finalize(self: Box<Self>) -> Result<(), String>;
+}
+
+/// WAV ファイルに書き出す
+pub struct WavFiles {
+    /// `bits` を省略したときの量子化ビット数（ 16 ， 24 ， 32 ）
+    bits: u16,
+    /// `dither` を省略したときのディザー
+    dither: Dither,
+    /// 相対パスの置き場所
+    directory: PathBuf,
+}
+
+impl Default for WavFiles {
+    fn default() -> WavFiles {
+        WavFiles::new(32, Dither::None, PathBuf::new())
+    }
+}
+
+impl WavFiles {
+    pub fn new(bits: u16, dither: Dither, directory: PathBuf) -> WavFiles {
+        WavFiles {
+            bits,
+            dither,
+            directory,
+        }
+    }
+}
+
+impl Output for WavFiles {
+    fn create(
+        &mut self,
+        name: &str,
+        channels: usize,
+        samplerate: f64,
+        metadata: &wav::Metadata,
+        encoding: &Encoding,
+    ) -> Result<Box<dyn Sink>, String> {
+        let bits = if encoding.float {
+            32
+        } else {
+            encoding.bits.unwrap_or(self.bits)
+        };
+        let spec = hound::WavSpec {
+            channels: channels as u16,
+            sample_rate: samplerate as u32,
+            bits_per_sample: bits,
+            sample_format: if encoding.float {
+                hound::SampleFormat::Float
+            } else {
+                hound::SampleFormat::Int
+            },
+        };
+        let path = self.directory.join(name);
+        let temporary = Temporary::new(&path);
+        let name = path.to_string_lossy();
+        let writer = hound::WavWriter::create(&temporary.path, spec)
+            .map_err(|err| format!("cannot write {}: {}", name, err))?;
+        Ok(Box::new(WavFile {
+            writer,
+            quantizer: if encoding.float {
+                None
+            } else {
+                Some(Quantizer::new(
+                    encoding.dither.unwrap_or(self.dither),
+                    2f64.powi(bits as i32 - 1) - 1.,
+                    channels,
+                ))
+            },
+            name: name.into_owned(),
+            temporary,
+            samplerate,
+            metadata: metadata.clone(),
+        }))
+    }
+    fn create_dir_all(&mut self, path: &Path) -> Result<(), String> {
+        let path = self.directory.join(path);
+        std::fs::create_dir_all(&path)
+            .map_err(|err| format!("cannot create {}: {}", path.display(), err))
+    }
+    fn exists(&self, name: &str) -> bool {
+        self.directory.join(name).exists()
+    }
+}
+
+/// 書き出し中の一時ファイル．書き終えたら本来の名前に変え，途中で失敗したら消す．
+/// 書きかけのファイルが本来の名前で残らないように
+struct Temporary {
+    path: PathBuf,
+    done: bool,
+}
+
+impl Temporary {
+    /// `path` と同じディレクトリの隠しファイル
+    fn new(path: &Path) -> Temporary {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temporary = format!(".{}.{}.tmp", name, std::process::id());
+        Temporary {
+            path: path.with_file_name(temporary),
+            done: false,
+        }
+    }
+    fn persist(mut self, path: &str) -> Result<(), String> {
+        std::fs::rename(&self.path, path)
+            .map_err(|err| format!("cannot write {}: {}", path, err))?;
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Drop for Temporary {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+struct WavFile {
+    writer: hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+    /// 浮動小数点数で書くなら `None`
+    quantizer: Option<Quantizer>,
+    name: String,
+    temporary: Temporary,
+    samplerate: f64,
+    metadata: wav::Metadata,
+}
+
+impl Sink for WavFile {
+    fn write(&mut self, frame: &[f64]) -> Result<(), String> {
+        for (channel, x) in frame.iter().enumerate() {
+            match &mut self.quantizer {
+                Some(quantizer) => self.writer.write_sample(quantizer.quantize(channel, *x)),
+                None => self.writer.write_sample(*x as f32),
+            }
+            .map_err(|err| format!("cannot write {}: {}", self.name, err))?;
+        }
+        Ok(())
+    }
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        let WavFile {
+            writer,
+            name,
+            temporary,
+            samplerate,
+            metadata,
+            ..
+        } = *self;
+        writer
+            .finalize()
+            .map_err(|err| format!("cannot write {}: {}", name, err))?;
+        if !metadata.is_empty() {
+            let bytes = std::fs::read(&temporary.path)
+                .map_err(|err| format!("cannot read {}: {}", name, err))?;
+            let bytes = wav::insert_metadata(&bytes, &metadata, samplerate)
+                .map_err(|err| format!("{}: {}", name, err))?;
+            std::fs::write(&temporary.path, bytes)
+                .map_err(|err| format!("cannot write {}: {}", name, err))?;
+        }
+        temporary.persist(&name)
+    }
+}
+
+/// 標準入力から WAV を読んで鳴らすコマンド（既定は `aplay -q` ， macOS では SoX の `play -q -t wav -` ）．
+/// 設定ファイルの `player` で変える
+pub struct Player {
+    command: Vec<String>,
+}
+
+impl Default for Player {
+    fn default() -> Player {
+        let command = if cfg!(target_os = "macos") {
+            "play -q -t wav -"
+        } else {
+            "aplay -q"
+        };
+        Player::new(command).unwrap()
+    }
+}
+
+impl Player {
+    /// 空白で区切ったコマンド
+    pub fn new(command: &str) -> Result<Player, String> {
+        let command: Vec<_> = command.split_whitespace().map(str::to_string).collect();
+        if command.is_empty() {
+            return Err("empty player command".to_string());
+        }
+        Ok(Player { command })
+    }
+}
+
+impl AudioBackend for Player {
+    fn open(&mut self, channels: usize, samplerate: f64) -> Result<Box<dyn Sink>, String> {
+        let command = self.command.join(" ");
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                format!(
+                    "cannot start `{}`: {} (set `player` in cryss.toml)",
+                    command, err
+                )
+            })?;
+        let mut stdin = std::io::BufWriter::new(child.stdin.take().unwrap());
+        // 長さの分からない 16 ビットの WAV
+        let (channels16, rate) = (channels as u16, samplerate as u32);
+        let mut header = b"RIFF\xff\xff\xff\xffWAVEfmt \x10\0\0\0\x01\0".to_vec();
+        header.extend(&channels16.to_le_bytes());
+        header.extend(&rate.to_le_bytes());
+        header.extend(&(rate * 2 * u32::from(channels16)).to_le_bytes());
+        header.extend(&(2 * channels16).to_le_bytes());
+        header.extend(&16u16.to_le_bytes());
+        header.extend(b"data\xff\xff\xff\xff");
+        stdin
+            .write_all(&header)
+            .map_err(|err| format!("cannot play with `{}`: {}", command, err))?;
+        Ok(Box::new(Playback {
+            child,
+            stdin,
+            quantizer: Quantizer::new(Dither::Tpdf, 32767., channels),
+            command,
+        }))
+    }
+}
+
+struct Playback {
+    child: Child,
+    stdin: std::io::BufWriter<ChildStdin>,
+    quantizer: Quantizer,
+    command: String,
+}
+
+impl Sink for Playback {
+    fn write(&mut self, frame: &[f64]) -> Result<(), String> {
+        for (channel, x) in frame.iter().enumerate() {
+            let x = self.quantizer.quantize(channel, *x) as i16;
+            self.stdin
+                .write_all(&x.to_le_bytes())
+                .map_err(|err| format!("cannot play with `{}`: {}", self.command, err))?;
+        }
+        Ok(())
+    }
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        let Playback {
+            mut child,
+            stdin,
+            command,
+            ..
+        } = *self;
+        // 標準入力を閉じて，鳴らし終えるのを待つ
+        stdin
+            .into_inner()
+            .map_err(|err| format!("cannot play with `{}`: {}", command, err.error()))?;
+        let status = child
+            .wait()
+            .map_err(|err| format!("cannot play with `{}`: {}", command, err))?;
+        if !status.success() {
+            return Err(format!("`{}` failed ({})", command, status));
+        }
+        Ok(())
+    }
+}
+
+/// 書き出しも再生もせずに捨てる
+pub struct Discard;
+
+impl Output for Discard {
+    fn create(
+        &mut self,
+        _: &str,
+        _: usize,
+        _: f64,
+        _: &wav::Metadata,
+        _: &Encoding,
+    ) -> Result<Box<dyn Sink>, String> {
+        Ok(Box::new(Discard))
+    }
+    fn create_dir_all(&mut self, _: &Path) -> Result<(), String> {
+        Ok(())
+    }
+    fn exists(&self, _: &str) -> bool {
+        false
+    }
+}
+
+impl AudioBackend for Discard {
+    fn open(&mut self, _: usize, _: f64) -> Result<Box<dyn Sink>, String> {
+        Ok(Box::new(Discard))
+    }
+}
+
+impl Sink for Discard {
+    fn write(&mut self, _: &[f64]) -> Result<(), String> {
+        Ok(())
+    }
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// メモリ上に書き出したもの
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub channels: usize,
+    pub samplerate: f64,
+    /// チャンネルを交互に並べた標本列
+    pub samples: Vec<f32>,
+    pub metadata: wav::Metadata,
+}
+
+/// 名前ごとに `Recording` をメモリに残す．ディレクトリは作らない．
+/// 再生先としては `PLAYED` という名前に残す（複製は同じ記録を共有する）
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Clone, Default)]
+pub struct Memory {
+    recordings: Rc<RefCell<HashMap<String, Recording>>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+impl Memory {
+    pub fn get(&self, name: &str) -> Option<Recording> {
+        self.recordings.borrow().get(name).cloned()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Output for Memory {
+    fn create(
+        &mut self,
+        name: &str,
+        channels: usize,
+        samplerate: f64,
+        metadata: &wav::Metadata,
+        _: &Encoding,
+    ) -> Result<Box<dyn Sink>, String> {
+        Ok(Box::new(MemorySink {
+            recordings: self.recordings.clone(),
+            name: name.to_string(),
+            recording: Recording {
+                channels,
+                samplerate,
+                samples: Vec::new(),
+                metadata: metadata.clone(),
+            },
+        }))
+    }
+    fn create_dir_all(&mut self, _: &Path) -> Result<(), String> {
+        Ok(())
+    }
+    fn exists(&self, name: &str) -> bool {
+        self.recordings.borrow().contains_key(name)
+    }
+}
+
+/// `Memory` を再生先にしたときに最後に鳴らした音の名前
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub const PLAYED: &str = "<play>";
+
+#[cfg(any(test, feature = "test-util"))]
+impl AudioBackend for Memory {
+    fn open(&mut self, channels: usize, samplerate: f64) -> Result<Box<dyn Sink>, String> {
+        self.create(
+            PLAYED,
+            channels,
+            samplerate,
+            &wav::Metadata::default(),
+            &Encoding::default(),
+        )
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+struct MemorySink {
+    recordings: Rc<RefCell<HashMap<String, Recording>>>,
+    name: String,
+    recording: Recording,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Sink for MemorySink {
+    fn write(&mut self, frame: &[f64]) -> Result<(), String> {
+        self.recording
+            .samples
+            .extend(frame.iter().map(|x| *x as f32));
+        Ok(())
+    }
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        self.recordings
+            .borrow_mut()
+            .insert(self.name, self.recording);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::lexer::Lexer;
+    use crate::parser;
+
+    #[test]
+    fn memory_output() {
+        let memory = Rc::new(RefCell::new(Memory::default()));
+        let mut environment = Environment::with_output(memory.clone());
+        let source = "set_master(\"none\");\n\
+                      write(.5 * End >> .001, .002, \"out.wav\", samplerate = 4000, title = \"t\");\n\
+                      write_stems({a: Begin, b: pan(Begin, 1)}, .001, \"stems\", samplerate = 4000);\n";
+        let mut lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_string())), false);
+        let mut log = Vec::new();
+        loop {
+            match parser::parse_statement(&mut lexer, &mut log) {
+                Ok(Some(statement)) => assert!(environment.run(statement).is_ok()),
+                Ok(None) => break,
+                Err(_) => panic!("syntax error"),
+            }
+        }
+        let recording = memory.borrow().get("out.wav").unwrap();
+        assert_eq!(recording.channels, 1);
+        assert_eq!(recording.samplerate, 4000.);
+        assert_eq!(recording.samples, vec![0.5, 0.5, 0.5, 0.5, 0., 0., 0., 0.]);
+        assert_eq!(recording.metadata.title, "t");
+        let stem = memory.borrow().get("stems/b.wav").unwrap();
+        assert_eq!(stem.channels, 2);
+        assert_eq!(stem.samples.len(), 8);
+        assert!(!Path::new("stems").exists());
+    }
+
+    #[test]
+    fn play_to_backend() {
+        let memory = Memory::default();
+        let mut environment = Environment::with_output(Rc::new(RefCell::new(Memory::default())));
+        environment.set_backend(Box::new(memory.clone()));
+        let source = "play(.5, .001, samplerate = 4000, master = \"none\");\n";
+        let mut lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_string())), false);
+        let statement = match parser::parse_statement(&mut lexer, &mut Vec::new()) {
+            Ok(Some(statement)) => statement,
+            _ => panic!("syntax error"),
+        };
+        assert!(environment.run(statement).is_ok());
+        let played = memory.get(PLAYED).unwrap();
+        assert_eq!((played.channels, played.samplerate), (1, 4000.));
+        assert_eq!(played.samples, vec![0.5; 4]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn player_command() {
+        let path = std::env::temp_dir().join(format!("cryss-player-{}.wav", std::process::id()));
+        let mut player = Player::new(&format!("dd status=none of={}", path.display())).unwrap();
+        let mut sink = player.open(2, 8000.).unwrap();
+        sink.write(&[0.5, -0.5]).unwrap();
+        sink.finalize().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u16::from_le_bytes([bytes[22], bytes[23]]), 2);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Player::new("cryss-no-such-player")
+            .unwrap()
+            .open(1, 8000.)
+            .is_err());
+    }
+
+    #[test]
+    fn atomic_wav_files() {
+        let directory = std::env::temp_dir().join(format!("cryss-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut output = WavFiles::new(16, Dither::None, directory.clone());
+        let files = || std::fs::read_dir(&directory).unwrap().count();
+        // 途中で失敗したものは何も残さない
+        let mut sink = output
+            .create(
+                "a.wav",
+                1,
+                8000.,
+                &wav::Metadata::default(),
+                &Encoding::default(),
+            )
+            .unwrap();
+        sink.write(&[0.5]).unwrap();
+        assert!(!directory.join("a.wav").exists());
+        drop(sink);
+        assert_eq!(files(), 0);
+        let mut sink = output
+            .create(
+                "a.wav",
+                1,
+                8000.,
+                &wav::Metadata::default(),
+                &Encoding::default(),
+            )
+            .unwrap();
+        sink.write(&[0.5]).unwrap();
+        sink.finalize().unwrap();
+        assert!(directory.join("a.wav").exists());
+        assert_eq!(files(), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn encoding() {
+        let directory = std::env::temp_dir().join(format!("cryss-encoding-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut output = WavFiles::new(24, Dither::None, directory.clone());
+        let mut write = |name: &str, encoding: Encoding| {
+            let mut sink = output
+                .create(name, 1, 8000., &wav::Metadata::default(), &encoding)
+                .unwrap();
+            sink.write(&[0.25]).unwrap();
+            sink.finalize().unwrap();
+            hound::WavReader::open(directory.join(name)).unwrap()
+        };
+        let spec = write("default.wav", Encoding::default()).spec();
+        assert_eq!(spec.bits_per_sample, 24);
+        let spec = write(
+            "16.wav",
+            Encoding {
+                bits: Some(16),
+                ..Encoding::default()
+            },
+        )
+        .spec();
+        assert_eq!(spec.bits_per_sample, 16);
+        let mut reader = write(
+            "float.wav",
+            Encoding {
+                float: true,
+                ..Encoding::default()
+            },
+        );
+        assert_eq!(reader.spec().bits_per_sample, 32);
+        assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+        let samples: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, vec![0.25]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
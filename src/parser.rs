@@ -1,9 +1,10 @@
 //! トークン（ `mod token` ）を抽象構文木（ `mod syntax` ）に変換する．
 
-use crate::{error, lexer, pos, syntax, token};
+use crate::{error, lexer, paths, pos, syntax, token, types};
 use error::Error;
-use syntax::{Expression, Node, Statement};
+use syntax::{Expression, Node, Parameter, Pattern, SampleEntry, Statement};
 use token::Token;
+use types::Type;
 
 use std::collections::HashMap;
 
@@ -22,6 +23,9 @@ fn parse_factor(
             Some((open, Token::OpeningParenthesis)) => {
                 let ((vec, map), end) = parse_invocation_arguments(lexer, log)?;
                 match end {
+                    Some((close, Token::ClosingParenthesis)) if name == "embed" => {
+                        embed(range + close, vec, map)?
+                    }
                     Some((close, Token::ClosingParenthesis)) => {
                         Expression::new(range + close, Node::Invocation(name, vec, map))
                     }
@@ -34,7 +38,11 @@ fn parse_factor(
         },
         Some((range, Token::Parameter(name))) => Expression::new(range, Node::Parameter(name)),
         Some((range, Token::Number(value))) => Expression::new(range, Node::Number(value)),
+        Some((range, Token::Int(value))) => Expression::new(range, Node::Int(value)),
         Some((range, Token::String(string))) => Expression::new(range, Node::String(string)),
+        Some((range, Token::FormatString(string))) => {
+            Expression::new(range, Node::FormatString(string))
+        }
         // 前置 `-` （負号）
         Some((op, Token::Hyphen)) => {
             let mut ret = parse_print(lexer, log)?;
@@ -64,12 +72,27 @@ fn parse_factor(
             };
             return Ok(ret);
         }
-        // 丸括弧でくくられた部分
+        // 丸括弧でくくられた部分か，ラムダ式の引数
         Some((open, Token::OpeningParenthesis)) => match parse_expression(lexer, log)? {
-            (expr, Some((close, Token::ClosingParenthesis))) => match expr {
-                Some(expr) => Expression::new(open + close, Node::Group(expr.into())),
-                None => return Err(Error::EmptyParentheses(open, close)),
-            },
+            (expr, Some((close, Token::ClosingParenthesis))) => {
+                if lexer.ask(|token| matches!(token, Token::EqualGreater), log)? {
+                    let parameters = match expr {
+                        Some(expr) => vec![lambda_parameter(expr, None)?],
+                        None => Vec::new(),
+                    };
+                    return parse_lambda(lexer, log, open, parameters);
+                }
+                match expr {
+                    Some(expr) => Expression::new(open + close, Node::Group(expr.into())),
+                    None => return Err(Error::EmptyParentheses(open, close)),
+                }
+            }
+            // `(x: Sound) =>` や `(x, y) =>`
+            (Some(expr), Some((separator, token @ (Token::Colon | Token::Comma)))) => {
+                let parameters =
+                    parse_lambda_parameters(lexer, log, &open, expr, (separator, token))?;
+                return parse_lambda(lexer, log, open, parameters);
+            }
             (_, Some((range, _))) => return Err(Error::UnclosedBracketUntil(open, range)),
             (_, None) => return Err(Error::UnclosedBracketUntilEOF(open)),
         },
@@ -80,12 +103,120 @@ fn parse_factor(
             (_, Some((range, _))) => return Err(Error::UnclosedBracketUntil(open, range)),
             (_, None) => return Err(Error::UnclosedBracketUntilEOF(open)),
         },
+        // レコード
+        Some((open, Token::OpeningBrace)) => match parse_record_fields(lexer, log)? {
+            (fields, Some((close, Token::ClosingBrace))) => {
+                Expression::new(open + close, Node::Record(fields))
+            }
+            (_, Some((range, _))) => return Err(Error::UnclosedBracketUntil(open, range)),
+            (_, None) => return Err(Error::UnclosedBracketUntilEOF(open)),
+        },
         other => return Ok((None, other)),
     };
     Ok((expression.into(), lexer.next(log)?))
 }
 
-/// 後置 `?` （出力）
+/// ラムダ式の引数 `expr` （名前）．型 `ty` を省略したら `real`
+fn lambda_parameter(expr: Expression, ty: Option<Type>) -> Result<Parameter, Error> {
+    match expr.node {
+        Node::Identifier(name) => Ok(Parameter {
+            range: expr.range,
+            name,
+            ty: Some(ty.unwrap_or(Type::Real)),
+            default: None,
+        }),
+        _ => Err(Error::InvalidParameter(expr.range)),
+    }
+}
+
+/// ラムダ式の引数を閉じ括弧まで読む．`first` は最初の引数の名前で， `separator` はその直後のトークン
+fn parse_lambda_parameters(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+    open: &pos::Range,
+    first: Expression,
+    separator: (pos::Range, Token),
+) -> Result<Vec<Parameter>, Error> {
+    let mut parameters = Vec::new();
+    let (mut name, mut separator) = (first, Some(separator));
+    loop {
+        let ty = match separator {
+            Some((colon, Token::Colon)) => {
+                let ty = match parse_type(lexer, log, &colon)? {
+                    (range, Type::Void) => return Err(Error::UnknownType(range)),
+                    (_, ty) => ty,
+                };
+                separator = lexer.next(log)?;
+                Some(ty)
+            }
+            _ => None,
+        };
+        parameters.push(lambda_parameter(name, ty)?);
+        match separator {
+            Some((_, Token::ClosingParenthesis)) => return Ok(parameters),
+            Some((_, Token::Comma)) => {}
+            Some((range, _)) => return Err(Error::UnclosedBracketUntil(open.clone(), range)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open.clone())),
+        }
+        name = match lexer.next(log)? {
+            Some((range, Token::Identifier(name))) => {
+                Expression::new(range, Node::Identifier(name))
+            }
+            Some((_, Token::ClosingParenthesis)) => return Ok(parameters),
+            Some((range, _)) => return Err(Error::InvalidParameter(range)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open.clone())),
+        };
+        separator = lexer.next(log)?;
+    }
+}
+
+/// `embed("パス")` ：ファイルの中身をそのまま文字列リテラルにする．
+/// 構文解析のときに読むので，コンパイルした台本はファイルなしで動く
+fn embed(
+    range: pos::Range,
+    arguments: Vec<Expression>,
+    named_arguments: HashMap<String, Expression>,
+) -> Result<Expression, Error> {
+    let path = match &arguments[..] {
+        [Expression {
+            node: Node::String(path),
+            ..
+        }] if named_arguments.is_empty() => path,
+        _ => {
+            return Err(Error::EmbedFailure(
+                range,
+                "`embed` takes a single string literal".to_string(),
+            ))
+        }
+    };
+    let text = std::fs::read_to_string(paths::resolve(path)).map_err(|err| {
+        Error::EmbedFailure(range.clone(), format!("cannot read {}: {}", path, err))
+    })?;
+    Ok(Expression::new(range, Node::String(text)))
+}
+
+/// ラムダ式の `=>` から後（本体は式）
+fn parse_lambda(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+    open: pos::Range,
+    parameters: Vec<Parameter>,
+) -> Result<Parsed<Option<Expression>>, Error> {
+    let arrow = match lexer.next(log)? {
+        Some((arrow, Token::EqualGreater)) => arrow,
+        Some((range, _)) => return Err(Error::UnexpectedToken(range)),
+        None => return Err(Error::UnclosedBracketUntilEOF(open)),
+    };
+    match parse_expression(lexer, log)? {
+        (Some(body), end) => Ok((
+            Expression::new(open + &body.range, Node::Lambda(parameters, body.into())).into(),
+            end,
+        )),
+        (None, _) => Err(Error::EmptyOperandRight(arrow)),
+    }
+}
+
+/// 後置 `?` （出力），後置 `[ ]` （添字），後置 `.` （フィールド）
 ///
 /// 前置演算子 `-` `/` `!` より優先順位は高い
 fn parse_print(
@@ -94,8 +225,33 @@ fn parse_print(
 ) -> Result<Parsed<Option<Expression>>, Error> {
     let mut ret = parse_factor(lexer, log)?;
     if let (Some(mut expr), mut end) = ret {
-        while let Some((op, Token::Question)) = end {
-            expr = Expression::new(&expr.range + op, Node::Print(expr.into()));
+        loop {
+            match end {
+                Some((op, Token::Question)) => {
+                    expr = Expression::new(&expr.range + op, Node::Print(expr.into()));
+                }
+                Some((open, Token::OpeningBracket)) => match parse_expression(lexer, log)? {
+                    (Some(index), Some((close, Token::ClosingBracket))) => {
+                        expr = Expression::new(
+                            &expr.range + close,
+                            Node::Index(expr.into(), index.into()),
+                        );
+                    }
+                    (None, Some((close, Token::ClosingBracket))) => {
+                        return Err(Error::EmptyIndex(open, close))
+                    }
+                    (_, Some((range, _))) => return Err(Error::UnclosedBracketUntil(open, range)),
+                    (_, None) => return Err(Error::UnclosedBracketUntilEOF(open)),
+                },
+                Some((dot, Token::Dot)) => match lexer.next(log)? {
+                    Some((range, Token::Identifier(name))) => {
+                        expr = Expression::new(&expr.range + range, Node::Field(expr.into(), name));
+                    }
+                    Some((range, _)) => return Err(Error::InvalidFieldName(dot, range)),
+                    None => return Err(Error::EmptyOperandRight(dot)),
+                },
+                _ => break,
+            }
             end = lexer.next(log)?;
         }
         ret = (Some(expr), end);
@@ -163,11 +319,50 @@ def_binary_operator! {
         Token::Plus => Node::Add,
         Token::Hyphen => Node::Sub,
 }
-def_binary_operator! {
-    / "比較演算子 `<`, `>`"
-    parse_operator4 => parse_operator5:
-        Token::Less => Node::Less,
-        Token::Greater => Node::Greater,
+/// 比較演算子 `<`, `>`, `<=`, `>=`
+///
+/// `0 <= x < 1` のように連ねたものは，隣り合う比較を `&&` でつないだものになる
+/// （間の式は 2 回評価される）
+fn parse_operator5(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+) -> Result<Parsed<Option<Expression>>, Error> {
+    let (mut left, mut end) = match parse_operator4(lexer, log)? {
+        (Some(expr), end) => (expr, end),
+        ret => return Ok(ret),
+    };
+    // 連鎖全体の範囲の始まり
+    let first = left.range.clone();
+    let mut chain: Option<Expression> = None;
+    loop {
+        let comparison: fn(Box<Expression>, Box<Expression>) -> Node = match end {
+            Some((_, Token::Less)) => Node::Less,
+            Some((_, Token::Greater)) => Node::Greater,
+            Some((_, Token::LessEqual)) => Node::LessEqual,
+            Some((_, Token::GreaterEqual)) => Node::GreaterEqual,
+            _ => break,
+        };
+        let op = end.unwrap().0;
+        let right = match parse_operator4(lexer, log)? {
+            (Some(right), token) => {
+                end = token;
+                right
+            }
+            (None, _) => return Err(Error::EmptyOperandRight(op)),
+        };
+        let expr = Expression::new(
+            &left.range + &right.range,
+            comparison(left.into(), right.clone().into()),
+        );
+        chain = Some(match chain {
+            Some(chain) => {
+                Expression::new(&first + &expr.range, Node::And(chain.into(), expr.into()))
+            }
+            None => expr,
+        });
+        left = right;
+    }
+    Ok((Some(chain.unwrap_or(left)), end))
 }
 def_binary_operator! {
     / "比較演算子 `==`, `!=`"
@@ -223,6 +418,31 @@ fn parse_invocation_arguments(
     }
 }
 
+/// レコードのフィールド（ identifier `:` expr の形）
+///
+/// 最後のカンマはあってもなくてもいい
+fn parse_record_fields(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+) -> Result<Parsed<Vec<(String, Expression)>>, Error> {
+    let mut vec = Vec::new();
+    loop {
+        let name = match lexer.next(log)? {
+            Some((_, Token::Identifier(name))) => name,
+            other => return Ok((vec, other)),
+        };
+        let colon = match lexer.next(log)? {
+            Some((colon, Token::Colon)) => colon,
+            other => return Ok((vec, other)),
+        };
+        let (item, end) = parse_expression(lexer, log)?;
+        vec.push((name, item.ok_or(Error::EmptyField(colon))?));
+        if !matches!(end, Some((_, Token::Comma))) {
+            return Ok((vec, end));
+        }
+    }
+}
+
 /// カンマ区切り（空の要素は無視）
 fn parse_list1(
     lexer: &mut lexer::Lexer,
@@ -253,10 +473,125 @@ fn parse_list2(
     }
 }
 
+/// `let` の分解の形の中身（ `,` で区切った名前）を，閉じ括弧 `close` まで読む
+fn parse_names(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+    open: &pos::Range,
+    close: fn(&Token) -> bool,
+) -> Result<(Vec<(pos::Range, String)>, pos::Range), Error> {
+    let mut names = Vec::new();
+    loop {
+        match lexer.next(log)? {
+            Some((range, token)) if close(&token) => return Ok((names, range)),
+            Some((range, Token::Identifier(name))) => names.push((range, name)),
+            Some((range, _)) => return Err(Error::UnexpectedToken(range)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open.clone())),
+        }
+        match lexer.next(log)? {
+            Some((range, token)) if close(&token) => return Ok((names, range)),
+            Some((_, Token::Comma)) => {}
+            Some((range, _)) => return Err(Error::UnexpectedToken(range)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open.clone())),
+        }
+    }
+}
+
+/// 関数定義に書く型（ `real` ， `Sound[]` など）．`after` は型の前の `:` や `->`
+fn parse_type(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+    after: &pos::Range,
+) -> Result<(pos::Range, Type), Error> {
+    let (range, name) = match lexer.next(log)? {
+        Some((range, Token::Identifier(name))) => (range, name),
+        Some((range, _)) => return Err(Error::UnknownType(range)),
+        None => return Err(Error::UnexpectedEOFAfterKeyword(after.clone())),
+    };
+    let array = lexer.ask(|token| matches!(token, Token::OpeningBracket), log)?;
+    let range = if array {
+        let (open, _) = lexer.next(log)?.unwrap();
+        match lexer.next(log)? {
+            Some((close, Token::ClosingBracket)) => range + close,
+            Some((other, _)) => return Err(Error::UnclosedBracketUntil(open, other)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open)),
+        }
+    } else {
+        range
+    };
+    match Type::from_name(&name, array) {
+        Some(ty) => Ok((range, ty)),
+        None => Err(Error::UnknownType(range)),
+    }
+}
+
+/// 関数定義の引数を閉じ括弧まで読む
+fn parse_parameters(
+    lexer: &mut lexer::Lexer,
+    log: &mut Vec<String>,
+    open: &pos::Range,
+) -> Result<Vec<Parameter>, Error> {
+    let mut parameters = Vec::new();
+    loop {
+        let (range, name) = match lexer.next(log)? {
+            Some((_, Token::ClosingParenthesis)) => return Ok(parameters),
+            Some((range, Token::Identifier(name))) => (range, name),
+            Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open.clone())),
+        };
+        let ty = if lexer.ask(|token| matches!(token, Token::Colon), log)? {
+            let (colon, _) = lexer.next(log)?.unwrap();
+            match parse_type(lexer, log, &colon)? {
+                (range, Type::Void) => return Err(Error::UnknownType(range)),
+                (_, ty) => Some(ty),
+            }
+        } else {
+            None
+        };
+        let (default, end) = if lexer.ask(|token| matches!(token, Token::Equal), log)? {
+            let (equal, _) = lexer.next(log)?.unwrap();
+            match parse_expression(lexer, log)? {
+                (Some(expr), end) => (Some(expr), end),
+                (None, _) => return Err(Error::EmptyNamedArgument(equal)),
+            }
+        } else {
+            (None, lexer.next(log)?)
+        };
+        if ty.is_none() && default.is_none() {
+            return Err(Error::UntypedParameter(name, range));
+        }
+        parameters.push(Parameter {
+            range,
+            name,
+            ty,
+            default,
+        });
+        match end {
+            Some((_, Token::Comma)) => {}
+            Some((_, Token::ClosingParenthesis)) => return Ok(parameters),
+            Some((other, _)) => return Err(Error::UnclosedBracketUntil(open.clone(), other)),
+            None => return Err(Error::UnclosedBracketUntilEOF(open.clone())),
+        }
+    }
+}
+
 pub fn parse_statement(
     lexer: &mut lexer::Lexer,
     log: &mut Vec<String>,
 ) -> Result<Option<Statement>, Error> {
+    // 文頭の `{` はレコードではなくブロック
+    if lexer.ask(|token| matches!(token, Token::OpeningBrace), log)? {
+        let (open, _) = lexer.next(log)?.unwrap();
+        let mut vec = Vec::new();
+        while !lexer.ask(|token| matches!(token, Token::ClosingBrace), log)? {
+            match parse_statement(lexer, log)? {
+                Some(statement) => vec.push(statement),
+                None => return Err(Error::UnclosedBracketUntilEOF(open)),
+            }
+        }
+        lexer.next(log)?;
+        return Ok(Some(Statement::Block(vec)));
+    }
     let ret = match parse_expression(lexer, log)? {
         (Some(expr), Some((_, Token::Semicolon))) => Statement::Expression(expr.into()),
         (None, Some((_, Token::Semicolon))) => Statement::Expression(None),
@@ -273,31 +608,102 @@ pub fn parse_statement(
                 (Some(expr), _) => return Err(Error::NoSemicolonAtEndOfStatement(expr.range)),
             }
         }
+        // 複合代入 `x += e` は `x = x + e`
+        (
+            Some(lhs),
+            Some((
+                equal,
+                token @ (Token::PlusEqual
+                | Token::HyphenEqual
+                | Token::AsteriskEqual
+                | Token::SlashEqual
+                | Token::PercentEqual
+                | Token::CircumflexEqual),
+            )),
+        ) => {
+            let name = match &lhs.node {
+                Node::Identifier(name) => name.clone(),
+                _ => return Err(Error::LHSNotIdentifier(lhs.range, equal)),
+            };
+            let node = match token {
+                Token::PlusEqual => Node::Add,
+                Token::HyphenEqual => Node::Sub,
+                Token::AsteriskEqual => Node::Mul,
+                Token::SlashEqual => Node::Div,
+                Token::PercentEqual => Node::Rem,
+                _ => Node::Pow,
+            };
+            match parse_expression(lexer, log)? {
+                (Some(expr), Some((_, Token::Semicolon))) => {
+                    let range = lhs.range.clone();
+                    let expr = Expression::new(
+                        lhs.range.clone() + &expr.range,
+                        node(lhs.into(), expr.into()),
+                    );
+                    Statement::Substitution(range, name, expr)
+                }
+                (None, _) => return Err(Error::EmptyRHS(equal)),
+                (Some(expr), _) => return Err(Error::NoSemicolonAtEndOfStatement(expr.range)),
+            }
+        }
         (None, Some((r#let, Token::KeywordLet))) => {
-            let (range, name) = match lexer.next(log)? {
-                Some((range, Token::Identifier(name))) => (range, name),
+            // 宣言する名前か，分解の形
+            let lhs = match lexer.next(log)? {
+                Some((range, Token::Identifier(name))) => Ok((range, name)),
+                Some((open, Token::OpeningBracket)) => {
+                    let (names, close) = parse_names(lexer, log, &open, |token| {
+                        matches!(token, Token::ClosingBracket)
+                    })?;
+                    Err((open + close, Pattern::Array(names)))
+                }
+                Some((open, Token::OpeningBrace)) => {
+                    let (names, close) = parse_names(lexer, log, &open, |token| {
+                        matches!(token, Token::ClosingBrace)
+                    })?;
+                    Err((open + close, Pattern::Record(names)))
+                }
                 Some((range, _)) => return Err(Error::UnexpectedTokenAfterKeyword(r#let, range)),
                 None => return Err(Error::UnexpectedEOFAfterKeyword(r#let)),
             };
+            let ty = match &lhs {
+                Ok((range, _)) if lexer.ask(|token| matches!(token, Token::Colon), log)? => {
+                    lexer.next(log)?;
+                    Some(parse_type(lexer, log, range)?.1)
+                }
+                _ => None,
+            };
             let equal = match lexer.next(log)? {
                 Some((equal, Token::Equal)) => equal,
                 _ => return Err(Error::NoSubstitutionAfterLet(r#let)),
             };
             match parse_expression(lexer, log)? {
-                (Some(expr), Some((_, Token::Semicolon))) => {
-                    Statement::Declaration(range, name, expr)
-                }
+                (Some(expr), Some((_, Token::Semicolon))) => match lhs {
+                    Ok((range, name)) => Statement::Declaration(range, name, ty, expr),
+                    Err((range, pattern)) => Statement::Destructuring(range, pattern, expr),
+                },
                 (None, _) => return Err(Error::EmptyRHS(equal)),
                 (Some(expr), _) => return Err(Error::NoSemicolonAtEndOfStatement(expr.range)),
             }
         }
         (None, Some((def, Token::KeywordDef))) => {
-            let (_, _) = match lexer.next(log)? {
+            let (range, name) = match lexer.next(log)? {
                 Some((range, Token::Identifier(name))) => (range, name),
                 Some((other, _)) => return Err(Error::UnexpectedTokenAfterKeyword(def, other)),
                 None => return Err(Error::UnexpectedEOFAfterKeyword(def)),
             };
-            todo!();
+            let parameters = match lexer.next(log)? {
+                Some((open, Token::OpeningParenthesis)) => parse_parameters(lexer, log, &open)?,
+                Some((other, _)) => return Err(Error::UnexpectedTokenAfterKeyword(def, other)),
+                None => return Err(Error::UnexpectedEOFAfterKeyword(def)),
+            };
+            let ty = if lexer.ask(|token| matches!(token, Token::HyphenGreater), log)? {
+                let (arrow, _) = lexer.next(log)?.unwrap();
+                parse_type(lexer, log, &arrow)?.1
+            } else {
+                Type::Void
+            };
+            let body = parse_statement(lexer, log)?.ok_or(Error::UnexpectedEOFAfterKeyword(def))?;
+            Statement::Definition(range, name, parameters, ty, body.into())
         }
         (None, Some((r#if, Token::KeywordIf))) => {
             let open = match lexer.next(log)? {
@@ -311,7 +717,7 @@ pub fn parse_statement(
                 (_, None) => return Err(Error::UnclosedBracketUntilEOF(open)),
             };
             let body_if = parse_statement(lexer, log)?
-                .ok_or(Error::UnexpectedEOFAfterCondition(r#if, open + close))?;
+                .ok_or_else(|| Error::UnexpectedEOFAfterCondition(r#if.clone(), &open + &close))?;
             let body_else = lexer
                 .ask(|token| matches!(token, Token::KeywordElse), log)?
                 .then(|| {
@@ -322,7 +728,56 @@ pub fn parse_statement(
                     }
                 })
                 .transpose()?;
-            Statement::If(condition, body_if.into(), body_else)
+            Statement::If(&r#if + &close, condition, body_if.into(), body_else)
+        }
+        (None, Some((samples, Token::KeywordSamples))) => {
+            let open = match lexer.next(log)? {
+                Some((open, Token::OpeningBrace)) => open,
+                Some((other, _)) => return Err(Error::UnexpectedTokenAfterKeyword(samples, other)),
+                None => return Err(Error::UnexpectedEOFAfterKeyword(samples)),
+            };
+            let mut entries = Vec::new();
+            loop {
+                let (range, name) = match lexer.next(log)? {
+                    Some((_, Token::ClosingBrace)) => break,
+                    Some((range, Token::String(name))) => (range, name),
+                    Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+                    None => return Err(Error::UnclosedBracketUntilEOF(open)),
+                };
+                match lexer.next(log)? {
+                    Some((_, Token::Colon)) => {}
+                    Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+                    None => return Err(Error::UnclosedBracketUntilEOF(open)),
+                }
+                let (end, path) = match lexer.next(log)? {
+                    Some((end, Token::String(path))) => (end, path),
+                    Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+                    None => return Err(Error::UnclosedBracketUntilEOF(open)),
+                };
+                let sha256 = if lexer.ask(
+                    |token| matches!(token, Token::Identifier(name) if name == "sha256"),
+                    log,
+                )? {
+                    lexer.next(log)?;
+                    match lexer.next(log)? {
+                        Some((_, Token::String(sha256))) => Some(sha256),
+                        Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+                        None => return Err(Error::UnclosedBracketUntilEOF(open)),
+                    }
+                } else {
+                    None
+                };
+                entries.push(SampleEntry {
+                    range: range + end,
+                    name,
+                    path,
+                    sha256,
+                });
+                if lexer.ask(|token| matches!(token, Token::Comma), log)? {
+                    lexer.next(log)?;
+                }
+            }
+            Statement::Samples(samples, entries)
         }
         (None, Some((r#while, Token::KeywordWhile))) => {
             let open = match lexer.next(log)? {
@@ -339,16 +794,30 @@ pub fn parse_statement(
                 .ok_or(Error::UnexpectedEOFAfterCondition(r#while, open + close))?;
             Statement::While(condition, body.into())
         }
-        (None, Some((open, Token::OpeningBrace))) => {
-            let mut vec = Vec::new();
-            while !lexer.ask(|token| matches!(token, Token::ClosingBrace), log)? {
-                match parse_statement(lexer, log)? {
-                    Some(statement) => vec.push(statement),
-                    None => return Err(Error::UnclosedBracketUntilEOF(open)),
-                }
+        (None, Some((r#for, Token::KeywordFor))) => {
+            let open = match lexer.next(log)? {
+                Some((open, Token::OpeningParenthesis)) => open,
+                Some((other, _)) => return Err(Error::UnexpectedTokenAfterKeyword(r#for, other)),
+                None => return Err(Error::UnexpectedEOFAfterKeyword(r#for)),
+            };
+            let (range, name) = match lexer.next(log)? {
+                Some((range, Token::Identifier(name))) => (range, name),
+                Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+                None => return Err(Error::UnclosedBracketUntilEOF(open)),
+            };
+            match lexer.next(log)? {
+                Some((_, Token::Identifier(r#in))) if r#in == "in" => {}
+                Some((other, _)) => return Err(Error::UnexpectedToken(other)),
+                None => return Err(Error::UnclosedBracketUntilEOF(open)),
             }
-            lexer.next(log)?;
-            Statement::Block(vec)
+            let (array, close) = match parse_expression(lexer, log)? {
+                (Some(expr), Some((close, Token::ClosingParenthesis))) => (expr, close),
+                (_, Some((range, _))) => return Err(Error::UnclosedBracketUntil(open, range)),
+                (_, None) => return Err(Error::UnclosedBracketUntilEOF(open)),
+            };
+            let body = parse_statement(lexer, log)?
+                .ok_or(Error::UnexpectedEOFAfterCondition(r#for, open + close))?;
+            Statement::For(range, name, array, body.into())
         }
         (None, Some((r#break, Token::KeywordBreak))) => match lexer.next(log)? {
             Some((_, Token::Semicolon)) => Statement::Break(r#break),
@@ -362,11 +831,27 @@ pub fn parse_statement(
         },
         (None, Some((r#return, Token::KeywordReturn))) => match parse_expression(lexer, log)? {
             (expr, Some((_, Token::Semicolon))) => Statement::Return(r#return, expr),
+            // `return 式 if 条件;` は `if (条件) return 式;` と同じ
+            (expr, Some((r#if, Token::KeywordIf))) => match parse_expression(lexer, log)? {
+                (Some(condition), Some((_, Token::Semicolon))) => Statement::If(
+                    &r#if + &condition.range,
+                    condition,
+                    Statement::Return(r#return, expr).into(),
+                    None,
+                ),
+                (Some(other), _) => return Err(Error::NoSemicolonAtEndOfStatement(other.range)),
+                (None, Some((other, _))) => {
+                    return Err(Error::UnexpectedTokenAfterKeyword(r#if, other))
+                }
+                (None, None) => return Err(Error::UnexpectedEOFAfterKeyword(r#if)),
+            },
             (Some(other), _) => return Err(Error::NoSemicolonAtEndOfStatement(other.range)),
             (None, _) => return Err(Error::UnexpectedEOFAfterKeyword(r#return)),
         },
         (Some(expr), _) => return Err(Error::NoSemicolonAtEndOfStatement(expr.range)),
         (None, None) => return Ok(None),
+        (None, Some((range, Token::Command(command)))) => Statement::Command(range, command),
+        (None, Some((r#else, Token::KeywordElse))) => return Err(Error::ElseWithoutIf(r#else)),
         (None, Some((range, _))) => return Err(Error::UnexpectedToken(range)),
     };
     Ok(Some(ret))
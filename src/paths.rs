@@ -0,0 +1,81 @@
+//! 台本に書いたファイルのパス
+//!
+//! 先頭の `~` はホームディレクトリにし， Windows では `/` も区切りとして扱う．
+//! 相対パスは台本のあるディレクトリから解決する（ `--relative-to-cwd` なら作業ディレクトリから）
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    /// 相対パスの基準．`None` なら作業ディレクトリ
+    static BASE: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+pub fn set_base(directory: Option<PathBuf>) {
+    BASE.with(|base| *base.borrow_mut() = directory);
+}
+
+fn home() -> Option<PathBuf> {
+    let name = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    std::env::var_os(name)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `~` と区切りだけを直す．相対パスは相対パスのまま
+pub fn expand(path: &str) -> PathBuf {
+    let path = if cfg!(windows) {
+        path.replace('/', "\\")
+    } else {
+        path.to_string()
+    };
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(std::path::is_separator) => rest,
+        _ => return PathBuf::from(path),
+    };
+    match home() {
+        Some(home) => home.join(rest.trim_start_matches(std::path::is_separator)),
+        None => PathBuf::from(path),
+    }
+}
+
+/// 読み書きする場所．`expand` したうえで相対パスを基準から解決する
+pub fn resolve(path: &str) -> PathBuf {
+    let path = expand(path);
+    BASE.with(|base| match &*base.borrow() {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path,
+    })
+}
+
+/// 文字列のパスを受け取る関数に渡す `resolve`
+pub fn resolve_lossy(path: &str) -> String {
+    resolve(path).to_string_lossy().into_owned()
+}
+
+/// 台本 `script` の相対パスの基準（台本のあるディレクトリ）
+pub fn script_directory(script: &Path) -> PathBuf {
+    script.parent().map_or_else(PathBuf::new, Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_and_resolve() {
+        let home = home().unwrap();
+        assert_eq!(expand("~"), home);
+        assert_eq!(expand("~/a.wav"), home.join("a.wav"));
+        assert_eq!(expand("~a.wav"), PathBuf::from("~a.wav"));
+        assert_eq!(
+            expand("a/b.wav"),
+            ["a", "b.wav"].iter().collect::<PathBuf>()
+        );
+        set_base(Some(PathBuf::from("songs")));
+        assert_eq!(resolve("a.wav"), Path::new("songs").join("a.wav"));
+        assert_eq!(resolve("~/a.wav"), home.join("a.wav"));
+        set_base(None);
+        assert_eq!(resolve("a.wav"), PathBuf::from("a.wav"));
+    }
+}
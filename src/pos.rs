@@ -25,6 +25,9 @@ impl Pos {
     pub fn new(line: usize, byte: usize) -> Pos {
         Pos { line, byte }
     }
+    pub fn line(&self) -> usize {
+        self.line
+    }
     pub fn byte(&self) -> usize {
         self.byte
     }
@@ -34,6 +37,12 @@ impl Range {
         debug_assert!(start <= end);
         Range { start, end }
     }
+    pub fn start(&self) -> &Pos {
+        &self.start
+    }
+    pub fn end(&self) -> &Pos {
+        &self.end
+    }
 }
 
 use std::fmt::{self, Debug, Display, Formatter};
@@ -70,52 +79,115 @@ impl Debug for Range {
 }
 
 impl Pos {
-    /// エラーが起こっている行を出力．
+    /// `log` の `self` から `end` の手前までの文字列
+    pub fn slice(&self, end: &Pos, log: &[String]) -> String {
+        if self.line == end.line {
+            return log[self.line][self.byte..end.byte].to_string();
+        }
+        let mut ret = log[self.line][self.byte..].to_string();
+        for row in &log[self.line + 1..end.line] {
+            ret += row;
+        }
+        ret + &log[end.line][..end.byte]
+    }
+    /// エラーが起こっている行を出力し，この位置の文字の下に `^` を引く
     pub fn print<W: std::io::Write>(
         &self,
         w: &mut W,
         log: &[String],
+        color: bool,
     ) -> Result<(), std::io::Error> {
-        let Pos { line, byte } = *self;
-        write!(w, "{} !-> {}", &log[line][..byte], &log[line][byte..])
+        Range::new(self.clone(), self.clone()).print(w, log, color)
     }
 }
 impl Range {
-    /// エラーが起こっている行を出力．
+    /// エラーが起こっている行を行番号つきで出力し，範囲の下に `^` を引く．
+    /// 複数行にわたれば行ごとに引く．`color` ならば行番号を青， `^` を赤にする
     pub fn print<W: std::io::Write>(
         &self,
         w: &mut W,
         log: &[String],
+        color: bool,
     ) -> Result<(), std::io::Error> {
-        let start = &self.start;
-        let end = &self.end;
-        if start.line == end.line {
-            // 一行の場合
-            write!(
-                w,
-                "{} !-> {} <-! {}",
-                &log[start.line][..start.byte],
-                &log[start.line][start.byte..end.byte],
-                &log[end.line][end.byte..]
-            )
+        let (start, end) = (&self.start, &self.end);
+        // 行頭で終わる範囲は前の行まで
+        let last = if end.byte == 0 && start.line < end.line {
+            end.line - 1
+        } else {
+            end.line
+        };
+        let gutter = (last + 1).to_string().len();
+        let (blue, red, reset) = if color {
+            ("\x1b[1;34m", "\x1b[1;31m", "\x1b[0m")
         } else {
-            // 複数行にわたる場合
-            write!(
+            ("", "", "")
+        };
+        writeln!(w, "{}{} |{}", blue, " ".repeat(gutter), reset)?;
+        for (line, text) in log.iter().enumerate().take(last + 1).skip(start.line) {
+            let text = text.trim_end_matches('\n');
+            let from = if line == start.line { start.byte } else { 0 };
+            let to = if line == end.line {
+                end.byte
+            } else {
+                text.len()
+            };
+            let (from, to) = (from.min(text.len()), to.min(text.len()));
+            writeln!(
                 w,
-                "{} !-> {}",
-                &log[start.line][..start.byte],
-                &log[start.line][start.byte..]
+                "{}{:>width$} |{} {}",
+                blue,
+                line + 1,
+                reset,
+                text,
+                width = gutter
             )?;
-            for row in &log[start.line + 1..end.line] {
-                write!(w, "{}", row)?;
-            }
-            write!(
+            let carets: usize = text[from..to].chars().map(width).sum();
+            let carets = match carets {
+                // 1 点や行末を指すときも 1 つは引く
+                0 if start.line == last => 1,
+                0 => continue,
+                carets => carets,
+            };
+            // タブはそのまま残して揃える
+            let indent: String = text[..from]
+                .chars()
+                .flat_map(|c| match c {
+                    '\t' => vec!['\t'],
+                    c => vec![' '; width(c)],
+                })
+                .collect();
+            writeln!(
                 w,
-                "{} <-! {}",
-                &log[end.line][..end.byte],
-                &log[end.line][end.byte..]
-            )
+                "{}{} |{} {}{}{}{}",
+                blue,
+                " ".repeat(gutter),
+                reset,
+                indent,
+                red,
+                "^".repeat(carets),
+                reset
+            )?;
         }
+        Ok(())
+    }
+}
+
+/// 端末での文字の幅．東アジアの全角文字は 2
+fn width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
     }
 }
 
@@ -149,3 +221,21 @@ impl Add<&Range> for &Range {
         Range::new(self.start.clone(), other.end.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print_underlines() {
+        let log = ["\t\"音\" + f(1,\n".to_string(), "  2);\n".to_string()];
+        let mut text = Vec::new();
+        Range::new(Pos::new(0, 9), Pos::new(1, 4))
+            .print(&mut text, &log, false)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "  |\n1 | \t\"音\" + f(1,\n  | \t       ^^^^\n2 |   2);\n  | ^^^^\n"
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! プリセット（実数のレコード）の JSON ファイルへの保存と読み込み
+
+use std::fmt::Write;
+
+/// レコードを JSON のオブジェクトとして書き出す
+pub fn to_json(record: &[(String, f64)]) -> String {
+    let mut json = String::from("{\n");
+    for (i, (name, value)) in record.iter().enumerate() {
+        let separator = if i + 1 < record.len() { "," } else { "" };
+        // JSON に NaN や無限大はないので null にする（読み込むときにエラーになる）
+        let value = if value.is_finite() {
+            value.to_string()
+        } else {
+            "null".to_string()
+        };
+        writeln!(json, "  {}: {}{}", quote(name), value, separator).unwrap();
+    }
+    json.push_str("}\n");
+    json
+}
+
+/// JSON の文字列リテラル
+pub fn quote(string: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// JSON を読んでレコードにする．
+///
+/// `schema` が空でなければ，
+/// - `schema` にないフィールドはエラー
+/// - ファイルにないフィールドは `schema` の値で補う
+///
+/// エラーは `行:列: メッセージ` の形（ 1-indexed ）
+pub fn from_json(json: &str, schema: &[(String, f64)]) -> Result<Vec<(String, f64)>, String> {
+    let mut parser = Parser {
+        chars: json.chars().collect(),
+        index: 0,
+        line: 0,
+        column: 0,
+    };
+    parser.whitespace();
+    let fields = match parser.value()? {
+        (_, Json::Object(fields)) => fields,
+        ((line, column), other) => {
+            return Err(format!(
+                "{}:{}: expected object, found {}",
+                line + 1,
+                column + 1,
+                other.name()
+            ))
+        }
+    };
+    parser.whitespace();
+    if parser.peek().is_some() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    let mut record: Vec<(String, f64)> = Vec::new();
+    for ((line, column), name, value) in fields {
+        let at =
+            |message: String| format!("{}:{}: field `{}`: {}", line + 1, column + 1, name, message);
+        if !schema.is_empty() && !schema.iter().any(|(field, _)| *field == name) {
+            return Err(at("unknown field".to_string()));
+        }
+        if record.iter().any(|(field, _)| *field == name) {
+            return Err(at("duplicate field".to_string()));
+        }
+        match value {
+            Json::Number(value) => record.push((name, value)),
+            other => return Err(at(format!("expected number, found {}", other.name()))),
+        }
+    }
+    for (name, value) in schema {
+        if !record.iter().any(|(field, _)| field == name) {
+            record.push((name.clone(), *value));
+        }
+    }
+    Ok(record)
+}
+
+/// 位置は 0-indexed の（行，列）
+type Position = (usize, usize);
+
+enum Json {
+    Null,
+    Boolean,
+    Number(f64),
+    String,
+    Array,
+    Object(Vec<(Position, String, Json)>),
+}
+
+impl Json {
+    fn name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Boolean => "boolean",
+            Json::Number(_) => "number",
+            Json::String => "string",
+            Json::Array => "array",
+            Json::Object(_) => "object",
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+    fn error(&self, message: &str) -> String {
+        format!("{}:{}: {}", self.line + 1, self.column + 1, message)
+    }
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.next();
+        }
+    }
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected `{}`", expected))),
+        }
+    }
+    fn value(&mut self) -> Result<(Position, Json), String> {
+        let position = (self.line, self.column);
+        let value = match self.peek() {
+            Some('{') => {
+                self.next();
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.next();
+                } else {
+                    loop {
+                        self.whitespace();
+                        let position = (self.line, self.column);
+                        let name = self.string()?;
+                        self.whitespace();
+                        self.expect(':')?;
+                        self.whitespace();
+                        let (_, value) = self.value()?;
+                        fields.push((position, name, value));
+                        self.whitespace();
+                        match self.next() {
+                            Some(',') => {}
+                            Some('}') => break,
+                            _ => return Err(self.error("expected `,` or `}`")),
+                        }
+                    }
+                }
+                Json::Object(fields)
+            }
+            Some('[') => {
+                self.next();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.next();
+                } else {
+                    loop {
+                        self.whitespace();
+                        self.value()?;
+                        self.whitespace();
+                        match self.next() {
+                            Some(',') => {}
+                            Some(']') => break,
+                            _ => return Err(self.error("expected `,` or `]`")),
+                        }
+                    }
+                }
+                Json::Array
+            }
+            Some('"') => {
+                self.string()?;
+                Json::String
+            }
+            Some('-' | '0'..='9') => {
+                let start = self.index;
+                while matches!(self.peek(), Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+                    self.next();
+                }
+                let literal: String = self.chars[start..self.index].iter().collect();
+                match literal.parse() {
+                    Ok(value) => Json::Number(value),
+                    Err(_) => {
+                        return Err(format!(
+                            "{}:{}: invalid number `{}`",
+                            position.0 + 1,
+                            position.1 + 1,
+                            literal
+                        ))
+                    }
+                }
+            }
+            Some('a'..='z') => {
+                let start = self.index;
+                while matches!(self.peek(), Some('a'..='z')) {
+                    self.next();
+                }
+                match self.chars[start..self.index]
+                    .iter()
+                    .collect::<String>()
+                    .as_str()
+                {
+                    "null" => Json::Null,
+                    "true" | "false" => Json::Boolean,
+                    _ => {
+                        return Err(format!(
+                            "{}:{}: unexpected literal",
+                            position.0 + 1,
+                            position.1 + 1
+                        ))
+                    }
+                }
+            }
+            Some(_) => return Err(self.error("unexpected character")),
+            None => return Err(self.error("unexpected end of file")),
+        };
+        Ok((position, value))
+    }
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error("invalid unicode escape"))?
+                    }
+                    Some(c) => c,
+                    None => return Err(self.error("unterminated string")),
+                }),
+                Some(c) => string.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[(&str, f64)]) -> Vec<(String, f64)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let preset = record(&[("attack", 0.01), ("decay", 0.2), ("cutoff", 1200.)]);
+        assert_eq!(from_json(&to_json(&preset), &[]), Ok(preset));
+    }
+
+    #[test]
+    fn schema() {
+        let schema = record(&[("attack", 0.1), ("decay", 0.5)]);
+        assert_eq!(
+            from_json(r#"{"decay": 1}"#, &schema),
+            Ok(record(&[("decay", 1.), ("attack", 0.1)]))
+        );
+        assert_eq!(
+            from_json("{\n  \"attack\": 1,\n  \"sustain\": 2\n}", &schema),
+            Err("3:3: field `sustain`: unknown field".to_string())
+        );
+    }
+
+    #[test]
+    fn type_mismatch() {
+        assert_eq!(
+            from_json(r#"{"attack": "fast"}"#, &[]),
+            Err("1:2: field `attack`: expected number, found string".to_string())
+        );
+        assert_eq!(
+            from_json("[1, 2]", &[]),
+            Err("1:1: expected object, found array".to_string())
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! 途中の信号を覗く `probe(sound, "名前")`
+//!
+//! 音はそのまま通し，鳴らしたときの標本を `BLOCK` 秒ごとにまとめてピークと RMS を記録する．
+//! `waveform = true` ならブロックごとの最小値と最大値（間引いた波形）も出す．
+//! 同じ名前の `probe` や，ステレオの左右，繰り返しの書き出しは同じ記録に重ねる．
+//! 記録は `probes()` か `--probe-report` で表示する
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// 1 ブロックの長さ（秒）
+pub const BLOCK: f64 = 0.1;
+
+#[derive(Clone, Copy)]
+struct Block {
+    peak: f64,
+    squares: f64,
+    count: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Block {
+    fn default() -> Block {
+        Block {
+            peak: 0.,
+            squares: 0.,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+pub struct Probe {
+    name: String,
+    waveform: Cell<bool>,
+    /// 最後に鳴らしたときの標本化周波数
+    samplerate: Cell<f64>,
+    blocks: RefCell<Vec<Block>>,
+}
+
+impl Probe {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// 標本化周波数 `samplerate` で鳴らし始める．1 ブロックの標本数を返す
+    pub fn start(&self, samplerate: f64) -> usize {
+        self.samplerate.set(samplerate);
+        ((BLOCK * samplerate) as usize).max(1)
+    }
+    /// `block` 番目のブロックの標本 `x` を記録する
+    pub fn record(&self, block: usize, x: f64) {
+        let mut blocks = self.blocks.borrow_mut();
+        if blocks.len() <= block {
+            blocks.resize(block + 1, Block::default());
+        }
+        let block = &mut blocks[block];
+        block.peak = block.peak.max(x.abs());
+        block.squares += x * x;
+        block.count += 1;
+        block.min = block.min.min(x);
+        block.max = block.max.max(x);
+    }
+}
+
+/// 名前ごとの `Probe`
+#[derive(Default)]
+pub struct Probes {
+    probes: Vec<Rc<Probe>>,
+}
+
+fn db(amplitude: f64) -> String {
+    if amplitude > 0. {
+        format!("{:.1} dBFS", 20. * amplitude.log10())
+    } else {
+        "-inf dBFS".to_string()
+    }
+}
+
+impl Probes {
+    /// 名前 `name` の `Probe` ．なければ作る
+    pub fn get(&mut self, name: &str, waveform: bool) -> Rc<Probe> {
+        let probe = match self.probes.iter().find(|probe| probe.name == name) {
+            Some(probe) => probe.clone(),
+            None => {
+                let probe = Rc::new(Probe {
+                    name: name.to_string(),
+                    waveform: Cell::new(false),
+                    samplerate: Cell::new(0.),
+                    blocks: RefCell::new(Vec::new()),
+                });
+                self.probes.push(probe.clone());
+                probe
+            }
+        };
+        if waveform {
+            probe.waveform.set(true);
+        }
+        probe
+    }
+    /// 作った順に，全体とブロックごとのピークと RMS
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for probe in &self.probes {
+            let blocks = probe.blocks.borrow();
+            let total = blocks.iter().fold(Block::default(), |total, block| Block {
+                peak: total.peak.max(block.peak),
+                squares: total.squares + block.squares,
+                count: total.count + block.count,
+                min: total.min.min(block.min),
+                max: total.max.max(block.max),
+            });
+            let rms = |block: &Block| (block.squares / block.count.max(1) as f64).sqrt();
+            report += &format!("probe {}: ", probe.name);
+            if total.count == 0 {
+                report += "never rendered\n";
+                continue;
+            }
+            report += &format!("peak {}, rms {}", db(total.peak), db(rms(&total)));
+            report += if total.peak == 0. {
+                " (silent)\n"
+            } else {
+                "\n"
+            };
+            let block_length =
+                (BLOCK * probe.samplerate.get()) as usize as f64 / probe.samplerate.get();
+            for (i, block) in blocks.iter().enumerate() {
+                report += &format!(
+                    "  {:>8.3} s  peak {:>10}  rms {:>10}",
+                    i as f64 * block_length,
+                    db(block.peak),
+                    db(rms(block))
+                );
+                if probe.waveform.get() && block.count > 0 {
+                    report += &format!("  min {:>7.4}  max {:>7.4}", block.min, block.max);
+                }
+                report.push('\n');
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::Sound;
+
+    #[test]
+    fn transparent_and_recorded() {
+        let mut probes = Probes::default();
+        let sound = Sound::Probe {
+            sound: Box::new(Sound::Mul(
+                Box::new(Sound::Const(0.5)),
+                Box::new(Sound::End(-0.1)),
+            )),
+            probe: probes.get("tone", true),
+        };
+        assert_eq!(sound.clone().render(0.2, 100.), {
+            let mut expected = vec![0.5; 10];
+            expected.extend(vec![0.; 10]);
+            expected
+        });
+        probes.get("unused", false);
+        assert_eq!(
+            probes.report(),
+            "probe tone: peak -6.0 dBFS, rms -9.0 dBFS\n\
+             \x20    0.000 s  peak  -6.0 dBFS  rms  -6.0 dBFS  min  0.5000  max  0.5000\n\
+             \x20    0.100 s  peak  -inf dBFS  rms  -inf dBFS  min  0.0000  max  0.0000\n\
+             probe unused: never rendered\n"
+        );
+    }
+}
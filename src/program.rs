@@ -3,11 +3,17 @@
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
+use crate::coercion;
+use crate::error::RuntimeFailure;
 use crate::types;
 
-use crate::function::{BooleanFunction, RealFunction, SoundFunction, StringFunction, VoidFunction};
+use crate::function::{
+    ArrayFunction, BooleanFunction, Function, IntFunction, RealFunction, RecordFunction,
+    SoundArrayFunction, SoundFunction, StringFunction, UserDefined, VoidFunction,
+};
 use crate::pos;
 use crate::sound::{self, Sound};
+use crate::value::Value;
 
 type RcCell<T> = Rc<Cell<T>>;
 type RcRefCell<T> = Rc<RefCell<T>>;
@@ -15,9 +21,15 @@ type RcRefCell<T> = Rc<RefCell<T>>;
 #[derive(Clone)]
 pub enum Expression {
     Real(RealExpression),
+    Int(IntExpression),
     Boolean(BooleanExpression),
     Sound(SoundExpression),
     String(StringExpression),
+    Array(ArrayExpression),
+    Record(RecordExpression),
+    SoundRecord(SoundRecordExpression),
+    SoundArray(SoundArrayExpression),
+    Function(FunctionExpression),
     Void(VoidExpression),
 }
 
@@ -32,9 +44,15 @@ macro_rules! def_convert {
 }
 
 def_convert!(RealExpression => Expression::Real);
+def_convert!(IntExpression => Expression::Int);
 def_convert!(BooleanExpression => Expression::Boolean);
 def_convert!(SoundExpression => Expression::Sound);
 def_convert!(StringExpression => Expression::String);
+def_convert!(ArrayExpression => Expression::Array);
+def_convert!(RecordExpression => Expression::Record);
+def_convert!(SoundRecordExpression => Expression::SoundRecord);
+def_convert!(SoundArrayExpression => Expression::SoundArray);
+def_convert!(FunctionExpression => Expression::Function);
 def_convert!(VoidExpression => Expression::Void);
 
 pub trait Evaluatable: Sized {
@@ -43,15 +61,43 @@ pub trait Evaluatable: Sized {
 
     /// いや，こいつには `Result<Self, Error>` を返させるべきかも
     fn from(_: Option<(Expression, pos::Range)>) -> Result<Self, Option<(Expression, pos::Range)>>;
+
+    /// ユーザ定義関数 `function` 自身を呼ぶ式ならば，その引数（ `Statement::TailCall` ）
+    fn self_call(&self, _function: &UserDefined<Self>) -> Option<Vec<Argument>> {
+        None
+    }
+}
+
+/// `Evaluatable::self_call` ． `$expr::Invocation` で `$function::UserDefined` を呼ぶものを探す
+macro_rules! self_call {
+    ($expr:ident, $function:ident) => {
+        fn self_call(&self, function: &UserDefined<$expr>) -> Option<Vec<Argument>> {
+            match self {
+                $expr::Invocation(fnc, arguments, ..) => match &**fnc {
+                    $function::UserDefined(callee) if std::ptr::eq(&**callee, function) => {
+                        Some(arguments.clone())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+    };
 }
 
 impl Expression {
     pub fn ty(&self) -> types::Type {
         match self {
             Expression::Real(_) => types::Type::Real,
+            Expression::Int(_) => types::Type::Int,
             Expression::Boolean(_) => types::Type::Boolean,
             Expression::Sound(_) => types::Type::Sound,
             Expression::String(_) => types::Type::String,
+            Expression::Array(_) => types::Type::Array,
+            Expression::Record(_) => types::Type::Record,
+            Expression::SoundRecord(_) => types::Type::SoundRecord,
+            Expression::SoundArray(_) => types::Type::SoundArray,
+            Expression::Function(expr) => types::Type::Function(expr.function().signature().into()),
             Expression::Void(_) => types::Type::Void,
         }
     }
@@ -60,6 +106,9 @@ impl Expression {
             Expression::Real(expr) => {
                 expr.evaluate();
             }
+            Expression::Int(expr) => {
+                expr.evaluate();
+            }
             Expression::Boolean(expr) => {
                 expr.evaluate();
             }
@@ -69,6 +118,21 @@ impl Expression {
             Expression::String(expr) => {
                 expr.evaluate();
             }
+            Expression::Array(expr) => {
+                expr.evaluate();
+            }
+            Expression::Record(expr) => {
+                expr.evaluate();
+            }
+            Expression::SoundRecord(expr) => {
+                expr.evaluate();
+            }
+            Expression::SoundArray(expr) => {
+                expr.evaluate();
+            }
+            Expression::Function(expr) => {
+                expr.evaluate();
+            }
             Expression::Void(expr) => {
                 expr.evaluate();
             }
@@ -79,30 +143,41 @@ impl Expression {
 #[derive(Clone)]
 pub enum Argument {
     Real(RcCell<f64>, RealExpression),
+    Int(RcCell<i64>, IntExpression),
     Boolean(RcCell<bool>, BooleanExpression),
     Sound(RcRefCell<Sound>, SoundExpression),
     String(RcRefCell<String>, StringExpression),
+    Array(RcRefCell<Vec<f64>>, ArrayExpression),
+    Record(RcRefCell<Vec<(String, f64)>>, RecordExpression),
+    SoundRecord(RcRefCell<Vec<(String, Sound)>>, SoundRecordExpression),
+    SoundArray(RcRefCell<Vec<Sound>>, SoundArrayExpression),
+    Function(RcRefCell<Rc<Function>>, FunctionExpression),
 }
 
 impl Argument {
-    fn set(self) {
-        match self {
-            Argument::Real(rc, expr) => rc.set(expr.evaluate()),
-            Argument::Boolean(rc, expr) => rc.set(expr.evaluate()),
-            Argument::Sound(rc, expr) => *rc.borrow_mut() = expr.evaluate(),
-            Argument::String(rc, expr) => *rc.borrow_mut() = expr.evaluate(),
-        }
-    }
     fn evaluate(self) -> sound::Argument {
         match self {
             Argument::Real(rc, expr) => sound::Argument::Real(rc, expr.evaluate()),
+            Argument::Int(rc, expr) => sound::Argument::Int(rc, expr.evaluate()),
             Argument::Boolean(rc, expr) => sound::Argument::Boolean(rc, expr.evaluate()),
             Argument::Sound(rc, expr) => sound::Argument::Sound(rc, expr.evaluate()),
             Argument::String(rc, expr) => sound::Argument::String(rc, expr.evaluate()),
+            Argument::Array(rc, expr) => sound::Argument::Array(rc, expr.evaluate()),
+            Argument::Record(rc, expr) => sound::Argument::Record(rc, expr.evaluate()),
+            Argument::SoundRecord(rc, expr) => sound::Argument::SoundRecord(rc, expr.evaluate()),
+            Argument::SoundArray(rc, expr) => sound::Argument::SoundArray(rc, expr.evaluate()),
+            Argument::Function(rc, expr) => sound::Argument::Function(rc, expr.evaluate()),
         }
     }
 }
 
+/// 引数をすべて評価してから関数の引数に書き込む．
+/// 引数の中で同じ関数を呼んでも，先に書き込んだ引数が上書きされない
+pub fn bind(arguments: Vec<Argument>) {
+    let arguments: Vec<_> = arguments.into_iter().map(Argument::evaluate).collect();
+    arguments.iter().for_each(sound::Argument::set);
+}
+
 #[derive(Clone)]
 pub enum RealExpression {
     Const(f64),
@@ -116,7 +191,14 @@ pub enum RealExpression {
     Div(Box<RealExpression>, Box<RealExpression>),
     Rem(Box<RealExpression>, Box<RealExpression>),
     Pow(Box<RealExpression>, Box<RealExpression>),
-    Invocation(Rc<RealFunction>, Vec<Argument>),
+    /// 添字が範囲外なら添字の式の位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Index(Box<ArrayExpression>, Box<RealExpression>, pos::Range),
+    /// フィールドがなければ `Index` と同じく panic する
+    Field(Box<RecordExpression>, String, pos::Range),
+    /// 評価できない引数ならば呼び出しの位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Invocation(Rc<RealFunction>, Vec<Argument>, pos::Range),
+    /// 整数を実数として使う
+    Int(Box<IntExpression>),
 }
 
 impl Evaluatable for RealExpression {
@@ -138,18 +220,140 @@ impl Evaluatable for RealExpression {
             RealExpression::Div(left, right) => left.evaluate() / right.evaluate(),
             RealExpression::Rem(left, right) => left.evaluate() % right.evaluate(),
             RealExpression::Pow(left, right) => left.evaluate().powf(right.evaluate()),
-            RealExpression::Invocation(fnc, arguments) => {
-                arguments.into_iter().for_each(Argument::set);
+            RealExpression::Index(array, index, range) => {
+                element(array.evaluate(), index.evaluate(), range)
+            }
+            RealExpression::Field(record, name, range) => field(record.evaluate(), &name, range),
+            RealExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
+                if let Err(message) = fnc.check() {
+                    std::panic::panic_any(RuntimeFailure(range, message));
+                }
                 fnc.evaluate()
             }
+            RealExpression::Int(expr) => expr.evaluate() as f64,
         }
         .clamp(f64::MIN, f64::MAX)
     }
+    self_call!(RealExpression, RealFunction);
     fn from(
         expr: Option<(Expression, pos::Range)>,
     ) -> Result<RealExpression, Option<(Expression, pos::Range)>> {
         match expr {
             Some((Expression::Real(expr), _)) => Ok(expr),
+            Some((Expression::Int(expr), _)) => Ok(RealExpression::Int(expr.into())),
+            other => Err(other),
+        }
+    }
+}
+
+/// 整数の式．溢れたり，0 で割ったり，64 ビット以上シフトしたりしたら
+/// 式の位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+#[derive(Clone)]
+pub enum IntExpression {
+    Const(i64),
+    Reference(RcCell<i64>),
+    Print(Box<IntExpression>),
+    Minus(Box<IntExpression>, pos::Range),
+    Add(Box<IntExpression>, Box<IntExpression>, pos::Range),
+    Sub(Box<IntExpression>, Box<IntExpression>, pos::Range),
+    Mul(Box<IntExpression>, Box<IntExpression>, pos::Range),
+    /// 余りは割る数と同じ符号（ `-1 % 3` は 2 ）
+    Rem(Box<IntExpression>, Box<IntExpression>, pos::Range),
+    LeftShift(Box<IntExpression>, Box<IntExpression>, pos::Range),
+    /// 算術シフト
+    RightShift(Box<IntExpression>, Box<IntExpression>, pos::Range),
+    Invocation(Rc<IntFunction>, Vec<Argument>),
+}
+
+impl Evaluatable for IntExpression {
+    type Output = i64;
+    fn evaluate(self) -> i64 {
+        let fail = |range: pos::Range, message: String| -> ! {
+            std::panic::panic_any(RuntimeFailure(range, message))
+        };
+        let overflow = |range: pos::Range, op: &str, left: i64, right: i64| -> i64 {
+            fail(
+                range,
+                format!("integer overflow in {} {} {}", left, op, right),
+            )
+        };
+        let shift = |range: pos::Range, op: &str, left: i64, right: i64| -> u32 {
+            match right {
+                0..=63 => right as u32,
+                _ => fail(
+                    range,
+                    format!("cannot shift {} {} {} (at most 63 bits)", left, op, right),
+                ),
+            }
+        };
+        match self {
+            IntExpression::Const(value) => value,
+            IntExpression::Reference(rc) => rc.get(),
+            IntExpression::Print(expr) => {
+                let ret = expr.evaluate();
+                println!("{}", ret);
+                ret
+            }
+            IntExpression::Minus(expr, range) => {
+                let x = expr.evaluate();
+                x.checked_neg()
+                    .unwrap_or_else(|| overflow(range, "*", x, -1))
+            }
+            IntExpression::Add(left, right, range) => {
+                let (left, right) = (left.evaluate(), right.evaluate());
+                left.checked_add(right)
+                    .unwrap_or_else(|| overflow(range, "+", left, right))
+            }
+            IntExpression::Sub(left, right, range) => {
+                let (left, right) = (left.evaluate(), right.evaluate());
+                left.checked_sub(right)
+                    .unwrap_or_else(|| overflow(range, "-", left, right))
+            }
+            IntExpression::Mul(left, right, range) => {
+                let (left, right) = (left.evaluate(), right.evaluate());
+                left.checked_mul(right)
+                    .unwrap_or_else(|| overflow(range, "*", left, right))
+            }
+            IntExpression::Rem(left, right, range) => {
+                let (left, right) = (left.evaluate(), right.evaluate());
+                if right == 0 {
+                    fail(range, format!("division by zero in {} % 0", left));
+                }
+                left.checked_rem_euclid(right)
+                    .map(|rem| {
+                        if right < 0 && rem != 0 {
+                            rem + right
+                        } else {
+                            rem
+                        }
+                    })
+                    .unwrap_or_else(|| overflow(range, "%", left, right))
+            }
+            IntExpression::LeftShift(left, right, range) => {
+                let (left, right) = (left.evaluate(), right.evaluate());
+                let bits = shift(range.clone(), "<<", left, right);
+                match left.checked_mul(1 << bits) {
+                    Some(value) if bits < i64::BITS - 1 || left == 0 => value,
+                    _ => overflow(range, "<<", left, right),
+                }
+            }
+            IntExpression::RightShift(left, right, range) => {
+                let (left, right) = (left.evaluate(), right.evaluate());
+                left >> shift(range, ">>", left, right)
+            }
+            IntExpression::Invocation(fnc, arguments) => {
+                bind(arguments);
+                fnc.evaluate()
+            }
+        }
+    }
+    self_call!(IntExpression, IntFunction);
+    fn from(
+        expr: Option<(Expression, pos::Range)>,
+    ) -> Result<IntExpression, Option<(Expression, pos::Range)>> {
+        match expr {
+            Some((Expression::Int(expr), _)) => Ok(expr),
             other => Err(other),
         }
     }
@@ -162,10 +366,18 @@ pub enum BooleanExpression {
     Not(Box<BooleanExpression>),
     RealLess(Box<RealExpression>, Box<RealExpression>),
     RealGreater(Box<RealExpression>, Box<RealExpression>),
+    /// `==` と同じく 1e-6 までの差は等しいとみなす
+    RealLessEqual(Box<RealExpression>, Box<RealExpression>),
+    RealGreaterEqual(Box<RealExpression>, Box<RealExpression>),
     RealEqual(Box<RealExpression>, Box<RealExpression>),
     StringEqual(Box<StringExpression>, Box<StringExpression>),
     RealNotEqual(Box<RealExpression>, Box<RealExpression>),
     StringNotEqual(Box<StringExpression>, Box<StringExpression>),
+    /// 文字列は辞書順で比べる
+    StringLess(Box<StringExpression>, Box<StringExpression>),
+    StringGreater(Box<StringExpression>, Box<StringExpression>),
+    StringLessEqual(Box<StringExpression>, Box<StringExpression>),
+    StringGreaterEqual(Box<StringExpression>, Box<StringExpression>),
     And(Box<BooleanExpression>, Box<BooleanExpression>),
     Or(Box<BooleanExpression>, Box<BooleanExpression>),
     Invocation(Rc<BooleanFunction>, Vec<Argument>),
@@ -184,6 +396,12 @@ impl Evaluatable for BooleanExpression {
             BooleanExpression::Not(expr) => !expr.evaluate(),
             BooleanExpression::RealLess(left, right) => left.evaluate() < right.evaluate(),
             BooleanExpression::RealGreater(left, right) => left.evaluate() > right.evaluate(),
+            BooleanExpression::RealLessEqual(left, right) => {
+                left.evaluate() <= right.evaluate() + 1e-6
+            }
+            BooleanExpression::RealGreaterEqual(left, right) => {
+                left.evaluate() + 1e-6 >= right.evaluate()
+            }
             BooleanExpression::RealEqual(left, right) => {
                 (left.evaluate() - right.evaluate()).abs() <= 1e-6
             }
@@ -192,14 +410,21 @@ impl Evaluatable for BooleanExpression {
             }
             BooleanExpression::StringEqual(left, right) => left.evaluate() == right.evaluate(),
             BooleanExpression::StringNotEqual(left, right) => left.evaluate() != right.evaluate(),
+            BooleanExpression::StringLess(left, right) => left.evaluate() < right.evaluate(),
+            BooleanExpression::StringGreater(left, right) => left.evaluate() > right.evaluate(),
+            BooleanExpression::StringLessEqual(left, right) => left.evaluate() <= right.evaluate(),
+            BooleanExpression::StringGreaterEqual(left, right) => {
+                left.evaluate() >= right.evaluate()
+            }
             BooleanExpression::And(left, right) => left.evaluate() && right.evaluate(),
             BooleanExpression::Or(left, right) => left.evaluate() || right.evaluate(),
             BooleanExpression::Invocation(fnc, arguments) => {
-                arguments.into_iter().for_each(Argument::set);
+                bind(arguments);
                 fnc.evaluate()
             }
         }
     }
+    self_call!(BooleanExpression, BooleanFunction);
     fn from(
         expr: Option<(Expression, pos::Range)>,
     ) -> Result<BooleanExpression, Option<(Expression, pos::Range)>> {
@@ -223,14 +448,28 @@ pub enum SoundExpression {
     Div(Box<SoundExpression>, Box<SoundExpression>),
     Rem(Box<SoundExpression>, Box<SoundExpression>),
     Pow(Box<SoundExpression>, Box<SoundExpression>),
+    /// `RealExpression::Field` と同じ
+    Field(Box<SoundRecordExpression>, String, pos::Range),
+    /// `RealExpression::Index` と同じ
+    Index(Box<SoundArrayExpression>, Box<RealExpression>, pos::Range),
     LeftShift(Box<SoundExpression>, Box<RealExpression>),
     RightShift(Box<SoundExpression>, Box<RealExpression>),
-    Invocation(Rc<SoundFunction>, Vec<Argument>),
+    /// 引数が `limits` を外れたり，失敗したりしたら呼び出しの位置 `pos::Range` をつけて
+    /// `RuntimeFailure` で panic する
+    Invocation(Rc<SoundFunction>, Vec<Argument>, pos::Range),
     Apply(
         Rc<RealFunction>,
         Vec<Argument>,
         Vec<(RcCell<f64>, SoundExpression)>,
     ),
+    /// 音を作る関数の変調できる引数（ `SoundFunction::modulatable` ）に音を渡す．
+    /// 失敗したら `Invocation` と同じく `RuntimeFailure` で panic する
+    Modulate(
+        Rc<SoundFunction>,
+        Vec<Argument>,
+        Vec<(RcCell<f64>, SoundExpression)>,
+        pos::Range,
+    ),
 }
 
 impl Evaluatable for SoundExpression {
@@ -238,11 +477,17 @@ impl Evaluatable for SoundExpression {
     fn evaluate(self) -> Sound {
         match self {
             SoundExpression::Reference(rc) => rc.borrow().clone(),
-            SoundExpression::Invocation(fnc, arguments) => {
-                arguments.into_iter().for_each(Argument::set);
-                fnc.evaluate()
+            SoundExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
+                fnc.check()
+                    .and_then(|()| fnc.evaluate())
+                    .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range, message)))
             }
             SoundExpression::Real(expr) => Sound::Const(expr.evaluate()),
+            SoundExpression::Field(record, name, range) => field(record.evaluate(), &name, range),
+            SoundExpression::Index(array, index, range) => {
+                element(array.evaluate(), index.evaluate(), range)
+            }
             SoundExpression::Apply(fnc, arguments, sounds) => Sound::Apply(
                 fnc,
                 arguments.into_iter().map(Argument::evaluate).collect(),
@@ -251,6 +496,15 @@ impl Evaluatable for SoundExpression {
                     .map(|(rc, expr)| (rc, expr.evaluate()))
                     .collect(),
             ),
+            SoundExpression::Modulate(fnc, arguments, sounds, range) => {
+                bind(arguments);
+                let sounds = sounds
+                    .into_iter()
+                    .map(|(rc, expr)| (rc, expr.evaluate()))
+                    .collect();
+                fnc.modulate(sounds)
+                    .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range, message)))
+            }
             SoundExpression::Play(expr) => expr.evaluate(),
             SoundExpression::Minus(expr) => Sound::Minus(expr.evaluate().into()),
             SoundExpression::Reciprocal(expr) => Sound::Reciprocal(expr.evaluate().into()),
@@ -276,6 +530,7 @@ impl Evaluatable for SoundExpression {
             SoundExpression::RightShift(left, right) => left.evaluate().shift(-right.evaluate()),
         }
     }
+    self_call!(SoundExpression, SoundFunction);
     fn from(
         expr: Option<(Expression, pos::Range)>,
     ) -> Result<SoundExpression, Option<(Expression, pos::Range)>> {
@@ -293,7 +548,12 @@ pub enum StringExpression {
     Reference(RcRefCell<String>),
     Print(Box<StringExpression>),
     Add(Box<StringExpression>, Box<StringExpression>),
-    Invocation(Rc<StringFunction>, Vec<Argument>),
+    /// 文字列との連結や埋め込みで，値を文字列にしたもの
+    Real(Box<RealExpression>),
+    Int(Box<IntExpression>),
+    Boolean(Box<BooleanExpression>),
+    /// 失敗したら呼び出しの位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Invocation(Rc<StringFunction>, Vec<Argument>, pos::Range),
 }
 
 impl Evaluatable for StringExpression {
@@ -308,12 +568,17 @@ impl Evaluatable for StringExpression {
                 ret
             }
             StringExpression::Add(left, right) => left.evaluate() + &right.evaluate(),
-            StringExpression::Invocation(fnc, arguments) => {
-                arguments.into_iter().for_each(Argument::set);
+            StringExpression::Real(expr) => expr.evaluate().to_string(),
+            StringExpression::Int(expr) => expr.evaluate().to_string(),
+            StringExpression::Boolean(expr) => expr.evaluate().to_string(),
+            StringExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
                 fnc.evaluate()
+                    .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range, message)))
             }
         }
     }
+    self_call!(StringExpression, StringFunction);
     fn from(
         expr: Option<(Expression, pos::Range)>,
     ) -> Result<StringExpression, Option<(Expression, pos::Range)>> {
@@ -323,19 +588,236 @@ impl Evaluatable for StringExpression {
         }
     }
 }
+#[derive(Clone)]
+pub enum ArrayExpression {
+    Reference(RcRefCell<Vec<f64>>),
+    /// 角括弧 `[ ]` でくくられた配列リテラル
+    Literal(Vec<RealExpression>),
+    Print(Box<ArrayExpression>),
+    /// 失敗したら呼び出しの位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Invocation(Rc<ArrayFunction>, Vec<Argument>, pos::Range),
+}
+
+impl Evaluatable for ArrayExpression {
+    type Output = Vec<f64>;
+    fn evaluate(self) -> Vec<f64> {
+        match self {
+            ArrayExpression::Reference(rc) => rc.borrow().clone(),
+            ArrayExpression::Literal(vec) => vec.into_iter().map(Evaluatable::evaluate).collect(),
+            ArrayExpression::Print(expr) => {
+                let ret = expr.evaluate();
+                println!(
+                    "[{}]",
+                    ret.iter()
+                        .map(f64::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                ret
+            }
+            ArrayExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
+                fnc.evaluate()
+                    .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range, message)))
+            }
+        }
+    }
+    self_call!(ArrayExpression, ArrayFunction);
+    fn from(
+        expr: Option<(Expression, pos::Range)>,
+    ) -> Result<ArrayExpression, Option<(Expression, pos::Range)>> {
+        match expr {
+            Some((Expression::Array(expr), _)) => Ok(expr),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum RecordExpression {
+    Reference(RcRefCell<Vec<(String, f64)>>),
+    /// 波括弧 `{ }` でくくられたレコードリテラル
+    Literal(Vec<(String, RealExpression)>),
+    Print(Box<RecordExpression>),
+    /// 失敗したら呼び出しの位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Invocation(Rc<RecordFunction>, Vec<Argument>, pos::Range),
+}
+
+impl Evaluatable for RecordExpression {
+    type Output = Vec<(String, f64)>;
+    fn evaluate(self) -> Vec<(String, f64)> {
+        match self {
+            RecordExpression::Reference(rc) => rc.borrow().clone(),
+            RecordExpression::Literal(vec) => vec
+                .into_iter()
+                .map(|(name, expr)| (name, expr.evaluate()))
+                .collect(),
+            RecordExpression::Print(expr) => {
+                let ret = expr.evaluate();
+                println!(
+                    "{{{}}}",
+                    ret.iter()
+                        .map(|(name, value)| format!("{}: {}", name, value))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                ret
+            }
+            RecordExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
+                fnc.evaluate()
+                    .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range, message)))
+            }
+        }
+    }
+    self_call!(RecordExpression, RecordFunction);
+    fn from(
+        expr: Option<(Expression, pos::Range)>,
+    ) -> Result<RecordExpression, Option<(Expression, pos::Range)>> {
+        match expr {
+            Some((Expression::Record(expr), _)) => Ok(expr),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum SoundRecordExpression {
+    Reference(RcRefCell<Vec<(String, Sound)>>),
+    /// Sound のフィールドを含むレコードリテラル
+    Literal(Vec<(String, SoundExpression)>),
+}
+
+impl Evaluatable for SoundRecordExpression {
+    type Output = Vec<(String, Sound)>;
+    fn evaluate(self) -> Vec<(String, Sound)> {
+        match self {
+            SoundRecordExpression::Reference(rc) => rc.borrow().clone(),
+            SoundRecordExpression::Literal(vec) => vec
+                .into_iter()
+                .map(|(name, expr)| (name, expr.evaluate()))
+                .collect(),
+        }
+    }
+    fn from(
+        expr: Option<(Expression, pos::Range)>,
+    ) -> Result<SoundRecordExpression, Option<(Expression, pos::Range)>> {
+        match expr {
+            Some((Expression::SoundRecord(expr), _)) => Ok(expr),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum SoundArrayExpression {
+    Reference(RcRefCell<Vec<Sound>>),
+    /// Sound の要素を含む配列リテラル
+    Literal(Vec<SoundExpression>),
+    /// 失敗したら呼び出しの位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Invocation(Rc<SoundArrayFunction>, Vec<Argument>, pos::Range),
+}
+
+impl Evaluatable for SoundArrayExpression {
+    type Output = Vec<Sound>;
+    fn evaluate(self) -> Vec<Sound> {
+        match self {
+            SoundArrayExpression::Reference(rc) => rc.borrow().clone(),
+            SoundArrayExpression::Literal(vec) => {
+                vec.into_iter().map(Evaluatable::evaluate).collect()
+            }
+            SoundArrayExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
+                fnc.evaluate()
+                    .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range, message)))
+            }
+        }
+    }
+    self_call!(SoundArrayExpression, SoundArrayFunction);
+    fn from(
+        expr: Option<(Expression, pos::Range)>,
+    ) -> Result<SoundArrayExpression, Option<(Expression, pos::Range)>> {
+        match expr {
+            Some((Expression::SoundArray(expr), _)) => Ok(expr),
+            other => Err(other),
+        }
+    }
+}
+
+/// 関数の値
+#[derive(Clone)]
+pub enum FunctionExpression {
+    /// `def` で定義した関数やラムダ式
+    Const(Rc<Function>),
+    Reference(RcRefCell<Rc<Function>>),
+}
+
+impl FunctionExpression {
+    /// コンパイルするときの値．型（ `Function::signature` ）はこれで決める
+    pub fn function(&self) -> Rc<Function> {
+        match self {
+            FunctionExpression::Const(function) => function.clone(),
+            FunctionExpression::Reference(rc) => rc.borrow().clone(),
+        }
+    }
+}
+
+impl Evaluatable for FunctionExpression {
+    type Output = Rc<Function>;
+    fn evaluate(self) -> Rc<Function> {
+        self.function()
+    }
+    fn from(
+        expr: Option<(Expression, pos::Range)>,
+    ) -> Result<FunctionExpression, Option<(Expression, pos::Range)>> {
+        match expr {
+            Some((Expression::Function(expr), _)) => Ok(expr),
+            other => Err(other),
+        }
+    }
+}
+
+/// 配列の `index` 番目の要素．負の添字は末尾から数える．
+/// 範囲外なら `range` をつけて `RuntimeFailure` で panic する
+fn element<T>(mut array: Vec<T>, index: f64, range: pos::Range) -> T {
+    let index = coercion::integer(index, "index")
+        .unwrap_or_else(|message| std::panic::panic_any(RuntimeFailure(range.clone(), message)));
+    let i = if index < 0 {
+        array.len() as i64 + index
+    } else {
+        index
+    };
+    if i < 0 || i as usize >= array.len() {
+        let message = format!("index {} out of range (length {})", index, array.len());
+        std::panic::panic_any(RuntimeFailure(range, message));
+    }
+    array.swap_remove(i as usize)
+}
+
+/// レコードのフィールド `name` の値．なければ `range` をつけて `RuntimeFailure` で panic する
+fn field<T>(record: Vec<(String, T)>, name: &str, range: pos::Range) -> T {
+    match record.into_iter().find(|(field, _)| field == name) {
+        Some((_, value)) => value,
+        None => std::panic::panic_any(RuntimeFailure(range, format!("undefined field {}", name))),
+    }
+}
+
 #[derive(Clone)]
 pub enum VoidExpression {
     Const,
-    Invocation(Rc<VoidFunction>, Vec<Argument>),
+    /// 失敗したら呼び出しの位置 `pos::Range` をつけて `RuntimeFailure` で panic する
+    Invocation(Rc<VoidFunction>, Vec<Argument>, pos::Range),
 }
 impl Evaluatable for VoidExpression {
     type Output = ();
     fn evaluate(self) {
         match self {
             VoidExpression::Const => (/* do nothing */),
-            VoidExpression::Invocation(fnc, arguments) => {
-                arguments.into_iter().for_each(Argument::set);
-                fnc.evaluate()
+            VoidExpression::Invocation(fnc, arguments, range) => {
+                bind(arguments);
+                if let Err(message) = fnc.evaluate() {
+                    std::panic::panic_any(RuntimeFailure(range, message));
+                }
             }
         }
     }
@@ -353,9 +835,15 @@ impl Evaluatable for VoidExpression {
 pub enum Statement<Expr: Evaluatable> {
     Expression(Option<Expression>),
     RealSubstitution(RcCell<f64>, RealExpression),
+    IntSubstitution(RcCell<i64>, IntExpression),
     BooleanSubstitution(RcCell<bool>, BooleanExpression),
     SoundSubstitution(RcRefCell<Sound>, SoundExpression),
     StringSubstitution(RcRefCell<String>, StringExpression),
+    ArraySubstitution(RcRefCell<Vec<f64>>, ArrayExpression),
+    RecordSubstitution(RcRefCell<Vec<(String, f64)>>, RecordExpression),
+    SoundRecordSubstitution(RcRefCell<Vec<(String, Sound)>>, SoundRecordExpression),
+    SoundArraySubstitution(RcRefCell<Vec<Sound>>, SoundArrayExpression),
+    FunctionSubstitution(RcRefCell<Rc<Function>>, FunctionExpression),
     While(BooleanExpression, Box<Statement<Expr>>),
     If(
         BooleanExpression,
@@ -364,10 +852,30 @@ pub enum Statement<Expr: Evaluatable> {
     ),
     Block(Vec<Statement<Expr>>),
     Return(Expr),
+    /// ユーザ定義関数の本体で自分自身を呼ぶ `return f(...);` （ `Statement::eliminate_tail_calls` ）．
+    /// 引数を書き込んで本体を最初からやり直す
+    TailCall(Vec<Argument>),
+}
+
+/// 文を最後まで実行せずに抜けた理由
+pub enum Exit<T> {
+    Return(T),
+    /// `Statement::TailCall` ．引数は書き込んである
+    TailCall,
 }
 
 impl<Expr: Evaluatable + Clone> Statement<Expr> {
+    /// 実行する．`return` したらその値
     pub fn run(self) -> Option<Expr::Output> {
+        match self.exit() {
+            Some(Exit::Return(value)) => Some(value),
+            // 関数の本体の外には現れない
+            Some(Exit::TailCall) => unreachable!("tail call outside a function body"),
+            None => None,
+        }
+    }
+    /// 実行する．`return` か末尾呼び出しで抜けたらその理由
+    pub fn exit(self) -> Option<Exit<Expr::Output>> {
         match self {
             Statement::Expression(expr) => {
                 if let Some(expr) = expr {
@@ -379,6 +887,10 @@ impl<Expr: Evaluatable + Clone> Statement<Expr> {
                 rc.set(expr.evaluate());
                 None
             }
+            Statement::IntSubstitution(rc, expr) => {
+                rc.set(expr.evaluate());
+                None
+            }
             Statement::BooleanSubstitution(rc, expr) => {
                 rc.set(expr.evaluate());
                 None
@@ -391,30 +903,113 @@ impl<Expr: Evaluatable + Clone> Statement<Expr> {
                 *rc.borrow_mut() = expr.evaluate();
                 None
             }
+            Statement::ArraySubstitution(rc, expr) => {
+                *rc.borrow_mut() = expr.evaluate();
+                None
+            }
+            Statement::RecordSubstitution(rc, expr) => {
+                *rc.borrow_mut() = expr.evaluate();
+                None
+            }
+            Statement::SoundRecordSubstitution(rc, expr) => {
+                *rc.borrow_mut() = expr.evaluate();
+                None
+            }
+            Statement::SoundArraySubstitution(rc, expr) => {
+                *rc.borrow_mut() = expr.evaluate();
+                None
+            }
+            Statement::FunctionSubstitution(rc, expr) => {
+                *rc.borrow_mut() = expr.evaluate();
+                None
+            }
             Statement::While(cond, stmt) => {
                 while cond.clone().evaluate() {
-                    if let Some(value) = stmt.clone().run() {
-                        return Some(value);
+                    if let Some(exit) = stmt.clone().exit() {
+                        return Some(exit);
                     }
                 }
                 None
             }
             Statement::If(cond, stmt1, stmt2) => {
                 if cond.evaluate() {
-                    stmt1.run()
+                    stmt1.exit()
                 } else {
-                    stmt2.map(Statement::run).flatten()
+                    stmt2.map(Statement::exit).flatten()
                 }
             }
             Statement::Block(vec) => {
                 for stmt in vec {
-                    if let Some(value) = stmt.run() {
-                        return Some(value);
+                    if let Some(exit) = stmt.exit() {
+                        return Some(exit);
                     }
                 }
                 None
             }
-            Statement::Return(expr) => Some(expr.evaluate()),
+            Statement::Return(expr) => Some(Exit::Return(expr.evaluate())),
+            Statement::TailCall(arguments) => {
+                bind(arguments);
+                Some(Exit::TailCall)
+            }
+        }
+    }
+    /// `function` の本体で，`function` 自身を呼んでその値をそのまま返す `return f(...);` を
+    /// `Statement::TailCall` にする．`return f(...) if 条件;` や if 文，while 文の中のものも含む．
+    ///
+    /// 次のものは対象外で，これまでどおり呼び出しを重ねる（ `--recursion-limit` を数える）
+    ///
+    /// - 値に手を加えて返すもの（ `return f(n - 1) + 1;` ）
+    /// - 他の関数を経由する相互再帰や，関数の値（引数や変数に入れたもの）を通した呼び出し
+    /// - 値を返さない関数の `f(); return;`
+    pub fn eliminate_tail_calls(self, function: &UserDefined<Expr>) -> Statement<Expr> {
+        match self {
+            Statement::Return(expr) => match expr.self_call(function) {
+                Some(arguments) => Statement::TailCall(arguments),
+                None => Statement::Return(expr),
+            },
+            Statement::While(cond, stmt) => {
+                Statement::While(cond, stmt.eliminate_tail_calls(function).into())
+            }
+            Statement::If(cond, stmt1, stmt2) => Statement::If(
+                cond,
+                stmt1.eliminate_tail_calls(function).into(),
+                stmt2.map(|stmt| stmt.eliminate_tail_calls(function)).into(),
+            ),
+            Statement::Block(vec) => Statement::Block(
+                vec.into_iter()
+                    .map(|stmt| stmt.eliminate_tail_calls(function))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+}
+
+impl<Expr: Evaluatable> Statement<Expr> {
+    /// 代入する先の変数をすべて `targets` に加える（ユーザ定義関数の局所変数を集める）
+    pub fn targets(&self, targets: &mut Vec<Value>) {
+        match self {
+            Statement::Expression(_) | Statement::Return(_) | Statement::TailCall(_) => {}
+            Statement::RealSubstitution(rc, _) => targets.push(Value::Real(rc.clone())),
+            Statement::IntSubstitution(rc, _) => targets.push(Value::Int(rc.clone())),
+            Statement::BooleanSubstitution(rc, _) => targets.push(Value::Boolean(rc.clone())),
+            Statement::SoundSubstitution(rc, _) => targets.push(Value::Sound(rc.clone())),
+            Statement::StringSubstitution(rc, _) => targets.push(Value::String(rc.clone())),
+            Statement::ArraySubstitution(rc, _) => targets.push(Value::Array(rc.clone())),
+            Statement::RecordSubstitution(rc, _) => targets.push(Value::Record(rc.clone())),
+            Statement::SoundRecordSubstitution(rc, _) => {
+                targets.push(Value::SoundRecord(rc.clone()))
+            }
+            Statement::SoundArraySubstitution(rc, _) => targets.push(Value::SoundArray(rc.clone())),
+            Statement::FunctionSubstitution(rc, _) => targets.push(Value::Function(rc.clone())),
+            Statement::While(_, stmt) => stmt.targets(targets),
+            Statement::If(_, stmt1, stmt2) => {
+                stmt1.targets(targets);
+                if let Some(stmt2) = &**stmt2 {
+                    stmt2.targets(targets);
+                }
+            }
+            Statement::Block(vec) => vec.iter().for_each(|stmt| stmt.targets(targets)),
         }
     }
 }
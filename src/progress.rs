@@ -0,0 +1,97 @@
+//! 書き出しの進み具合（ `Environment::set_progress` ）
+//!
+//! 書き出しのあいだ `BLOCK` 標本ごとに，登録した関数へ書き出した時間と各ファイルのピークを渡す．
+//! 進捗表示やメーターに使う．コマンドラインでは標準エラー出力が端末のときに `terminal` を使う
+
+use std::cell::RefCell;
+use std::io::Write;
+
+/// 何標本ごとに知らせるか
+pub const BLOCK: i64 = 4096;
+
+pub struct RenderProgress {
+    /// 書き出した時間（秒）
+    pub rendered: f64,
+    /// 書き出す時間（秒）．余韻を自動で切るときはその上限
+    pub total: f64,
+    /// ファイルごとの，直前のブロックの各チャンネルのピーク
+    pub peaks: Vec<(String, Vec<f64>)>,
+    /// 書き出しが終わったか（それぞれの書き出しの最後の 1 回だけ true ）
+    pub finished: bool,
+}
+
+pub type Callback = Box<dyn FnMut(RenderProgress)>;
+
+thread_local! {
+    static CALLBACK: RefCell<Option<Callback>> = const { RefCell::new(None) };
+}
+
+pub fn set(callback: Option<Callback>) {
+    CALLBACK.with(|cell| *cell.borrow_mut() = callback);
+}
+
+/// 知らせる先があるか．なければピークを測らなくてよい
+pub fn active() -> bool {
+    CALLBACK.with(|cell| cell.borrow().is_some())
+}
+
+pub fn report(progress: RenderProgress) {
+    CALLBACK.with(|cell| {
+        if let Some(callback) = &mut *cell.borrow_mut() {
+            callback(progress);
+        }
+    });
+}
+
+/// 1 行で書き出した時間と全体のピーク（ dBFS ）を出し，終わったら改行する
+pub fn terminal() -> Callback {
+    Box::new(|progress| {
+        let names: Vec<_> = progress
+            .peaks
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let peak = progress
+            .peaks
+            .iter()
+            .flat_map(|(_, peaks)| peaks)
+            .fold(0f64, |peak, x| peak.max(*x));
+        eprint!(
+            "\rwriting {} {:.1}/{:.1} s peak {:.1} dBFS ",
+            names.join(", "),
+            progress.rendered,
+            progress.total,
+            20. * peak.log10()
+        );
+        if progress.finished {
+            eprintln!();
+        }
+        let _ = std::io::stderr().flush();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn callback() {
+        assert!(!active());
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let sink = reports.clone();
+        set(Some(Box::new(move |progress: RenderProgress| {
+            sink.borrow_mut()
+                .push((progress.rendered, progress.finished))
+        })));
+        assert!(active());
+        report(RenderProgress {
+            rendered: 0.5,
+            total: 1.,
+            peaks: vec![("a.wav".to_string(), vec![0.25])],
+            finished: false,
+        });
+        set(None);
+        assert_eq!(*reports.borrow(), vec![(0.5, false)]);
+    }
+}
@@ -0,0 +1,189 @@
+//! 台本や対話環境の文を 1 つずつ読んで実行する
+//!
+//! 対話環境ではエラーの後も読みかけの行を捨てて次の行から続け，変数などの環境はそのまま残す．
+//! 成功した文は `Session` に記録し， `:history` で表示， `:export` で書き出す
+
+use crate::environment::Environment;
+use crate::error::{self, Error};
+use crate::lexer::Lexer;
+use crate::parser;
+use crate::pos;
+use crate::session::Session;
+use crate::syntax::Statement;
+
+pub struct Repl {
+    lexer: Lexer,
+    /// 読んだ行（エラーの表示に使う）
+    log: Vec<String>,
+    session: Session,
+    /// エラーの後も続けるか
+    interactive: bool,
+}
+
+impl Repl {
+    pub fn new(lexer: Lexer, interactive: bool) -> Repl {
+        Repl {
+            lexer,
+            log: Vec::new(),
+            session: Session::new(),
+            interactive,
+        }
+    }
+    /// 入力が尽きるまで実行する．エラーは読んだ行と一緒に `report` に渡し，
+    /// 対話環境でなければそこで止める．エラーがあったかを返す
+    pub fn run(
+        &mut self,
+        environment: &mut Environment,
+        mut report: impl FnMut(Error, &[String]),
+    ) -> bool {
+        let mut failed = false;
+        while let Some(result) = self.step(environment) {
+            if let Err(err) = result {
+                report(err, &self.log);
+                failed = true;
+                if !self.interactive {
+                    break;
+                }
+                self.recover();
+            }
+        }
+        failed
+    }
+    /// 1 文読んで実行する．入力が尽きたら `None`
+    pub fn step(&mut self, environment: &mut Environment) -> Option<Result<(), Error>> {
+        let (result, record) = match parser::parse_statement(&mut self.lexer, &mut self.log) {
+            Ok(Some(Statement::Command(range, command))) => {
+                let result = match command.strip_prefix("export ") {
+                    Some(path) => self.session.export(range, path.trim()),
+                    None if command.trim() == "history" => {
+                        eprint!("{}", self.session.script());
+                        Ok(())
+                    }
+                    None => environment.run(Statement::Command(range, command)),
+                };
+                (result, false)
+            }
+            Ok(Some(statement)) => (self.execute(environment, statement), true),
+            Ok(None) => return None,
+            Err(err) => (Err(err), false),
+        };
+        match result {
+            Ok(()) if record => self.session.succeed(self.lexer.end(), &self.log),
+            Ok(()) => self.session.skip(self.lexer.end()),
+            Err(_) => {}
+        }
+        Some(result)
+    }
+    /// 文を実行する．対話環境では， `RuntimeFailure` でない panic （内部の不具合）も
+    /// 文全体の位置で `Error::RuntimeFailure` にして続ける
+    fn execute(&self, environment: &mut Environment, statement: Statement) -> Result<(), Error> {
+        if !self.interactive {
+            return environment.run(statement);
+        }
+        let payload = match error::catch_quietly(|| environment.run(statement)) {
+            Ok(result) => return result,
+            Err(payload) => payload,
+        };
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(payload) => std::panic::resume_unwind(payload),
+            },
+        };
+        Err(Error::RuntimeFailure(self.statement(), message))
+    }
+    /// いま読んだ文の位置．前の空白は含めない
+    fn statement(&self) -> pos::Range {
+        let end = self.lexer.end();
+        let mut start = self.session.start().clone();
+        while start < end {
+            let rest = &self.log[start.line()][start.byte()..];
+            start = match rest.find(|c: char| !c.is_whitespace()) {
+                Some(offset) => {
+                    start = pos::Pos::new(start.line(), start.byte() + offset);
+                    break;
+                }
+                None => pos::Pos::new(start.line() + 1, 0),
+            };
+        }
+        pos::Range::new(start.min(end.clone()), end)
+    }
+    /// エラーの後，読みかけのトークンと行の残りを捨てて次の行から読み直す
+    fn recover(&mut self) {
+        self.lexer.discard();
+        self.session.skip(pos::Pos::new(self.log.len(), 0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl(source: &str, interactive: bool) -> Repl {
+        let lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_string())), false);
+        Repl::new(lexer, interactive)
+    }
+
+    #[test]
+    fn recovers_from_errors() {
+        let source = "let a = 1;\nlet b = @ + 1; let c = 0;\nlet d = (a +\n  z);\nlet e = a + 1;\n";
+        let mut environment = Environment::new();
+        let mut errors = Vec::new();
+        assert!(repl(source, true).run(&mut environment, |err, _| errors.push(err.code())));
+        // 字句解析と意味解析のエラーの後も続き， `a` は残る
+        assert_eq!(errors, ["E0001", "E0020"]);
+        assert!(environment.get::<f64>("c").is_err());
+        assert_eq!(environment.get::<f64>("e"), Ok(2.));
+        let mut environment = Environment::new();
+        assert!(repl(source, false).run(&mut environment, |_, _| {}));
+        assert!(environment.get::<f64>("e").is_err());
+        let mut environment = Environment::new();
+        assert!(!repl("let a = 1;\n", false).run(&mut environment, |_, _| {}));
+    }
+
+    #[test]
+    fn recovers_from_runtime_panics() {
+        // 負の幅は `coercion` の変換で失敗する
+        let source = "let a = 1;\nlet s = format(1, width = -1);\nlet e = a + 1;\n";
+        let mut environment = Environment::new();
+        let mut errors = Vec::new();
+        assert!(repl(source, true).run(&mut environment, |err, _| errors.push(err)));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "E0059");
+        match &errors[0] {
+            Error::RuntimeFailure(range, _) => assert_eq!(range.to_string(), "2:9-2:29"),
+            err => panic!("{:?}", err),
+        }
+        assert_eq!(environment.get::<f64>("e"), Ok(2.));
+        // 位置のつく実行時エラーの後も続く
+        let source =
+            "let i: int = 9223372036854775807;\nlet j: int = i + 1;\nlet k: int = i - 1;\n";
+        let mut environment = Environment::new();
+        let mut errors = Vec::new();
+        assert!(repl(source, true).run(&mut environment, |err, _| errors.push(err.code())));
+        assert_eq!(errors, ["E0059"]);
+        assert_eq!(environment.get::<i64>("k"), Ok(9223372036854775806));
+    }
+
+    #[test]
+    fn multi_line_statements() {
+        let source = "let a = (1 +\n  2);\nlet b = a\n  * 2; let c = b;\n";
+        let mut environment = Environment::new();
+        assert!(!repl(source, true).run(&mut environment, |_, _| {}));
+        assert_eq!(environment.get::<f64>("a"), Ok(3.));
+        assert_eq!(environment.get::<f64>("c"), Ok(6.));
+    }
+
+    #[test]
+    fn commands() {
+        let source = "let a = 1;\n:snapshot s\na = 2;\n:restore s\n:restore t\n:nope\n:history\nlet b = a;\n";
+        // メタコマンドはプロンプトを出す対話環境でだけ読む
+        let lexer = Lexer::new(Box::new(std::io::Cursor::new(source.to_string())), true);
+        let mut environment = Environment::new();
+        let mut errors = Vec::new();
+        assert!(Repl::new(lexer, true).run(&mut environment, |err, _| errors.push(err.code())));
+        assert_eq!(errors, ["E0057", "E0056"]);
+        assert_eq!(environment.get::<f64>("b"), Ok(1.));
+    }
+}
@@ -0,0 +1,101 @@
+//! 録音した音の音高と音量を追跡して合成し直す
+
+use crate::analysis;
+use crate::dynamics::Envelope;
+use crate::sound::Sound;
+use std::rc::Rc;
+
+/// 波形表の長さ
+const TABLE_SIZE: usize = 2048;
+/// 音量の追従の時間（秒）
+const ATTACK: f64 = 0.005;
+const RELEASE: f64 = 0.05;
+
+/// 録音 `samples` の音高と音量で `oscillator` を鳴らす．
+///
+/// `oscillator` は 0 秒から 1 秒までを 1 周期の波形とみなす（ `Linear(1) % 1` ならのこぎり波）．
+/// 音高が見つからない間は直前の音高を保つ
+pub fn track_and_resynth(samples: &[f64], samplerate: f64, oscillator: Sound) -> Sound {
+    let table = oscillator.channel(0).render(1., TABLE_SIZE as f64);
+    let pitches = analysis::track_pitch(samples, samplerate);
+    // 無声の区間を前後の音高で埋める
+    let first = pitches
+        .iter()
+        .copied()
+        .find(|&pitch| pitch > 0.)
+        .unwrap_or(0.);
+    let pitches: Vec<f64> = pitches
+        .iter()
+        .scan(first, |last, &pitch| {
+            if pitch > 0. {
+                *last = pitch;
+            }
+            Some(*last)
+        })
+        .collect();
+    let hop = analysis::PITCH_HOP * samplerate;
+    let mut envelope = Envelope::new(ATTACK, RELEASE, samplerate);
+    let mut phase = 0.;
+    let resynthesized = samples
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            // フレームの間は線形補間
+            let position = i as f64 / hop;
+            let index = position.floor() as usize;
+            let fraction = position - index as f64;
+            let at = |index: usize| pitches.get(index.min(pitches.len() - 1)).copied();
+            let pitch =
+                at(index).unwrap_or(0.) * (1. - fraction) + at(index + 1).unwrap_or(0.) * fraction;
+            let position = phase * TABLE_SIZE as f64;
+            let index = position.floor() as usize;
+            let fraction = position - index as f64;
+            let value = table[index % TABLE_SIZE] * (1. - fraction)
+                + table[(index + 1) % TABLE_SIZE] * fraction;
+            phase = (phase + pitch / samplerate).fract();
+            value * envelope.next(*x)
+        })
+        .collect();
+    Sound::Samples {
+        samples: Rc::new(resynthesized),
+        samplerate,
+        offset: 0.,
+        looping: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_pitch_and_amplitude() {
+        let samplerate = 8000.;
+        // 0.2 秒の 200 Hz ，0.2 秒の無音， 0.2 秒の 300 Hz
+        let samples: Vec<f64> = (0..4800)
+            .map(|i| {
+                let t = i as f64 / samplerate;
+                match i / 1600 {
+                    0 => 0.5 * (2. * std::f64::consts::PI * 200. * t).sin(),
+                    1 => 0.,
+                    _ => 0.5 * (2. * std::f64::consts::PI * 300. * t).sin(),
+                }
+            })
+            .collect();
+        let saw = Sound::Rem(
+            Sound::Linear {
+                slope: 1.,
+                intercept: 0.,
+            }
+            .into(),
+            Sound::Const(1.).into(),
+        );
+        let output = track_and_resynth(&samples, samplerate, saw).render(0.6, samplerate);
+        let pitch =
+            |range: std::ops::Range<usize>| analysis::detect_pitch(&output[range], samplerate);
+        assert!((pitch(400..1400) - 200.).abs() < 2.);
+        assert!((pitch(3400..4400) - 300.).abs() < 3.);
+        // 無音の区間は音量も下がる
+        assert!(analysis::peak(&output[2800..3200]) < 0.05);
+    }
+}
@@ -0,0 +1,347 @@
+//! `samples { }` で宣言する外部の音声ファイル
+//!
+//! 宣言した時点で（複数のスレッドで）読み込み， `sha256` があれば中身と照らし合わせる．
+//! 読み込んだ音は `sample("名前")` で使う．宣言せずにその場で読むには `read("パス")`
+
+use crate::cache::Cache;
+use crate::events;
+use crate::sound::Sound;
+use crate::wav;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// 名前から読み込み済みの音を引く表
+pub type Registry = HashMap<String, Sound>;
+
+/// 復号した標本．`Sound` と違ってスレッド間で受け渡せる
+#[derive(Debug, PartialEq)]
+pub struct Decoded {
+    /// 1 つか 2 つ
+    pub channels: Vec<Vec<f64>>,
+    pub samplerate: f64,
+    /// ファイルに書かれたループ区間 `[start, end)` （標本数）
+    pub loop_points: Option<(usize, usize)>,
+}
+
+impl Decoded {
+    pub fn into_sound(self) -> Sound {
+        let samplerate = self.samplerate;
+        let looping = self
+            .loop_points
+            .map(|(start, end)| (start as f64 / samplerate, end as f64 / samplerate));
+        let mut channels = self.channels.into_iter().map(|samples| Sound::Samples {
+            samples: Rc::new(samples),
+            samplerate,
+            offset: 0.,
+            looping,
+        });
+        let left = channels.next().expect("no channels");
+        match channels.next() {
+            Some(right) => Sound::Stereo(left.into(), right.into()),
+            None => left,
+        }
+    }
+}
+
+/// `(パス, SHA-256)` の音声ファイルを `threads` 個のスレッドで読み込む．
+/// 結果は `files` と同じ順に並べる．進み具合を標準エラー出力に出す
+pub fn load_all(
+    files: &[(String, Option<String>)],
+    cache: Option<&Cache>,
+    threads: usize,
+) -> Vec<Result<Sound, String>> {
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results = Mutex::new((0..files.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, files.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let (path, sha256) = match files.get(i) {
+                    Some(file) => file,
+                    None => break,
+                };
+                let result = load(path, sha256.as_deref(), cache);
+                results.lock().unwrap()[i] = Some(result);
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                eprint!("\rloading samples {}/{}", done, files.len());
+            });
+        }
+    });
+    if !files.is_empty() {
+        eprintln!();
+    }
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("not loaded").map(Decoded::into_sound))
+        .collect()
+}
+
+/// `read("パス")` で読む音．標本化周波数が違えば鳴らすときに補間する
+pub fn read(path: &str) -> Result<Sound, String> {
+    load(path, None, None).map(Decoded::into_sound)
+}
+
+/// `path` の音声ファイルを読み込む．`sha256` （16 進）が与えられていれば照合する．
+/// `cache` があれば，復号した音をそこから引き，なければ置く
+fn load(path: &str, sha256: Option<&str>, cache: Option<&Cache>) -> Result<Decoded, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    let actual = hex(&digest(&bytes));
+    if let Some(expected) = sha256 {
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(format!(
+                "checksum mismatch for {} (expected sha256 {}, found {})",
+                path, expected, actual
+            ));
+        }
+    }
+    if let Some(decoded) = cache.and_then(|cache| cache.get(&actual)) {
+        return Ok(decoded);
+    }
+    let decoded =
+        decode(&bytes).map_err(|message| format!("cannot decode {}: {}", path, message))?;
+    if let Some(cache) = cache {
+        // キャッシュに置けなくても読み込みは成功している
+        if let Err(message) = cache.put(&actual, &decoded) {
+            events::warning(&message);
+        }
+    }
+    Ok(decoded)
+}
+
+/// 音声ファイルの形式
+#[derive(Debug, PartialEq)]
+enum Format {
+    Wav,
+    Aiff,
+    Flac,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Wav => "WAV",
+            Format::Aiff => "AIFF",
+            Format::Flac => "FLAC",
+        }
+    }
+}
+
+/// 拡張子ではなく先頭のバイト列で形式を見分ける
+fn detect(bytes: &[u8]) -> Result<Format, String> {
+    match (bytes.get(..4), bytes.get(8..12)) {
+        (Some(b"RIFF" | b"RF64"), Some(b"WAVE")) => Ok(Format::Wav),
+        (Some(b"FORM"), Some(b"AIFF" | b"AIFC")) => Ok(Format::Aiff),
+        (Some(b"fLaC"), _) => Ok(Format::Flac),
+        _ => {
+            let magic = &bytes[..bytes.len().min(12)];
+            let hex: Vec<_> = magic.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = magic
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7e => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            Err(format!(
+                "unsupported format (magic bytes {} \"{}\"; expected WAV, AIFF or FLAC)",
+                hex.join(" "),
+                text
+            ))
+        }
+    }
+}
+
+/// 中身を形式に応じて復号する．2 チャンネル以上なら先頭の 2 チャンネル
+fn decode(bytes: &[u8]) -> Result<Decoded, String> {
+    match detect(bytes)? {
+        Format::Wav => decode_wav(bytes),
+        #[cfg(feature = "aiff")]
+        Format::Aiff => crate::aiff::decode(bytes),
+        #[cfg(feature = "flac")]
+        Format::Flac => crate::flac::decode(bytes),
+        #[allow(unreachable_patterns)]
+        format => Err(format!(
+            "{} support is disabled (build with the `{}` feature)",
+            format.name(),
+            format.name().to_lowercase()
+        )),
+    }
+}
+
+/// WAV の中身を復号する．`smpl` チャンクがあればそのループ区間も読む
+fn decode_wav(bytes: &[u8]) -> Result<Decoded, String> {
+    let reader =
+        hound::WavReader::new(std::io::Cursor::new(bytes)).map_err(|err| err.to_string())?;
+    let spec = reader.spec();
+    let interleaved: Vec<f64> = match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let scale = 2f64.powi(spec.bits_per_sample as i32 - 1);
+            reader
+                .into_samples::<i32>()
+                .map(|x| x.map(|x| x as f64 / scale))
+                .collect::<Result<_, _>>()
+        }
+        hound::SampleFormat::Float => reader
+            .into_samples::<f32>()
+            .map(|x| x.map(f64::from))
+            .collect::<Result<_, _>>(),
+    }
+    .map_err(|err| err.to_string())?;
+    let channels = spec.channels as usize;
+    if channels == 0 {
+        return Err("no channels".to_string());
+    }
+    let channel = |i: usize| {
+        interleaved
+            .iter()
+            .skip(i)
+            .step_by(channels)
+            .copied()
+            .collect()
+    };
+    Ok(Decoded {
+        channels: (0..channels.min(2)).map(channel).collect(),
+        samplerate: spec.sample_rate as f64,
+        loop_points: wav::loop_points(bytes),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// SHA-256 （FIPS 180-4）
+fn digest(bytes: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    // 1 ビットと 0 で埋め，最後にビット長
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(&(bytes.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for (h, v) in h.iter_mut().zip(&v) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+    let mut ret = [0; 32];
+    for (bytes, h) in ret.chunks_mut(4).zip(&h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_in_parallel() {
+        let directory = std::env::temp_dir().join(format!("cryss-samples-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let files: Vec<_> = (1..=4)
+            .map(|channels| {
+                let path = directory.join(format!("{}.wav", channels));
+                let spec = hound::WavSpec {
+                    channels,
+                    sample_rate: 8000,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+                for _ in 0..channels * 3 {
+                    writer.write_sample(i16::MIN / 2).unwrap();
+                }
+                writer.finalize().unwrap();
+                (path.to_str().unwrap().to_string(), None)
+            })
+            .collect();
+        let results = load_all(&files, None, 2);
+        assert!(matches!(results[0], Ok(Sound::Samples { .. })));
+        assert!(results[1..]
+            .iter()
+            .all(|result| matches!(result, Ok(Sound::Stereo(_, _)))));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn detect_by_header() {
+        assert_eq!(detect(b"RIFF\0\0\0\0WAVEfmt "), Ok(Format::Wav));
+        assert_eq!(detect(b"FORM\0\0\0\0AIFC"), Ok(Format::Aiff));
+        assert_eq!(detect(b"fLaC\0\0\0\x22"), Ok(Format::Flac));
+        let message = decode(b"OggS\0\x02").unwrap_err();
+        assert!(
+            message.contains("magic bytes 4f 67 67 53 00 02 \"OggS..\""),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn sha256() {
+        assert_eq!(
+            hex(&digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&digest(&[b'a'; 1000])),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! 対話環境で実行に成功した文の記録
+//!
+//! `:export <パス>` で，成功した文だけを入力した順に並べた台本として書き出す
+
+use crate::error::Error;
+use crate::pos;
+
+pub struct Session {
+    /// 次の文が始まりうる位置（直前の文の終わり）
+    start: pos::Pos,
+    /// 成功した文のソースコード
+    statements: Vec<String>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            start: pos::Pos::new(0, 0),
+            statements: Vec::new(),
+        }
+    }
+    /// 次の文が始まりうる位置
+    pub fn start(&self) -> &pos::Pos {
+        &self.start
+    }
+    /// 文の実行に成功した．`end` はその文の最後のトークンの終わり
+    pub fn succeed(&mut self, end: pos::Pos, log: &[String]) {
+        let statement = self.start.slice(&end, log);
+        self.statements.push(statement.trim().to_string());
+        self.start = end;
+    }
+    /// `next` までを記録せずに読み飛ばす（失敗した文やメタコマンド）
+    pub fn skip(&mut self, next: pos::Pos) {
+        self.start = next;
+    }
+    /// 成功した文を並べた台本
+    pub fn script(&self) -> String {
+        self.statements
+            .iter()
+            .map(|statement| format!("{}\n", statement))
+            .collect()
+    }
+    /// `:export <パス>`
+    pub fn export(&self, range: pos::Range, path: &str) -> Result<(), Error> {
+        std::fs::write(path, self.script()).map_err(|err| {
+            Error::ExportFailure(range, format!("cannot write {}: {}", path, err))
+        })?;
+        eprintln!("exported {} statements to {}", self.statements.len(), path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script() {
+        let log: Vec<String> = ["let x = 1; let y = x +\n", "  2;\n", "y = z;\n", "x = y;\n"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let mut session = Session::new();
+        session.succeed(pos::Pos::new(0, 10), &log);
+        session.succeed(pos::Pos::new(1, 4), &log);
+        // 3 行目は失敗した
+        session.skip(pos::Pos::new(3, 0));
+        session.succeed(pos::Pos::new(3, 6), &log);
+        assert_eq!(session.script(), "let x = 1;\nlet y = x +\n  2;\nx = y;\n");
+    }
+}
@@ -1,6 +1,13 @@
 //! Sound
 
-use crate::function::RealFunction;
+use crate::buffer;
+use crate::dynamics::{self, Dynamics};
+use crate::effects::{self, Effect};
+use crate::envelope::Adsr;
+use crate::filter::{Biquad, Filter};
+use crate::function::{Function, RealFunction};
+use crate::oscillator::{self, Waveform};
+use crate::probe::Probe;
 
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
@@ -11,34 +18,132 @@ use std::f64::consts::TAU;
 
 use num::complex::Complex64;
 use rand::prelude::*;
+use std::collections::VecDeque;
+
+/// ループの継ぎ目で重ねる長さ（秒）
+const LOOP_CROSSFADE: f64 = 0.01;
 
 #[derive(Clone)]
 pub enum Argument {
     Real(RcCell<f64>, f64),
+    Int(RcCell<i64>, i64),
     Boolean(RcCell<bool>, bool),
     String(RcRefCell<String>, String),
     Sound(RcRefCell<Sound>, Sound),
+    Array(RcRefCell<Vec<f64>>, Vec<f64>),
+    Record(RcRefCell<Vec<(String, f64)>>, Vec<(String, f64)>),
+    SoundRecord(RcRefCell<Vec<(String, Sound)>>, Vec<(String, Sound)>),
+    SoundArray(RcRefCell<Vec<Sound>>, Vec<Sound>),
+    Function(RcRefCell<Rc<Function>>, Rc<Function>),
 }
 impl Argument {
-    fn set(&self) {
+    pub fn set(&self) {
         match self {
             Argument::Real(rc, value) => rc.set(*value),
+            Argument::Int(rc, value) => rc.set(*value),
             Argument::Boolean(rc, value) => rc.set(*value),
             Argument::String(rc, value) => *rc.borrow_mut() = value.clone(),
             Argument::Sound(rc, value) => *rc.borrow_mut() = value.clone(),
+            Argument::Array(rc, value) => *rc.borrow_mut() = value.clone(),
+            Argument::Record(rc, value) => *rc.borrow_mut() = value.clone(),
+            Argument::SoundRecord(rc, value) => *rc.borrow_mut() = value.clone(),
+            Argument::SoundArray(rc, value) => *rc.borrow_mut() = value.clone(),
+            Argument::Function(rc, value) => *rc.borrow_mut() = value.clone(),
+        }
+    }
+}
+
+/// ステップシーケンサ（ `steps` ）のパラメータ
+pub struct Steps {
+    /// 各ステップの音量（ 0 は休符）
+    pub pattern: Vec<f64>,
+    /// `every` 周期に 1 回，最後の周期で `pattern` の代わりに使うパターン
+    pub variation: Option<(usize, Vec<f64>)>,
+    /// 1 ステップの長さ（秒）
+    pub step: f64,
+    /// 各ステップが発音する確率
+    pub chance: f64,
+    /// 発音時刻のずれの最大値（秒）．ステップの長さの半分を超えない
+    pub jitter: f64,
+    /// 音量のずれの最大値（比）
+    pub velocity_jitter: f64,
+    pub seed: u64,
+}
+
+impl Steps {
+    /// `index` 番目のステップの発音時刻のずれ（秒）と音量．発音しないなら `None`
+    ///
+    /// 乱数はシードとステップ番号だけから決まるので，どこから再生しても同じ結果になる
+    fn event(&self, index: i64) -> Option<(f64, f64)> {
+        let len = self.pattern.len() as i64;
+        if index < 0 || len == 0 {
+            return None;
         }
+        let cycle = (index / len) as usize;
+        let position = (index % len) as usize;
+        let velocity = match &self.variation {
+            Some((every, variation)) if *every > 0 && cycle % every == every - 1 => {
+                variation.get(position).copied().unwrap_or(0.)
+            }
+            _ => self.pattern[position],
+        };
+        let mut rng =
+            StdRng::seed_from_u64(self.seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        if velocity <= 0. || rng.gen::<f64>() >= self.chance {
+            return None;
+        }
+        let jitter = self.jitter.min(self.step / 2.) * rng.gen_range(-1.0..=1.0);
+        let velocity = velocity * (1. + self.velocity_jitter * rng.gen_range(-1.0..=1.0));
+        Some((jitter, velocity.max(0.)))
     }
 }
 
 #[derive(Clone)]
 pub enum Sound {
     Const(f64),
-    Linear { slope: f64, intercept: f64 }, // x = at + b
-    Sin { frequency: f64, phase: f64 },    // x = sin(τft + θ)
-    Exp { slope: f64, intercept: f64 },    // x = e^(at + b)
+    Linear {
+        slope: f64,
+        intercept: f64,
+    }, // x = at + b
+    Sin {
+        frequency: f64,
+        phase: f64,
+    }, // x = sin(τft + θ)
+    Exp {
+        slope: f64,
+        intercept: f64,
+    }, // x = e^(at + b)
+    /// 基本波形．`phase` は 1 周期を 1 とした位相．`bandlimit` ならば折り返しを抑える
+    Oscillator {
+        waveform: Waveform,
+        frequency: f64,
+        phase: f64,
+        bandlimit: bool,
+    },
     Begin(f64),
     End(f64),
     Rand,
+    /// ハードシンク．周波数 `frequency` ，位相 `phase` （ 1 周期を 1 とする）のオシレーターが
+    /// 1 周期進むごとに `slave` を最初から鳴らし直す
+    Sync {
+        frequency: f64,
+        phase: f64,
+        slave: Box<Sound>,
+    },
+    /// 周波数が音のオシレーター（周波数変調）．`waveform` が `None` なら正弦波．
+    /// ずらす前の時刻 0 の位相（ 1 周期を 1 とする）が `phase` で，そこから標本ごとに周波数を積分する
+    Fm {
+        waveform: Option<Waveform>,
+        frequency: Box<Sound>,
+        phase: f64,
+        bandlimit: bool,
+        offset: f64,
+    },
+    /// シードから決まる白色雑音（ `noise` ）
+    Noise {
+        seed: u64,
+        offset: f64,
+    },
     Minus(Box<Sound>),
     Reciprocal(Box<Sound>),
     Add(Box<Sound>, Box<Sound>),
@@ -48,9 +153,586 @@ pub enum Sound {
     Pow(Box<Sound>, Box<Sound>),
     Rem(Box<Sound>, Box<Sound>),
     Apply(Rc<RealFunction>, Vec<Argument>, Vec<(RcCell<f64>, Sound)>),
+    /// `sound` をパターンに従って鳴らす．
+    /// 次に鳴るまで前の音を鳴らし続ける（同時には鳴らない）
+    Steps {
+        sound: Box<Sound>,
+        steps: Rc<Steps>,
+        offset: f64,
+    },
+    /// 標本列（ `render` で作る）．範囲外は 0 ．
+    /// `looping` があれば，その区間 `[start, end)` （秒）を終わりなく繰り返す
+    Samples {
+        samples: Rc<Vec<f64>>,
+        samplerate: f64,
+        offset: f64,
+        looping: Option<(f64, f64)>,
+    },
+    /// 時刻 `[from, to)` の外で `sound` の代わりに一定の値 `before` ， `after` を返す．
+    /// 時刻は `offset` だけずらして測る
+    Clamp {
+        sound: Box<Sound>,
+        from: f64,
+        to: f64,
+        before: f64,
+        after: f64,
+        offset: f64,
+    },
+    /// ADSR エンベロープ．時刻 `-offset` に鳴り始める
+    Adsr {
+        envelope: Adsr,
+        offset: f64,
+    },
+    /// 左右 2 チャンネルの音．モノラルとして鳴らすと左右の平均になる
+    Stereo(Box<Sound>, Box<Sound>),
+    /// 定パワーのパンの係数．位置 -1 が左端， 1 が右端
+    PanLaw {
+        position: Box<Sound>,
+        right: bool,
+    },
+    /// 処理の遅延（秒）を報告する．鳴らすと `sound` のまま
+    Latency {
+        sound: Box<Sound>,
+        latency: f64,
+    },
+    /// `sidechain` の振幅を検出して `sound` の音量を変える
+    Dynamics {
+        sound: Box<Sound>,
+        sidechain: Box<Sound>,
+        dynamics: Dynamics,
+    },
+    Filter {
+        sound: Box<Sound>,
+        filter: Filter,
+    },
+    /// 周波数が `cutoff` の音に従って変わるフィルタ．
+    /// `filter` の周波数は余韻の見積もりだけに使う（ `filter::LOWEST_FREQUENCY` ）
+    ModulatedFilter {
+        sound: Box<Sound>,
+        cutoff: Box<Sound>,
+        filter: Filter,
+    },
+    /// 遅延や残響（ `mod effects` ）
+    Effect {
+        sound: Box<Sound>,
+        effect: Effect,
+    },
+    /// 音をそのまま通して `probe` に記録する
+    Probe {
+        sound: Box<Sound>,
+        probe: Rc<Probe>,
+    },
+    /// 初めて鳴らすときに描いた `sound` の標本列を鳴らす（ `freeze` ）．
+    /// 描いたものは複製どうしで共有し，標本化周波数が変われば描き直す．
+    /// `channel` があればそのチャンネルだけ．時刻 `-offset` に鳴り始める
+    Frozen {
+        sound: Box<Sound>,
+        frozen: RcRefCell<Option<(f64, Sound)>>,
+        channel: Option<usize>,
+        offset: f64,
+    },
+    /// 音量の層ごとに順番に鳴らす音（ `multisample` ）．
+    /// 層は選ばれる音量の下限の昇順で，`velocity` の層の `round` 番目（余り）の音を鳴らす
+    Multisample {
+        layers: Rc<Vec<(f64, Vec<Sound>)>>,
+        velocity: f64,
+        round: usize,
+    },
+    /// 音を順に鳴らす（ `seq` ， `repeat` ）．始まる時刻（秒）の昇順で，どの音も長さが決まっている．
+    /// 時刻 `-offset` に鳴り始める
+    Seq {
+        sounds: Rc<Vec<(f64, Sound)>>,
+        offset: f64,
+    },
 }
 
 impl Sound {
+    /// 長さの決まっている音の長さ（秒）．その後はずっと 0 になる．
+    /// 積はどちらかが終われば，和と差は両方が終われば終わる
+    pub fn duration(&self) -> Option<f64> {
+        match self {
+            Sound::End(time) => Some((-time).max(0.)),
+            Sound::Samples {
+                looping: Some(_), ..
+            } => None,
+            Sound::Samples {
+                samples,
+                samplerate,
+                offset,
+                looping: None,
+            } => Some((samples.len() as f64 / samplerate - offset).max(0.)),
+            Sound::Stereo(left, right) => match (left.duration(), right.duration()) {
+                (Some(left), Some(right)) => Some(left.max(right)),
+                _ => None,
+            },
+            Sound::Multisample { layers, .. } => layers
+                .iter()
+                .flat_map(|(_, sounds)| sounds.iter().map(Sound::duration))
+                .try_fold(0., |max, duration| Some(duration?.max(max))),
+            Sound::Adsr { envelope, offset } => Some((envelope.duration()? - offset).max(0.)),
+            Sound::Seq { sounds, offset } => match sounds.last() {
+                Some((start, sound)) => Some((start + sound.duration()? - offset).max(0.)),
+                None => Some(0.),
+            },
+            Sound::Mul(left, right) => match (left.duration(), right.duration()) {
+                (Some(left), Some(right)) => Some(left.min(right)),
+                (duration, None) | (None, duration) => duration,
+            },
+            Sound::Add(left, right) | Sound::Sub(left, right) => {
+                Some(left.duration()?.max(right.duration()?))
+            }
+            Sound::Filter { sound, filter } | Sound::ModulatedFilter { sound, filter, .. } => {
+                Some(sound.duration()? + filter.ring_time())
+            }
+            Sound::Effect { sound, effect } => Some(sound.duration()? + effect.ring_time()),
+            Sound::Minus(sound) | Sound::Latency { sound, .. } | Sound::Probe { sound, .. } => {
+                sound.duration()
+            }
+            Sound::Frozen { sound, offset, .. } => Some((sound.duration()? - offset).max(0.)),
+            _ => None,
+        }
+    }
+    /// チャンネル数（ `Stereo` を含めば 2 ）
+    pub fn channels(&self) -> usize {
+        match self {
+            Sound::Stereo(_, _) => 2,
+            Sound::Minus(sound) | Sound::Reciprocal(sound) => sound.channels(),
+            Sound::Add(left, right)
+            | Sound::Sub(left, right)
+            | Sound::Mul(left, right)
+            | Sound::Div(left, right)
+            | Sound::Pow(left, right)
+            | Sound::Rem(left, right) => left.channels().max(right.channels()),
+            Sound::Apply(_, _, sounds) => sounds
+                .iter()
+                .map(|(_, sound)| sound.channels())
+                .max()
+                .unwrap_or(1),
+            Sound::Steps { sound, .. } => sound.channels(),
+            Sound::Frozen {
+                channel: Some(_), ..
+            } => 1,
+            Sound::Frozen { sound, .. } => sound.channels(),
+            Sound::PanLaw { position, .. } => position.channels(),
+            Sound::Dynamics {
+                sound, sidechain, ..
+            } => sound.channels().max(sidechain.channels()),
+            Sound::ModulatedFilter { sound, cutoff, .. } => sound.channels().max(cutoff.channels()),
+            Sound::Filter { sound, .. }
+            | Sound::Effect { sound, .. }
+            | Sound::Latency { sound, .. }
+            | Sound::Probe { sound, .. }
+            | Sound::Clamp { sound, .. }
+            | Sound::Sync { slave: sound, .. }
+            | Sound::Fm {
+                frequency: sound, ..
+            } => sound.channels(),
+            Sound::Multisample { layers, .. } => layers
+                .iter()
+                .flat_map(|(_, sounds)| sounds.iter().map(Sound::channels))
+                .max()
+                .unwrap_or(1),
+            Sound::Seq { sounds, .. } => sounds
+                .iter()
+                .map(|(_, sound)| sound.channels())
+                .max()
+                .unwrap_or(1),
+            _ => 1,
+        }
+    }
+    /// 標本列の音（ `Stereo` なら左右とも）のループ区間（秒）を `looping` にする
+    pub fn with_looping(self, looping: Option<(f64, f64)>) -> Sound {
+        match self {
+            Sound::Samples {
+                samples,
+                samplerate,
+                offset,
+                ..
+            } => Sound::Samples {
+                samples,
+                samplerate,
+                offset,
+                looping,
+            },
+            Sound::Stereo(left, right) => Sound::Stereo(
+                left.with_looping(looping).into(),
+                right.with_looping(looping).into(),
+            ),
+            sound => sound,
+        }
+    }
+    /// 標本列の音のループ区間（秒）
+    pub fn looping(&self) -> Option<(f64, f64)> {
+        match self {
+            Sound::Samples { looping, .. } => *looping,
+            Sound::Stereo(left, _) => left.looping(),
+            _ => None,
+        }
+    }
+    /// `steps` の `index` 番目のステップで音量 `velocity` で鳴らす音．
+    /// `multisample` ならば音量で層を選び，ステップごとに次の音に進む
+    fn trigger(self, velocity: f64, index: usize) -> Sound {
+        match self {
+            Sound::Multisample { layers, round, .. } => Sound::Multisample {
+                layers,
+                velocity,
+                round: round + index,
+            },
+            sound => sound,
+        }
+    }
+    /// 標本列の音（ `Stereo` なら左右とも）の高さを `ratio` 倍にする（速く鳴らす）
+    pub fn transpose(self, ratio: f64) -> Sound {
+        match self {
+            Sound::Samples {
+                samples,
+                samplerate,
+                offset,
+                looping,
+            } => Sound::Samples {
+                samples,
+                samplerate: samplerate * ratio,
+                offset: offset / ratio,
+                looping: looping.map(|(start, end)| (start / ratio, end / ratio)),
+            },
+            Sound::Stereo(left, right) => {
+                Sound::Stereo(left.transpose(ratio).into(), right.transpose(ratio).into())
+            }
+            sound => sound,
+        }
+    }
+    /// `index` 番目（ 0 が左）のチャンネルだけを取り出したモノラルの音
+    pub fn channel(self, index: usize) -> Sound {
+        let channel = |sound: Box<Sound>| Box::new(sound.channel(index));
+        match self {
+            Sound::Stereo(left, right) => {
+                if index == 0 {
+                    left.channel(index)
+                } else {
+                    right.channel(index)
+                }
+            }
+            Sound::Minus(sound) => Sound::Minus(channel(sound)),
+            Sound::Reciprocal(sound) => Sound::Reciprocal(channel(sound)),
+            Sound::Add(left, right) => Sound::Add(channel(left), channel(right)),
+            Sound::Sub(left, right) => Sound::Sub(channel(left), channel(right)),
+            Sound::Mul(left, right) => Sound::Mul(channel(left), channel(right)),
+            Sound::Div(left, right) => Sound::Div(channel(left), channel(right)),
+            Sound::Pow(left, right) => Sound::Pow(channel(left), channel(right)),
+            Sound::Rem(left, right) => Sound::Rem(channel(left), channel(right)),
+            Sound::Apply(function, arguments, sounds) => Sound::Apply(
+                function,
+                arguments,
+                sounds
+                    .into_iter()
+                    .map(|(rc, sound)| (rc, sound.channel(index)))
+                    .collect(),
+            ),
+            Sound::Steps {
+                sound,
+                steps,
+                offset,
+            } => Sound::Steps {
+                sound: channel(sound),
+                steps,
+                offset,
+            },
+            Sound::PanLaw { position, right } => Sound::PanLaw {
+                position: channel(position),
+                right,
+            },
+            Sound::Dynamics {
+                sound,
+                sidechain,
+                dynamics,
+            } => Sound::Dynamics {
+                sound: channel(sound),
+                sidechain: channel(sidechain),
+                dynamics,
+            },
+            Sound::Filter { sound, filter } => Sound::Filter {
+                sound: channel(sound),
+                filter,
+            },
+            Sound::ModulatedFilter {
+                sound,
+                cutoff,
+                filter,
+            } => Sound::ModulatedFilter {
+                sound: channel(sound),
+                cutoff: channel(cutoff),
+                filter,
+            },
+            Sound::Effect { sound, effect } => Sound::Effect {
+                sound: channel(sound),
+                effect,
+            },
+            Sound::Latency { sound, latency } => Sound::Latency {
+                sound: channel(sound),
+                latency,
+            },
+            Sound::Probe { sound, probe } => Sound::Probe {
+                sound: channel(sound),
+                probe,
+            },
+            Sound::Frozen {
+                sound,
+                frozen,
+                channel: None,
+                offset,
+            } if sound.channels() == 2 => Sound::Frozen {
+                sound,
+                frozen,
+                channel: Some(index),
+                offset,
+            },
+            Sound::Sync {
+                frequency,
+                phase,
+                slave,
+            } => Sound::Sync {
+                frequency,
+                phase,
+                slave: channel(slave),
+            },
+            Sound::Fm {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+                offset,
+            } => Sound::Fm {
+                waveform,
+                frequency: channel(frequency),
+                phase,
+                bandlimit,
+                offset,
+            },
+            Sound::Clamp {
+                sound,
+                from,
+                to,
+                before,
+                after,
+                offset,
+            } => Sound::Clamp {
+                sound: channel(sound),
+                from,
+                to,
+                before,
+                after,
+                offset,
+            },
+            Sound::Multisample {
+                layers,
+                velocity,
+                round,
+            } => Sound::Multisample {
+                layers: map_layers(&layers, |sound| sound.channel(index)),
+                velocity,
+                round,
+            },
+            Sound::Seq { sounds, offset } => Sound::Seq {
+                sounds: Rc::new(
+                    sounds
+                        .iter()
+                        .map(|(start, sound)| (*start, sound.clone().channel(index)))
+                        .collect(),
+                ),
+                offset,
+            },
+            other => other,
+        }
+    }
+    /// 先読みなどによる処理の遅延（秒）．並列の経路の遅延の最大値
+    pub fn latency(&self) -> f64 {
+        match self {
+            Sound::Latency { sound, latency } => sound.latency() + latency,
+            Sound::Minus(sound) | Sound::Reciprocal(sound) => sound.latency(),
+            Sound::Add(left, right)
+            | Sound::Sub(left, right)
+            | Sound::Mul(left, right)
+            | Sound::Div(left, right)
+            | Sound::Pow(left, right)
+            | Sound::Rem(left, right)
+            | Sound::Stereo(left, right)
+            | Sound::ModulatedFilter {
+                sound: left,
+                cutoff: right,
+                ..
+            } => left.latency().max(right.latency()),
+            Sound::Apply(_, _, sounds) => sounds
+                .iter()
+                .map(|(_, sound)| sound.latency())
+                .fold(0., f64::max),
+            Sound::Steps { sound, .. }
+            | Sound::Filter { sound, .. }
+            | Sound::Effect { sound, .. }
+            | Sound::Dynamics { sound, .. }
+            | Sound::Probe { sound, .. }
+            | Sound::Frozen { sound, .. }
+            | Sound::Clamp { sound, .. }
+            | Sound::Sync { slave: sound, .. }
+            | Sound::Fm {
+                frequency: sound, ..
+            } => sound.latency(),
+            Sound::PanLaw { position, .. } => position.latency(),
+            _ => 0.,
+        }
+    }
+    /// 遅延が `latency` 秒になるように遅らせる
+    fn delay_to(self, latency: f64) -> Sound {
+        let difference = latency - self.latency();
+        if difference > 0. {
+            self.shift(-difference)
+        } else {
+            self
+        }
+    }
+    /// フィルタなど，前の標本に依存する処理を含むか
+    fn has_state(&self) -> bool {
+        match self {
+            Sound::Filter { .. }
+            | Sound::ModulatedFilter { .. }
+            | Sound::Effect { .. }
+            | Sound::Dynamics { .. } => true,
+            Sound::Latency { sound, .. }
+            | Sound::Probe { sound, .. }
+            | Sound::Clamp { sound, .. }
+            | Sound::Sync { slave: sound, .. } => sound.has_state(),
+            Sound::Minus(sound) | Sound::Reciprocal(sound) => sound.has_state(),
+            Sound::Add(left, right)
+            | Sound::Sub(left, right)
+            | Sound::Mul(left, right)
+            | Sound::Div(left, right)
+            | Sound::Pow(left, right)
+            | Sound::Rem(left, right)
+            | Sound::Stereo(left, right) => left.has_state() || right.has_state(),
+            Sound::Apply(_, _, sounds) => sounds.iter().any(|(_, sound)| sound.has_state()),
+            Sound::Steps { sound, .. } => sound.has_state(),
+            Sound::PanLaw { position, .. } => position.has_state(),
+            _ => false,
+        }
+    }
+    /// 音源が止まった後に鳴り続ける余韻の長さ（秒）の見積もり
+    pub fn tail(&self) -> f64 {
+        match self {
+            Sound::Filter { sound, filter } => sound.tail() + filter.ring_time(),
+            Sound::ModulatedFilter {
+                sound,
+                cutoff,
+                filter,
+            } => sound.tail().max(cutoff.tail()) + filter.ring_time(),
+            Sound::Effect { sound, effect } => sound.tail() + effect.ring_time(),
+            Sound::Dynamics {
+                sound,
+                sidechain,
+                dynamics,
+            } => {
+                let release = match dynamics {
+                    Dynamics::Follow { release, .. } => 1000f64.ln() * release,
+                    _ => 0.,
+                };
+                sound.tail().max(sidechain.tail()) + release
+            }
+            Sound::Minus(sound) | Sound::Reciprocal(sound) => sound.tail(),
+            Sound::Add(left, right)
+            | Sound::Sub(left, right)
+            | Sound::Mul(left, right)
+            | Sound::Div(left, right)
+            | Sound::Pow(left, right)
+            | Sound::Rem(left, right)
+            | Sound::Stereo(left, right) => left.tail().max(right.tail()),
+            Sound::Apply(_, _, sounds) => sounds
+                .iter()
+                .map(|(_, sound)| sound.tail())
+                .fold(0., f64::max),
+            Sound::Steps { sound, .. }
+            | Sound::Latency { sound, .. }
+            | Sound::Probe { sound, .. }
+            | Sound::Clamp { sound, .. }
+            | Sound::Sync { slave: sound, .. } => sound.tail(),
+            Sound::PanLaw { position, .. } => position.tail(),
+            _ => 0.,
+        }
+    }
+    /// `end` 秒で音源を止め，フィルタなどの余韻だけを残す．
+    /// 積や商などで状態をもたない側は変調とみなしてそのまま残す
+    pub fn cut(self, end: f64) -> Sound {
+        if !self.has_state() {
+            return Sound::Mul(self.into(), Sound::End(-end).into());
+        }
+        let cut = |sound: Box<Sound>| Box::new(sound.cut(end));
+        let modulated = |sound: Box<Sound>| {
+            if sound.has_state() {
+                cut(sound)
+            } else {
+                sound
+            }
+        };
+        match self {
+            Sound::Minus(sound) => Sound::Minus(cut(sound)),
+            Sound::Reciprocal(sound) => Sound::Reciprocal(modulated(sound)),
+            Sound::Add(left, right) => Sound::Add(cut(left), cut(right)),
+            Sound::Sub(left, right) => Sound::Sub(cut(left), cut(right)),
+            Sound::Stereo(left, right) => Sound::Stereo(cut(left), cut(right)),
+            Sound::Mul(left, right) => Sound::Mul(modulated(left), modulated(right)),
+            Sound::Div(left, right) => Sound::Div(modulated(left), modulated(right)),
+            Sound::Pow(left, right) => Sound::Pow(modulated(left), modulated(right)),
+            Sound::Rem(left, right) => Sound::Rem(modulated(left), modulated(right)),
+            Sound::Apply(function, arguments, sounds) => Sound::Apply(
+                function,
+                arguments,
+                sounds
+                    .into_iter()
+                    .map(|(rc, sound)| (rc, *modulated(sound.into())))
+                    .collect(),
+            ),
+            Sound::Filter { sound, filter } => Sound::Filter {
+                sound: cut(sound),
+                filter,
+            },
+            Sound::ModulatedFilter {
+                sound,
+                cutoff,
+                filter,
+            } => Sound::ModulatedFilter {
+                sound: cut(sound),
+                cutoff: modulated(cutoff),
+                filter,
+            },
+            Sound::Effect { sound, effect } => Sound::Effect {
+                sound: cut(sound),
+                effect,
+            },
+            Sound::Latency { sound, latency } => Sound::Latency {
+                sound: cut(sound),
+                latency,
+            },
+            Sound::Probe { sound, probe } => Sound::Probe {
+                sound: cut(sound),
+                probe,
+            },
+            Sound::Dynamics {
+                sound,
+                sidechain,
+                dynamics,
+            } => Sound::Dynamics {
+                sound: cut(sound),
+                sidechain: cut(sidechain),
+                dynamics,
+            },
+            // パターンは止められないので，音ごと止める
+            other => Sound::Mul(other.into(), Sound::End(-end).into()),
+        }
+    }
+    /// 先頭から `seconds` 秒ぶんの標本列
+    pub fn render(self, seconds: f64, samplerate: f64) -> Vec<f64> {
+        let mut iter = self.iter(samplerate);
+        (0..(seconds * samplerate) as i64)
+            .map(|_| iter.next())
+            .collect()
+    }
     pub fn shift(self, t: f64) -> Self {
         match self {
             Sound::Const(value) => Sound::Const(value),
@@ -66,9 +748,47 @@ impl Sound {
                 slope,
                 intercept: slope * t + intercept,
             },
+            Sound::Oscillator {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+            } => Sound::Oscillator {
+                waveform,
+                frequency,
+                phase: (phase + frequency * t).rem_euclid(1.),
+                bandlimit,
+            },
             Sound::Begin(time) => Sound::Begin(time + t),
             Sound::End(time) => Sound::End(time + t),
             Sound::Rand => Sound::Rand,
+            Sound::Noise { seed, offset } => Sound::Noise {
+                seed,
+                offset: offset + t,
+            },
+            // 鳴らし直すときの `slave` はそのまま
+            Sound::Sync {
+                frequency,
+                phase,
+                slave,
+            } => Sound::Sync {
+                frequency,
+                phase: (phase + frequency * t).rem_euclid(1.),
+                slave,
+            },
+            Sound::Fm {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+                offset,
+            } => Sound::Fm {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+                offset: offset + t,
+            },
             Sound::Minus(sound) => Sound::Minus(sound.shift(t).into()),
             Sound::Reciprocal(sound) => Sound::Reciprocal(sound.shift(t).into()),
             Sound::Add(left, right) => Sound::Add(left.shift(t).into(), right.shift(t).into()),
@@ -85,6 +805,110 @@ impl Sound {
                     .map(|(rc, sound)| (rc, sound.shift(t)))
                     .collect(),
             ),
+            Sound::Steps {
+                sound,
+                steps,
+                offset,
+            } => Sound::Steps {
+                sound,
+                steps,
+                offset: offset + t,
+            },
+            Sound::Samples {
+                samples,
+                samplerate,
+                offset,
+                looping,
+            } => Sound::Samples {
+                samples,
+                samplerate,
+                offset: offset + t,
+                looping,
+            },
+            Sound::Clamp {
+                sound,
+                from,
+                to,
+                before,
+                after,
+                offset,
+            } => Sound::Clamp {
+                sound: sound.shift(t).into(),
+                from,
+                to,
+                before,
+                after,
+                offset: offset + t,
+            },
+            Sound::Adsr { envelope, offset } => Sound::Adsr {
+                envelope,
+                offset: offset + t,
+            },
+            Sound::Stereo(left, right) => {
+                Sound::Stereo(left.shift(t).into(), right.shift(t).into())
+            }
+            Sound::PanLaw { position, right } => Sound::PanLaw {
+                position: position.shift(t).into(),
+                right,
+            },
+            Sound::Dynamics {
+                sound,
+                sidechain,
+                dynamics,
+            } => Sound::Dynamics {
+                sound: sound.shift(t).into(),
+                sidechain: sidechain.shift(t).into(),
+                dynamics,
+            },
+            Sound::Filter { sound, filter } => Sound::Filter {
+                sound: sound.shift(t).into(),
+                filter,
+            },
+            Sound::ModulatedFilter {
+                sound,
+                cutoff,
+                filter,
+            } => Sound::ModulatedFilter {
+                sound: sound.shift(t).into(),
+                cutoff: cutoff.shift(t).into(),
+                filter,
+            },
+            Sound::Effect { sound, effect } => Sound::Effect {
+                sound: sound.shift(t).into(),
+                effect,
+            },
+            Sound::Latency { sound, latency } => Sound::Latency {
+                sound: sound.shift(t).into(),
+                latency,
+            },
+            Sound::Probe { sound, probe } => Sound::Probe {
+                sound: sound.shift(t).into(),
+                probe,
+            },
+            Sound::Frozen {
+                sound,
+                frozen,
+                channel,
+                offset,
+            } => Sound::Frozen {
+                sound,
+                frozen,
+                channel,
+                offset: offset + t,
+            },
+            Sound::Multisample {
+                layers,
+                velocity,
+                round,
+            } => Sound::Multisample {
+                layers: map_layers(&layers, |sound| sound.shift(t)),
+                velocity,
+                round,
+            },
+            Sound::Seq { sounds, offset } => Sound::Seq {
+                sounds,
+                offset: offset + t,
+            },
         }
     }
     pub fn iter(self, samplerate: f64) -> SoundIter {
@@ -107,38 +931,339 @@ impl Sound {
             Sound::Begin(time) => SoundIter::Begin((time * samplerate) as i64),
             Sound::End(time) => SoundIter::End((time * samplerate) as i64),
             Sound::Rand => SoundIter::Rand(rand::thread_rng()),
+            Sound::Oscillator {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+            } => SoundIter::Oscillator {
+                waveform,
+                phase,
+                increment: frequency / samplerate,
+                bandlimit,
+            },
+            Sound::Noise { seed, offset } => SoundIter::Noise {
+                seed,
+                counter: (offset * samplerate).round() as i64,
+            },
+            Sound::Sync {
+                frequency,
+                phase,
+                slave,
+            } => SoundIter::Sync {
+                iter: since_reset(&slave, phase, frequency, samplerate).into(),
+                slave,
+                frequency,
+                phase,
+                samplerate,
+            },
+            Sound::Fm {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+                offset,
+            } => {
+                // 時刻 0 より前から鳴らすときは，先にその間の位相の進みを求めておく
+                let start = (offset * samplerate).round() as i64;
+                let anchor = start.min(0);
+                let from = |n: i64| {
+                    frequency
+                        .clone()
+                        .shift(n as f64 / samplerate)
+                        .iter(samplerate)
+                };
+                let mut phase = phase;
+                if anchor < 0 {
+                    let mut iter = from(anchor);
+                    phase -= (anchor..0).map(|_| iter.next() / samplerate).sum::<f64>();
+                }
+                let mut iter = from(anchor);
+                for _ in anchor..start {
+                    phase += iter.next() / samplerate;
+                }
+                SoundIter::Fm {
+                    waveform,
+                    frequency: iter.into(),
+                    phase: phase.rem_euclid(1.),
+                    bandlimit,
+                    samplerate,
+                }
+            }
             Sound::Minus(sound) => SoundIter::Minus(sound.iter(samplerate).into()),
             Sound::Reciprocal(sound) => SoundIter::Reciprocal(sound.iter(samplerate).into()),
             Sound::Add(left, right) => {
-                SoundIter::Add(left.iter(samplerate).into(), right.iter(samplerate).into())
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Add(left, right)
             }
             Sound::Sub(left, right) => {
-                SoundIter::Sub(left.iter(samplerate).into(), right.iter(samplerate).into())
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Sub(left, right)
             }
             Sound::Mul(left, right) => {
-                SoundIter::Mul(left.iter(samplerate).into(), right.iter(samplerate).into())
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Mul(left, right)
             }
             Sound::Div(left, right) => {
-                SoundIter::Div(left.iter(samplerate).into(), right.iter(samplerate).into())
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Div(left, right)
             }
             Sound::Pow(left, right) => {
-                SoundIter::Pow(left.iter(samplerate).into(), right.iter(samplerate).into())
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Pow(left, right)
             }
             Sound::Rem(left, right) => {
-                SoundIter::Rem(left.iter(samplerate).into(), right.iter(samplerate).into())
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Rem(left, right)
             }
-            Sound::Apply(function, arguments, sounds) => SoundIter::Apply(
-                function,
-                arguments,
-                sounds
-                    .into_iter()
-                    .map(|(rc, sound)| (rc, sound.iter(samplerate)))
-                    .collect(),
-            ),
+            Sound::Apply(function, arguments, sounds) => {
+                let latency = sounds
+                    .iter()
+                    .map(|(_, sound)| sound.latency())
+                    .fold(0., f64::max);
+                SoundIter::Apply(
+                    function,
+                    arguments,
+                    sounds
+                        .into_iter()
+                        .map(|(rc, sound)| (rc, sound.delay_to(latency).iter(samplerate)))
+                        .collect(),
+                )
+            }
+            Sound::Steps {
+                sound,
+                steps,
+                offset,
+            } => {
+                let counter = (offset * samplerate).round() as i64;
+                let length = (steps.step * samplerate).max(1.);
+                SoundIter::Steps {
+                    sound,
+                    next: (counter as f64 / length).floor().max(0.) as i64,
+                    steps,
+                    samplerate,
+                    counter,
+                    pending: VecDeque::new(),
+                    voice: None,
+                }
+            }
+            Sound::Samples {
+                samples,
+                samplerate: original,
+                offset,
+                looping,
+            } => SoundIter::Samples {
+                samples,
+                position: offset * original,
+                step: original / samplerate,
+                looping: looping.map(|(start, end)| {
+                    let (start, end) = (start * original, end * original);
+                    // ループの前の部分と重ねるので，その長さとループの半分を超えない
+                    let crossfade = (LOOP_CROSSFADE * original)
+                        .min(start)
+                        .min((end - start) / 2.);
+                    (start, end, crossfade)
+                }),
+            },
+            Sound::Clamp {
+                sound,
+                from,
+                to,
+                before,
+                after,
+                offset,
+            } => SoundIter::Clamp {
+                sound: sound.iter(samplerate).into(),
+                from,
+                to,
+                before,
+                after,
+                samplerate,
+                counter: (offset * samplerate).round() as i64,
+            },
+            Sound::Adsr { envelope, offset } => SoundIter::Adsr {
+                envelope,
+                samplerate,
+                counter: (offset * samplerate).round() as i64,
+            },
+            Sound::Multisample {
+                layers,
+                velocity,
+                round,
+            } => match played(&layers, velocity, round) {
+                Some(sound) => sound.clone().iter(samplerate),
+                None => SoundIter::Const(0.),
+            },
+            Sound::Seq { sounds, offset } => SoundIter::Seq {
+                sounds,
+                samplerate,
+                counter: (offset * samplerate).round() as i64,
+                index: 0,
+                current: None,
+            },
+            Sound::Stereo(left, right) => {
+                let (left, right) = aligned(*left, *right, samplerate);
+                SoundIter::Stereo(left, right)
+            }
+            Sound::PanLaw { position, right } => {
+                SoundIter::PanLaw(position.iter(samplerate).into(), right)
+            }
+            Sound::Dynamics {
+                sound,
+                sidechain,
+                dynamics,
+            } => SoundIter::Dynamics {
+                sound: sound.iter(samplerate).into(),
+                sidechain: sidechain.iter(samplerate).into(),
+                state: dynamics.state(samplerate),
+            },
+            Sound::Filter { sound, filter } => {
+                SoundIter::Filter(sound.iter(samplerate).into(), filter.biquad(samplerate))
+            }
+            Sound::ModulatedFilter {
+                sound,
+                cutoff,
+                filter,
+            } => {
+                let (sound, cutoff) = aligned(*sound, *cutoff, samplerate);
+                SoundIter::ModulatedFilter {
+                    sound,
+                    cutoff,
+                    biquad: filter.biquad(samplerate),
+                    filter,
+                    samplerate,
+                }
+            }
+            Sound::Effect { sound, effect } => {
+                SoundIter::Effect(sound.iter(samplerate).into(), effect.state(samplerate))
+            }
+            Sound::Latency { sound, .. } => sound.iter(samplerate),
+            Sound::Probe { sound, probe } => SoundIter::Probe {
+                block: probe.start(samplerate),
+                sound: sound.iter(samplerate).into(),
+                probe,
+                counter: 0,
+            },
+            Sound::Frozen {
+                sound,
+                frozen,
+                channel,
+                offset,
+            } => {
+                let cached = match &*frozen.borrow() {
+                    Some((rate, rendered)) if *rate == samplerate => Some(rendered.clone()),
+                    _ => None,
+                };
+                let rendered = cached.unwrap_or_else(|| {
+                    let rendered = buffer::render(&sound, samplerate);
+                    *frozen.borrow_mut() = Some((samplerate, rendered.clone()));
+                    rendered
+                });
+                let rendered = match channel {
+                    Some(index) => rendered.channel(index),
+                    None => rendered,
+                };
+                rendered.shift(offset).iter(samplerate)
+            }
+        }
+    }
+}
+
+/// 位相 `phase` のオシレーター（周波数 `frequency` ）で同期した `slave` ．
+/// 周期の始まりから標本の間の端数の時間だけ進めておく
+fn since_reset(slave: &Sound, phase: f64, frequency: f64, samplerate: f64) -> SoundIter {
+    let elapsed = if frequency > 0. {
+        phase / frequency
+    } else {
+        0.
+    };
+    slave.clone().shift(elapsed).iter(samplerate)
+}
+
+/// `multisample` の層の音をすべて `f` で変える
+fn map_layers(
+    layers: &[(f64, Vec<Sound>)],
+    mut f: impl FnMut(Sound) -> Sound,
+) -> Rc<Vec<(f64, Vec<Sound>)>> {
+    Rc::new(
+        layers
+            .iter()
+            .map(|(lower, sounds)| (*lower, sounds.iter().cloned().map(&mut f).collect()))
+            .collect(),
+    )
+}
+
+/// `multisample` で実際に鳴らす音．音量 `velocity` 以下の下限のうち最も高い層（なければ最初の層）の
+/// `round` 番目（余り）
+pub fn played(layers: &[(f64, Vec<Sound>)], velocity: f64, round: usize) -> Option<&Sound> {
+    let (_, sounds) = layers
+        .iter()
+        .rev()
+        .find(|(lower, _)| velocity >= *lower)
+        .or_else(|| layers.first())?;
+    sounds.get(round.checked_rem(sounds.len())?)
+}
+
+/// `seq` の `counter` 番目の標本．`current` は鳴らしている音とそれが終わる標本番号
+fn sequence_next(
+    sounds: &[(f64, Sound)],
+    samplerate: f64,
+    counter: i64,
+    index: &mut usize,
+    current: &mut Option<(Box<SoundIter>, i64)>,
+) -> f64 {
+    loop {
+        if let Some((iter, end)) = current {
+            if counter < *end {
+                return iter.next();
+            }
+            *current = None;
+            *index += 1;
         }
+        let (start, sound) = match sounds.get(*index) {
+            Some(entry) => entry,
+            None => return 0.,
+        };
+        let begin = (start * samplerate).round() as i64;
+        if counter < begin {
+            return 0.;
+        }
+        let end = ((start + sound.duration().unwrap_or(0.)) * samplerate).round() as i64;
+        if counter >= end {
+            *index += 1;
+            continue;
+        }
+        let elapsed = (counter - begin) as f64 / samplerate;
+        *current = Some((sound.clone().shift(elapsed).iter(samplerate).into(), end));
+    }
+}
+
+/// `sounds` を順に，前の音が終わったらすぐ次の音を鳴らす．どの音も長さが決まっていること
+pub fn seq(sounds: impl IntoIterator<Item = Sound>) -> Sound {
+    let mut start = 0.;
+    let sounds = sounds
+        .into_iter()
+        .map(|sound| {
+            let entry = (start, sound);
+            start += entry.1.duration().unwrap_or(0.);
+            entry
+        })
+        .collect();
+    Sound::Seq {
+        sounds: Rc::new(sounds),
+        offset: 0.,
     }
 }
 
+/// 遅延の小さい方を遅らせて揃えた 2 つの音の `SoundIter`
+fn aligned(left: Sound, right: Sound, samplerate: f64) -> (Box<SoundIter>, Box<SoundIter>) {
+    let latency = left.latency().max(right.latency());
+    (
+        left.delay_to(latency).iter(samplerate).into(),
+        right.delay_to(latency).iter(samplerate).into(),
+    )
+}
+
 pub enum SoundIter {
     Const(f64),
     Linear {
@@ -155,9 +1280,34 @@ pub enum SoundIter {
         next: Complex64,
         ratio: Complex64,
     },
+    Oscillator {
+        waveform: Waveform,
+        phase: f64,
+        /// 1 標本で進む位相
+        increment: f64,
+        bandlimit: bool,
+    },
     Begin(i64),
     End(i64),
     Rand(ThreadRng),
+    Noise {
+        seed: u64,
+        counter: i64,
+    },
+    Sync {
+        slave: Box<Sound>,
+        iter: Box<SoundIter>,
+        frequency: f64,
+        phase: f64,
+        samplerate: f64,
+    },
+    Fm {
+        waveform: Option<Waveform>,
+        frequency: Box<SoundIter>,
+        phase: f64,
+        bandlimit: bool,
+        samplerate: f64,
+    },
     Minus(Box<SoundIter>),
     Reciprocal(Box<SoundIter>),
     Add(Box<SoundIter>, Box<SoundIter>),
@@ -171,6 +1321,74 @@ pub enum SoundIter {
         Vec<Argument>,
         Vec<(RcCell<f64>, SoundIter)>,
     ),
+    Steps {
+        sound: Box<Sound>,
+        steps: Rc<Steps>,
+        samplerate: f64,
+        /// パターンの先頭を 0 としたサンプル番号
+        counter: i64,
+        /// 次に発音を決めるステップ
+        next: i64,
+        /// 発音が決まったがまだ始まっていない音（開始サンプル，音量，ステップ番号）
+        pending: VecDeque<(i64, f64, i64)>,
+        /// 今鳴っている音
+        voice: Option<(f64, Box<SoundIter>)>,
+    },
+    Samples {
+        samples: Rc<Vec<f64>>,
+        /// 元の標本列での位置
+        position: f64,
+        /// 1 標本ごとに進む量
+        step: f64,
+        /// ループの始まりと終わり，クロスフェードの長さ（元の標本列での位置）
+        looping: Option<(f64, f64, f64)>,
+    },
+    Clamp {
+        sound: Box<SoundIter>,
+        from: f64,
+        to: f64,
+        before: f64,
+        after: f64,
+        samplerate: f64,
+        counter: i64,
+    },
+    Adsr {
+        envelope: Adsr,
+        samplerate: f64,
+        /// 鳴り始めを 0 としたサンプル番号
+        counter: i64,
+    },
+    Stereo(Box<SoundIter>, Box<SoundIter>),
+    PanLaw(Box<SoundIter>, bool),
+    Dynamics {
+        sound: Box<SoundIter>,
+        sidechain: Box<SoundIter>,
+        state: dynamics::State,
+    },
+    Filter(Box<SoundIter>, Biquad),
+    /// 標本ごとに係数を計算し直す
+    ModulatedFilter {
+        sound: Box<SoundIter>,
+        cutoff: Box<SoundIter>,
+        filter: Filter,
+        biquad: Biquad,
+        samplerate: f64,
+    },
+    Effect(Box<SoundIter>, effects::State),
+    Probe {
+        sound: Box<SoundIter>,
+        probe: Rc<Probe>,
+        /// 1 ブロックの標本数
+        block: usize,
+        counter: usize,
+    },
+    Seq {
+        sounds: Rc<Vec<(f64, Sound)>>,
+        samplerate: f64,
+        counter: i64,
+        index: usize,
+        current: Option<(Box<SoundIter>, i64)>,
+    },
 }
 
 impl SoundIter {
@@ -217,6 +1435,54 @@ impl SoundIter {
                 }
             }
             SoundIter::Rand(rng) => rng.gen(),
+            SoundIter::Oscillator {
+                waveform,
+                phase,
+                increment,
+                bandlimit,
+            } => {
+                let dt = if *bandlimit { increment.abs() } else { 0. };
+                let value = waveform.value(*phase, dt);
+                *phase = (*phase + *increment).rem_euclid(1.);
+                value
+            }
+            SoundIter::Noise { seed, counter } => {
+                let value = oscillator::noise(*seed, *counter);
+                *counter += 1;
+                value
+            }
+            SoundIter::Sync {
+                slave,
+                iter,
+                frequency,
+                phase,
+                samplerate,
+            } => {
+                let value = iter.next();
+                let next = *phase + *frequency / *samplerate;
+                *phase = next.rem_euclid(1.);
+                if next >= 1. {
+                    **iter = since_reset(slave, *phase, *frequency, *samplerate);
+                }
+                value
+            }
+            SoundIter::Fm {
+                waveform,
+                frequency,
+                phase,
+                bandlimit,
+                samplerate,
+            } => {
+                let increment = frequency.next() / *samplerate;
+                let value = match waveform {
+                    None => (TAU * *phase).sin(),
+                    Some(waveform) => {
+                        waveform.value(*phase, if *bandlimit { increment.abs() } else { 0. })
+                    }
+                };
+                *phase = (*phase + increment).rem_euclid(1.);
+                value
+            }
             SoundIter::Minus(iter) => -iter.next(),
             SoundIter::Reciprocal(iter) => iter.next().recip(),
             SoundIter::Add(left, right) => left.next() + right.next(),
@@ -232,7 +1498,337 @@ impl SoundIter {
                 }
                 fnc.evaluate()
             }
+            SoundIter::Steps {
+                sound,
+                steps,
+                samplerate,
+                counter,
+                next,
+                pending,
+                voice,
+            } => {
+                let length = (steps.step * *samplerate).max(1.);
+                // 時刻が前にずれる音のために，ずれの最大値だけ先読みする
+                let lookahead = (steps.jitter.min(steps.step / 2.) * *samplerate).ceil() as i64;
+                while ((*next as f64 * length).round() as i64) - lookahead <= *counter {
+                    if let Some((jitter, velocity)) = steps.event(*next) {
+                        let start = ((*next as f64 * length) + jitter * *samplerate).round();
+                        pending.push_back((start as i64, velocity, *next));
+                    }
+                    *next += 1;
+                }
+                while let Some(&(start, velocity, index)) = pending.front() {
+                    if start > *counter {
+                        break;
+                    }
+                    pending.pop_front();
+                    let elapsed = (*counter - start) as f64 / *samplerate;
+                    let iter = sound
+                        .as_ref()
+                        .clone()
+                        .trigger(velocity, index as usize)
+                        .shift(elapsed)
+                        .iter(*samplerate);
+                    *voice = Some((velocity, iter.into()));
+                }
+                *counter += 1;
+                match voice {
+                    Some((velocity, iter)) => *velocity * iter.next(),
+                    None => 0.,
+                }
+            }
+            SoundIter::Samples {
+                samples,
+                position,
+                step,
+                looping,
+            } => {
+                let at = |i: f64| {
+                    if i < 0. {
+                        0.
+                    } else {
+                        samples.get(i as usize).copied().unwrap_or(0.)
+                    }
+                };
+                // 線形補間
+                let interpolated = |position: f64| {
+                    let index = position.floor();
+                    let fraction = position - index;
+                    at(index) * (1. - fraction) + at(index + 1.) * fraction
+                };
+                if let Some((start, end, _)) = *looping {
+                    while *position >= end {
+                        *position -= end - start;
+                    }
+                }
+                let value = match *looping {
+                    // ループの終わりの手前では，ループの始まりの手前の部分へ移っていく
+                    Some((start, end, crossfade))
+                        if crossfade > 0. && *position >= end - crossfade =>
+                    {
+                        let gain = (*position - (end - crossfade)) / crossfade;
+                        interpolated(*position) * (1. - gain)
+                            + interpolated(*position - (end - start)) * gain
+                    }
+                    _ => interpolated(*position),
+                };
+                *position += *step;
+                value
+            }
+            SoundIter::Clamp {
+                sound,
+                from,
+                to,
+                before,
+                after,
+                samplerate,
+                counter,
+            } => {
+                let value = sound.next();
+                let t = *counter as f64 / *samplerate;
+                *counter += 1;
+                if t < *from {
+                    *before
+                } else if t >= *to {
+                    *after
+                } else {
+                    value
+                }
+            }
+            SoundIter::Adsr {
+                envelope,
+                samplerate,
+                counter,
+            } => {
+                let value = envelope.value(*counter as f64 / *samplerate);
+                *counter += 1;
+                value
+            }
+            SoundIter::Stereo(left, right) => (left.next() + right.next()) / 2.,
+            SoundIter::PanLaw(position, right) => {
+                let angle = (position.next().clamp(-1., 1.) + 1.) * std::f64::consts::FRAC_PI_4;
+                if *right {
+                    angle.sin()
+                } else {
+                    angle.cos()
+                }
+            }
+            SoundIter::Dynamics {
+                sound,
+                sidechain,
+                state,
+            } => {
+                let sidechain = sidechain.next();
+                state.process(sound.next(), sidechain)
+            }
+            SoundIter::Filter(sound, biquad) => biquad.process(sound.next()),
+            SoundIter::ModulatedFilter {
+                sound,
+                cutoff,
+                filter,
+                biquad,
+                samplerate,
+            } => {
+                let retuned = filter
+                    .with_frequency(cutoff.next().max(0.))
+                    .biquad(*samplerate);
+                biquad.retune(&retuned);
+                biquad.process(sound.next())
+            }
+            SoundIter::Effect(sound, state) => state.process(sound.next()),
+            SoundIter::Probe {
+                sound,
+                probe,
+                block,
+                counter,
+            } => {
+                let x = sound.next();
+                probe.record(*counter / *block, x);
+                *counter += 1;
+                x
+            }
+            SoundIter::Seq {
+                sounds,
+                samplerate,
+                counter,
+                index,
+                current,
+            } => {
+                *counter += 1;
+                sequence_next(sounds, *samplerate, *counter - 1, index, current)
+            }
         }
         .clamp(f64::MIN, f64::MAX)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(chance: f64, jitter: f64) -> Steps {
+        Steps {
+            pattern: vec![1., 0., 0.5, 0.],
+            variation: Some((2, vec![1., 1.])),
+            step: 0.25,
+            chance,
+            jitter,
+            velocity_jitter: 0.,
+            seed: 0,
+        }
+    }
+
+    #[test]
+    fn steps_pattern_and_variation() {
+        let steps = steps(1., 0.);
+        let events: Vec<_> = (0..8).map(|i| steps.event(i).map(|e| e.1)).collect();
+        assert_eq!(
+            events,
+            [
+                Some(1.),
+                None,
+                Some(0.5),
+                None,
+                Some(1.),
+                Some(1.),
+                None,
+                None
+            ]
+        );
+        assert_eq!(steps.event(-1), None);
+    }
+
+    #[test]
+    fn stereo_channels() {
+        let stereo = Sound::Mul(
+            Sound::Stereo(Sound::Const(1.).into(), Sound::Const(3.).into()).into(),
+            Sound::Const(2.).into(),
+        );
+        assert_eq!(stereo.channels(), 2);
+        assert_eq!(stereo.clone().channel(0).iter(1.).next(), 2.);
+        assert_eq!(stereo.clone().channel(1).iter(1.).next(), 6.);
+        // モノラルとしては平均
+        assert_eq!(stereo.iter(1.).next(), 4.);
+        assert_eq!(Sound::Const(1.).channels(), 1);
+    }
+
+    #[test]
+    fn crossfaded_loop() {
+        // 0 から 1 へ上がる 1 秒の標本列の後半を繰り返す
+        let ramp = Sound::Samples {
+            samples: (0..1000)
+                .map(|i| i as f64 / 1000.)
+                .collect::<Vec<_>>()
+                .into(),
+            samplerate: 1000.,
+            offset: 0.,
+            looping: Some((0.5, 1.)),
+        };
+        assert_eq!(ramp.duration(), None);
+        let samples = ramp.render(3., 1000.);
+        assert_eq!(samples[700], samples[1200]);
+        assert_eq!(samples[700], samples[2700]);
+        // 継ぎ目で 0.5 跳ばずに，クロスフェードの間に少しずつ下がる
+        let jump = samples
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0., f64::max);
+        assert!(jump < 0.06, "{}", jump);
+    }
+
+    #[test]
+    fn latency_compensation() {
+        let impulse = || Sound::Samples {
+            samples: vec![1.].into(),
+            samplerate: 100.,
+            offset: 0.,
+            looping: None,
+        };
+        // 2 標本遅れる処理と並べると，遅延のない方も 2 標本遅れる
+        let latent = Sound::Latency {
+            sound: impulse().shift(-0.02).into(),
+            latency: 0.02,
+        };
+        let mixed = Sound::Add(latent.into(), impulse().into());
+        assert_eq!(mixed.latency(), 0.02);
+        assert_eq!(mixed.render(0.04, 100.), vec![0., 0., 2., 0.]);
+    }
+
+    #[test]
+    fn frozen() {
+        let ramp = |scale: f64| Sound::Samples {
+            samples: vec![scale, 2. * scale, 3. * scale].into(),
+            samplerate: 10.,
+            offset: 0.,
+            looping: None,
+        };
+        let frozen = Rc::new(RefCell::new(None));
+        let sound = Sound::Frozen {
+            sound: Sound::Stereo(ramp(1.).into(), ramp(-1.).into()).into(),
+            frozen: frozen.clone(),
+            channel: None,
+            offset: 0.,
+        };
+        assert_eq!(sound.duration(), Some(0.3));
+        assert_eq!(sound.clone().channel(1).render(0.3, 10.), [-1., -2., -3.]);
+        // 描いたものは複製と共有する
+        assert!(matches!(&*frozen.borrow(), Some((rate, _)) if *rate == 10.));
+        assert_eq!(
+            sound.clone().shift(0.1).channel(0).render(0.3, 10.),
+            [2., 3., 0.]
+        );
+        sound.channel(0).render(0.1, 20.);
+        assert!(matches!(&*frozen.borrow(), Some((rate, _)) if *rate == 20.));
+    }
+
+    #[test]
+    fn cut_keeps_tail() {
+        let filtered = Sound::Mul(
+            Sound::Filter {
+                sound: Sound::Const(1.).into(),
+                filter: Filter::Lowpass {
+                    frequency: 10.,
+                    q: 0.5,
+                },
+            }
+            .into(),
+            Sound::Const(2.).into(),
+        );
+        assert!(filtered.tail() > 0.);
+        let samples = Sound::Add(filtered.into(), Sound::Const(1.).into())
+            .cut(1.)
+            .render(1.1, 100.);
+        // 止めた後は定数の音源が消え，フィルタの余韻だけが残る
+        assert!(samples[99] > 2.5);
+        assert!(samples[101] > 0. && samples[101] < 2.);
+        assert_eq!(Sound::Const(1.).tail(), 0.);
+    }
+
+    #[test]
+    fn sequence() {
+        // 時刻 0 より前も鳴る音を，その音の長さの間だけ鳴らす
+        let note = |value: f64, length: f64| {
+            Sound::Mul(Sound::Const(value).into(), Sound::End(-length).into())
+        };
+        let melody = seq([note(1., 0.5), note(2., 0.25), note(3., 0.5)]);
+        assert_eq!(melody.duration(), Some(1.25));
+        let samples = melody.clone().render(1.5, 4.);
+        assert_eq!(samples, vec![1., 1., 2., 3., 3., 0.]);
+        assert_eq!(melody.clone().shift(0.5).render(1., 4.), samples[2..]);
+        assert_eq!(melody.shift(-0.25).render(0.5, 4.), vec![0., 1.]);
+        assert_eq!(seq(None).duration(), Some(0.));
+    }
+
+    #[test]
+    fn steps_chance_and_jitter() {
+        assert!((0..16).all(|i| steps(0., 0.).event(i).is_none()));
+        let steps = steps(1., 1.);
+        for i in 0..16 {
+            if let Some((jitter, _)) = steps.event(i) {
+                assert!(jitter.abs() <= 0.125);
+                // 同じステップは何度呼んでも同じ結果
+                assert_eq!(steps.event(i), steps.event(i));
+            }
+        }
+    }
+}
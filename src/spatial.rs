@@ -0,0 +1,142 @@
+//! 音の左右の配置（パン，簡単なバイノーラル）とチャンネルの入れ替え
+
+use crate::sound::Sound;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// 頭の半径（メートル）と音速（メートル毎秒）．両耳間時間差の計算に使う
+const HEAD_RADIUS: f64 = 0.0875;
+const SPEED_OF_SOUND: f64 = 343.;
+/// 真横のときに遠い側の耳で下がる音量（ dB ）
+const MAX_ILD: f64 = 6.;
+
+/// 定パワーのパン．`position` は -1 （左）から 1 （右）．
+/// ステレオの音は左右それぞれに係数をかける（中央で -3 dB ）
+pub fn pan(sound: Sound, position: Sound) -> Sound {
+    let gain = |right| Sound::PanLaw {
+        position: position.clone().into(),
+        right,
+    };
+    Sound::Stereo(
+        Sound::Mul(sound.clone().channel(0).into(), gain(false).into()).into(),
+        Sound::Mul(sound.channel(1).into(), gain(true).into()).into(),
+    )
+}
+
+/// 左チャンネルだけのモノラルの音
+pub fn left(sound: Sound) -> Sound {
+    sound.channel(0)
+}
+
+/// 右チャンネルだけのモノラルの音
+pub fn right(sound: Sound) -> Sound {
+    sound.channel(1)
+}
+
+/// 左右を入れ替える
+pub fn swap_channels(sound: Sound) -> Sound {
+    Sound::Stereo(sound.clone().channel(1).into(), sound.channel(0).into())
+}
+
+/// 左右を -3 dB ずつ下げて足したモノラルの音．中央にパンした音は元に戻る．
+/// モノラルの音はそのまま
+pub fn mono(sound: Sound) -> Sound {
+    if sound.channels() == 1 {
+        return sound;
+    }
+    Sound::Mul(
+        Sound::Add(sound.clone().channel(0).into(), sound.channel(1).into()).into(),
+        Sound::Const(FRAC_1_SQRT_2).into(),
+    )
+}
+
+/// 左右（ L/R ）をミッド・サイド（ (L+R)/√2 ， (L-R)/√2 ）にする．
+/// 同じ変換なので，もう一度かけると左右に戻る
+pub fn mid_side(sound: Sound) -> Sound {
+    let (left, right) = (sound.clone().channel(0), sound.channel(1));
+    let scale = |sound: Sound| Sound::Mul(sound.into(), Sound::Const(FRAC_1_SQRT_2).into());
+    Sound::Stereo(
+        scale(Sound::Add(left.clone().into(), right.clone().into())).into(),
+        scale(Sound::Sub(left.into(), right.into())).into(),
+    )
+}
+
+/// 方位角 `azimuth` （度，正が右）から来る音を，両耳間の時間差（ Woodworth の式）と
+/// 音量差で近似する
+pub fn binaural(sound: Sound, azimuth: f64) -> Sound {
+    let theta = azimuth.to_radians().sin().asin(); // -90° から 90° に畳む
+    let itd = HEAD_RADIUS / SPEED_OF_SOUND * (theta.abs() + theta.abs().sin());
+    let far_gain = 10f64.powf(-MAX_ILD * theta.sin().abs() / 20.);
+    let mono = sound.channel(0);
+    let far = Sound::Mul(
+        mono.clone().shift(-itd).into(),
+        Sound::Const(far_gain).into(),
+    );
+    if theta >= 0. {
+        Sound::Stereo(far.into(), mono.into())
+    } else {
+        Sound::Stereo(mono.into(), far.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(sound: &Sound, samplerate: f64, index: usize) -> (f64, f64) {
+        let mut left = sound.clone().channel(0).iter(samplerate);
+        let mut right = sound.clone().channel(1).iter(samplerate);
+        for _ in 0..index {
+            left.next();
+            right.next();
+        }
+        (left.next(), right.next())
+    }
+
+    #[test]
+    fn constant_power() {
+        for &position in &[-1., -0.3, 0., 0.5, 1.] {
+            let (left, right) = frame(&pan(Sound::Const(1.), Sound::Const(position)), 1., 0);
+            assert!((left * left + right * right - 1.).abs() < 1e-12);
+        }
+        assert_eq!(
+            frame(&pan(Sound::Const(1.), Sound::Const(-1.)), 1., 0),
+            (1., 0.)
+        );
+        // 位置は音でもよい
+        let moving = pan(
+            Sound::Const(1.),
+            Sound::Linear {
+                slope: 1.,
+                intercept: -1.,
+            },
+        );
+        let (left, right) = frame(&moving, 2., 2);
+        assert!((left - right).abs() < 1e-12);
+    }
+
+    #[test]
+    fn routing() {
+        let sound = Sound::Stereo(Sound::Const(0.5).into(), Sound::Const(0.25).into());
+        assert_eq!(frame(&left(sound.clone()), 1., 0), (0.5, 0.5));
+        assert_eq!(frame(&right(sound.clone()), 1., 0), (0.25, 0.25));
+        assert_eq!(frame(&swap_channels(sound.clone()), 1., 0), (0.25, 0.5));
+        let (x, _) = frame(&mono(pan(Sound::Const(0.5), Sound::Const(0.))), 1., 0);
+        assert!((x - 0.5).abs() < 1e-12);
+        assert!(matches!(mono(Sound::Const(0.5)), Sound::Const(x) if x == 0.5));
+        let (l, r) = frame(&mid_side(mid_side(sound)), 1., 0);
+        assert!((l - 0.5).abs() < 1e-12 && (r - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn binaural_delay() {
+        let click = Sound::Begin(0.);
+        let samplerate = 48000.;
+        let itd = HEAD_RADIUS / SPEED_OF_SOUND * (std::f64::consts::FRAC_PI_2 + 1.);
+        let delay = (itd * samplerate).ceil() as usize;
+        let sound = binaural(click, 90.);
+        // 右から来る音は右耳に先に届き，左耳には遅れて小さく届く
+        assert_eq!(frame(&sound, samplerate, 0), (0., 1.));
+        let (left, _) = frame(&sound, samplerate, delay);
+        assert!((left - 10f64.powf(-MAX_ILD / 20.)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,162 @@
+//! `cryss sweep` ：引数の値を変えながら台本を繰り返し書き出す
+//!
+//! 値の組ごとに自分自身を `--param NAME=VALUE` と `--out FILE` つきで起動する．
+//! 書き出すファイル名の `{NAME}` はその回の値に置き換わる
+
+use std::ffi::OsString;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `NAME=VALUE` （ `--param` ）
+pub fn parameter(spec: &str) -> Result<(String, f64), String> {
+    let (name, value) = split(spec)?;
+    Ok((name, number(value)?))
+}
+
+/// `NAME=FROM..TO:COUNT` （両端を含めて等間隔に `COUNT` 個）， `NAME=A,B,C` ， `NAME=VALUE`
+pub fn values(spec: &str) -> Result<(String, Vec<f64>), String> {
+    let (name, values) = split(spec)?;
+    let values = match values.split_once("..") {
+        Some((from, rest)) => {
+            let (to, count) = rest
+                .split_once(':')
+                .ok_or_else(|| format!("missing the count in `{}` (e.g. `200..2000:8`)", spec))?;
+            let (from, to) = (number(from)?, number(to)?);
+            match count.trim().parse::<usize>() {
+                Ok(0) | Err(_) => return Err(format!("invalid count `{}`", count)),
+                Ok(1) => vec![from],
+                Ok(count) => (0..count)
+                    .map(|i| from + (to - from) * i as f64 / (count - 1) as f64)
+                    .collect(),
+            }
+        }
+        None => values.split(',').map(number).collect::<Result<_, _>>()?,
+    };
+    Ok((name, values))
+}
+
+fn split(spec: &str) -> Result<(String, &str), String> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE (got `{}`)", spec))?;
+    let name = name.trim();
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("invalid parameter name `{}`", name));
+    }
+    Ok((name.to_string(), value))
+}
+
+fn number(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("invalid number `{}`", value))
+}
+
+/// 値の組すべて（直積）．先に与えた引数ほどゆっくり変わる
+pub fn combinations(parameters: &[(String, Vec<f64>)]) -> Vec<Vec<(String, f64)>> {
+    parameters
+        .iter()
+        .fold(vec![Vec::new()], |combinations, (name, values)| {
+            combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |&value| {
+                        let mut combination = combination.clone();
+                        combination.push((name.clone(), value));
+                        combination
+                    })
+                })
+                .collect()
+        })
+}
+
+/// ファイル名の `{NAME}` を値に置き換える．値は小数点以下 3 桁まで
+pub fn expand(template: &str, values: &[(String, f64)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |filename, (name, value)| {
+            let value = format!("{:.3}", value);
+            let value = value.trim_end_matches('0').trim_end_matches('.');
+            filename.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// 値の組ごとに `command` に `--param` と `--out` を足して実行する．
+/// 同時に `jobs` 個まで走らせ，失敗した回数を返す
+pub fn run(
+    command: &[OsString],
+    combinations: &[Vec<(String, f64)>],
+    out: &str,
+    jobs: usize,
+) -> usize {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, combinations.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(values) = combinations.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let mut child = Command::new(&command[0]);
+                    child.args(&command[1..]);
+                    for (name, value) in values {
+                        child.arg("--param").arg(format!("{}={}", name, value));
+                    }
+                    child.arg("--out").arg(out);
+                    let filename = expand(out, values);
+                    match child.status() {
+                        Ok(status) if status.success() => eprintln!("wrote {}", filename),
+                        Ok(_) => {
+                            eprintln!("error: failed to write {}", filename);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(err) => {
+                            eprintln!("error: cannot run {:?}: {}", command[0], err);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    failed.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweeps() {
+        assert_eq!(
+            values("cutoff=200..2000:4"),
+            Ok(("cutoff".to_string(), vec![200., 800., 1400., 2000.]))
+        );
+        assert_eq!(values("q=0.5,1"), Ok(("q".to_string(), vec![0.5, 1.])));
+        assert!(values("cutoff=200..2000").is_err());
+        assert!(values("1x=1").is_err());
+        let combinations = combinations(&[
+            ("a".to_string(), vec![1., 2.]),
+            ("b".to_string(), vec![0.25]),
+        ]);
+        assert_eq!(combinations.len(), 2);
+        assert_eq!(
+            expand("sweep_{a}_{b}_{n}.wav", &combinations[1]),
+            "sweep_2_0.25_{n}.wav"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_failures() {
+        let command = |program: &str| vec![OsString::from(program)];
+        let combinations = combinations(&[("a".to_string(), vec![1., 2., 3.])]);
+        assert_eq!(run(&command("true"), &combinations, "x.wav", 2), 0);
+        // 台本のエラーで終了コードが 0 でない子は失敗と数える
+        assert_eq!(run(&command("false"), &combinations, "x.wav", 2), 3);
+        assert_eq!(run(&command("/nonexistent"), &combinations, "x.wav", 2), 3);
+    }
+}
@@ -1,9 +1,11 @@
 //! 抽象構文木
 
 use crate::pos;
+use crate::types::Type;
 use std::collections::HashMap;
 
 /// 式
+#[derive(Clone)]
 pub struct Expression {
     pub range: pos::Range,
     pub node: Node,
@@ -16,7 +18,7 @@ impl Expression {
 }
 
 /// 式を表す木のノード
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Node {
     /// 識別子
     Identifier(String),
@@ -26,10 +28,18 @@ pub enum Node {
     Parameter(String),
     /// 数値リテラル
     Number(f64),
+    /// 整数リテラル
+    Int(i64),
     /// 文字列リテラル
     String(String),
+    /// `f"..."` ．`{name}` に変数 `name` の値を埋め込み， `{{` `}}` は波括弧そのもの
+    FormatString(String),
     /// 出力（後置演算子 `?` ）
     Print(Box<Expression>),
+    /// 添字（後置演算子 `[ ]` ）
+    Index(Box<Expression>, Box<Expression>),
+    /// フィールド（後置演算子 `.` ）
+    Field(Box<Expression>, String),
     /// 負号（前置演算子 `-` ）
     Minus(Box<Expression>),
     /// 逆数（前置演算子 `/` ）
@@ -46,6 +56,8 @@ pub enum Node {
     RightShift(Box<Expression>, Box<Expression>),
     Less(Box<Expression>, Box<Expression>),
     Greater(Box<Expression>, Box<Expression>),
+    LessEqual(Box<Expression>, Box<Expression>),
+    GreaterEqual(Box<Expression>, Box<Expression>),
     Equal(Box<Expression>, Box<Expression>),
     NotEqual(Box<Expression>, Box<Expression>),
     And(Box<Expression>, Box<Expression>),
@@ -54,6 +66,10 @@ pub enum Node {
     Group(Box<Expression>),
     /// 角括弧 `[ ]` でくくって `,` `;` で区切る
     Score(Vec<Vec<Expression>>),
+    /// 波括弧 `{ }` でくくって `名前: 式` を `,` で区切る
+    Record(Vec<(String, Expression)>),
+    /// ラムダ式 `(x, y: Sound) => 式`．引数に既定値はなく，型を省略したら `real`
+    Lambda(Vec<Parameter>, Box<Expression>),
 }
 
 /// 文
@@ -63,19 +79,62 @@ pub enum Statement {
     Expression(Option<Expression>),
     /// 代入文
     Substitution(pos::Range, String, Expression),
-    /// 宣言と代入
-    Declaration(pos::Range, String, Expression),
+    /// 宣言と代入．型 `let i: int = 0;` を書かなければ，整数の値でも実数の変数にする
+    Declaration(pos::Range, String, Option<Type>, Expression),
+    /// 配列やレコードを分解して宣言する `let [a, b] = 式;` `let {a, b} = 式;`
+    Destructuring(pos::Range, Pattern, Expression),
     /// 波括弧 `{ }` で囲まれたブロック
     Block(Vec<Statement>),
-    /// if 文
-    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    /// if 文．範囲は `if (条件)` の部分．`else if` の連なりは else 節に if 文を入れ子にして表す
+    If(
+        pos::Range,
+        Expression,
+        Box<Statement>,
+        Option<Box<Statement>>,
+    ),
     /// while 文
     While(Expression, Box<Statement>),
+    /// for 文 `for (名前 in 配列) 文`．範囲は名前
+    For(pos::Range, String, Expression, Box<Statement>),
     Break(pos::Range),
     Continue(pos::Range),
+    /// return 文．`return 式 if 条件;` は if 文にする
     Return(pos::Range, Option<Expression>),
-    /// 関数定義
-    Definition(pos::Range, String, Box<Statement>),
+    /// 関数定義 `def 名前(引数) -> 型 文`．範囲は名前．戻り値の型を省略したら `Type::Void`
+    Definition(pos::Range, String, Vec<Parameter>, Type, Box<Statement>),
+    /// 外部の音声ファイルの宣言 `samples { "名前": "パス" sha256 "..." }`
+    Samples(pos::Range, Vec<SampleEntry>),
+    /// 対話環境のメタコマンド `:snapshot 名前` など
+    Command(pos::Range, String),
+}
+
+/// `let` で分解するときの形．名前とその位置を並べる
+#[derive(Debug)]
+pub enum Pattern {
+    /// `[a, b]` ：配列の先頭から順に
+    Array(Vec<(pos::Range, String)>),
+    /// `{a, b}` ：レコードの同名のフィールド
+    Record(Vec<(pos::Range, String)>),
+}
+
+/// 関数定義の引数 `名前: 型` `名前 = 既定値` `名前: 型 = 既定値`．
+/// 既定値のあるものは名前つき引数になる
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub range: pos::Range,
+    pub name: String,
+    pub ty: Option<Type>,
+    pub default: Option<Expression>,
+}
+
+/// `samples` 宣言の 1 項目
+#[derive(Debug)]
+pub struct SampleEntry {
+    pub range: pos::Range,
+    pub name: String,
+    pub path: String,
+    /// 16 進で書いた SHA-256 （省略可）
+    pub sha256: Option<String>,
 }
 
 use std::fmt::{Debug, Formatter, Result as FResult};
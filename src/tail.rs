@@ -0,0 +1,59 @@
+//! 書き出しの末尾（フィルタなどの余韻）の扱い
+
+/// `auto` で余韻を待つ上限（秒）
+pub const MAX_AUTO: f64 = 60.;
+/// `auto` で余韻が消えたとみなす振幅（ -80 dBFS ）
+pub const THRESHOLD: f64 = 1e-4;
+/// `auto` で振幅がしきい値を下回り続けたら止める時間（秒）
+pub const HOLD: f64 = 0.01;
+
+/// `write` などの名前つき引数 `tail` で選ぶ余韻の扱い
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tail {
+    /// 指定した長さでそのまま切る
+    None,
+    /// 音源を止めてから決まった秒数だけ余韻を書き出す
+    Fixed(f64),
+    /// 音源を止めてから余韻が消えるまで書き出す
+    Auto,
+}
+
+impl Tail {
+    /// `""` （余韻なし）， `"auto"` ，または秒数
+    pub fn parse(tail: &str) -> Result<Tail, String> {
+        match tail {
+            "" => Ok(Tail::None),
+            "auto" => Ok(Tail::Auto),
+            seconds => match seconds.parse::<f64>() {
+                Ok(seconds) if seconds >= 0. => Ok(Tail::Fixed(seconds)),
+                _ => Err(format!(
+                    "invalid tail `{}` (expected `auto` or seconds)",
+                    tail
+                )),
+            },
+        }
+    }
+    /// 余韻として書き出す長さの上限（秒）． `reported` は音が報告する余韻の長さ
+    pub fn limit(&self, reported: f64) -> f64 {
+        match *self {
+            Tail::None => 0.,
+            Tail::Fixed(seconds) => seconds,
+            Tail::Auto => reported.min(MAX_AUTO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Tail::parse(""), Ok(Tail::None));
+        assert_eq!(Tail::parse("auto"), Ok(Tail::Auto));
+        assert_eq!(Tail::parse("2.5"), Ok(Tail::Fixed(2.5)));
+        assert!(Tail::parse("-1").is_err());
+        assert!(Tail::parse("long").is_err());
+        assert_eq!(Tail::Auto.limit(1e9), MAX_AUTO);
+    }
+}
@@ -0,0 +1,79 @@
+//! `cryss new` で書き出すひな形（バイナリに埋め込む）
+
+use std::path::{Path, PathBuf};
+
+/// ひな形の名前と内容
+pub const TEMPLATES: &[(&str, &str)] = &[
+    ("kick", include_str!("../templates/kick.crs")),
+    ("ambient", include_str!("../templates/ambient.crs")),
+    ("steps", include_str!("../templates/steps.crs")),
+];
+
+/// 設定ファイルのひな形
+pub const CONFIG: &str = include_str!("../templates/cryss.toml");
+
+pub fn names() -> Vec<&'static str> {
+    TEMPLATES.iter().map(|(name, _)| *name).collect()
+}
+
+/// ひな形 `name` を `directory/<name>.crs` に書き出す． `config` なら `cryss.toml` も書き出す
+///
+/// 既にあるファイルは上書きしない．書き出したファイルの一覧を返す
+pub fn create(name: &str, directory: &Path, config: bool) -> Result<Vec<PathBuf>, String> {
+    let (_, script) = TEMPLATES
+        .iter()
+        .find(|(template, _)| *template == name)
+        .ok_or_else(|| {
+            format!(
+                "unknown template `{}` (available: {})",
+                name,
+                names().join(", ")
+            )
+        })?;
+    let mut files = vec![(directory.join(format!("{}.crs", name)), *script)];
+    if config {
+        files.push((directory.join("cryss.toml"), CONFIG));
+    }
+    if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+        return Err(format!("{} already exists", path.display()));
+    }
+    std::fs::create_dir_all(directory)
+        .map_err(|err| format!("cannot create {}: {}", directory.display(), err))?;
+    for (path, content) in &files {
+        std::fs::write(path, content)
+            .map_err(|err| format!("cannot write {}: {}", path.display(), err))?;
+    }
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser;
+
+    #[test]
+    fn templates_parse() {
+        for (name, script) in TEMPLATES {
+            let mut lexer = Lexer::new(Box::new(std::io::Cursor::new(script.to_string())), false);
+            let mut log = Vec::new();
+            while parser::parse_statement(&mut lexer, &mut log)
+                .unwrap_or_else(|_| panic!("template `{}` does not parse", name))
+                .is_some()
+            {}
+        }
+    }
+
+    #[test]
+    fn create_files() {
+        let directory = std::env::temp_dir().join(format!("cryss-template-{}", std::process::id()));
+        let files = create("kick", &directory, true).unwrap();
+        assert_eq!(
+            files,
+            vec![directory.join("kick.crs"), directory.join("cryss.toml")]
+        );
+        assert!(create("kick", &directory, false).is_err());
+        assert!(create("unknown", &directory, false).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
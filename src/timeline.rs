@@ -0,0 +1,245 @@
+//! タイムライン上の名前つきマーカーと区間，テンポ
+
+use std::collections::HashMap;
+
+/// テンポが指定されていないときの BPM
+const DEFAULT_BPM: f64 = 120.;
+/// 一小節あたりの拍数
+const BEATS_PER_BAR: f64 = 4.;
+
+/// 書き出すトラックの区切り（ `write_cue` ， `write_chapters` ）．
+/// マーカーは終わりをもたない
+#[derive(Debug, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub start: f64,
+    pub end: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    /// `marker(name, time)` で置かれた時刻
+    markers: HashMap<String, f64>,
+    /// `region(name, start, end)` で置かれた区間
+    regions: HashMap<String, (f64, f64)>,
+    /// コマンドライン引数 `--from` `--to` で指定された書き出し範囲（マーカー名または区間名）
+    from: Option<String>,
+    to: Option<String>,
+    /// コマンドライン引数 `--preview` で指定された書き出しの長さ（秒）と標本化周波数
+    preview: Option<(f64, Option<f64>)>,
+    /// テンポの変化点（小節，BPM，直前の変化点から徐々に変化するか）．
+    /// BPM が `None` の点では直前のテンポを保つ
+    tempo: Vec<(f64, Option<f64>, bool)>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline::default()
+    }
+    pub fn set_window(&mut self, from: Option<String>, to: Option<String>) {
+        self.from = from;
+        self.to = to;
+    }
+    pub fn set_preview(&mut self, seconds: f64, samplerate: Option<f64>) {
+        self.preview = Some((seconds, samplerate));
+    }
+    /// 書き出す範囲 `(start, end)` と標本化周波数を `--preview` の指定まで縮める
+    pub fn preview(&self, (start, end): (f64, f64), samplerate: f64) -> ((f64, f64), f64) {
+        match self.preview {
+            Some((seconds, preview_samplerate)) => (
+                (start, end.min(start + seconds)),
+                preview_samplerate.map_or(samplerate, |preview| preview.min(samplerate)),
+            ),
+            None => ((start, end), samplerate),
+        }
+    }
+    pub fn add_marker(&mut self, name: String, time: f64) {
+        self.markers.insert(name, time);
+    }
+    pub fn add_region(&mut self, name: String, start: f64, end: f64) {
+        self.regions.insert(name, (start.min(end), start.max(end)));
+    }
+    /// 区間名ならその区間を，マーカー名ならその時刻だけからなる区間を返す
+    pub fn region(&self, name: &str) -> Option<(f64, f64)> {
+        match (self.regions.get(name), self.markers.get(name)) {
+            (Some(&region), _) => Some(region),
+            (None, Some(&time)) => Some((time, time)),
+            (None, None) => None,
+        }
+    }
+    /// `--from` `--to` で指定された範囲を `[0, time)` と重ねた範囲．
+    ///
+    /// - `--from` が区間名で `--to` がないときは，その区間全体
+    /// - 未定義の名前があればその名前を `Err` で返す
+    pub fn window(&self, time: f64) -> Result<(f64, f64), String> {
+        let lookup = |name: &String| self.region(name).ok_or_else(|| name.clone());
+        let (start, end) = match (&self.from, &self.to) {
+            (None, None) => (0., time),
+            (Some(from), None) => match self.regions.get(from) {
+                Some(&region) => region,
+                None => (lookup(from)?.0, time),
+            },
+            (None, Some(to)) => (0., lookup(to)?.1),
+            (Some(from), Some(to)) => (lookup(from)?.0, lookup(to)?.1),
+        };
+        Ok((start.max(0.), end.min(time)))
+    }
+    /// マーカーと区間を始まりの順（同時なら名前の順）に並べたもの．
+    /// 時刻は書き出す範囲（ `--from` `--to` ）の始まりから測り，範囲の外のものは除く
+    pub fn chapters(&self) -> Result<Vec<Chapter>, String> {
+        let (from, to) = self
+            .window(f64::INFINITY)
+            .map_err(|name| format!("undefined marker or region `{}`", name))?;
+        let markers = self.markers.iter().map(|(name, &time)| (name, time, None));
+        let regions = self
+            .regions
+            .iter()
+            .map(|(name, &(start, end))| (name, start, Some(end)));
+        let mut chapters: Vec<_> = markers
+            .chain(regions)
+            .filter(|&(_, start, end)| end.unwrap_or(start) >= from && start <= to)
+            .map(|(name, start, end)| Chapter {
+                title: name.clone(),
+                start: (start - from).max(0.),
+                end: end.map(|end| end.min(to) - from),
+            })
+            .collect();
+        chapters.sort_by(|a, b| {
+            a.start
+                .total_cmp(&b.start)
+                .then_with(|| a.title.cmp(&b.title))
+        });
+        Ok(chapters)
+    }
+    /// `bar` 小節目（ 0 小節目が時刻 0 ）からテンポを `bpm` に切り替える
+    pub fn tempo_change(&mut self, bar: f64, bpm: f64) {
+        self.tempo.push((bar, Some(bpm), false));
+        self.sort_tempo();
+    }
+    /// `from` 小節目から `to` 小節目にかけて，テンポを `bpm` まで直線的に変化させる
+    pub fn tempo_ramp(&mut self, from: f64, to: f64, bpm: f64) {
+        self.tempo.push((from, None, false));
+        self.tempo.push((to, Some(bpm), true));
+        self.sort_tempo();
+    }
+    fn sort_tempo(&mut self) {
+        // 同じ小節に複数の点があるときは追加した順（安定ソート）
+        self.tempo
+            .sort_by(|(x, _, _), (y, _, _)| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+    }
+    /// `beat` 拍目（ 0 拍目が時刻 0 ）の時刻（秒）
+    pub fn time_at_beat(&self, beat: f64) -> f64 {
+        let mut time = 0.;
+        let mut position = 0.;
+        let mut bpm = DEFAULT_BPM;
+        for &(bar, next, ramp) in &self.tempo {
+            let next_position = bar * BEATS_PER_BAR;
+            let next_bpm = next.unwrap_or(bpm);
+            if next_position > position {
+                let end = next_position.min(beat);
+                let to = if ramp { next_bpm } else { bpm };
+                time += segment(bpm, to, next_position - position, end - position);
+                if beat <= next_position {
+                    return time;
+                }
+                position = next_position;
+            }
+            bpm = next_bpm;
+        }
+        time + 60. / bpm * (beat - position)
+    }
+    /// `bar` 小節目（ 0 小節目が時刻 0 ）の時刻（秒）
+    pub fn time_at_bar(&self, bar: f64) -> f64 {
+        self.time_at_beat(bar * BEATS_PER_BAR)
+    }
+}
+
+/// 長さ `length` 拍でテンポが `from` から `to` まで直線的に変化する区間の，
+/// 先頭から `beats` 拍ぶんの時間（秒）
+fn segment(from: f64, to: f64, length: f64, beats: f64) -> f64 {
+    if (to - from).abs() <= 1e-9 {
+        60. / from * beats
+    } else {
+        let slope = (to - from) / length;
+        60. / slope * ((from + slope * beats) / from).ln()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(from: Option<&str>, to: Option<&str>) -> Timeline {
+        let mut timeline = Timeline::new();
+        timeline.add_marker("a".to_string(), 1.);
+        timeline.add_marker("b".to_string(), 3.);
+        timeline.add_region("chorus".to_string(), 2., 4.);
+        timeline.set_window(from.map(str::to_string), to.map(str::to_string));
+        timeline
+    }
+
+    #[test]
+    fn whole() {
+        assert_eq!(timeline(None, None).window(5.), Ok((0., 5.)));
+    }
+
+    #[test]
+    fn markers() {
+        assert_eq!(timeline(Some("a"), Some("b")).window(5.), Ok((1., 3.)));
+        assert_eq!(timeline(Some("a"), None).window(5.), Ok((1., 5.)));
+        assert_eq!(timeline(None, Some("b")).window(5.), Ok((0., 3.)));
+    }
+
+    #[test]
+    fn region() {
+        assert_eq!(timeline(Some("chorus"), None).window(5.), Ok((2., 4.)));
+        assert_eq!(timeline(Some("chorus"), None).window(3.), Ok((2., 3.)));
+        assert_eq!(timeline(Some("a"), Some("chorus")).window(5.), Ok((1., 4.)));
+    }
+
+    #[test]
+    fn tempo_default() {
+        let timeline = Timeline::new();
+        assert!((timeline.time_at_bar(2.) - 4.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tempo_change() {
+        let mut timeline = Timeline::new();
+        timeline.tempo_change(0., 60.);
+        timeline.tempo_change(2., 240.);
+        assert!((timeline.time_at_bar(1.) - 4.).abs() < 1e-9);
+        assert!((timeline.time_at_bar(3.) - 9.).abs() < 1e-9);
+        assert!((timeline.time_at_beat(9.) - 8.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tempo_ramp() {
+        let mut timeline = Timeline::new();
+        timeline.tempo_change(0., 60.);
+        timeline.tempo_ramp(1., 2., 120.);
+        // 60 BPM から 120 BPM まで 4 拍かけて変化： 240 / 60 * ln 2 秒
+        let ramp = 4. * 2f64.ln();
+        assert!((timeline.time_at_bar(2.) - (4. + ramp)).abs() < 1e-9);
+        assert!((timeline.time_at_bar(3.) - (6. + ramp)).abs() < 1e-9);
+        assert!(timeline.time_at_bar(1.5) < 4. + ramp);
+    }
+
+    #[test]
+    fn undefined() {
+        assert_eq!(
+            timeline(Some("verse"), None).window(5.),
+            Err("verse".to_string())
+        );
+    }
+
+    #[test]
+    fn preview() {
+        let mut timeline = timeline(Some("chorus"), None);
+        let window = timeline.window(5.).unwrap();
+        assert_eq!(timeline.preview(window, 44100.), ((2., 4.), 44100.));
+        timeline.set_preview(0.5, Some(22050.));
+        assert_eq!(timeline.preview(window, 44100.), ((2., 2.5), 22050.));
+        assert_eq!(timeline.preview(window, 8000.), ((2., 2.5), 8000.));
+    }
+}
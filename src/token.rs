@@ -8,7 +8,11 @@ pub enum Token {
     /// `$` で始まる
     Parameter(String),
     Number(f64),
+    /// 小数点も指数もない数値．`i64` に収まらなければエラー
+    Int(i64),
     String(String),
+    /// `f"..."` ．`{name}` に変数の値を埋め込む
+    FormatString(String),
     KeywordLet,
     KeywordBreak,
     KeywordContinue,
@@ -23,6 +27,10 @@ pub enum Token {
     KeywordFor,
     KeywordReturn,
     KeywordDef,
+    /// キーワード `samples`: 外部の音声ファイルの宣言
+    KeywordSamples,
+    /// 対話環境で `:` から始まる行（メタコマンド）．`:` を除いた残り
+    Command(String),
     /// `+`: 足し算
     Plus,
     /// `-`: （ 2 項）引き算，（単項）負号
@@ -37,6 +45,18 @@ pub enum Token {
     Percent,
     /// `^`: 累乗
     Circumflex,
+    /// `+=`: 足して代入
+    PlusEqual,
+    /// `-=`: 引いて代入
+    HyphenEqual,
+    /// `*=`: 掛けて代入
+    AsteriskEqual,
+    /// `/=`: 割って代入
+    SlashEqual,
+    /// `%=`: 余りを代入
+    PercentEqual,
+    /// `^=`: 累乗して代入
+    CircumflexEqual,
     /// `=`: 代入
     Equal,
     /// `=>`: 右代入
@@ -51,10 +71,14 @@ pub enum Token {
     Less,
     /// `<<`: 左シフト（ Sound ）
     DoubleLess,
+    /// `<=`: 以下
+    LessEqual,
     /// `>`: より大きい
     Greater,
     /// `>>`: 右シフト（ Sound ）
     DoubleGreater,
+    /// `>=`: 以上
+    GreaterEqual,
     /// `&&`: 論理積
     DoubleAmpersand,
     /// `|`
@@ -67,6 +91,8 @@ pub enum Token {
     Semicolon,
     /// `,`
     Comma,
+    /// `.`: フィールド
+    Dot,
     /// `?`: 出力
     Question,
     /// `(`
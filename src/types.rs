@@ -1,11 +1,51 @@
-//! 型リスト（デバッグ用）
+//! 型リスト
 
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Type {
     Real,
+    /// 整数（小数点も指数もない数値リテラルとその演算）
+    Int,
     Boolean,
     Sound,
     String,
+    /// 実数の配列
+    Array,
+    /// 実数をフィールドにもつレコード
+    Record,
+    /// Sound をフィールドにもつレコード
+    SoundRecord,
+    /// Sound の配列
+    SoundArray,
     Void,
+    /// 関数（ `def` で定義したものやラムダ式 `(x) => x * 2` ）
+    Function(Rc<Signature>),
+}
+
+/// 関数の位置引数の型と戻り値の型
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub parameters: Vec<Type>,
+    pub ret: Type,
+}
+
+impl Type {
+    /// 関数定義に書く型の名前（表示と同じ）．`real[]` などの `[]` は `array` で付ける
+    pub fn from_name(name: &str, array: bool) -> Option<Type> {
+        Some(match (name, array) {
+            ("real", false) => Type::Real,
+            ("int", false) => Type::Int,
+            ("boolean", false) => Type::Boolean,
+            ("Sound", false) => Type::Sound,
+            ("string", false) => Type::String,
+            ("real", true) => Type::Array,
+            ("record", false) => Type::Record,
+            ("Sound", true) => Type::SoundArray,
+            ("void", false) => Type::Void,
+            _ => return None,
+        })
+    }
 }
 
 use std::fmt::{Display, Formatter, Result as FResult};
@@ -13,10 +53,23 @@ impl Display for Type {
     fn fmt(&self, f: &mut Formatter) -> FResult {
         match self {
             Type::Real => write!(f, "real"),
+            Type::Int => write!(f, "int"),
             Type::Boolean => write!(f, "boolean"),
             Type::Sound => write!(f, "Sound"),
             Type::String => write!(f, "string"),
+            Type::Array => write!(f, "real[]"),
+            Type::Record => write!(f, "record"),
+            Type::SoundRecord => write!(f, "Sound record"),
+            Type::SoundArray => write!(f, "Sound[]"),
             Type::Void => write!(f, "void"),
+            Type::Function(signature) => {
+                let parameters: Vec<_> = signature
+                    .parameters
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+                write!(f, "fn({}) -> {}", parameters.join(", "), signature.ret)
+            }
         }
     }
 }
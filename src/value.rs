@@ -1,9 +1,11 @@
 use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
 use std::rc::Rc;
 
 type RcCell<T> = Rc<Cell<T>>;
 type RcRefCell<T> = Rc<RefCell<T>>;
 
+use crate::function::Function;
 use crate::sound::Sound;
 use crate::types::Type;
 
@@ -11,18 +13,153 @@ use crate::types::Type;
 #[derive(Clone)]
 pub enum Value {
     Real(RcCell<f64>),
+    Int(RcCell<i64>),
     Boolean(RcCell<bool>),
     Sound(RcRefCell<Sound>),
     String(RcRefCell<String>),
+    Array(RcRefCell<Vec<f64>>),
+    Record(RcRefCell<Vec<(String, f64)>>),
+    SoundRecord(RcRefCell<Vec<(String, Sound)>>),
+    SoundArray(RcRefCell<Vec<Sound>>),
+    Function(RcRefCell<Rc<Function>>),
 }
 
 impl Value {
+    /// 中身を複製した新しい値（別の `Rc` ）
+    pub fn deep_copy(&self) -> Value {
+        match self {
+            Value::Real(rc) => Value::from(rc.get()),
+            Value::Int(rc) => Value::from(rc.get()),
+            Value::Boolean(rc) => Value::from(rc.get()),
+            Value::Sound(rc) => Value::from(rc.borrow().clone()),
+            Value::String(rc) => Value::from(rc.borrow().clone()),
+            Value::Array(rc) => Value::from(rc.borrow().clone()),
+            Value::Record(rc) => Value::from(rc.borrow().clone()),
+            Value::SoundRecord(rc) => Value::from(rc.borrow().clone()),
+            Value::SoundArray(rc) => Value::from(rc.borrow().clone()),
+            Value::Function(rc) => Value::Function(Rc::new(RefCell::new(rc.borrow().clone()))),
+        }
+    }
+    /// `other` の中身を書き込む．型が違えば何もしない
+    pub fn assign(&self, other: &Value) {
+        match (self, other) {
+            (Value::Real(rc), Value::Real(other)) => rc.set(other.get()),
+            (Value::Int(rc), Value::Int(other)) => rc.set(other.get()),
+            (Value::Boolean(rc), Value::Boolean(other)) => rc.set(other.get()),
+            (Value::Sound(rc), Value::Sound(other)) => rc.borrow_mut().clone_from(&other.borrow()),
+            (Value::String(rc), Value::String(other)) => {
+                rc.borrow_mut().clone_from(&other.borrow())
+            }
+            (Value::Array(rc), Value::Array(other)) => rc.borrow_mut().clone_from(&other.borrow()),
+            (Value::Record(rc), Value::Record(other)) => {
+                rc.borrow_mut().clone_from(&other.borrow())
+            }
+            (Value::SoundRecord(rc), Value::SoundRecord(other)) => {
+                rc.borrow_mut().clone_from(&other.borrow())
+            }
+            (Value::SoundArray(rc), Value::SoundArray(other)) => {
+                rc.borrow_mut().clone_from(&other.borrow())
+            }
+            (Value::Function(rc), Value::Function(other)) => {
+                rc.borrow_mut().clone_from(&other.borrow())
+            }
+            _ => {}
+        }
+    }
+    /// 同じ変数（同じ `Rc` ）か
+    pub fn ptr_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Real(rc), Value::Real(other)) => Rc::ptr_eq(rc, other),
+            (Value::Int(rc), Value::Int(other)) => Rc::ptr_eq(rc, other),
+            (Value::Boolean(rc), Value::Boolean(other)) => Rc::ptr_eq(rc, other),
+            (Value::Sound(rc), Value::Sound(other)) => Rc::ptr_eq(rc, other),
+            (Value::String(rc), Value::String(other)) => Rc::ptr_eq(rc, other),
+            (Value::Array(rc), Value::Array(other)) => Rc::ptr_eq(rc, other),
+            (Value::Record(rc), Value::Record(other)) => Rc::ptr_eq(rc, other),
+            (Value::SoundRecord(rc), Value::SoundRecord(other)) => Rc::ptr_eq(rc, other),
+            (Value::SoundArray(rc), Value::SoundArray(other)) => Rc::ptr_eq(rc, other),
+            (Value::Function(rc), Value::Function(other)) => Rc::ptr_eq(rc, other),
+            _ => false,
+        }
+    }
     pub fn ty(&self) -> Type {
         match self {
             Value::Real(_) => Type::Real,
+            Value::Int(_) => Type::Int,
             Value::Boolean(_) => Type::Boolean,
             Value::Sound(_) => Type::Sound,
             Value::String(_) => Type::String,
+            Value::Array(_) => Type::Array,
+            Value::Record(_) => Type::Record,
+            Value::SoundRecord(_) => Type::SoundRecord,
+            Value::SoundArray(_) => Type::SoundArray,
+            Value::Function(rc) => Type::Function(rc.borrow().signature().into()),
         }
     }
 }
+
+/// Rust の値と `Value` の間の変換．`TryFrom` は型が違えば説明つきのエラーを返す
+macro_rules! conversion {
+    ($type:ty, $variant:ident, Cell) => {
+        conversion!(@impl $type, $variant, Cell, |rc: &RcCell<$type>| rc.get());
+    };
+    ($type:ty, $variant:ident, RefCell) => {
+        conversion!(@impl $type, $variant, RefCell, |rc: &RcRefCell<$type>| rc.borrow().clone());
+    };
+    (@impl $type:ty, $variant:ident, $cell:ident, $get:expr) => {
+        impl From<$type> for Value {
+            fn from(x: $type) -> Value {
+                Value::$variant(Rc::new($cell::new(x)))
+            }
+        }
+        impl TryFrom<&Value> for $type {
+            type Error = String;
+            fn try_from(value: &Value) -> Result<$type, String> {
+                match value {
+                    Value::$variant(rc) => Ok($get(rc)),
+                    _ => Err(format!(
+                        "expected {}, found {}",
+                        Type::$variant,
+                        value.ty()
+                    )),
+                }
+            }
+        }
+        impl TryFrom<Value> for $type {
+            type Error = String;
+            fn try_from(value: Value) -> Result<$type, String> {
+                <$type>::try_from(&value)
+            }
+        }
+    };
+}
+
+conversion!(f64, Real, Cell);
+conversion!(i64, Int, Cell);
+conversion!(bool, Boolean, Cell);
+conversion!(Sound, Sound, RefCell);
+conversion!(String, String, RefCell);
+conversion!(Vec<f64>, Array, RefCell);
+conversion!(Vec<(String, f64)>, Record, RefCell);
+conversion!(Vec<(String, Sound)>, SoundRecord, RefCell);
+conversion!(Vec<Sound>, SoundArray, RefCell);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion() {
+        let value = Value::from(vec![1., 2.]);
+        assert_eq!(Vec::<f64>::try_from(&value), Ok(vec![1., 2.]));
+        assert_eq!(
+            f64::try_from(value),
+            Err("expected real, found real[]".to_string())
+        );
+        assert_eq!(bool::try_from(Value::from(true)), Ok(true));
+        assert_eq!(
+            String::try_from(Value::from(0.5)),
+            Err("expected string, found real".to_string())
+        );
+    }
+}
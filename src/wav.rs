@@ -0,0 +1,228 @@
+//! WAV ファイルのメタデータ（ `LIST`/`INFO` チャンクと BWF の `bext` チャンク，
+//! 読み込むときの `smpl` チャンクのループ）
+
+/// 書き出すファイルにつけるメタデータ．空の項目は書かない
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: String,
+    pub artist: String,
+    pub comment: String,
+    /// ファイルの先頭のタイムコード（秒）． BWF の TimeReference になる
+    pub time_reference: Option<f64>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+}
+
+/// RIFF のチャンク（ ID と中身）．奇数長なら 0 で埋める
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend(&(data.len() as u32).to_le_bytes());
+    bytes.extend(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+/// 固定長の欄に文字列を詰める（あふれた分は切り捨てる）
+fn field(string: &str, length: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = string.bytes().take(length).collect();
+    bytes.resize(length, 0);
+    bytes
+}
+
+/// `LIST`/`INFO` チャンク
+fn info(metadata: &Metadata) -> Option<Vec<u8>> {
+    let mut data = b"INFO".to_vec();
+    for (id, value) in &[
+        (b"INAM", &metadata.title),
+        (b"IART", &metadata.artist),
+        (b"ICMT", &metadata.comment),
+    ] {
+        if !value.is_empty() {
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            data.extend(chunk(id, &value));
+        }
+    }
+    if data.len() > 4 {
+        Some(chunk(b"LIST", &data))
+    } else {
+        None
+    }
+}
+
+/// BWF の `bext` チャンク（版 1 ，コーディング履歴なし）
+fn bext(metadata: &Metadata, samplerate: f64, date: &str, time: &str) -> Option<Vec<u8>> {
+    let time_reference = (metadata.time_reference? * samplerate).round().max(0.) as u64;
+    let mut data = field(&metadata.comment, 256);
+    data.extend(field(concat!("cryss ", env!("CARGO_PKG_VERSION")), 32));
+    data.extend(field("", 32));
+    data.extend(field(date, 10));
+    data.extend(field(time, 8));
+    data.extend(&(time_reference as u32).to_le_bytes());
+    data.extend(&((time_reference >> 32) as u32).to_le_bytes());
+    data.extend(&1u16.to_le_bytes());
+    // UMID （ 64 バイト），ラウドネス（ 10 バイト），予約（ 180 バイト）
+    data.resize(602, 0);
+    Some(chunk(b"bext", &data))
+}
+
+/// 今の日付と時刻（ UTC ， `yyyy-mm-dd` と `hh:mm:ss` ）
+fn now() -> (String, String) {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    // 1970-03-01 から数えた日数をグレゴリオ暦に直す
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = seconds % 86400;
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60),
+    )
+}
+
+/// WAV ファイルの中身 `bytes` の `data` チャンクの前にメタデータのチャンクを差し込む
+pub fn insert_metadata(
+    bytes: &[u8],
+    metadata: &Metadata,
+    samplerate: f64,
+) -> Result<Vec<u8>, String> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+    // data チャンクの位置を探す
+    let mut position = 12;
+    loop {
+        if position + 8 > bytes.len() {
+            return Err("no data chunk".to_string());
+        }
+        if &bytes[position..position + 4] == b"data" {
+            break;
+        }
+        let size = u32::from_le_bytes([
+            bytes[position + 4],
+            bytes[position + 5],
+            bytes[position + 6],
+            bytes[position + 7],
+        ]) as usize;
+        position += 8 + size + size % 2;
+    }
+    let (date, time) = now();
+    let mut chunks = bext(metadata, samplerate, &date, &time).unwrap_or_default();
+    chunks.extend(info(metadata).unwrap_or_default());
+    let mut ret = bytes[..position].to_vec();
+    ret.extend(&chunks);
+    ret.extend(&bytes[position..]);
+    let riff_size = (ret.len() - 8) as u32;
+    ret[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(ret)
+}
+
+/// `smpl` チャンクの最初のループ区間 `[start, end)` （標本数）
+pub fn loop_points(bytes: &[u8]) -> Option<(usize, usize)> {
+    if bytes.get(..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let word = |position: usize| -> Option<usize> {
+        let bytes = bytes.get(position..position + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let size = word(position + 4)?;
+        if &bytes[position..position + 4] == b"smpl" {
+            // 36 バイトの見出しの後に 24 バイトずつループが並ぶ．終わりの標本も含む
+            if word(position + 8 + 28)? == 0 {
+                return None;
+            }
+            let start = word(position + 8 + 36 + 8)?;
+            let end = word(position + 8 + 36 + 12)? + 1;
+            return if start < end {
+                Some((start, end))
+            } else {
+                None
+            };
+        }
+        position += 8 + size + size % 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// fmt と 2 標本の data だけの WAV
+    fn minimal_wav() -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend(&1u16.to_le_bytes());
+        fmt.extend(&1u16.to_le_bytes());
+        fmt.extend(&44100u32.to_le_bytes());
+        fmt.extend(&88200u32.to_le_bytes());
+        fmt.extend(&2u16.to_le_bytes());
+        fmt.extend(&16u16.to_le_bytes());
+        let mut body = b"WAVE".to_vec();
+        body.extend(chunk(b"fmt ", &fmt));
+        body.extend(chunk(b"data", &[1, 0, 2, 0]));
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn metadata_chunks() {
+        let metadata = Metadata {
+            title: "Pad".to_string(),
+            artist: "me".to_string(),
+            comment: String::new(),
+            time_reference: Some(2.),
+        };
+        let original = minimal_wav();
+        let bytes = insert_metadata(&original, &metadata, 44100.).unwrap();
+        let riff_size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        assert_eq!(riff_size, bytes.len() - 8);
+        // fmt のすぐ後に bext ，その後に LIST ，最後が data
+        assert_eq!(&bytes[36..40], b"bext");
+        let time_reference = &bytes[44 + 256 + 32 + 32 + 10 + 8..][..4];
+        assert_eq!(time_reference, &88200u32.to_le_bytes());
+        let list = 36 + 8 + 602;
+        assert_eq!(&bytes[list..list + 4], b"LIST");
+        assert_eq!(
+            &bytes[list + 8..list + 24],
+            b"INFOINAM\x04\x00\x00\x00Pad\x00"
+        );
+        assert_eq!(&bytes[bytes.len() - 12..], &original[original.len() - 12..]);
+
+        assert!(insert_metadata(b"RIFF", &metadata, 44100.).is_err());
+        assert_eq!(
+            insert_metadata(&original, &Metadata::default(), 44100.).unwrap(),
+            original
+        );
+    }
+
+    #[test]
+    fn smpl_loop() {
+        assert_eq!(loop_points(&minimal_wav()), None);
+        let mut smpl = vec![0; 28];
+        smpl.extend(&1u32.to_le_bytes());
+        smpl.extend(&[0; 4 + 8]);
+        smpl.extend(&100u32.to_le_bytes());
+        smpl.extend(&199u32.to_le_bytes());
+        smpl.extend(&[0; 8]);
+        let mut body = minimal_wav()[8..].to_vec();
+        body.extend(chunk(b"smpl", &smpl));
+        assert_eq!(loop_points(&chunk(b"RIFF", &body)), Some((100, 200)));
+    }
+}